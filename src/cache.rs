@@ -0,0 +1,73 @@
+/**************************************************************************
+* cache.rs
+*
+* Saves the report of each run so that an identical run can print it again
+* without repeating the simulations.
+**************************************************************************/
+
+use std::fs;
+use std::path::PathBuf;
+use crate::i18n::Language;
+
+const CACHE_DIR: &str = ".retirement-simulator-cache";
+
+// 64 bit FNV-1a hash. Unlike the standard library's hasher this is
+// guaranteed to be the same between builds, so saved results can be found.
+fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    let mut hash = hash;
+    for byte in bytes.iter() {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+// identifies a run from everything that affects its results: the program
+// version, the normalized configuration, the historical returns, the
+// random seed, today's date (the simulation starts today), and the output
+// format and language.
+pub fn get_run_hash(config_text: &str, seed: u64, is_json: bool, language: Language) -> String {
+    let today = chrono::Utc::now().naive_utc().date();
+    // a missing dataset is reported when the historical scan runs
+    let dataset = fs::read("returns.csv").unwrap_or_default();
+
+    let mut hash = 0xcbf29ce484222325;
+    hash = fnv1a(hash, env!("CARGO_PKG_VERSION").as_bytes());
+    hash = fnv1a(hash, config_text.as_bytes());
+    hash = fnv1a(hash, &dataset);
+    hash = fnv1a(hash, &seed.to_le_bytes());
+    hash = fnv1a(hash, today.to_string().as_bytes());
+    hash = fnv1a(hash, &[is_json as u8]);
+    hash = fnv1a(hash, language.code().as_bytes());
+    format!("{:016x}", hash)
+}
+
+fn get_report_path(hash: &str) -> PathBuf {
+    PathBuf::from(CACHE_DIR).join(format!("{}.txt", hash))
+}
+
+pub fn load_report(hash: &str) -> Option<String> {
+    fs::read_to_string(get_report_path(hash)).ok()
+}
+
+pub fn save_report(hash: &str, report: &str) -> Result<(), String> {
+    fs::create_dir_all(CACHE_DIR).map_err(|e| e.to_string())?;
+    fs::write(get_report_path(hash), report).map_err(|e| e.to_string())
+}
+
+// number of reports saved by earlier runs
+#[cfg(feature = "tools")]
+pub fn count_saved_reports() -> usize {
+    fs::read_dir(CACHE_DIR).map(|v| v.count()).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fnv1a() {
+        assert_eq!(fnv1a(0xcbf29ce484222325, b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a(0xcbf29ce484222325, b"a"), 0xaf63dc4c8601ec8c);
+    }
+}
@@ -0,0 +1,281 @@
+/**************************************************************************
+* doctor.rs
+*
+* Checks the environment the simulator runs in: the historical returns
+* dataset, the configuration, and the saved results, and prints the
+* settings that will be used with suggested fixes for any problems.
+**************************************************************************/
+
+use std::env;
+use std::path::Path;
+use chrono::Datelike;
+use crate::historical_scan::HistoricalScan;
+use crate::{cache, portfolio, Input, MonteCarloMethod, AllocationCheck, Rebalancing};
+
+// the dataset is probably out of date when its last year is older than this
+const STALE_YEARS: u32 = 3;
+
+struct Checkup {
+    problems: u32,
+    warnings: u32,
+}
+
+impl Checkup {
+    fn ok(&self, out: &mut String, message: String) {
+        outln!(out, "[ok]      {}", message);
+    }
+
+    fn warning(&mut self, out: &mut String, message: String, fix: &str) {
+        self.warnings += 1;
+        outln!(out, "[warning] {}", message);
+        outln!(out, "          Fix: {}", fix);
+    }
+
+    fn problem(&mut self, out: &mut String, message: String, fix: &str) {
+        self.problems += 1;
+        outln!(out, "[problem] {}", message);
+        outln!(out, "          Fix: {}", fix);
+    }
+}
+
+fn check_returns(out: &mut String, checkup: &mut Checkup) {
+    if !Path::new("returns.csv").exists() {
+        checkup.problem(out, "returns.csv not found in the working directory".to_string(),
+                        "run from the directory that contains returns.csv, it comes with the source in the project's root directory");
+        return;
+    }
+    let scan = match HistoricalScan::new() {
+        Ok(v) => v,
+        Err(err) => {
+            checkup.problem(out, format!("returns.csv can't be parsed: {}", err),
+                            "restore returns.csv from the project, or fix the line in the error");
+            return;
+        },
+    };
+
+    let years = scan.get_years();
+    let (Some(first), Some(last)) = (years.first(), years.last()) else {
+        checkup.problem(out, "returns.csv has no years of returns".to_string(),
+                        "restore returns.csv from the project");
+        return;
+    };
+    checkup.ok(out, format!("returns.csv has {} years of returns from {} to {}", years.len(), first.0, last.0));
+
+    let gaps: Vec<String> = years.windows(2)
+        .filter(|v| v[1].0 != v[0].0 + 1)
+        .map(|v| format!("{} to {}", v[0].0, v[1].0))
+        .collect();
+    if !gaps.is_empty() {
+        checkup.warning(out, format!("returns.csv has gaps between years {}", gaps.join(", ")),
+                        "the historical simulation treats the years on either side of a gap as consecutive, add the missing years");
+    }
+
+    match years.iter().find(|v| v.1) {
+        Some(international) => {
+            checkup.ok(out, format!("International returns from {}, US returns are used before then", international.0));
+            if years.iter().any(|v| v.0 > international.0 && !v.1) {
+                checkup.warning(out, "Some years after international returns start are missing them".to_string(),
+                                "fill in the International column, US returns are used for those years");
+            }
+        },
+        None => checkup.warning(out, "returns.csv has no international returns".to_string(),
+                                "US returns are used for international stocks, restore returns.csv from the project"),
+    }
+
+    let this_year = chrono::Utc::now().naive_utc().date().year() as u32;
+    if last.0 + STALE_YEARS < this_year {
+        checkup.warning(out, format!("returns.csv ends in {}, the dataset may be out of date", last.0),
+                        "update returns.csv from a newer copy of the project");
+    }
+}
+
+fn check_config(out: &mut String, checkup: &mut Checkup, input_file: &str) -> Option<Input> {
+    if !Path::new(input_file).exists() {
+        checkup.problem(out, format!("Configuration {} not found", input_file),
+                        "give the path to the configuration, input.yaml in the project is a template to copy");
+        return None;
+    }
+    let mut input = match crate::parse_input_file(input_file) {
+        Ok(v) => v,
+        Err(err) => {
+            checkup.problem(out, format!("Configuration {} can't be used: {}", input_file, err),
+                            "compare the value in the error with the README and the input.yaml template");
+            return None;
+        },
+    };
+    match crate::validate_input(&mut input) {
+        Ok(warnings) => {
+            checkup.ok(out, format!("Configuration {} is valid", input_file));
+            for warning in warnings {
+                checkup.warning(out, warning.message, &format!("check {} in the configuration", warning.field));
+            }
+        },
+        Err(err) => {
+            checkup.problem(out, format!("Configuration {} has an invalid value: {}", input_file, err),
+                            "compare the value in the error with the README and the input.yaml template");
+            return None;
+        },
+    }
+    Some(input)
+}
+
+fn print_settings(out: &mut String, input: &Input) {
+    outln!(out);
+    outln!(out, "Effective settings:");
+    outln!(out, "    Time step: {}", if input.simulation.months_per_step == 12 {"annual"} else {"monthly"});
+    outln!(out, "    Monte Carlo: {}", match input.simulation.monte_carlo {
+        MonteCarloMethod::Parametric => "parametric",
+        MonteCarloMethod::Bootstrap => "bootstrap",
+    });
+    outln!(out, "    Allocation check: {}", match input.simulation.allocation_check {
+        AllocationCheck::Normalize => "normalize",
+        AllocationCheck::Strict => "strict",
+    });
+    outln!(out, "    Expected inflation: {:.1}%{}", input.portfolio.expected_inflation,
+           if input.portfolio.inflation_standard_deviation > 0.0 {", random in Monte Carlo"} else {""});
+    outln!(out, "    Expected returns: real{}", if input.portfolio.nominal_returns {
+        let mut returns: Vec<String> = (0..input.portfolio.num_asset_classes)
+            .map(|i| format!("{:.1}% {}", input.portfolio.asset_classes[i].expected_returns, input.get_asset_class_description(i)))
+            .collect();
+        let last = returns.pop().unwrap();
+        format!(", converted from nominal to {} and {}", returns.join(", "), last)
+    } else {
+        "".to_string()
+    });
+    outln!(out, "    Monthly expenses: ${:.0}{}", input.expenses.monthly,
+           if input.expenses.replacement_percent.is_some() {", from replacement_percent"} else {""});
+    let tables = &input.tax_rates.tables;
+    if tables.federal.is_empty() && tables.state.is_empty() {
+        outln!(out, "    Tax brackets: from the configuration");
+    }
+    else {
+        outln!(out, "    Tax brackets: {} federal and {} state tables from the brackets file",
+               tables.federal.len(), tables.state.len());
+    }
+
+    // the optional parts of the configuration that are in use
+    let mut features = Vec::new();
+    for retiree in input.retirees.iter() {
+        let uses = [
+            ("bonus", retiree.bonus.is_some()),
+            ("compensation", !retiree.compensation.is_empty()),
+            ("employer match", retiree.employer_match.is_some()),
+            ("annuity", retiree.annuity.is_some()),
+            ("part-time work", !retiree.part_time.is_empty()),
+            ("pension lump sum", retiree.pension_take_lump_sum),
+            ("NUA", retiree.employer_stock.as_ref().is_some_and(|v| v.nua)),
+            ("backdoor Roth", retiree.backdoor_roth_annual > 0.0 || retiree.mega_backdoor_roth_annual > 0.0),
+        ];
+        for (name, _) in uses.iter().filter(|v| v.1) {
+            features.push(format!("{} ({})", name, retiree.name));
+        }
+    }
+    if !input.expenses.annual.is_empty() {
+        features.push("annual expenses".to_string());
+    }
+    if input.education.balance_529 > 0.0 || !input.education.expenses.is_empty() {
+        features.push("education".to_string());
+    }
+    if input.relocation.is_some() {
+        features.push("relocation".to_string());
+    }
+    if let Some(home) = input.home.as_ref() {
+        features.push(match (&home.downsize, &home.reverse_mortgage) {
+            (Some(_), _) => "home with downsizing",
+            (None, Some(_)) => "home with a reverse mortgage",
+            (None, None) => "home",
+        }.to_string());
+    }
+    if !input.rental_properties.is_empty() {
+        features.push("rental properties".to_string());
+    }
+    if !input.windfalls.is_empty() {
+        features.push("windfalls".to_string());
+    }
+    if !input.one_time_expenses.is_empty() {
+        features.push("one-time expenses".to_string());
+    }
+    if let Some(bridge) = input.expenses.bridge_healthcare.as_ref() {
+        features.push(match bridge.aca_subsidy {
+            true => "healthcare before Medicare with the ACA subsidy",
+            false => "healthcare before Medicare",
+        }.to_string());
+    }
+    if let Some(medicare) = input.expenses.medicare.as_ref() {
+        features.push(format!("Medicare premiums of ${:.0} a month", medicare.get_monthly_premiums()));
+    }
+    if let Some(tracking) = input.tracking.as_ref() {
+        features.push(match (&tracking.actuals_file, tracking.success_floor) {
+            (Some(file), Some(floor)) => format!("plan tracking with the balance from {} and a {:.0}% success floor", file, floor),
+            (Some(file), None) => format!("plan tracking with the balance from {}", file),
+            (None, Some(floor)) => format!("plan tracking with a {:.0}% success floor", floor),
+            (None, None) => "plan tracking".to_string(),
+        });
+    }
+    if input.portfolio.rebalancing == Rebalancing::CashFlow {
+        features.push(format!("cash-flow rebalancing with a {:.0} point band", input.portfolio.rebalance_band));
+    }
+    if !input.accounts.is_empty() {
+        features.push(format!("{} accounts", input.accounts.len()));
+    }
+    if input.portfolio.num_glide_points > 0 {
+        features.push(format!("a glide path of {} points", input.portfolio.num_glide_points));
+    }
+    if input.portfolio.cash_bucket_years > 0.0 {
+        features.push(format!("a cash bucket of {:.1} years of withdrawals", input.portfolio.cash_bucket_years));
+    }
+    if input.portfolio.num_asset_classes > portfolio::NUM_BUILT_IN_ASSET_CLASSES {
+        features.push(format!("asset classes {}", input.asset_class_names[portfolio::NUM_BUILT_IN_ASSET_CLASSES..].join(", ")));
+    }
+    if input.portfolio.tax_loss_harvest_percent > 0.0 {
+        features.push("tax-loss harvesting".to_string());
+    }
+    if input.analysis.asset_location {
+        features.push("asset location".to_string());
+    }
+    if !input.analysis.longevity_scenarios.is_empty() {
+        features.push("longevity scenarios".to_string());
+    }
+    match input.analysis.stress_tests.len() {
+        0 => {},
+        1 => features.push("1 stress test".to_string()),
+        v => features.push(format!("{} stress tests", v)),
+    }
+    outln!(out, "    Features: {}", if features.is_empty() {"none".to_string()} else {features.join(", ")});
+}
+
+// prints the checks and returns true if nothing is wrong enough to stop a
+// run
+pub fn run_doctor(out: &mut String, input_file: &str) -> bool {
+    let mut checkup = Checkup {problems: 0, warnings: 0};
+
+    outln!(out, "-= Doctor =-");
+    outln!(out);
+    outln!(out, "Version {}", env!("CARGO_PKG_VERSION"));
+    match env::current_dir() {
+        Ok(dir) => outln!(out, "Working directory: {}", dir.display()),
+        Err(err) => outln!(out, "Working directory: unknown ({})", err),
+    }
+    outln!(out);
+
+    check_returns(out, &mut checkup);
+    let input = check_config(out, &mut checkup, input_file);
+
+    let saved = cache::count_saved_reports();
+    if saved > 0 {
+        checkup.ok(out, format!("{} saved reports, identical seeded runs print them unless --force is given", saved));
+    }
+
+    if let Some(input) = input.as_ref() {
+        print_settings(out, input);
+    }
+
+    outln!(out);
+    match (checkup.problems, checkup.warnings) {
+        (0, 0) => outln!(out, "No problems found"),
+        (0, warnings) => outln!(out, "{} warnings, the simulations can run", warnings),
+        (problems, warnings) => outln!(out, "{} problems and {} warnings, fix the problems before running the simulations",
+                                       problems, warnings),
+    }
+    checkup.problems == 0
+}
@@ -0,0 +1,152 @@
+/**************************************************************************
+* fixture.rs
+*
+* A small plan for the tests, simulated from a fixed date so the results
+* don't depend on the day the tests run. Each test changes what it needs
+* in its own copy of the plan.
+**************************************************************************/
+
+use chrono::NaiveDate;
+use crate::Input;
+use crate::portfolio::{Portfolio, MAX_ASSET_CLASSES};
+use crate::simulate::{self, PlanContext, SimulationResults};
+
+// a couple of 65 and 61 at the start. The first retires in September 2027
+// with a pension, the second keeps working until 2031.
+const PLAN: &str = "
+retirees:
+    - name: John Doe
+      date_of_birth: 9/30/1960
+      retirement_age: 67
+      life_expectency: 90
+      wage_annual_salary: 75000.0
+      retirement_contribution_percent: 12.0
+      hsa_contribution_annual: 8300.0
+      social_security_age: 67
+      social_security_months: 0
+      pension_age: 62
+      pension_monthly_income: 1000.0
+      pension_cola: false
+      other_monthly_retirement_income: 500.0
+      social_security_amount_full: 3870.0
+    - name: Jane Doe
+      date_of_birth: 4/16/1964
+      retirement_age: 67
+      life_expectency: 90
+      wage_annual_salary: 87000.0
+      retirement_contribution_percent: 12.0
+      hsa_contribution_annual: 0.0
+      social_security_age: 67
+      social_security_months: 0
+      pension_age: 0
+      pension_monthly_income: 0.0
+      other_monthly_retirement_income: 0.0
+      social_security_amount_full: 3870.0
+expenses:
+    monthly: 9000.0
+    healthcare_monthly: 0.0
+tax_rates:
+    standard_deduction: 30000.0
+    levels:
+        - income: 23850.0
+          rate: 12.0
+        - income: 96950.0
+          rate: 22.0
+        - income: 206700.0
+          rate: 24.0
+        - income: 394600.0
+          rate: 32.0
+        - income: 501050.0
+          rate: 35.0
+    payroll:
+        social_security_rate: 6.2
+        social_security_wage_base: 176100.0
+        medicare_rate: 1.45
+        additional_medicare_rate: 0.9
+        additional_medicare_threshold: 250000.0
+    capital_gains:
+        levels:
+            - income: 96700.0
+              rate: 15.0
+            - income: 600050.0
+              rate: 20.0
+    state_rate: 0.0
+    irmaa:
+        levels:
+            - income: 212000.0
+              monthly_surcharge: 87.70
+            - income: 266000.0
+              monthly_surcharge: 220.30
+";
+
+// the plan's balances
+const PORTFOLIO: &str = "
+portfolio:
+    balance: 1200000.0
+    taxable_percent: 0.0
+    hsa_balance: 0.0
+";
+
+// the rest of the portfolio block without the allocations, what's expected
+// of the asset classes
+const RETURNS: &str = "
+    us_equity_expected_returns: 3.1
+    us_equity_standard_deviation: 15.0
+    international_equity_expected_returns: 4.2
+    international_equity_standard_deviation: 16.0
+    bonds_expected_returns: 2.6
+    bonds_standard_deviation: 6.0
+    expected_inflation: 2.6
+    us_equity_dividend_yield: 1.3
+    international_equity_dividend_yield: 3.0
+    bonds_interest_yield: 4.0
+";
+
+pub const ALLOCATIONS: &str = "
+    pre-retirement_allocation:
+        us_equities: 56.0
+        bonds: 20.0
+        international: 24.0
+    post-retirement_allocation:
+        us_equities: 42.0
+        bonds: 40.0
+        international: 18.0
+";
+
+pub fn get_input() -> Input {
+    crate::parse_input(&format!("{}{}{}{}", PLAN, PORTFOLIO, RETURNS, ALLOCATIONS), "fixture.yaml").unwrap()
+}
+
+// the plan's portfolio with other lines in its block, such as the
+// allocations
+pub fn parse_portfolio(lines: &str) -> Result<(Portfolio, Vec<String>), String> {
+    let text = format!("{}{}{}", PORTFOLIO, RETURNS, lines);
+    crate::parse_portfolio(&yaml_rust::YamlLoader::load_from_str(&text).unwrap()[0])
+}
+
+// the same without the balances, for lines that replace them such as the
+// accounts
+pub fn parse_portfolio_without_balances(lines: &str) -> Result<(Portfolio, Vec<String>), String> {
+    let text = format!("portfolio:{}{}", RETURNS, lines);
+    crate::parse_portfolio(&yaml_rust::YamlLoader::load_from_str(&text).unwrap()[0])
+}
+
+pub fn get_start_date() -> NaiveDate {
+    NaiveDate::from_ymd_opt(2026, 1, 1).unwrap()
+}
+
+pub fn get_context(input: &Input) -> PlanContext<'_> {
+    PlanContext::with_start_date(input, get_start_date())
+}
+
+// the uniform simulation of the plan from the start date
+pub fn run_simulation(input: &Input) -> Result<SimulationResults, String> {
+    run_simulation_with_returns(input, &input.portfolio.get_expected_returns(), input.portfolio.expected_inflation)
+}
+
+// the same with other constant real returns and inflation
+pub fn run_simulation_with_returns(input: &Input,
+                                   returns: &[f32; MAX_ASSET_CLASSES],
+                                   inflation: f32) -> Result<SimulationResults, String> {
+    simulate::run_simulation_with_context(&get_context(input), returns, inflation)
+}
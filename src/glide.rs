@@ -0,0 +1,170 @@
+/**************************************************************************
+* glide.rs
+*
+* Compares the projected balance in each year before retirement against
+* the balance needed to stay on track for the target success rate.
+**************************************************************************/
+
+use chrono::NaiveDate;
+use crate::{Input, simulate};
+use crate::historical_scan::HistoricalScan;
+use crate::scan::Scannable;
+
+// number of bisection steps when searching for the required balance
+const SEARCH_ITERATIONS: u32 = 20;
+
+// times the balance is doubled looking for one that reaches the target
+// before giving up
+const MAX_DOUBLINGS: u32 = 10;
+
+// projected balances are considered close enough to be watched, rather than
+// behind, when they are within this percentage of the required balance
+const WARNING_BAND_PERCENT: f32 = 10.0;
+
+#[derive(Debug, PartialEq)]
+pub enum GlideStatus {
+    OnTrack,
+    Watch,
+    Behind,
+}
+
+#[derive(Debug)]
+pub struct GlideYear {
+    pub date: NaiveDate,
+    pub projected_balance: f32,
+    pub required_balance: f32,
+    pub status: GlideStatus,
+}
+
+#[derive(Debug)]
+pub struct GlideReport {
+    pub target_success_rate: f32,
+    // None if no balance reaches the target success rate, with no years
+    pub required_starting_balance: Option<f32>,
+    pub years: Vec<GlideYear>,
+}
+
+fn get_status(projected_balance: f32, required_balance: f32) -> GlideStatus {
+    if projected_balance >= required_balance {
+        GlideStatus::OnTrack
+    }
+    else if projected_balance >= required_balance * (1.0 - WARNING_BAND_PERCENT / 100.0) {
+        GlideStatus::Watch
+    }
+    else {
+        GlideStatus::Behind
+    }
+}
+
+fn get_success_rate(input: &Input, balance: f32, historical_scan: &mut HistoricalScan) -> Result<f32, String> {
+    let mut trial = input.clone();
+    trial.portfolio.balance = balance;
+    let results = historical_scan.run_scan(&trial)?;
+    Ok(results.success_rate())
+}
+
+// finds the smallest balance that reaches the target success rate, doubling
+// the starting guess until one does and then bisecting. None if doubling
+// never reaches it.
+fn find_required_balance(target: f32,
+                         start: f32,
+                         mut get_success_rate: impl FnMut(f32) -> Result<f32, String>) -> Result<Option<f32>, String> {
+    let mut low = 0.0;
+    let mut high = start;
+    let mut doublings = 0;
+    while get_success_rate(high)? < target {
+        low = high;
+        high *= 2.0;
+        doublings += 1;
+        if doublings > MAX_DOUBLINGS {
+            return Ok(None);
+        }
+    }
+
+    for _ in 0..SEARCH_ITERATIONS {
+        let mid = (low + high) / 2.0;
+        if get_success_rate(mid)? >= target {
+            high = mid;
+        }
+        else {
+            low = mid;
+        }
+    }
+
+    Ok(Some(high))
+}
+
+// returns None if retirement has already started, since there is nothing
+// left to accumulate
+pub fn run_glide_report(input: &Input, historical_scan: &mut HistoricalScan) -> Result<Option<GlideReport>, String> {
+    let projected = simulate::run_simulation(input)?;
+    if projected.monthly_snapshot.is_empty() ||
+        projected.monthly_snapshot[0].date >= projected.retirement_date {
+        return Ok(None);
+    }
+
+    // the historical scan is deterministic, which keeps the search stable
+    // between runs
+    let target_success_rate = input.analysis.target_success_rate;
+    let start = f32::max(input.portfolio.balance, input.expenses.monthly * 12.0 * 25.0);
+    let required_starting_balance = find_required_balance(target_success_rate, start,
+                                                          |balance| get_success_rate(input, balance, historical_scan))?;
+    let Some(required_starting_balance) = required_starting_balance else {
+        return Ok(Some(GlideReport {
+            target_success_rate,
+            required_starting_balance: None,
+            years: Vec::new(),
+        }));
+    };
+    let mut required_input = input.clone();
+    required_input.portfolio.balance = required_starting_balance;
+    let required = simulate::run_simulation(&required_input)?;
+
+    let mut years = Vec::new();
+    for (i, snapshot) in projected.monthly_snapshot.iter().enumerate().step_by(projected.snapshots_per_year()) {
+        if snapshot.date > projected.retirement_date || i >= required.monthly_snapshot.len() {
+            break;
+        }
+        let required_balance = required.monthly_snapshot[i].balance;
+        years.push(GlideYear {
+            date: snapshot.date,
+            projected_balance: snapshot.balance,
+            required_balance,
+            status: get_status(snapshot.balance, required_balance),
+        });
+    }
+
+    Ok(Some(GlideReport {
+        target_success_rate,
+        required_starting_balance: Some(required_starting_balance),
+        years,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status() {
+        assert_eq!(get_status(100.0, 100.0), GlideStatus::OnTrack);
+        assert_eq!(get_status(95.0, 100.0), GlideStatus::Watch);
+        assert_eq!(get_status(80.0, 100.0), GlideStatus::Behind);
+    }
+
+    #[test]
+    fn test_find_required_balance() {
+        // the success rate rises a point for every $10,000
+        let success_rate = |balance: f32| Ok(f32::min(balance / 10000.0, 100.0));
+        let required = find_required_balance(95.0, 100000.0, success_rate).unwrap().unwrap();
+        assert!((required - 950000.0).abs() < 1.0);
+
+        // already reached at the first guess
+        let required = find_required_balance(5.0, 100000.0, success_rate).unwrap().unwrap();
+        assert!((required - 50000.0).abs() < 1.0);
+
+        // a plan that fails whatever the balance
+        assert_eq!(find_required_balance(95.0, 100000.0, |_| Ok(50.0)).unwrap(), None);
+        assert!(find_required_balance(95.0, 100000.0, |_| Err("bad scan".to_string())).is_err());
+    }
+}
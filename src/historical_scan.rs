@@ -4,7 +4,8 @@
 * Simulate starting a retirement at every year since 1928.
 **************************************************************************/
 
-use crate::{Input, scan, simulate};
+use crate::{scan, simulate};
+use crate::portfolio::{Portfolio, MAX_ASSET_CLASSES};
 use std::fs::File;
 use std::io::{self, BufRead};
 use std::path::Path;
@@ -34,23 +35,22 @@ pub struct HistoricalReturns {
 }
 
 fn str_to_u32(s: &str) -> Result<u32, String> {
-    s.trim().parse::<u32>().map_err(|v| (format!("Invalid integer: {}", v)))
+    s.trim().parse::<u32>().map_err(|v| format!("Invalid integer: {}", v))
 }
 
 fn str_to_f32(s: &str) -> Result<f32, String> {
-    s.trim().parse::<f32>().map_err(|v| (format!("Invalid floating point: {}", v)))
+    s.trim().parse::<f32>().map_err(|v| format!("Invalid floating point: {}", v))
 }
 
 fn str_to_f32_optional(s: &str) -> Option<f32> {
-    match s.trim().parse::<f32>() {
-        Ok(v) => Some(v),
-        Err(_) => None,
-    }
+    s.trim().parse::<f32>().ok()
 }
 
 fn calculate_averages(returns: &[HistoricalReturnsOneYear]) -> HistoricalReturnsOneYear {
-    let mut totals = HistoricalReturnsOneYear::default();
-    totals.international = Some(0.0);
+    let mut totals = HistoricalReturnsOneYear {
+        international: Some(0.0),
+        ..Default::default()
+    };
     let mut international_count = 0.0;
     
     for ret in returns.iter() {
@@ -60,9 +60,8 @@ fn calculate_averages(returns: &[HistoricalReturnsOneYear]) -> HistoricalReturns
         totals.tbill10year += ret.tbill10year;
         totals.corp_bonds += ret.corp_bonds;
         totals.real_estate += ret.real_estate;
-        if ret.international.is_some() {
-            totals.international = Some(totals.international.unwrap() +
-                ret.international.unwrap());
+        if let Some(international) = ret.international {
+            totals.international = Some(totals.international.unwrap() + international);
             international_count += 1.0;
         }
     };
@@ -95,7 +94,7 @@ fn parse_returns() -> Result<HistoricalReturns, String> {
         if i < 2 {
             continue;
         }
-        let line = line.map_err(|v| format!("Can't read line from returns.csv: {}", v.to_string()))?;
+        let line = line.map_err(|v| format!("Can't read line from returns.csv: {}", v))?;
         let toks: Vec<&str> = line.split(',').collect();
         if toks.len() < 14 {
             return Err(format!("Can't parse line [{}]", line));
@@ -139,58 +138,212 @@ fn parse_returns() -> Result<HistoricalReturns, String> {
 // Run scan
 ///////////////////////////////////////////////////////////////////////////
 
+// a column of the historical returns an asset class can follow
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HistoricalColumn {
+    // the S&P 500
+    UsEquity,
+    InternationalEquity,
+    // 10-year treasuries
+    Bonds,
+    // 3-month T-bills
+    Cash,
+    CorporateBonds,
+    RealEstate,
+}
+
+impl HistoricalColumn {
+    // the names used in the configuration
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "sp500" => Some(HistoricalColumn::UsEquity),
+            "international" => Some(HistoricalColumn::InternationalEquity),
+            "tbill10year" => Some(HistoricalColumn::Bonds),
+            "tbill3month" => Some(HistoricalColumn::Cash),
+            "corp_bonds" => Some(HistoricalColumn::CorporateBonds),
+            "real_estate" => Some(HistoricalColumn::RealEstate),
+            _ => None,
+        }
+    }
+}
+
+// the returns of every column for one historical year
+#[derive(Debug, Clone, Copy)]
+pub struct ReturnPath {
+    pub us_equity: f32,
+    pub international_equity: f32,
+    pub bonds: f32,
+    pub cash: f32,
+    pub corporate_bonds: f32,
+    pub real_estate: f32,
+    pub inflation: f32,
+}
+
+impl ReturnPath {
+    pub fn get(&self, column: HistoricalColumn) -> f32 {
+        match column {
+            HistoricalColumn::UsEquity => self.us_equity,
+            HistoricalColumn::InternationalEquity => self.international_equity,
+            HistoricalColumn::Bonds => self.bonds,
+            HistoricalColumn::Cash => self.cash,
+            HistoricalColumn::CorporateBonds => self.corporate_bonds,
+            HistoricalColumn::RealEstate => self.real_estate,
+        }
+    }
+
+    // the return of each of the portfolio's asset classes in this year, the
+    // expected returns for the ones without a historical column
+    pub fn get_returns(&self, portfolio: &Portfolio) -> [f32; MAX_ASSET_CLASSES] {
+        portfolio.asset_classes.map(|v| v.historical.map_or(v.expected_returns, |column| self.get(column)))
+    }
+}
+
+// the average returns of the years of a decade in the dataset
+#[derive(Debug, Clone, Copy)]
+pub struct DecadeAverages {
+    pub first_year: u32,
+    pub last_year: u32,
+    // annualized, so the same returns every year compound to the same
+    // growth as the decade
+    pub returns: ReturnPath,
+}
+
 pub struct HistoricalScan {
     pub historical_returns: HistoricalReturns,
+
+    // derived once from historical_returns so repeated scans, such as
+    // sweeps, don't have to
+    return_paths: Vec<ReturnPath>,
+
+    // where each scenario is written as it finishes, if anywhere
+    stream: Option<scan::ScenarioStream>,
 }
 
 impl HistoricalScan {
     pub fn new() -> Result<Self, String> {
         let historical_returns = parse_returns()?;
         // println!("Averages: {:?}", historical_returns.averages);
-        Ok(HistoricalScan {historical_returns})
+        let return_paths = historical_returns.annual_returns.iter().map(|v| ReturnPath {
+            us_equity: v.sp500return,
+            // international data starts later, use US returns before then
+            international_equity: v.international.unwrap_or(v.sp500return),
+            bonds: v.tbill10year,
+            cash: v.tbill3month,
+            corporate_bonds: v.corp_bonds,
+            real_estate: v.real_estate,
+            inflation: v.inflation,
+        }).collect();
+        Ok(HistoricalScan {historical_returns, return_paths, stream: None})
+    }
+
+    // the years in the dataset, in order, and whether each has international
+    // returns
+    #[cfg(feature = "tools")]
+    pub fn get_years(&self) -> Vec<(u32, bool)> {
+        self.historical_returns.annual_returns.iter()
+            .map(|v| (v.year, v.international.is_some()))
+            .collect()
+    }
+
+    // the scenarios of the following scans are also written to the
+    // stream, until it's set back to None
+    pub fn set_stream(&mut self, stream: Option<scan::ScenarioStream>) {
+        self.stream = stream;
+    }
+
+    // the returns of every historical year, in order
+    pub fn get_return_paths(&self) -> &[ReturnPath] {
+        &self.return_paths
+    }
+
+    // the annualized returns and inflation of each decade with at least
+    // min_years years in the dataset, in order
+    pub fn get_decade_averages(&self, min_years: usize) -> Vec<DecadeAverages> {
+        let years = &self.historical_returns.annual_returns;
+        let mut averages = Vec::new();
+        let mut start = 0;
+        while start < years.len() {
+            let decade = years[start].year / 10;
+            let end = start + years[start..].iter().take_while(|v| v.year / 10 == decade).count();
+            let paths = &self.return_paths[start..end];
+            let annualize = |get: fn(&ReturnPath) -> f32| {
+                (paths.iter().map(|v| 1.0 + get(v) / 100.0).product::<f32>().powf(1.0 / paths.len() as f32) - 1.0) * 100.0
+            };
+            if paths.len() >= min_years {
+                averages.push(DecadeAverages {
+                    first_year: years[start].year,
+                    last_year: years[end - 1].year,
+                    returns: ReturnPath {
+                        us_equity: annualize(|v| v.us_equity),
+                        international_equity: annualize(|v| v.international_equity),
+                        bonds: annualize(|v| v.bonds),
+                        cash: annualize(|v| v.cash),
+                        corporate_bonds: annualize(|v| v.corporate_bonds),
+                        real_estate: annualize(|v| v.real_estate),
+                        inflation: annualize(|v| v.inflation),
+                    },
+                });
+            }
+            start = end;
+        }
+        averages
     }
     
     fn run_scenario(&mut self,
                     starting_index: usize, 
-                    input: &Input) -> Result<scan::Scenario, String> {
-        let mut simulation = simulate::Simulation::new(input);
+                    context: &simulate::PlanContext) -> Result<scan::Scenario, String> {
+        let mut simulation = simulate::Simulation::new(context);
         let mut index = starting_index;
+        let mut anomalies = Vec::new();
+        let mut year = 0;
 
         'outer: loop {
-            for _month in 0..12 {
-                let international = self.historical_returns.annual_returns[index].international.unwrap_or(
-                    self.historical_returns.annual_returns[index].sp500return);
-                let is_finished = simulation.run_simulation_one_month(
-                    self.historical_returns.annual_returns[index].sp500return,
-                    international,
-                    self.historical_returns.annual_returns[index].tbill10year)?;
+            let path = self.return_paths[index];
+            simulation.set_inflation(path.inflation);
+            let returns = path.get_returns(&context.input.portfolio);
+            for _step in 0..simulation.steps_per_year() {
+                let is_finished = simulation.run_simulation_one_step(&returns)?;
                 if is_finished {
                     break 'outer;
                 }
             }
             index += 1;
-            if index >= self.historical_returns.annual_returns.len() {
+            year += 1;
+            if index >= self.return_paths.len() {
                 index = 0;
+                anomalies.push(scan::Anomaly {
+                    scenario: starting_index,
+                    kind: scan::AnomalyKind::DataWrapAround,
+                    year,
+                    message: format!("Reached the end of the historical returns, continuing with {}",
+                                     self.historical_returns.annual_returns[0].year),
+                });
             }
         }
 
         Ok(scan::Scenario {
+            index: starting_index,
             simulation_results: simulation.simulation_results_,
             starting_year: self.historical_returns.annual_returns[starting_index].year,
             ending_year: self.historical_returns.annual_returns[index].year,
+            anomalies,
+            metadata: scan::ScenarioMetadata::new(scan::ReturnModel::Historical, None, context),
         })
     }
 }
 
 impl scan::Scannable for HistoricalScan {
-    fn run_scan(&mut self, input: &Input) -> Result<scan::ScanResults, String> {
+    fn run_scan_with_context(&mut self, context: &simulate::PlanContext) -> Result<scan::ScanResults, String> {
         let mut results = scan::ScanResults::new();
 
-        for index in 0..self.historical_returns.annual_returns.len() {
+        for index in 0..self.return_paths.len() {
             let historical_scenario = self.run_scenario(
                 index,
-                input)?;
-            scan::add_scenario_to_results(&mut results, historical_scenario, index);
+                context)?;
+            if let Some(stream) = self.stream.as_mut() {
+                stream.write_scenario(&historical_scenario)?;
+            }
+            scan::add_scenario_to_results(&mut results, historical_scenario);
         }
 
         results.sort_results();
@@ -200,3 +353,23 @@ impl scan::Scannable for HistoricalScan {
 }
 
     
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decade_averages() {
+        let scan = HistoricalScan::new().unwrap();
+        let decades = scan.get_decade_averages(5);
+        // 1928 and 1929 are too few years for the 1920s
+        let thirties = decades[0];
+        assert_eq!((thirties.first_year, thirties.last_year), (1930, 1939));
+        assert!(decades.windows(2).all(|v| v[1].first_year == v[0].first_year + 10));
+
+        // ten years of the average compound to the decade's growth
+        let paths = &scan.get_return_paths()[2..12];
+        let growth: f32 = paths.iter().map(|v| 1.0 + v.us_equity / 100.0).product();
+        assert!(((1.0 + thirties.returns.us_equity / 100.0).powi(10) - growth).abs() < 0.001);
+    }
+}
@@ -0,0 +1,347 @@
+/**************************************************************************
+* i18n.rs
+*
+* Translations of the text report. The English text is the key of each
+* translation, so anything without one is printed in English. Numbers,
+* dates and the JSON field names are the same in every language.
+**************************************************************************/
+
+use std::fmt;
+use std::sync::OnceLock;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Language {
+    English,
+    Spanish,
+}
+
+impl Language {
+    pub fn parse(code: &str) -> Result<Language, String> {
+        match code {
+            "en" => Ok(Language::English),
+            "es" => Ok(Language::Spanish),
+            _ => Err(format!("Unknown language: {}, must be en or es", code)),
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Language::English => "en",
+            Language::Spanish => "es",
+        }
+    }
+
+    fn catalog(&self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Language::English => &[],
+            Language::Spanish => SPANISH,
+        }
+    }
+}
+
+// set once from the command line before the report is written
+static LANGUAGE: OnceLock<Language> = OnceLock::new();
+
+pub fn set_language(language: Language) {
+    let _ = LANGUAGE.set(language);
+}
+
+pub fn get_language() -> Language {
+    LANGUAGE.get().copied().unwrap_or(Language::English)
+}
+
+pub fn translate_to(language: Language, text: &str) -> &str {
+    language.catalog().iter()
+        .find(|(english, _)| *english == text)
+        .map_or(text, |(_, translated)| translated)
+}
+
+// the text in the report's language
+pub fn translate(text: &str) -> &str {
+    translate_to(get_language(), text)
+}
+
+// fills the {} placeholders of a translated text in order. A placeholder can
+// have a sign and a precision, such as {:+.1}, which are applied to its
+// argument like format! does.
+pub fn fill(template: &str, args: &[&dyn fmt::Display]) -> String {
+    let mut result = String::new();
+    let mut args = args.iter();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}').map(|v| start + v) else {
+            break;
+        };
+        result.push_str(&rest[..start]);
+        let spec = rest[start + 1..end].trim_start_matches(':');
+        let arg = args.next().expect("missing argument for placeholder");
+        let plus = spec.starts_with('+');
+        match spec.trim_start_matches('+').strip_prefix('.').and_then(|v| v.parse::<usize>().ok()) {
+            Some(precision) if plus => result.push_str(&format!("{:+.*}", precision, arg)),
+            Some(precision) => result.push_str(&format!("{:.*}", precision, arg)),
+            None => result.push_str(&arg.to_string()),
+        }
+        rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+    result
+}
+
+const SPANISH: &[(&str, &str)] = &[
+    // section headings
+    ("-= Simulation using uniform returns =-", "-= Simulación con rendimientos uniformes =-"),
+    ("-= Historical simulation =-", "-= Simulación histórica =-"),
+    ("-= Savings glide report =-", "-= Trayectoria del ahorro =-"),
+    ("-= Monte Carlo Simulation =-", "-= Simulación de Monte Carlo =-"),
+    ("-= Summary =-", "-= Resumen =-"),
+    ("-= Plan tracking =-", "-= Seguimiento del plan =-"),
+    ("-= Accounts =-", "-= Cuentas =-"),
+    ("-= Asset location =-", "-= Ubicación de activos =-"),
+    ("-= Market crash now =-", "-= Caída del mercado ahora =-"),
+    ("-= Invariant check =-", "-= Verificación de invariantes =-"),
+
+    // uniform returns
+    ("Retired on {}, the simulation starts with withdrawals from the portfolio",
+     "Jubilación el {}, la simulación empieza con retiros de la cartera"),
+    ("Retirement failed", "La jubilación fracasó"),
+    ("The essential expenses were still funded by cutting the discretionary spending",
+     "Los gastos esenciales se cubrieron recortando los gastos discrecionales"),
+    ("Retirement succeeded!", "¡La jubilación tuvo éxito!"),
+    ("Retirement expenses: {:.0}% of the pay in the last year before retirement, after savings and payroll taxes, ${} per month",
+     "Gastos de jubilación: {:.0}% del sueldo del último año antes de jubilarse, después del ahorro y los impuestos sobre la nómina, ${} al mes"),
+    ("Average return: {:.2}%", "Rendimiento promedio: {:.2}%"),
+    ("Geometric mean return: {:.2}%, volatility {:.2}%, best year {:.2}% ({}), worst year {:.2}% ({})",
+     "Rendimiento medio geométrico: {:.2}%, volatilidad {:.2}%, mejor año {:.2}% ({}), peor año {:.2}% ({})"),
+    ("Take-home pay while working: ${} per month (wages ${}, contributions ${}, payroll tax ${}, income tax ${})",
+     "Sueldo neto mientras trabaja: ${} al mes (salario ${}, aportaciones ${}, impuesto sobre la nómina ${}, impuesto sobre la renta ${})"),
+    ("Principal {}: ${} at retirement, ${} at the end", "Capital {}: ${} al jubilarse, ${} al final"),
+    ("kept", "conservado"),
+    ("spent", "gastado"),
+    ("Investment fees: ${} less at the end than without fees",
+     "Comisiones de inversión: ${} menos al final que sin comisiones"),
+    ("Reverse mortgage: ${} drawn from age {}, ${} owed and ${} of credit left at the end",
+     "Hipoteca inversa: ${} dispuestos desde los {} años, ${} adeudados y ${} de crédito disponible al final"),
+    ("Tax brackets (annual taxable income before the standard deduction):",
+     "Tramos de impuestos (ingreso gravable anual antes de la deducción estándar):"),
+    ("Monthly paycheck in retirement (income plus withdrawals less taxes, average of each year):",
+     "Ingreso mensual en la jubilación (ingresos más retiros menos impuestos, promedio de cada año):"),
+    ("Guaranteed income (social security, pensions and annuities) against {} (monthly, average of each year):",
+     "Ingreso garantizado (seguro social, pensiones y rentas vitalicias) frente a {} (mensual, promedio de cada año):"),
+    ("essential expenses", "los gastos esenciales"),
+    ("all expenses", "todos los gastos"),
+    ("Guaranteed income covers the expenses in every year of retirement",
+     "El ingreso garantizado cubre los gastos en todos los años de jubilación"),
+    ("Guaranteed income falls short in {} of {} years, ${} in today's dollars in total that depends on the portfolio",
+     "El ingreso garantizado no alcanza en {} de {} años, ${} en dólares de hoy en total que dependen de la cartera"),
+    ("Income in today's dollars and nominal at {:.1}% inflation, and fixed pensions and annuities that lose value to inflation (monthly, average of each year):",
+     "Ingresos en dólares de hoy y nominales con {:.1}% de inflación, y pensiones y rentas fijas que pierden valor con la inflación (mensual, promedio de cada año):"),
+    ("{} (age {})", "{} (edad {})"),
+    ("Warning: fixed income falls below {:.0}% of the expenses in {}",
+     "Advertencia: los ingresos fijos caen por debajo del {:.0}% de los gastos en {}"),
+    ("Healthcare costs (annual, today's dollars):", "Costos de salud (anuales, dólares de hoy):"),
+    ("  Insurance and out-of-pocket costs before Medicare grow {:.1}% a year",
+     "  El seguro y los gastos de bolsillo antes de Medicare crecen {:.1}% al año"),
+    ("  Part B, Part D and Medigap premiums from 65 grow {:.1}% a year, with IRMAA surcharges on top",
+     "  Las primas de la Parte B, la Parte D y Medigap desde los 65 crecen {:.1}% al año, más los recargos de IRMAA"),
+    ("Total ${}, ${} after the ACA premium tax credit", "Total ${}, ${} después del crédito fiscal de la ACA"),
+    ("Events:", "Eventos:"),
+    ("Pension for {} has {}. Value in today's dollars at {:.1}% inflation:",
+     "Pensión de {}, {}. Valor en dólares de hoy con {:.1}% de inflación:"),
+    ("a cost of living adjustment", "con ajuste por costo de vida"),
+    ("no cost of living adjustment", "sin ajuste por costo de vida"),
+    ("a fixed {:.1}% cost of living adjustment", "con un ajuste por costo de vida fijo del {:.1}%"),
+    ("a cost of living adjustment capped at {:.1}%", "con un ajuste por costo de vida limitado al {:.1}%"),
+
+    // scans
+    ("Successful runs: {} of {} ({:.1}%)", "Simulaciones exitosas: {} de {} ({:.1}%)"),
+    ("Floor funded: {} of {} ({:.1}%), {} runs only cut discretionary spending",
+     "Mínimo cubierto: {} de {} ({:.1}%), {} simulaciones solo recortaron los gastos discrecionales"),
+    ("Principal kept: {} of {} ({:.1}%)", "Capital conservado: {} de {} ({:.1}%)"),
+    ("Lowest ending balance: ${}", "Saldo final más bajo: ${}"),
+    ("Highest ending balance: ${}", "Saldo final más alto: ${}"),
+    ("Runs with errors (counted as failed): {}, first error: {}",
+     "Simulaciones con errores (contadas como fallidas): {}, primer error: {}"),
+    ("Note: {} runs reached the end of the historical returns and continued from the start",
+     "Nota: {} simulaciones llegaron al final de los rendimientos históricos y continuaron desde el principio"),
+    ("Note: {} runs sampled annual returns below -100%, these were limited to -100%",
+     "Nota: {} simulaciones obtuvieron rendimientos anuales inferiores a -100%, que se limitaron a -100%"),
+    ("Unfunded expenses in failed runs:", "Gastos sin cubrir en las simulaciones fallidas:"),
+    ("    median {}", "    mediana {}"),
+    ("    90th percentile {}", "    percentil 90 {}"),
+    ("    worst {}", "    peor {}"),
+    ("${} ({:.1} years of spending)", "${} ({:.1} años de gastos)"),
+    ("Scenarios (sorted by worst to best):", "Escenarios (ordenados de peor a mejor):"),
+    ("    years {} to {}, ending balance {} {}", "    años {} a {}, saldo final {} {}"),
+    ("Worst result was years {} to {}", "El peor resultado fue en los años {} a {}"),
+    ("Success rate by monthly expenses:", "Tasa de éxito según los gastos mensuales:"),
+    ("Success rate if forced to retire early, with social security {}% lower for each year:",
+     "Tasa de éxito con una jubilación anticipada forzosa, con el seguro social {}% más bajo por cada año:"),
+    ("(as planned)", "(según lo previsto)"),
+    ("(1 year early)", "(1 año antes)"),
+    ("({} years early)", "({} años antes)"),
+    ("Success rates with each stress test added to the plan on its own:",
+     "Tasas de éxito con cada prueba de estrés añadida al plan por separado:"),
+    ("${} expense in {}", "Gasto de ${} en {}"),
+    ("Crash now, equities {:.0}%/{:.0}%, bonds {:.0}%", "Caída ahora, acciones {:.0}%/{:.0}%, bonos {:.0}%"),
+    ("{:.1}% inflation for {} years", "{:.1}% de inflación durante {} años"),
+    ("Test {}", "Prueba {}"),
+    ("To {}", "Hasta los {}"),
+    ("Both to {}", "Ambos hasta los {}"),
+    ("All to {}", "Todos hasta los {}"),
+    ("{} to {}", "{} hasta los {}"),
+    ("Success rate if the retirees live to other ages:", "Tasa de éxito si los jubilados viven hasta otras edades:"),
+    ("Runs out at {}", "Se agota a los {}"),
+    ("Succeeds", "Tiene éxito"),
+    ("Uniform returns with the average real returns and inflation of each decade (annualized):",
+     "Rendimientos uniformes con los rendimientos reales promedio y la inflación de cada década (anualizados):"),
+    ("year", "año"),
+    ("years", "años"),
+    ("month", "mes"),
+    ("months", "meses"),
+    ("({} earlier)", "({} antes)"),
+    ("({} later)", "({} después)"),
+    ("Success rate by retirement date:", "Tasa de éxito según la fecha de jubilación:"),
+    ("Warning: no balance today reaches a {:.0}% historical success rate, the savings glide report is skipped",
+     "Advertencia: ningún saldo actual alcanza una tasa de éxito histórica del {:.0}%, se omite el informe de trayectoria del ahorro"),
+    ("Balance needed today for a {:.0}% historical success rate: ${}",
+     "Saldo necesario hoy para una tasa de éxito histórica del {:.0}%: ${}"),
+    ("Returns drawn from historical years with replacement", "Rendimientos tomados de años históricos con reemplazo"),
+    ("Lifetimes drawn from the life tables", "Duraciones de vida tomadas de las tablas de mortalidad"),
+    ("Worst year (scenario {} of seed {}):", "Peor año (escenario {} de la semilla {}):"),
+    ("Monte Carlo compared with historical ending balances:", "Saldos finales de Monte Carlo comparados con los históricos:"),
+    ("Outcomes:", "Resultados:"),
+    ("Started from the actual balance of ${} on {} in {}, {:+.1}% from the ${} in the configuration",
+     "Se partió del saldo real de ${} el {} en {}, {:+.1}% respecto a los ${} de la configuración"),
+    ("Alert: the historical success rate of {:.1}% is below the floor of {:.1}%, time to revisit the plan",
+     "Alerta: la tasa de éxito histórica de {:.1}% está por debajo del mínimo de {:.1}%, es hora de revisar el plan"),
+    ("The historical success rate of {:.1}% is at or above the floor of {:.1}%",
+     "La tasa de éxito histórica de {:.1}% está en o por encima del mínimo de {:.1}%"),
+    ("US equities {:.0}%, international equities {:.0}%, bonds {:.0}%",
+     "Acciones de EE. UU. {:.0}%, acciones internacionales {:.0}%, bonos {:.0}%"),
+    ("The balance falls from ${} to ${} ({:.1}%) before every simulation",
+     "El saldo cae de ${} a ${} ({:.1}%) antes de cada simulación"),
+    ("Checked {} simulations, no violations", "Se verificaron {} simulaciones, sin violaciones"),
+    ("Checked {} simulations, {} violations in {} of them:", "Se verificaron {} simulaciones, {} violaciones en {} de ellas:"),
+    ("    ... and {} more", "    ... y {} más"),
+    ("Least taxed asset classes in the taxable accounts, highest expected returns in the Roth accounts and HSAs:",
+     "Los activos con menos impuestos en las cuentas gravables, los de mayor rendimiento esperado en las cuentas Roth y HSA:"),
+    ("The suggested location changes the ending balance by {} and the lifetime taxes by {}",
+     "La ubicación sugerida cambia el saldo final en {} y los impuestos de toda la vida en {}"),
+    ("Nothing to move, the balance is all in one of taxable, tax-deferred and tax-free accounts",
+     "Nada que mover, todo el saldo está en cuentas gravables, con impuestos diferidos o libres de impuestos"),
+    ("Compared with simple strategies on the same inputs, with the historical median ending balance:",
+     "Comparado con estrategias simples con los mismos datos, con la mediana histórica del saldo final:"),
+
+    // table headings and labels
+    ("Year", "Año"),
+    ("Age", "Edad"),
+    ("Balance", "Saldo"),
+    ("Account", "Cuenta"),
+    ("Asset class", "Clase de activo"),
+    ("Taxable now", "Gravable ahora"),
+    ("Tax-deferred", "Diferido"),
+    ("Tax-free", "Libre de impuestos"),
+    ("Type", "Tipo"),
+    ("Total", "Total"),
+    ("taxable", "gravable"),
+    ("tax-deferred", "impuestos diferidos"),
+    ("Expenses", "Gastos"),
+    ("Income", "Ingresos"),
+    ("Tax", "Impuesto"),
+    ("State", "Estatal"),
+    ("Rate", "Tasa"),
+    ("Draw", "Retiro"),
+    ("Yield", "Rend."),
+    ("Retired!", "¡Jubilado!"),
+    ("Taxable", "Gravable"),
+    ("Bracket", "Tramo"),
+    ("Effective", "Efectiva"),
+    ("Headroom", "Margen"),
+    ("Withdrawals", "Retiros"),
+    ("Taxes", "Impuestos"),
+    ("Paycheck", "Neto"),
+    ("Guaranteed", "Garantizado"),
+    ("Essential", "Esencial"),
+    ("Shortfall", "Déficit"),
+    ("Fixed", "Fijo"),
+    ("Fixed share", "Parte fija"),
+    ("Below", "Por debajo"),
+    ("Taxable income", "Ingreso gravable"),
+    ("Before Medicare", "Antes de Medicare"),
+    ("ACA credit", "Crédito ACA"),
+    ("Net cost", "Costo neto"),
+    ("Monthly", "Mensual"),
+    ("Value", "Valor"),
+    ("Success", "Éxito"),
+    ("Retires", "Jubilación"),
+    ("Median ending", "Mediana final"),
+    ("Stress test", "Prueba de estrés"),
+    ("Event", "Evento"),
+    ("Historical", "Histórica"),
+    ("Change", "Cambio"),
+    ("Baseline", "Base"),
+    ("Lives to", "Vive hasta"),
+    ("Returns", "Rendimientos"),
+    ("Expected", "Esperados"),
+    ("US equity", "Acciones EE. UU."),
+    ("International", "Internacional"),
+    ("Bonds", "Bonos"),
+    ("Cash", "Efectivo"),
+    ("Inflation", "Inflación"),
+    ("Result", "Resultado"),
+    ("Ending balance", "Saldo final"),
+    ("Percentile", "Percentil"),
+    ("Years", "Años"),
+    ("Difference", "Diferencia"),
+    ("Simulation", "Simulación"),
+    ("Uniform", "Uniforme"),
+    ("Worst ending", "Peor final"),
+    ("Earliest depletion age", "Edad de agotamiento más temprana"),
+    ("Principal kept", "Capital conservado"),
+    ("Outcome", "Resultado"),
+    ("Ran out before 80", "Se agotó antes de los 80"),
+    ("Ran out at 80 to 89", "Se agotó entre los 80 y los 89"),
+    ("Ran out at 90 or later", "Se agotó a los 90 o después"),
+    ("Succeeded with under $250,000", "Éxito con menos de $250,000"),
+    ("Succeeded with $250,000 to $1,000,000", "Éxito con $250,000 a $1,000,000"),
+    ("Succeeded with over $1,000,000", "Éxito con más de $1,000,000"),
+    ("Stopped with an error", "Se detuvo con un error"),
+    ("Projected", "Proyectado"),
+    ("Required", "Requerido"),
+    ("Gap", "Diferencia"),
+    ("On track", "En camino"),
+    ("Watch", "Vigilar"),
+    ("Behind", "Atrasado"),
+    ("Strategy", "Estrategia"),
+    ("This plan", "Este plan"),
+    ("100% bonds", "100% bonos"),
+    ("60/40, 4% rule", "60/40, regla del 4%"),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_translate() {
+        assert_eq!(translate_to(Language::Spanish, "Retirement failed"), "La jubilación fracasó");
+        assert_eq!(translate_to(Language::English, "Retirement failed"), "Retirement failed");
+        // anything without a translation stays in English
+        assert_eq!(translate_to(Language::Spanish, "1,200,000"), "1,200,000");
+
+        assert_eq!(fill("{} of {} ({:.1}%)", &[&7, &10, &70.0f32]), "7 of 10 (70.0%)");
+        assert_eq!(fill("{:+.1}% from ${}", &[&-2.345f32, &"1,000"]), "-2.3% from $1,000");
+
+        // the placeholders of every translation match the English text
+        let placeholders = |text: &'static str| text.match_indices('{')
+            .map(|(i, _)| &text[i..=i + text[i..].find('}').unwrap()])
+            .collect::<Vec<_>>();
+        for (english, spanish) in SPANISH.iter() {
+            assert_eq!(placeholders(english), placeholders(spanish), "{}", english);
+        }
+        let mut keys: Vec<&str> = SPANISH.iter().map(|v| v.0).collect();
+        keys.sort();
+        keys.dedup();
+        assert_eq!(keys.len(), SPANISH.len());
+    }
+}
@@ -0,0 +1,138 @@
+/**************************************************************************
+* invariants.rs
+*
+* Checks that hold after every step of a simulation, run with --check: the
+* balances are never negative, the accounts add up to no more than the
+* balance, the balance only changes by the deposits, withdrawals and growth,
+* the taxes aren't negative and the allocations add up to 100%. A violation
+* is a bug in the simulation rather than in the plan.
+**************************************************************************/
+
+use chrono::NaiveDate;
+use crate::portfolio::Portfolio;
+use crate::report::Json;
+use crate::simulate::MonthlySnapshot;
+
+// amounts are f32, so differences of up to a dollar, or a few millionths
+// of a large amount, are rounding
+fn get_tolerance(amount: f32) -> f32 {
+    f32::max(1.0, amount.abs() * 0.00001)
+}
+
+#[derive(Debug, Clone)]
+pub struct Violation {
+    pub date: NaiveDate,
+    pub check: &'static str,
+    pub message: String,
+}
+
+impl Violation {
+    pub fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("date", Json::string(&self.date.format("%Y-%m-%d").to_string())),
+            ("check", Json::string(self.check)),
+            ("message", Json::string(&self.message)),
+        ])
+    }
+}
+
+// the checks after a step. The portfolio's flows were set to 0 at the start
+// of the step, when the balance was start_balance, and pre_growth_balance
+// is the balance after the step's deposits and withdrawals.
+pub fn check_step(portfolio: &Portfolio,
+                  start_balance: f32,
+                  pre_growth_balance: f32,
+                  snapshot: &MonthlySnapshot) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    let mut check = |is_ok: bool, check: &'static str, message: String| {
+        if !is_ok {
+            violations.push(Violation {date: snapshot.date, check, message});
+        }
+    };
+
+    let balances = [
+        ("balance", portfolio.balance),
+        ("taxable balance", portfolio.taxable_balance),
+        ("taxable basis", portfolio.taxable_basis),
+        ("HSA balance", portfolio.hsa_balance),
+        ("Roth balance", portfolio.roth_balance),
+        ("IRA balance", portfolio.ira_balance),
+        ("IRA basis", portfolio.ira_basis),
+        ("529 balance", portfolio.education_balance),
+    ];
+    for (name, value) in balances {
+        check(value >= -get_tolerance(0.0), "negative_balance", format!("The {} is {:.2}", name, value));
+    }
+
+    let accounts = portfolio.taxable_balance + portfolio.hsa_balance + portfolio.roth_balance + portfolio.ira_balance;
+    check(accounts <= portfolio.balance + get_tolerance(portfolio.balance), "accounts_exceed_balance",
+          format!("The taxable, HSA, Roth and IRA balances add up to {:.2}, more than the balance of {:.2}",
+                  accounts, portfolio.balance));
+    check(portfolio.ira_basis <= portfolio.ira_balance + get_tolerance(portfolio.ira_balance), "basis_exceeds_ira",
+          format!("The IRA basis of {:.2} is more than the IRA balance of {:.2}",
+                  portfolio.ira_basis, portfolio.ira_balance));
+
+    check(portfolio.taxable_basis <= portfolio.taxable_balance + get_tolerance(portfolio.taxable_balance), "basis_exceeds_taxable",
+          format!("The taxable basis of {:.2} is more than the taxable balance of {:.2}",
+                  portfolio.taxable_basis, portfolio.taxable_balance));
+
+    // the deposits and withdrawals are all the balance changed by before the
+    // growth
+    let expected = start_balance + portfolio.net_flows;
+    check((pre_growth_balance - expected).abs() <= get_tolerance(expected), "cash_flow",
+          format!("The balance went from {:.2} to {:.2} before growth, but the deposits less withdrawals were {:.2}",
+                  start_balance, pre_growth_balance, portfolio.net_flows));
+
+    let taxes = [
+        ("taxes", snapshot.taxes),
+        ("payroll taxes", snapshot.payroll_taxes),
+        ("federal taxes", snapshot.federal_taxes),
+        ("capital gains taxes", snapshot.capital_gains_taxes),
+        ("state taxes", snapshot.state_taxes),
+        ("IRMAA surcharge", snapshot.irmaa),
+    ];
+    for (name, value) in taxes {
+        check(value >= -get_tolerance(0.0), "negative_taxes", format!("The {} are {:.2}", name, value));
+    }
+    check(snapshot.withdrawals >= -get_tolerance(0.0), "negative_withdrawals",
+          format!("The withdrawals are {:.2}", snapshot.withdrawals));
+
+    let allocations = [
+        ("pre-retirement", &portfolio.pre_retirement_allocation),
+        ("post-retirement", &portfolio.post_retirement_allocation),
+    ];
+    for (name, allocation) in allocations {
+        let total = allocation.get_total();
+        check((total - 100.0).abs() <= 0.01, "allocation",
+              format!("The {} allocation adds up to {:.2}%", name, total));
+    }
+
+    violations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture;
+
+    #[test]
+    fn test_check_step() {
+        let input = fixture::get_input();
+        let results = fixture::run_simulation(&input).unwrap();
+        let snapshot = &results.monthly_snapshot[0];
+        let mut portfolio = input.portfolio;
+        let balance = portfolio.balance;
+        assert!(check_step(&portfolio, balance, balance, snapshot).is_empty());
+
+        // a withdrawal that didn't come out of the balance
+        portfolio.net_flows = -1000.0;
+        let violations = check_step(&portfolio, balance, balance, snapshot);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].check, "cash_flow");
+
+        portfolio.net_flows = 0.0;
+        portfolio.roth_balance = -5.0;
+        let violations = check_step(&portfolio, balance, balance, snapshot);
+        assert_eq!(violations[0].check, "negative_balance");
+    }
+}
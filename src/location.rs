@@ -0,0 +1,213 @@
+/**************************************************************************
+* location.rs
+*
+* Suggests which asset classes to hold in the taxable, tax-deferred and
+* tax-free accounts, and compares the uniform simulation of the plan holding
+* the current location with the same plan holding the suggested one. Each
+* kind of account grows with what it holds, so both the taxes on the taxable
+* accounts' yields and the growth moved into the tax-free accounts count.
+**************************************************************************/
+
+use chrono::NaiveDate;
+use crate::{Input, simulate, utils};
+use crate::portfolio::{Account, AccountType, Allocation, Portfolio, Rebalancing, ACCOUNT_TYPES, MAX_ACCOUNTS,
+                       MAX_ASSET_CLASSES};
+
+// the kinds of accounts by how they're taxed. The tax-free ones are the Roth
+// accounts and the HSAs.
+pub const TAXABLE: usize = 0;
+pub const TAX_DEFERRED: usize = 1;
+pub const TAX_FREE: usize = 2;
+pub const NUM_LOCATIONS: usize = 3;
+
+#[derive(Debug)]
+pub struct LocationReport {
+    // the amount of each asset class in each kind of account, now and
+    // suggested, in today's dollars
+    pub current: [[f32; MAX_ASSET_CLASSES]; NUM_LOCATIONS],
+    pub suggested: [[f32; MAX_ASSET_CLASSES]; NUM_LOCATIONS],
+    // the uniform simulation's ending balance and lifetime taxes with each
+    pub ending_balance: f32,
+    pub suggested_ending_balance: f32,
+    pub taxes: f32,
+    pub suggested_taxes: f32,
+}
+
+// fills the accounts with the amount of each asset class, in order, until
+// each is full
+fn fill(amounts: &mut [f32; MAX_ASSET_CLASSES], order: &[usize], capacity: f32) -> [f32; MAX_ASSET_CLASSES] {
+    let mut filled = [0.0; MAX_ASSET_CLASSES];
+    let mut left = capacity;
+    for &i in order {
+        filled[i] = f32::min(amounts[i], left.max(0.0));
+        amounts[i] -= filled[i];
+        left -= filled[i];
+    }
+    filled
+}
+
+// the taxable accounts get the asset classes that pay the least interest,
+// then the least dividends, since interest is taxed as ordinary income and
+// qualified dividends at the lower capital gains rates. Of the rest, the
+// ones with the highest expected returns go in the tax-free accounts, where
+// the growth is never taxed, and the others in the tax-deferred ones.
+pub fn get_suggested_location(portfolio: &Portfolio,
+                              allocation: &Allocation) -> [[f32; MAX_ASSET_CLASSES]; NUM_LOCATIONS] {
+    let asset_classes = &portfolio.asset_classes;
+    let mut amounts = allocation.weights.map(|v| v / 100.0 * portfolio.balance);
+    let mut order: Vec<usize> = (0..portfolio.num_asset_classes).collect();
+    order.sort_by(|&a, &b| {
+        let get_yields = |i: usize| (asset_classes[i].get_interest_yield(portfolio.expected_inflation),
+                                     asset_classes[i].dividend_yield);
+        let ((a_interest, a_dividends), (b_interest, b_dividends)) = (get_yields(a), get_yields(b));
+        a_interest.total_cmp(&b_interest).then(a_dividends.total_cmp(&b_dividends))
+    });
+    let tax_free_balance = portfolio.roth_balance + portfolio.hsa_balance;
+    let mut location = [[0.0; MAX_ASSET_CLASSES]; NUM_LOCATIONS];
+    location[TAXABLE] = fill(&mut amounts, &order, portfolio.taxable_balance);
+    order.sort_by(|&a, &b| asset_classes[b].expected_returns.total_cmp(&asset_classes[a].expected_returns));
+    location[TAX_FREE] = fill(&mut amounts, &order, tax_free_balance);
+    location[TAX_DEFERRED] = amounts;
+    location
+}
+
+// the amounts held now, by the accounts with their own allocations and at
+// the allocation in the others
+fn get_current_location(portfolio: &Portfolio, allocation: &Allocation) -> [[f32; MAX_ASSET_CLASSES]; NUM_LOCATIONS] {
+    let mut location = [[0.0; MAX_ASSET_CLASSES]; NUM_LOCATIONS];
+    for account_type in ACCOUNT_TYPES {
+        let amounts = &mut location[get_location(account_type)];
+        for (amount, holding) in amounts.iter_mut().zip(portfolio.get_type_holdings(account_type, allocation)) {
+            *amount += holding;
+        }
+    }
+    location
+}
+
+fn get_location(account_type: AccountType) -> usize {
+    match account_type {
+        AccountType::Taxable => TAXABLE,
+        AccountType::TaxDeferred | AccountType::Ira => TAX_DEFERRED,
+        AccountType::Roth | AccountType::Hsa => TAX_FREE,
+    }
+}
+
+// the plan with an account of each kind that has a balance, holding its
+// kind's share of the location for the whole plan. Both simulations are
+// rebalanced continuously without a glide path or a cash bucket, so they
+// can differ from the plan's own.
+fn get_location_input(input: &Input,
+                      location: &[[f32; MAX_ASSET_CLASSES]; NUM_LOCATIONS],
+                      allocation: &Allocation) -> Input {
+    let mut location_input = input.clone();
+    let portfolio = &mut location_input.portfolio;
+    let type_balances = portfolio.get_account_type_balances();
+    portfolio.pre_retirement_allocation = *allocation;
+    portfolio.post_retirement_allocation = *allocation;
+    portfolio.num_glide_points = 0;
+    portfolio.rebalancing = Rebalancing::Continuous;
+    portfolio.cash_bucket_years = 0.0;
+    portfolio.num_accounts = 0;
+    for (account_type, balance) in ACCOUNT_TYPES.into_iter().zip(type_balances) {
+        let amounts = &location[get_location(account_type)];
+        let total: f32 = amounts.iter().sum();
+        if balance <= 0.0 || total <= 0.0 || portfolio.num_accounts == MAX_ACCOUNTS {
+            continue;
+        }
+        portfolio.accounts[portfolio.num_accounts] = Account {
+            account_type,
+            balance,
+            allocation: Allocation {weights: amounts.map(|v| v / total * 100.0)},
+        };
+        portfolio.num_accounts += 1;
+    }
+    location_input
+}
+
+// the ending balance and the lifetime taxes of the uniform simulation
+fn get_outcome(input: &Input, start_date: NaiveDate) -> Result<(f32, f32), String> {
+    let context = simulate::PlanContext::with_start_date(input, start_date);
+    let portfolio = &input.portfolio;
+    let results = simulate::run_simulation_with_context(&context, &portfolio.get_expected_returns(),
+                                                        portfolio.expected_inflation)?;
+    let ending_balance = results.monthly_snapshot.last().map_or(0.0, |v| v.balance);
+    let taxes = results.monthly_snapshot.iter().map(|v| v.taxes * results.months_per_step as f32).sum();
+    Ok((ending_balance, taxes))
+}
+
+// returns None unless the balance is in more than one kind of account, since
+// there is nothing to move
+pub fn run_location_report(input: &Input) -> Result<Option<LocationReport>, String> {
+    run_location_report_with_start_date(input, chrono::Utc::now().naive_utc().date())
+}
+
+// the same for a plan that starts at a date other than today
+pub fn run_location_report_with_start_date(input: &Input, start_date: NaiveDate) -> Result<Option<LocationReport>, String> {
+    let portfolio = &input.portfolio;
+    let tax_free_balance = portfolio.roth_balance + portfolio.hsa_balance;
+    let location_balances = [portfolio.taxable_balance, portfolio.balance - portfolio.taxable_balance - tax_free_balance,
+                             tax_free_balance];
+    if location_balances.iter().filter(|v| **v > 0.0).count() < 2 {
+        return Ok(None);
+    }
+
+    // the allocation the plan starts with
+    let context = simulate::PlanContext::with_start_date(input, start_date);
+    let mut starting = *portfolio;
+    starting.age = utils::get_age_months(&input.retirees[0].date_of_birth, &context.start_date) as f32 / 12.0;
+    let allocation = starting.get_target_allocation(context.retirement_date <= context.start_date);
+
+    let current = get_current_location(portfolio, &allocation);
+    let suggested = get_suggested_location(portfolio, &allocation);
+    let (ending_balance, taxes) = get_outcome(&get_location_input(input, &current, &allocation), start_date)?;
+    let (suggested_ending_balance, suggested_taxes) =
+        get_outcome(&get_location_input(input, &suggested, &allocation), start_date)?;
+
+    Ok(Some(LocationReport {
+        current,
+        suggested,
+        ending_balance,
+        suggested_ending_balance,
+        taxes,
+        suggested_taxes,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture;
+    use crate::portfolio::{BONDS, INTERNATIONAL_EQUITY, US_EQUITY};
+
+    #[test]
+    fn test_suggested_location() {
+        let mut portfolio = fixture::get_input().portfolio;
+        portfolio.taxable_balance = 500000.0;
+        portfolio.roth_balance = 200000.0;
+        let location = get_suggested_location(&portfolio, &portfolio.post_retirement_allocation);
+
+        // $500,000 taxable, filled with the US stocks that pay the lowest
+        // dividends before the international ones
+        assert!((location[TAXABLE][US_EQUITY] - 500000.0).abs() < 1.0);
+        assert_eq!(location[TAXABLE][INTERNATIONAL_EQUITY], 0.0);
+        // the highest expected returns in the Roth, the bonds tax-deferred
+        assert!((location[TAX_FREE][INTERNATIONAL_EQUITY] + location[TAX_FREE][US_EQUITY] - 200000.0).abs() < 1.0);
+        assert!((location[TAX_DEFERRED][BONDS] - 480000.0).abs() < 1.0);
+        let total: f32 = location.iter().flatten().sum();
+        assert!((total - portfolio.balance).abs() < 1.0);
+    }
+
+    #[test]
+    fn test_location_report() {
+        // nothing to move with everything tax-deferred
+        let mut input = fixture::get_input();
+        assert!(run_location_report_with_start_date(&input, fixture::get_start_date()).unwrap().is_none());
+
+        // with no taxable accounts only the growth moved into the Roth
+        // accounts changes the outcome, and it leaves more at the end
+        input.portfolio.roth_balance = 300000.0;
+        let report = run_location_report_with_start_date(&input, fixture::get_start_date()).unwrap().unwrap();
+        assert_eq!(report.current[TAXABLE], [0.0; MAX_ASSET_CLASSES]);
+        assert!(report.suggested_ending_balance > report.ending_balance);
+    }
+}
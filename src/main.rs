@@ -10,12 +10,43 @@ use yaml_rust::{YamlLoader, YamlEmitter};
 use chrono::{NaiveDate};
 use std::env;
 use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
 use std::process;
+use std::thread;
 use num_format::{Locale, ToFormattedString};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha12Rng;
 
 use crate::historical_scan::HistoricalScan;
 use crate::monte_carlo::MonteCarloScan;
-use crate::portfolio::Portfolio;
+use crate::portfolio::{AccountType, Portfolio, Rebalancing};
+use crate::report::{Json, Warning};
+use crate::scan::Scannable;
+use crate::tax_tables::{TaxTable, TaxTables};
+
+// adds a line to a report, works like println!. Defined before the
+// modules so they can use it too.
+macro_rules! outln {
+    ($out:expr) => {
+        $out.push('\n')
+    };
+    ($out:expr, $($arg:tt)*) => {{
+        $out.push_str(&format!($($arg)*));
+        $out.push('\n');
+    }};
+}
+
+// report text in the language of the report, works like format! but only
+// with {} placeholders, which can have a sign and a precision
+macro_rules! tr {
+    ($text:expr) => {
+        crate::i18n::translate($text)
+    };
+    ($text:expr, $($arg:expr),+ $(,)?) => {
+        crate::i18n::fill(crate::i18n::translate($text), &[$(&$arg as &dyn std::fmt::Display),+])
+    };
+}
 
 mod simulate;
 mod scan;
@@ -23,31 +54,438 @@ mod historical_scan;
 mod monte_carlo;
 mod utils;
 mod portfolio;
+mod glide;
+mod location;
+mod cache;
+mod report;
+mod tax_tables;
+mod tax_report;
+#[cfg(feature = "tools")]
+mod doctor;
+#[cfg(feature = "tools")]
+mod merge;
+mod reconcile;
+mod mortality;
+mod invariants;
+mod i18n;
+#[cfg(test)]
+mod fixture;
 
 ///////////////////////////////////////////////////////////////////////////
 // Parsing input
 ///////////////////////////////////////////////////////////////////////////
 
-#[derive(Debug)]
+// how a pension is adjusted for inflation each year
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PensionCola {
+    // keeps up with inflation
+    Cpi,
+    // fixed in nominal dollars
+    None,
+    // a fixed percentage a year, whatever the inflation
+    Fixed(f32),
+    // keeps up with inflation up to a percentage a year
+    Capped(f32),
+}
+
+impl PensionCola {
+    // the pension's value in today's dollars after a number of months, as a
+    // fraction of its value when it started
+    fn get_real_value(&self, annual_inflation: f32, months: u32) -> f32 {
+        let discount = utils::get_inflation_discount(annual_inflation, months);
+        match *self {
+            PensionCola::Cpi => 1.0,
+            PensionCola::None => discount,
+            PensionCola::Fixed(percent) => discount / utils::get_inflation_discount(percent, months),
+            PensionCola::Capped(percent) =>
+                discount / utils::get_inflation_discount(f32::min(percent, annual_inflation), months),
+        }
+    }
+
+    fn describe(&self) -> String {
+        match *self {
+            PensionCola::Cpi => tr!("a cost of living adjustment").to_string(),
+            PensionCola::None => tr!("no cost of living adjustment").to_string(),
+            PensionCola::Fixed(percent) => tr!("a fixed {:.1}% cost of living adjustment", percent),
+            PensionCola::Capped(percent) => tr!("a cost of living adjustment capped at {:.1}%", percent),
+        }
+    }
+}
+
+// a raise on top of the salary growth, from January of a year
+#[derive(Debug, Clone)]
+pub struct Raise {
+    year: i32,
+    percent: f32,
+}
+
+// an annual bonus paid in one month while working
+#[derive(Debug, Clone)]
+pub struct Bonus {
+    amount: f32,
+    // percentage of the salary, added to the amount
+    percent: f32,
+    month: u32,
+    contribution_percent: f32,
+    // flat (supplemental) tax rate, taxed like the salary if there is none
+    tax_rate: Option<f32>,
+    // percentage of the bonus left after contributions and taxes that is
+    // saved in the taxable accounts
+    savings_percent: f32,
+}
+
+// a lump sum of compensation paid while working, such as a bonus or an RSU
+// vest, repeating every few months through the end date if it's a vesting
+// schedule
+#[derive(Debug, Clone)]
+pub struct Compensation {
+    date: NaiveDate,
+    // in today's dollars, for each payment
+    amount: f32,
+    repeat_months: u32,
+    end_date: NaiveDate,
+    // flat (supplemental) tax rate, taxed like the salary if there is none
+    tax_rate: Option<f32>,
+    // percentage left after taxes that is saved in the taxable accounts
+    savings_percent: f32,
+}
+
+impl Compensation {
+    // total paid from the start date up to the end date
+    pub fn get_amount(&self, start: &NaiveDate, end: &NaiveDate) -> f32 {
+        let mut total = 0.0;
+        let mut payments = 0;
+        let mut date = self.date;
+        while date < *end && date <= self.end_date {
+            if date >= *start {
+                total += self.amount;
+            }
+            if self.repeat_months == 0 {
+                break;
+            }
+            payments += 1;
+            date = utils::add_months(&self.date, payments * self.repeat_months);
+        }
+        total
+    }
+}
+
+// how the employer match becomes the retiree's to keep
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Vesting {
+    Immediate,
+    // all at once after the vesting years
+    Cliff,
+    // an equal part after each of the vesting years
+    Graded,
+}
+
+// employer contributions matching the retiree's own until retirement
+#[derive(Debug, Clone)]
+pub struct EmployerMatch {
+    // percentage of the retiree's contributions that is matched
+    percent: f32,
+    // contributions up to this percentage of the salary are matched
+    limit_percent: f32,
+    // vesting counts from the hire date
+    hire_date: Option<NaiveDate>,
+    vesting: Vesting,
+    vesting_years: u32,
+}
+
+impl EmployerMatch {
+    // fraction (0 to 1) of the matches the retiree keeps on a date
+    pub fn get_vested_fraction(&self, date: &NaiveDate) -> f32 {
+        let Some(hire_date) = self.hire_date else {
+            return 1.0;
+        };
+        let years = utils::get_age(&hire_date, date);
+        match self.vesting {
+            Vesting::Immediate => 1.0,
+            Vesting::Cliff => if years >= self.vesting_years {1.0} else {0.0},
+            Vesting::Graded => f32::min(years as f32 / self.vesting_years as f32, 1.0),
+        }
+    }
+}
+
+// part-time work after retiring, such as consulting or a bridge job,
+// from the start date through the end date
+#[derive(Debug, Clone)]
+pub struct PartTimeWork {
+    start_date: NaiveDate,
+    end_date: NaiveDate,
+    // in today's dollars
+    monthly_income: f32,
+}
+
+impl PartTimeWork {
+    pub fn is_active(&self, date: &NaiveDate) -> bool {
+        *date >= self.start_date && *date <= self.end_date
+    }
+}
+
+// most that can be put into a QLAC, and the latest age its payments can
+// start
+const QLAC_LIMIT: f32 = 210000.0;
+const QLAC_MAX_AGE: u32 = 85;
+
+// an income annuity bought at retirement that pays for the rest of the
+// retiree's life, right away (a SPIA) or starting at a later age (a DIA)
+#[derive(Debug, Clone)]
+pub struct Annuity {
+    // price of the annuity in today's dollars, or a percentage of the
+    // balance at retirement. One of them is 0.
+    premium: f32,
+    premium_percent: f32,
+    // payments start at retirement if None
+    age: Option<u32>,
+    // payment in today's dollars at the purchase, or the annual payments as
+    // a percentage of the premium. One of them is 0.
+    monthly_income: f32,
+    payout_percent: f32,
+    // false if the payments stay the same in nominal dollars
+    cola: bool,
+    // a qualified annuity is bought with tax-deferred money and its payments
+    // are fully taxable. Otherwise it's bought from the taxable accounts and
+    // the premium comes back tax free.
+    qualified: bool,
+    // a qualifying longevity annuity contract is a qualified deferred
+    // annuity with a limited premium
+    qlac: bool,
+}
+
+impl Annuity {
+    // first payment date, for a retiree born on a date
+    pub fn get_start_date(&self, date_of_birth: &NaiveDate, retirement_date: NaiveDate) -> NaiveDate {
+        match self.age {
+            Some(age) => utils::add_years(date_of_birth, age),
+            None => retirement_date,
+        }
+    }
+
+    // monthly payment in today's dollars at the purchase for a premium paid
+    pub fn get_monthly_income(&self, premium: f32) -> f32 {
+        match self.payout_percent > 0.0 {
+            true => premium * self.payout_percent / 100.0 / 12.0,
+            false => self.monthly_income * premium / self.premium,
+        }
+    }
+
+    // fraction (0 to 1) of the payments that is taxable. The premium is
+    // returned tax free over the expected payments (the exclusion ratio).
+    pub fn get_taxable_fraction(&self, premium: f32, monthly_income: f32, expected_months: u32) -> f32 {
+        if self.qualified {
+            return 1.0;
+        }
+        let expected_payments = monthly_income * expected_months.max(12) as f32;
+        f32::max(1.0 - premium / expected_payments, 0.0)
+    }
+}
+
+// employer stock held in a 401(k). At retirement it's rolled over with the
+// rest of the account, or the shares are distributed with net unrealized
+// appreciation (NUA) treatment: the basis is taxed as ordinary income and the
+// appreciation at the capital gains rates.
+#[derive(Debug, Clone)]
+pub struct EmployerStock {
+    // at retirement, in today's dollars. It's part of the tax-deferred
+    // balance.
+    value: f32,
+    // what the plan paid for the shares
+    basis: f32,
+    // distribute the shares instead of rolling them over
+    nua: bool,
+}
+
+// used to look up the life tables
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sex {
+    Male,
+    Female,
+}
+
+#[derive(Debug, Clone)]
 struct Retiree {
     name: String,
     date_of_birth: NaiveDate,
+    // only needed for the life tables
+    sex: Option<Sex>,
     retirement_age: u32,
     life_expectency: u32,
     salary_annual: f32,
+    // real growth of the salary each year from today, on top of inflation
+    salary_growth_percent: f32,
+    raises: Vec<Raise>,
     retirement_contribution_percent: f32,
+    // fixed contribution in today's dollars, on top of the percentage
+    retirement_contribution_monthly: f32,
+    // false if the fixed contribution stays the same in nominal dollars
+    contribution_inflation_indexed: bool,
+    // contributions, and the employer match, start on this date, such as
+    // when starting a new job
+    contribution_start_date: Option<NaiveDate>,
+    employer_match: Option<EmployerMatch>,
+    bonus: Option<Bonus>,
+    // bonuses and RSU vests on given dates
+    compensation: Vec<Compensation>,
+    hsa_contribution_annual: f32,
+    backdoor_roth_annual: f32,
+    nondeductible_ira_annual: f32,
+    mega_backdoor_roth_annual: f32,
     social_security_age: u32,
+    // extra months on top of social_security_age, 0 to 11
+    social_security_months: u32,
     pension_age: u32,
     pension_monthly_income: f32,
+    pension_cola: PensionCola,
+    // percentage of the pension the spouse keeps after the retiree dies
+    pension_survivor_percent: f32,
+    // one-time amount offered instead of the pension, and whether it's
+    // taken. It's rolled over into the tax-deferred accounts.
+    pension_lump_sum: f32,
+    pension_take_lump_sum: bool,
+    annuity: Option<Annuity>,
+    employer_stock: Option<EmployerStock>,
+    part_time: Vec<PartTimeWork>,
     other_monthly_retirement_income: f32,
-    social_security_amount_early: f32,
+    // benefit at full retirement age, the benefits at other ages are
+    // calculated from it
     social_security_amount_full: f32,
-    social_security_amount_delayed: f32,
+}
+
+impl Retiree {
+    // salary on a date in today's dollars, after the annual growth since the
+    // start of the simulation and the raises so far
+    pub fn get_salary(&self, start_date: &NaiveDate, date: &NaiveDate) -> f32 {
+        let years = utils::months_between(start_date, date) / 12;
+        let mut salary = self.salary_annual * (1.0 + self.salary_growth_percent / 100.0).powi(years as i32);
+        for raise in self.raises.iter().filter(|v| v.year <= chrono::Datelike::year(date)) {
+            salary *= 1.0 + raise.percent / 100.0;
+        }
+        salary
+    }
+
+    // pay from part-time work in a month, 0 when there isn't any
+    pub fn get_part_time_income(&self, date: &NaiveDate) -> f32 {
+        self.part_time.iter()
+            .filter(|v| v.is_active(date))
+            .map(|v| v.monthly_income)
+            .sum()
+    }
 }
     
-#[derive(Debug)]
+// an expense paid once a year in retirement, such as property tax or an
+// insurance premium, in today's dollars
+#[derive(Debug, Clone)]
+struct AnnualExpense {
+    amount: f32,
+    month: u32,
+}
+
+// when the annual expenses are taken from the portfolio
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum AnnualTiming {
+    // a twelfth every month
+    Spread,
+    // all of it in the month it's paid
+    Actual,
+}
+
+// health insurance and out-of-pocket costs for each retiree from retirement
+// until Medicare starts at 65, in today's dollars. They're paid on top of the
+// monthly expenses.
+#[derive(Debug, Clone)]
+pub struct BridgeHealthcare {
+    monthly_premium: f32,
+    monthly_out_of_pocket: f32,
+    // nominal growth of the costs, percent per year
+    inflation: f32,
+    // the premiums are reduced by the ACA premium tax credit, based on the
+    // household's income
+    aca_subsidy: bool,
+}
+
+// Medicare premiums for each retiree from 65, in today's dollars. They're
+// paid on top of the monthly expenses, and the IRMAA surcharges grow with them.
+#[derive(Debug, Clone)]
+pub struct Medicare {
+    part_b_monthly: f32,
+    part_d_monthly: f32,
+    medigap_monthly: f32,
+    // nominal growth of the premiums, percent per year
+    inflation: f32,
+}
+
+impl Medicare {
+    pub fn get_monthly_premiums(&self) -> f32 {
+        self.part_b_monthly + self.part_d_monthly + self.medigap_monthly
+    }
+}
+
+#[derive(Debug, Clone)]
 struct Expenses {
     monthly: f32,
+
+    // portion of the monthly expenses that are healthcare costs
+    healthcare_monthly: f32,
+
+    // portion of the monthly expenses that can't be cut, all of them if None
+    essential_monthly: Option<f32>,
+
+    annual: Vec<AnnualExpense>,
+    annual_timing: AnnualTiming,
+
+    // monthly expenses as a percentage of the pay in the last year before
+    // retirement, after savings and payroll taxes, instead of an amount
+    replacement_percent: Option<f32>,
+
+    // percentage of the monthly expenses that remain after the first death
+    survivor_percent: f32,
+
+    bridge_healthcare: Option<BridgeHealthcare>,
+    medicare: Option<Medicare>,
+}
+
+impl Expenses {
+    // the annual expenses spread evenly, per month
+    pub fn get_annual_expenses_spread(&self) -> f32 {
+        self.annual.iter().map(|v| v.amount).sum::<f32>() / 12.0
+    }
+
+    // the annual expenses per month for a step of a number of months starting
+    // in a month (1-12), using the configured timing
+    pub fn get_annual_expenses(&self, month: u32, months: u32) -> f32 {
+        if self.annual_timing == AnnualTiming::Spread || months >= 12 {
+            return self.get_annual_expenses_spread();
+        }
+        let in_step = |v: &AnnualExpense| (v.month + 12 - month) % 12 < months;
+        self.annual.iter().filter(|v| in_step(v)).map(|v| v.amount).sum::<f32>() / months as f32
+    }
+}
+
+// college costs for a number of years starting in a given year, in today's
+// dollars
+#[derive(Debug, Clone)]
+pub struct EducationExpense {
+    year: i32,
+    years: u32,
+    annual_amount: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Education {
+    balance_529: f32,
+    expenses: Vec<EducationExpense>,
+}
+
+impl Education {
+    // monthly education expenses in a given year
+    pub fn get_monthly_expenses(&self, year: i32) -> f32 {
+        self.expenses.iter()
+            .filter(|v| year >= v.year && year < v.year + v.years as i32)
+            .map(|v| v.annual_amount / 12.0)
+            .sum()
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -56,18 +494,471 @@ pub struct TaxLevel {
     rate: f32,
 }
     
-#[derive(Debug)]
+// tax law that takes over in a given year, such as the brackets from before
+// the Tax Cuts and Jobs Act if it expires
+#[derive(Debug, Clone)]
+pub struct TaxSunset {
+    year: i32,
+    standard_deduction: f32,
+    tax_levels: Vec<TaxLevel>,
+}
+
+// social security and medicare taxes on wages while working
+#[derive(Debug, Clone)]
+pub struct PayrollTaxes {
+    social_security_rate: f32,
+    social_security_wage_base: f32,
+    medicare_rate: f32,
+    additional_medicare_rate: f32,
+    additional_medicare_threshold: f32,
+}
+
+// Medicare premium surcharge (IRMAA) per person on Medicare when income is
+// above a level
+#[derive(Debug, Copy, Clone)]
+pub struct IrmaaLevel {
+    income: f32,
+    monthly_surcharge: f32,
+}
+
+#[derive(Debug, Clone)]
 pub struct TaxRates {
     standard_deduction: f32,
+
+    // additional standard deduction for each person 65 or older
+    senior_deduction: f32,
     tax_levels: Vec<TaxLevel>,
+    sunset: Option<TaxSunset>,
+    payroll: PayrollTaxes,
+    capital_gains_levels: Vec<TaxLevel>,
+
+    // flat state income tax rate on the same income as the federal tax
+    state_rate: f32,
+    irmaa_levels: Vec<IrmaaLevel>,
+
+    // tables loaded from the brackets file, these take over from the
+    // brackets above starting in their year
+    tables: TaxTables,
 }
 
-#[derive(Debug)]
+impl TaxRates {
+    // returns the standard deduction and brackets in effect for a year
+    pub fn for_year(&self, year: i32) -> (f32, &[TaxLevel]) {
+        if let Some(table) = tax_tables::find_table(&self.tables.federal, year) {
+            return (table.standard_deduction, &table.tax_levels);
+        }
+        match &self.sunset {
+            Some(sunset) if year >= sunset.year => (sunset.standard_deduction, &sunset.tax_levels),
+            _ => (self.standard_deduction, &self.tax_levels),
+        }
+    }
+
+    // returns the state standard deduction and brackets in effect for a
+    // year, or None if the flat state_rate applies
+    pub fn state_for_year(&self, year: i32) -> Option<(f32, &[TaxLevel])> {
+        tax_tables::find_table(&self.tables.state, year)
+            .map(|v| (v.standard_deduction, v.tax_levels.as_slice()))
+    }
+
+    // the rates for a single filer, from the married filing jointly ones.
+    // The standard deduction and most of the brackets and surcharge levels
+    // for a single filer are half of the joint ones.
+    pub fn for_single(&self) -> TaxRates {
+        let halve = |levels: &[TaxLevel]| -> Vec<TaxLevel> {
+            levels.iter().map(|v| TaxLevel {income: v.income / 2.0, rate: v.rate}).collect()
+        };
+        let halve_tables = |tables: &[TaxTable]| -> Vec<TaxTable> {
+            tables.iter().map(|v| TaxTable {
+                year: v.year,
+                standard_deduction: v.standard_deduction / 2.0,
+                tax_levels: halve(&v.tax_levels),
+            }).collect()
+        };
+        TaxRates {
+            standard_deduction: self.standard_deduction / 2.0,
+            tax_levels: halve(&self.tax_levels),
+            sunset: self.sunset.as_ref().map(|v| TaxSunset {
+                year: v.year,
+                standard_deduction: v.standard_deduction / 2.0,
+                tax_levels: halve(&v.tax_levels),
+            }),
+            capital_gains_levels: halve(&self.capital_gains_levels),
+            irmaa_levels: self.irmaa_levels.iter()
+                .map(|v| IrmaaLevel {income: v.income / 2.0, monthly_surcharge: v.monthly_surcharge})
+                .collect(),
+            tables: TaxTables {
+                federal: halve_tables(&self.tables.federal),
+                state: halve_tables(&self.tables.state),
+            },
+            ..self.clone()
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Analysis {
+    target_success_rate: f32,
+    // years earlier the early retirement stress test retires, 0 to skip it
+    early_retirement_years: u32,
+    // social security lost for each year retired early, a percentage
+    early_retirement_benefit_reduction: f32,
+    // months between the retirement dates on the retirement date curve, 0
+    // to skip it
+    retirement_curve_months: u32,
+    // years either side of the planned date the curve covers
+    retirement_curve_years: u32,
+    // also runs the uniform simulation with the average returns and
+    // inflation of each historical decade
+    decade_presets: bool,
+    // also report whether the plans keep their principal, living off the
+    // real returns
+    perpetuity: bool,
+    // the share of the expenses, a percentage, below which the pensions and
+    // annuities that lose value to inflation are flagged
+    fixed_income_warning_percent: f32,
+    // the drawdown applied at the start with --crash now
+    crash: Crash,
+    // adverse events each added to the plan on their own, and the scans
+    // rerun to compare the success rates
+    stress_tests: Vec<StressTest>,
+    // the ages the retirees live to in each longevity scenario, one for
+    // each retiree, empty to skip them
+    longevity_scenarios: Vec<Vec<u32>>,
+    // also runs the scans with the reference strategies, all in bonds and
+    // 60/40 with the 4% rule, to compare the plan with
+    benchmarks: bool,
+    // also suggests which asset classes to hold in each kind of account
+    asset_location: bool,
+}
+
+// an immediate market crash, as the return of each asset class in percent
+#[derive(Debug, Clone, Copy)]
+struct Crash {
+    us_equity: f32,
+    international_equity: f32,
+    bonds: f32,
+}
+
+// inflation at a rate for the first years of the plan, instead of each
+// scenario's
+#[derive(Debug, Clone, Copy)]
+struct InflationShock {
+    rate: f32,
+    years: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum StressEvent {
+    // an expense in today's dollars paid at the start of a year, a random
+    // year in retirement if None
+    Expense {amount: f32, year: Option<i32>},
+    Crash(Crash),
+    Inflation(InflationShock),
+}
+
+#[derive(Debug, Clone)]
+struct StressTest {
+    // described from the event if None
+    name: Option<String>,
+    event: StressEvent,
+}
+
+// how allocations that don't add up to 100% are handled
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum AllocationCheck {
+    // allocations within ALLOCATION_TOLERANCE of 100% are scaled to 100%
+    Normalize,
+    // allocations must add up to 100%
+    Strict,
+}
+
+// allocations further than this from 100% are always rejected
+const ALLOCATION_TOLERANCE: f32 = 2.0;
+
+// how the Monte Carlo simulation picks each year's returns
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MonteCarloMethod {
+    // from normal distributions with the expected returns and deviations
+    Parametric,
+    // whole historical years drawn with replacement
+    Bootstrap,
+}
+
+// how long the retirees live in the Monte Carlo simulation
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mortality {
+    // until the life expectancy
+    Fixed,
+    // drawn from the life tables
+    LifeTable,
+}
+
+#[derive(Debug, Clone)]
+struct SimulationSettings {
+    months_per_step: u32,
+    allocation_check: AllocationCheck,
+    monte_carlo: MonteCarloMethod,
+    mortality: Mortality,
+    // checks the invariants after every step, set by --check rather than
+    // the configuration
+    check_invariants: bool,
+    // set by an inflation stress test rather than the configuration
+    inflation_shock: Option<InflationShock>,
+}
+
+// a move in retirement, at an age of the first retiree, that changes the
+// cost of living and the state taxes
+#[derive(Debug, Clone)]
+struct Relocation {
+    age: u32,
+    // expenses after the move, unchanged if None
+    monthly_expenses: Option<f32>,
+    // flat state income tax rate after the move, replacing the state brackets.
+    // Unchanged if None.
+    state_rate: Option<f32>,
+    // cash from selling the home less the cost of the new one, in today's
+    // dollars. Negative if the new home costs more.
+    home_equity: f32,
+}
+
+// selling the home to buy a smaller one, at an age of the first retiree
+#[derive(Debug, Clone)]
+pub struct Downsize {
+    age: u32,
+    // what the new home would cost today
+    new_value: f32,
+    // percentage of the sale price
+    selling_costs_percent: f32,
+    // expenses after downsizing, unchanged if None
+    monthly_expenses: Option<f32>,
+}
+
+// a reverse mortgage (HECM) line of credit on the home, opened at an age of
+// the first retiree. It's only drawn on once the portfolio runs out, and the
+// loan is repaid from the home after the plan ends.
+#[derive(Debug, Clone)]
+pub struct ReverseMortgage {
+    age: u32,
+    // in today's dollars, when it's opened
+    credit_limit: f32,
+    // real growth of the unused credit and the loan, percent per year
+    growth_rate: f32,
+}
+
+// the primary home. Its property taxes and insurance are paid with the other
+// expenses. Its value isn't part of the balance until it's sold to downsize.
+#[derive(Debug, Clone)]
+pub struct Home {
+    // in today's dollars
+    value: f32,
+    // real appreciation, percent per year
+    appreciation: f32,
+    // percentage of the value a year
+    property_tax_rate: f32,
+    // in today's dollars, for a home of the value
+    insurance_annual: f32,
+    downsize: Option<Downsize>,
+    reverse_mortgage: Option<ReverseMortgage>,
+}
+
+impl Home {
+    // the value at a date of the home lived in then, appreciating from the
+    // start of the plan
+    pub fn get_value(&self, start_date: &NaiveDate, date: &NaiveDate, downsize_date: Option<NaiveDate>) -> f32 {
+        let value = match (&self.downsize, downsize_date) {
+            (Some(downsize), Some(downsize_date)) if *date >= downsize_date => downsize.new_value,
+            _ => self.value,
+        };
+        let years = utils::months_between(start_date, date) as f32 / 12.0;
+        value * (1.0 + self.appreciation / 100.0).powf(years)
+    }
+
+    // monthly property taxes and insurance on a home of a value. The
+    // insurance goes up and down with the value.
+    pub fn get_monthly_costs(&self, value: f32) -> f32 {
+        let insurance = match self.value > 0.0 {
+            true => self.insurance_annual * value / self.value,
+            false => self.insurance_annual,
+        };
+        (value * self.property_tax_rate / 100.0 + insurance) / 12.0
+    }
+
+    // the sale price less the selling costs and the price of the new home,
+    // negative if the new home costs more
+    pub fn get_downsize_equity(&self, start_date: &NaiveDate, date: &NaiveDate) -> f32 {
+        let Some(downsize) = self.downsize.as_ref() else {
+            return 0.0;
+        };
+        // the new home has appreciated as much as the old one
+        let price = self.get_value(start_date, date, None);
+        let new_price = downsize.new_value * price / self.value;
+        price * (1.0 - downsize.selling_costs_percent / 100.0) - new_price
+    }
+}
+
+// a one-time amount saved in the taxable accounts, such as an inheritance
+// or the proceeds of selling a home. It isn't taxed.
+#[derive(Debug, Clone)]
+pub struct Windfall {
+    name: String,
+    // in today's dollars
+    amount: f32,
+    date: NaiveDate,
+    // percent chance it happens. Only certain windfalls are counted, except
+    // in the Monte Carlo simulation, which counts the others in that share
+    // of the scenarios.
+    probability: f32,
+}
+
+impl Windfall {
+    pub fn is_certain(&self) -> bool {
+        self.probability >= 100.0
+    }
+}
+
+// a future expense paid once, or every number of years, such as a new roof
+// or replacing a car. It's withdrawn in the month it's paid.
+#[derive(Debug, Clone)]
+pub struct OneTimeExpense {
+    name: String,
+    amount: f32,
+    // the first time it's paid
+    date: NaiveDate,
+    // false if the amount is in the dollars of the date it's paid, so it's
+    // worth less in today's dollars the later that is
+    inflation_adjusted: bool,
+    // paid again every this many years until the end of the plan
+    every_years: Option<u32>,
+}
+
+impl OneTimeExpense {
+    // the dates it's paid from a date until before another
+    pub fn get_dates(&self, from: &NaiveDate, to: &NaiveDate) -> Vec<NaiveDate> {
+        let mut dates = Vec::new();
+        let mut date = self.date;
+        while date < *to {
+            if date >= *from {
+                dates.push(date);
+            }
+            match self.every_years {
+                Some(years) => date = utils::add_years(&date, years),
+                None => break,
+            }
+        }
+        dates
+    }
+
+    // the amount in today's dollars when it's paid on a date
+    pub fn get_amount(&self, annual_inflation: f32, start_date: &NaiveDate, date: &NaiveDate) -> f32 {
+        match self.inflation_adjusted {
+            true => self.amount,
+            false => self.amount * utils::get_inflation_discount(annual_inflation, utils::months_between(start_date, date)),
+        }
+    }
+}
+
+// keeping the plan up to date: the balance comes from the latest line of a
+// CSV file of actual balances, and the report alerts when the success rate
+// falls below a floor
+#[derive(Debug, Clone)]
+pub struct Tracking {
+    // relative to the configuration file, in the format reconcile reads
+    actuals_file: Option<String>,
+    // the date and amount of the latest actual balance
+    latest_balance: Option<(NaiveDate, f32)>,
+    // the balance in the configuration file, before the actual one replaced it
+    configured_balance: f32,
+    // the lowest historical success rate, a percentage, before alerting
+    success_floor: Option<f32>,
+}
+
+// a rental property, held until it's sold. The rent is taxed like other
+// income and the gain on the sale at the capital gains rates.
+#[derive(Debug, Clone)]
+pub struct RentalProperty {
+    name: String,
+    // in today's dollars
+    value: f32,
+    // real appreciation, percent per year
+    appreciation: f32,
+    // rent less the mortgage, property taxes, insurance and upkeep, in
+    // today's dollars
+    monthly_rent: f32,
+    // sold in January of this year, held for the whole plan if None
+    sale_year: Option<i32>,
+    // the price paid plus improvements less the depreciation taken
+    basis: f32,
+}
+
+impl RentalProperty {
+    pub fn get_sale_date(&self) -> Option<NaiveDate> {
+        self.sale_year.and_then(|v| NaiveDate::from_ymd_opt(v, 1, 1))
+    }
+
+    pub fn is_held(&self, date: &NaiveDate) -> bool {
+        self.get_sale_date().is_none_or(|v| *date < v)
+    }
+
+    // the value at a date, appreciating from the start of the plan
+    pub fn get_value(&self, start_date: &NaiveDate, date: &NaiveDate) -> f32 {
+        let years = utils::months_between(start_date, date) as f32 / 12.0;
+        self.value * (1.0 + self.appreciation / 100.0).powf(years)
+    }
+}
+
+// an account listed in the portfolio block, such as one spouse's 401k or a
+// joint brokerage account, as it starts. The balances add up to the
+// portfolio's, and accounts with their own allocations are also in the
+// portfolio to grow separately.
+#[derive(Debug, Clone)]
+struct Account {
+    name: String,
+    account_type: AccountType,
+    balance: f32,
+    // the after-tax basis of an IRA, 0 for the other kinds
+    basis: f32,
+    // None if the portfolio's allocation is used for every account
+    allocation: Option<portfolio::Allocation>,
+}
+
+#[derive(Debug, Clone)]
 struct Input {
     retirees: Vec<Retiree>,
     portfolio: Portfolio,
+    // the accounts that make up the portfolio, empty unless they're listed
+    accounts: Vec<Account>,
+    // the name of each of the portfolio's asset classes, as in the exported
+    // returns
+    asset_class_names: Vec<String>,
     expenses: Expenses,
+    education: Education,
+    relocation: Option<Relocation>,
+    home: Option<Home>,
+    windfalls: Vec<Windfall>,
+    rental_properties: Vec<RentalProperty>,
+    one_time_expenses: Vec<OneTimeExpense>,
+    tracking: Option<Tracking>,
     tax_rates: TaxRates,
+    // the tax rates once the survivor of a couple files as a single
+    survivor_tax_rates: TaxRates,
+    analysis: Analysis,
+    simulation: SimulationSettings,
+    // the balance before the crash, None unless run with --crash now
+    crash_balance: Option<f32>,
+
+    // the normalized configuration file
+    config_text: String,
+}
+
+impl Input {
+    // an asset class as it's described in the report
+    fn get_asset_class_description(&self, index: usize) -> &str {
+        match index {
+            portfolio::US_EQUITY => "US equity",
+            portfolio::INTERNATIONAL_EQUITY => "international equity",
+            _ => &self.asset_class_names[index],
+        }
+    }
 }
 
 fn parse_string(yaml: &yaml_rust::Yaml, field_name: &str) -> Result<String, String> {
@@ -84,6 +975,13 @@ fn parse_u32(yaml: &yaml_rust::Yaml, field_name: &str) -> Result<u32, String> {
     Ok(value as u32)
 }
     
+fn parse_u32_or(yaml: &yaml_rust::Yaml, field_name: &str, default: u32) -> Result<u32, String> {
+    if yaml[field_name].is_badvalue() {
+        return Ok(default);
+    }
+    parse_u32(yaml, field_name)
+}
+
 fn parse_f32(yaml: &yaml_rust::Yaml, field_name: &str) -> Result<f32, String> {
     let value = yaml[field_name].as_f64()
         .ok_or("Invalid value: ".to_string() + field_name)?;
@@ -91,39 +989,183 @@ fn parse_f32(yaml: &yaml_rust::Yaml, field_name: &str) -> Result<f32, String> {
     Ok(value as f32)
 }
 
-fn parse_allocation(input_yaml: &yaml_rust::Yaml) -> Result<portfolio::Allocation, String> {
-    let us_equities = parse_f32(input_yaml, "us_equities")?;
-    let international = parse_f32(input_yaml, "international")?;
-    let bonds = parse_f32(input_yaml, "bonds")?;
+fn parse_f32_or(yaml: &yaml_rust::Yaml, field_name: &str, default: f32) -> Result<f32, String> {
+    if yaml[field_name].is_badvalue() {
+        return Ok(default);
+    }
+    parse_f32(yaml, field_name)
+}
+
+fn parse_bool_or(yaml: &yaml_rust::Yaml, field_name: &str, default: bool) -> Result<bool, String> {
+    if yaml[field_name].is_badvalue() {
+        return Ok(default);
+    }
+    let value = yaml[field_name].as_bool()
+        .ok_or("Invalid value: ".to_string() + field_name)?;
+
+    Ok(value)
+}
+
+// the asset classes defined in the configuration are allocated by name
+fn parse_allocation(input_yaml: &yaml_rust::Yaml, asset_class_names: &[String]) -> Result<portfolio::Allocation, String> {
+    let mut weights = [0.0; portfolio::MAX_ASSET_CLASSES];
+    weights[portfolio::US_EQUITY] = parse_f32(input_yaml, "us_equities")?;
+    weights[portfolio::INTERNATIONAL_EQUITY] = parse_f32(input_yaml, "international")?;
+    weights[portfolio::BONDS] = parse_f32(input_yaml, "bonds")?;
+    weights[portfolio::CASH] = parse_f32_or(input_yaml, "cash", 0.0)?;
+    for (i, name) in asset_class_names.iter().enumerate().skip(portfolio::NUM_BUILT_IN_ASSET_CLASSES) {
+        weights[i] = parse_f32_or(input_yaml, name, 0.0)?;
+    }
+
+    Ok(portfolio::Allocation {weights})
+}
 
-    let allocation = portfolio::Allocation {
-        us_equities,
-        international,
-        bonds,
+// the allocations at ages of the first retiree, in order of age, and the
+// number of them. None of them if there's no glide path.
+fn parse_glide_path(input_yaml: &yaml_rust::Yaml,
+                    asset_class_names: &[String]) -> Result<([portfolio::GlidePoint; portfolio::MAX_GLIDE_POINTS], usize), String> {
+    let mut glide_path = [portfolio::GlidePoint::default(); portfolio::MAX_GLIDE_POINTS];
+    let Some(vec) = input_yaml.as_vec() else {
+        if !input_yaml.is_badvalue() {
+            return Err("Invalid value: glide_path".to_string());
+        }
+        return Ok((glide_path, 0));
     };
+    if vec.is_empty() || vec.len() > portfolio::MAX_GLIDE_POINTS {
+        return Err(format!("glide_path must have 1 to {} points", portfolio::MAX_GLIDE_POINTS));
+    }
+    for (i, element) in vec.iter().enumerate() {
+        let age = parse_u32(element, "age")? as f32;
+        if i > 0 && age <= glide_path[i - 1].age {
+            return Err("glide_path ages must be in increasing order".to_string());
+        }
+        glide_path[i] = portfolio::GlidePoint {age, allocation: parse_allocation(element, asset_class_names)?};
+    }
+    Ok((glide_path, vec.len()))
+}
 
-    Ok(allocation)
+// an asset class defined in the configuration and its name. Its returns are
+// made real like the built-in ones.
+fn parse_asset_class(input_yaml: &yaml_rust::Yaml,
+                     get_real_return: impl Fn(f32) -> f32) -> Result<(String, portfolio::AssetClass), String> {
+    let name = parse_string(input_yaml, "name")?;
+    // inflation-linked bonds earn their real yield in every simulation, so
+    // they have no other returns
+    if parse_bool_or(input_yaml, "inflation_linked", false)? {
+        if ["expected_returns", "standard_deviation", "historical"].iter().any(|v| !input_yaml[*v].is_badvalue()) {
+            return Err(format!("asset class {} is inflation_linked, it only has a real_yield", name));
+        }
+        let real_yield = parse_f32(input_yaml, "real_yield")?;
+        let asset_class = portfolio::AssetClass {
+            expected_returns: real_yield,
+            standard_deviation: 0.0,
+            dividend_yield: 0.0,
+            interest_yield: parse_f32_or(input_yaml, "interest_yield", real_yield)?,
+            historical: None,
+            inflation_linked: true,
+            expense_ratio: parse_f32_or(input_yaml, "expense_ratio", 0.0)?,
+        };
+        return Ok((name, asset_class));
+    }
+    let historical = match input_yaml["historical"].as_str() {
+        None => None,
+        Some(v) => Some(historical_scan::HistoricalColumn::parse(v)
+            .ok_or(format!("Invalid value: asset class {} historical {}", name, v))?),
+    };
+    let asset_class = portfolio::AssetClass {
+        expected_returns: get_real_return(parse_f32(input_yaml, "expected_returns")?),
+        standard_deviation: parse_f32_or(input_yaml, "standard_deviation", 0.0)?,
+        dividend_yield: parse_f32_or(input_yaml, "dividend_yield", 0.0)?,
+        interest_yield: parse_f32_or(input_yaml, "interest_yield", 0.0)?,
+        historical,
+        inflation_linked: false,
+        expense_ratio: parse_f32_or(input_yaml, "expense_ratio", 0.0)?,
+    };
+    if asset_class.standard_deviation < 0.0 {
+        return Err(format!("asset class {} standard_deviation can't be negative", name));
+    }
+    Ok((name, asset_class))
 }
-    
-fn parse_portfolio(input_yaml: &yaml_rust::Yaml) -> Result<Portfolio, String> {
-    let block = &input_yaml["portfolio"];
-    if block.is_badvalue() {
-        return Err("portfolio block missing".to_string());
+
+// the accounts in the portfolio block, empty if they aren't listed. Either
+// every account has an allocation or none does.
+fn parse_accounts(block: &yaml_rust::Yaml, asset_class_names: &[String]) -> Result<Vec<Account>, String> {
+    let Some(vec) = block["accounts"].as_vec() else {
+        if !block["accounts"].is_badvalue() {
+            return Err("Invalid value: accounts".to_string());
+        }
+        return Ok(Vec::new());
+    };
+    let mut accounts: Vec<Account> = Vec::new();
+    for element in vec {
+        let name = parse_string(element, "name")?;
+        if accounts.iter().any(|v| v.name == name) {
+            return Err(format!("account {} is listed twice", name));
+        }
+        if accounts.len() == portfolio::MAX_ACCOUNTS {
+            return Err(format!("at most {} accounts can be listed", portfolio::MAX_ACCOUNTS));
+        }
+        let account_type = match element["type"].as_str() {
+            Some("taxable") => AccountType::Taxable,
+            Some("tax_deferred") => AccountType::TaxDeferred,
+            Some("ira") => AccountType::Ira,
+            Some("roth") => AccountType::Roth,
+            Some("hsa") => AccountType::Hsa,
+            _ => return Err(format!("Invalid value: type of account {}", name)),
+        };
+        let balance = parse_f32(element, "balance")?;
+        if balance < 0.0 {
+            return Err(format!("account {} balance can't be negative", name));
+        }
+        let basis = parse_f32_or(element, "basis", 0.0)?;
+        if basis != 0.0 && account_type != AccountType::Ira {
+            return Err(format!("account {} can't have a basis, only an IRA has one", name));
+        }
+        if !(0.0..=balance).contains(&basis) {
+            return Err(format!("account {} basis must be 0 to its balance", name));
+        }
+        let allocation = match element["allocation"].is_badvalue() {
+            true => None,
+            false => Some(parse_allocation(&element["allocation"], asset_class_names)?),
+        };
+        if let Some(total) = allocation.map(|v| v.get_total()) {
+            if (total - 100.0).abs() > 0.01 {
+                return Err(format!("account {} allocation adds up to {:.1}% instead of 100%", name, total));
+            }
+        }
+        if accounts.first().is_some_and(|v| v.allocation.is_some() != allocation.is_some()) {
+            return Err("every account needs an allocation if any has one".to_string());
+        }
+        accounts.push(Account {name, account_type, balance, basis, allocation});
     }
+    Ok(accounts)
+}
 
-    let balance = parse_f32(block, "balance")?;
-    
-    let pre_retirement_block = &block["pre-retirement_allocation"];
-    if pre_retirement_block.is_badvalue() {
-        return Err("pre-retirement portfolio block missing".to_string());
+// the allocation of the accounts together, weighted by their balances. None
+// if there are none or they have no allocations.
+fn get_accounts_allocation(accounts: &[Account]) -> Option<portfolio::Allocation> {
+    let accounts: Vec<&Account> = accounts.iter().filter(|v| v.allocation.is_some()).collect();
+    if accounts.is_empty() {
+        return None;
     }
-    let pre_retirement_allocation = parse_allocation(&pre_retirement_block)?;
+    // accounts that are all empty count equally
+    let total: f32 = accounts.iter().map(|v| v.balance).sum();
+    let get_weight = |v: &Account| if total > 0.0 {v.balance / total} else {1.0 / accounts.len() as f32};
+    let mut weights = [0.0; portfolio::MAX_ASSET_CLASSES];
+    for account in accounts.iter() {
+        for (weight, account_weight) in weights.iter_mut().zip(account.allocation.unwrap().weights) {
+            *weight += account_weight * get_weight(account);
+        }
+    }
+    Some(portfolio::Allocation {weights})
+}
 
-    let post_retirement_block = &block["post-retirement_allocation"];
-    if post_retirement_block.is_badvalue() {
-        return Err("post-retirement portfolio block missing".to_string());
+// the portfolio and the name of each asset class
+fn parse_portfolio(input_yaml: &yaml_rust::Yaml) -> Result<(Portfolio, Vec<String>), String> {
+    let block = &input_yaml["portfolio"];
+    if block.is_badvalue() {
+        return Err("portfolio block missing".to_string());
     }
-    let post_retirement_allocation = parse_allocation(&post_retirement_block)?;
 
     let us_equity_expected_returns = parse_f32(block, "us_equity_expected_returns")?;
     let us_equity_standard_deviation = parse_f32(block, "us_equity_standard_deviation")?;
@@ -132,239 +1174,1666 @@ fn parse_portfolio(input_yaml: &yaml_rust::Yaml) -> Result<Portfolio, String> {
     let bonds_expected_returns = parse_f32(block, "bonds_expected_returns")?;
     let bonds_standard_deviation = parse_f32(block, "bonds_standard_deviation")?;
     let expected_inflation = parse_f32(block, "expected_inflation")?;
-
+    // everything is simulated in today's dollars, so nominal returns are
+    // turned into real ones. That's the same as growing the expenses and
+    // every other amount with inflation and deflating the results.
+    let nominal_returns = match block["expected_returns"].as_str() {
+        None | Some("real") => false,
+        Some("nominal") => true,
+        Some(v) => return Err(format!("Invalid value: expected_returns {}", v)),
+    };
+    let get_real_return = |returns: f32| match nominal_returns {
+        true => ((1.0 + returns / 100.0) / (1.0 + expected_inflation / 100.0) - 1.0) * 100.0,
+        false => returns,
+    };
+    let us_equity_expected_returns = get_real_return(us_equity_expected_returns);
+    let international_equity_expected_returns = get_real_return(international_equity_expected_returns);
+    let bonds_expected_returns = get_real_return(bonds_expected_returns);
+    // the default is the historical average of 3-month T-bills after
+    // inflation, which is already real
+    let cash_expected_returns = match block["cash_expected_returns"].is_badvalue() {
+        true => 0.3,
+        false => get_real_return(parse_f32(block, "cash_expected_returns")?),
+    };
+    let inflation_standard_deviation = parse_f32_or(block, "inflation_standard_deviation", 0.0)?;
+    let inflation_equity_correlation = parse_f32_or(block, "inflation_equity_correlation", -0.1)?;
+    let inflation_bonds_correlation = parse_f32_or(block, "inflation_bonds_correlation", -0.3)?;
+    // the asset classes are drawn independently, so the correlations with
+    // them can't explain more than all of inflation's variance
+    if 2.0 * inflation_equity_correlation.powi(2) + inflation_bonds_correlation.powi(2) > 1.0 {
+        return Err("inflation correlations are too strong, twice the equity correlation squared \
+                    plus the bonds correlation squared must be at most 1".to_string());
+    }
+
+    let mut asset_classes = [portfolio::AssetClass::default(); portfolio::MAX_ASSET_CLASSES];
+    asset_classes[portfolio::US_EQUITY] = portfolio::AssetClass {
+        expected_returns: us_equity_expected_returns,
+        standard_deviation: us_equity_standard_deviation,
+        dividend_yield: parse_f32_or(block, "us_equity_dividend_yield", 1.3)?,
+        interest_yield: 0.0,
+        historical: Some(historical_scan::HistoricalColumn::UsEquity),
+        inflation_linked: false,
+        expense_ratio: parse_f32_or(block, "us_equity_expense_ratio", 0.0)?,
+    };
+    asset_classes[portfolio::INTERNATIONAL_EQUITY] = portfolio::AssetClass {
+        expected_returns: international_equity_expected_returns,
+        standard_deviation: international_equity_standard_deviation,
+        dividend_yield: parse_f32_or(block, "international_equity_dividend_yield", 3.0)?,
+        interest_yield: 0.0,
+        historical: Some(historical_scan::HistoricalColumn::InternationalEquity),
+        inflation_linked: false,
+        expense_ratio: parse_f32_or(block, "international_equity_expense_ratio", 0.0)?,
+    };
+    asset_classes[portfolio::BONDS] = portfolio::AssetClass {
+        expected_returns: bonds_expected_returns,
+        standard_deviation: bonds_standard_deviation,
+        dividend_yield: 0.0,
+        interest_yield: parse_f32_or(block, "bonds_interest_yield", 4.0)?,
+        historical: Some(historical_scan::HistoricalColumn::Bonds),
+        inflation_linked: false,
+        expense_ratio: parse_f32_or(block, "bonds_expense_ratio", 0.0)?,
+    };
+    asset_classes[portfolio::CASH] = portfolio::AssetClass {
+        expected_returns: cash_expected_returns,
+        standard_deviation: 0.0,
+        dividend_yield: 0.0,
+        interest_yield: parse_f32_or(block, "cash_interest_yield", 4.0)?,
+        historical: Some(historical_scan::HistoricalColumn::Cash),
+        inflation_linked: false,
+        expense_ratio: parse_f32_or(block, "cash_expense_ratio", 0.0)?,
+    };
+    let mut asset_class_names: Vec<String> = portfolio::BUILT_IN_ASSET_CLASS_NAMES.iter().map(|v| v.to_string()).collect();
+    if let Some(vec) = block["asset_classes"].as_vec() {
+        if vec.len() > portfolio::MAX_ASSET_CLASSES - portfolio::NUM_BUILT_IN_ASSET_CLASSES {
+            return Err(format!("{} asset_classes are listed, but at most {} can be added to the {} built-in ones",
+                               vec.len(),
+                               portfolio::MAX_ASSET_CLASSES - portfolio::NUM_BUILT_IN_ASSET_CLASSES,
+                               portfolio::NUM_BUILT_IN_ASSET_CLASSES));
+        }
+        for element in vec {
+            let (name, asset_class) = parse_asset_class(element, get_real_return)?;
+            // the built-in ones are allocated as us_equities and international
+            if asset_class_names.contains(&name) || ["us_equities", "international"].contains(&name.as_str()) {
+                return Err(format!("asset class {} is already defined", name));
+            }
+            asset_classes[asset_class_names.len()] = asset_class;
+            asset_class_names.push(name);
+        }
+    }
+
+    let (glide_path, num_glide_points) = parse_glide_path(&block["glide_path"], &asset_class_names)?;
+    let accounts = parse_accounts(block, &asset_class_names)?;
+    // accounts with their own allocations grow with them for the whole plan,
+    // and together they're the allocation of any money in kinds of accounts
+    // that aren't listed
+    let accounts_allocation = get_accounts_allocation(&accounts);
+
+    // a glide path replaces the allocations before and after retirement,
+    // which are set to its ends when it's validated
+    let pre_retirement_block = &block["pre-retirement_allocation"];
+    let post_retirement_block = &block["post-retirement_allocation"];
+    let (pre_retirement_allocation, post_retirement_allocation) = if num_glide_points > 0 {
+        if !pre_retirement_block.is_badvalue() || !post_retirement_block.is_badvalue() {
+            return Err("glide_path replaces pre-retirement_allocation and post-retirement_allocation".to_string());
+        }
+        if accounts_allocation.is_some() {
+            return Err("glide_path can't be used with account allocations".to_string());
+        }
+        (portfolio::Allocation::default(), portfolio::Allocation::default())
+    }
+    else if let Some(allocation) = accounts_allocation {
+        if !pre_retirement_block.is_badvalue() || !post_retirement_block.is_badvalue() {
+            return Err("account allocations replace pre-retirement_allocation and post-retirement_allocation".to_string());
+        }
+        (allocation, allocation)
+    }
+    else {
+        if pre_retirement_block.is_badvalue() {
+            return Err("pre-retirement portfolio block missing".to_string());
+        }
+        if post_retirement_block.is_badvalue() {
+            return Err("post-retirement portfolio block missing".to_string());
+        }
+        (parse_allocation(pre_retirement_block, &asset_class_names)?,
+         parse_allocation(post_retirement_block, &asset_class_names)?)
+    };
+
+    let tax_loss_harvest_percent = parse_f32_or(block, "tax_loss_harvest_percent", 0.0)?;
+    let advisor_fee_percent = parse_f32_or(block, "advisor_fee_percent", 0.0)?;
+    if !(0.0..100.0).contains(&advisor_fee_percent) {
+        return Err("advisor_fee_percent must be at least 0 and less than 100".to_string());
+    }
+    if asset_classes.iter().any(|v| !(0.0..100.0).contains(&v.expense_ratio)) {
+        return Err("expense ratios must be at least 0 and less than 100".to_string());
+    }
+    let rebalance_gain_percent = parse_f32_or(block, "rebalance_gain_percent", 50.0)?;
+    if !(0.0..=100.0).contains(&rebalance_gain_percent) {
+        return Err("rebalance_gain_percent must be 0 to 100".to_string());
+    }
+    let rebalancing = match block["rebalancing"].as_str() {
+        None | Some("continuous") => Rebalancing::Continuous,
+        Some("cash_flow") => Rebalancing::CashFlow,
+        Some(v) => return Err(format!("Invalid value: rebalancing {}", v)),
+    };
+    let rebalance_band = parse_f32_or(block, "rebalance_band", 5.0)?;
+    if !(0.0..=100.0).contains(&rebalance_band) {
+        return Err("rebalance_band must be 0 to 100".to_string());
+    }
+    let cash_bucket_years = parse_f32_or(block, "cash_bucket_years", 0.0)?;
+    if cash_bucket_years < 0.0 {
+        return Err("cash_bucket_years can't be negative".to_string());
+    }
+    // the accounts are rebalanced continuously, each to its own allocation
+    if accounts_allocation.is_some() && (rebalancing == Rebalancing::CashFlow || cash_bucket_years > 0.0) {
+        return Err("account allocations can't be used with cash_flow rebalancing or cash_bucket_years".to_string());
+    }
+    let mut portfolio_accounts = [portfolio::Account::default(); portfolio::MAX_ACCOUNTS];
+    let mut num_accounts = 0;
+    for account in accounts.iter() {
+        if let Some(allocation) = account.allocation {
+            portfolio_accounts[num_accounts] = portfolio::Account {
+                account_type: account.account_type,
+                balance: account.balance,
+                allocation,
+            };
+            num_accounts += 1;
+        }
+    }
+    let (balance, taxable_balance, hsa_balance, roth_balance, ira_balance, ira_basis) = match accounts.is_empty() {
+        true => parse_balances(block)?,
+        false => {
+            for field in ["balance", "taxable_percent", "hsa_balance", "roth_balance", "ira_balance", "ira_basis"] {
+                if !block[field].is_badvalue() {
+                    return Err(format!("accounts replace {}", field));
+                }
+            }
+            let get_total = |account_type: AccountType, get_amount: fn(&Account) -> f32| -> f32 {
+                accounts.iter().filter(|v| v.account_type == account_type).map(get_amount).sum()
+            };
+            (accounts.iter().map(|v| v.balance).sum(),
+             get_total(AccountType::Taxable, |v| v.balance),
+             get_total(AccountType::Hsa, |v| v.balance),
+             get_total(AccountType::Roth, |v| v.balance),
+             get_total(AccountType::Ira, |v| v.balance),
+             get_total(AccountType::Ira, |v| v.basis))
+        },
+    };
+
     let portfolio = Portfolio {
         balance,
         pre_retirement_allocation,
         post_retirement_allocation,
-        us_equity_expected_returns,
-        us_equity_standard_deviation,
-        international_equity_expected_returns,
-        international_equity_standard_deviation,
-        bonds_expected_returns,
-        bonds_standard_deviation,
+        asset_classes,
+        num_asset_classes: asset_class_names.len(),
         expected_inflation,
+        nominal_returns,
+        inflation_standard_deviation,
+        inflation_equity_correlation,
+        inflation_bonds_correlation,
+        taxable_balance,
+        taxable_basis: 0.0,
+        hsa_balance,
+        roth_balance,
+        ira_balance,
+        ira_basis,
+        education_balance: 0.0,
+        tax_loss_harvest_percent,
+        rebalance_gain_percent,
+        rebalance_gains: 0.0,
+        advisor_fee_percent,
+        rebalancing,
+        rebalance_band,
+        holdings: [0.0; portfolio::MAX_ASSET_CLASSES],
+        cash_bucket_years,
+        cash_bucket: 0.0,
+        recent_growth: [1.0; 12],
+        glide_path,
+        num_glide_points,
+        age: 0.0,
+        accounts: portfolio_accounts,
+        num_accounts,
+        net_flows: 0.0,
     };
     
-    Ok(portfolio)
+    Ok((portfolio, asset_class_names))
+}
+
+// the balance and the part of it in each kind of account when the accounts
+// aren't listed: the balance, the taxable, HSA, Roth and IRA balances, and the
+// IRA basis
+fn parse_balances(block: &yaml_rust::Yaml) -> Result<(f32, f32, f32, f32, f32, f32), String> {
+    let balance = parse_f32(block, "balance")?;
+    let taxable_percent = parse_f32_or(block, "taxable_percent", 0.0)?;
+    let hsa_balance = parse_f32_or(block, "hsa_balance", 0.0)?;
+    let roth_balance = parse_f32_or(block, "roth_balance", 0.0)?;
+    if hsa_balance + roth_balance > balance {
+        return Err("hsa_balance and roth_balance can't be more than the balance".to_string());
+    }
+
+    let ira_balance = parse_f32_or(block, "ira_balance", 0.0)?;
+    let ira_basis = parse_f32_or(block, "ira_basis", 0.0)?;
+    let taxable_balance = (balance - hsa_balance - roth_balance) * taxable_percent / 100.0;
+    if ira_balance > balance - hsa_balance - roth_balance - taxable_balance {
+        return Err("ira_balance can't be more than the tax-deferred balance".to_string());
+    }
+    if ira_basis > ira_balance {
+        return Err("ira_basis can't be more than the ira_balance".to_string());
+    }
+    Ok((balance, taxable_balance, hsa_balance, roth_balance, ira_balance, ira_basis))
+}
+
+fn parse_annual_expense(input_yaml: &yaml_rust::Yaml) -> Result<AnnualExpense, String> {
+    let name = parse_string(input_yaml, "name")?;
+    let amount = parse_f32(input_yaml, "amount")?;
+    let month = parse_u32(input_yaml, "month")?;
+    if !(1..=12).contains(&month) {
+        return Err(format!("Invalid value: month {} of annual expense {}", month, name));
+    }
+
+    let expense = AnnualExpense {
+        amount,
+        month,
+    };
+
+    Ok(expense)
+}
+
+// the bridge_healthcare block is optional. The costs grow with the expected
+// inflation unless they have their own rate.
+fn parse_bridge_healthcare(input_yaml: &yaml_rust::Yaml, expected_inflation: f32) -> Result<Option<BridgeHealthcare>, String> {
+    let block = &input_yaml["bridge_healthcare"];
+    if block.is_badvalue() {
+        return Ok(None);
+    }
+    let monthly_premium = parse_f32(block, "monthly_premium")?;
+    let monthly_out_of_pocket = parse_f32_or(block, "monthly_out_of_pocket", 0.0)?;
+    if monthly_premium < 0.0 || monthly_out_of_pocket < 0.0 {
+        return Err("bridge_healthcare costs can't be negative".to_string());
+    }
+    Ok(Some(BridgeHealthcare {
+        monthly_premium,
+        monthly_out_of_pocket,
+        inflation: parse_f32_or(block, "inflation", expected_inflation)?,
+        aca_subsidy: parse_bool_or(block, "aca_subsidy", false)?,
+    }))
+}
+
+// the medicare block is optional. Part B defaults to the standard premium, and
+// the premiums grow with the expected inflation unless they have their own
+// rate.
+fn parse_medicare(input_yaml: &yaml_rust::Yaml, expected_inflation: f32) -> Result<Option<Medicare>, String> {
+    let block = &input_yaml["medicare"];
+    if block.is_badvalue() {
+        return Ok(None);
+    }
+    let medicare = Medicare {
+        part_b_monthly: parse_f32_or(block, "part_b_monthly", 185.0)?,
+        part_d_monthly: parse_f32_or(block, "part_d_monthly", 0.0)?,
+        medigap_monthly: parse_f32_or(block, "medigap_monthly", 0.0)?,
+        inflation: parse_f32_or(block, "inflation", expected_inflation)?,
+    };
+    if medicare.part_b_monthly < 0.0 || medicare.part_d_monthly < 0.0 || medicare.medigap_monthly < 0.0 {
+        return Err("medicare premiums can't be negative".to_string());
+    }
+    Ok(Some(medicare))
 }
 
-fn parse_expenses(input_yaml: &yaml_rust::Yaml) -> Result<Expenses, String> {
+fn parse_expenses(input_yaml: &yaml_rust::Yaml, expected_inflation: f32) -> Result<Expenses, String> {
     let block = &input_yaml["expenses"];
     if block.is_badvalue() {
         return Err("expenses block missing".to_string());
     }
 
-    let monthly = parse_f32(block, "monthly")?;
+    // the monthly amount is worked out from the pay later when the expenses
+    // are a replacement ratio
+    let (monthly, replacement_percent) = match (block["monthly"].is_badvalue(), block["replacement_percent"].is_badvalue()) {
+        (_, true) => (parse_f32(block, "monthly")?, None),
+        (true, false) => (0.0, Some(parse_f32(block, "replacement_percent")?)),
+        (false, false) => return Err("expenses can't have both monthly and replacement_percent".to_string()),
+    };
+    let healthcare_monthly = parse_f32_or(block, "healthcare_monthly", 0.0)?;
+    let essential_monthly = match block["essential_monthly"].is_badvalue() {
+        true => None,
+        false => Some(parse_f32(block, "essential_monthly")?),
+    };
+
+    let mut annual = Vec::new();
+    if let Some(vec) = block["annual"].as_vec() {
+        for element in vec {
+            annual.push(parse_annual_expense(element)?);
+        }
+    }
+    let annual_timing = match block["annual_timing"].as_str() {
+        None | Some("spread") => AnnualTiming::Spread,
+        Some("actual") => AnnualTiming::Actual,
+        Some(v) => return Err(format!("Invalid value: annual_timing {}", v)),
+    };
+    let survivor_percent = parse_f32_or(block, "survivor_expense_percent", 100.0)?;
+    if !(0.0..=100.0).contains(&survivor_percent) {
+        return Err("survivor_expense_percent must be between 0 and 100".to_string());
+    }
 
     let expenses = Expenses {
         monthly,
+        healthcare_monthly,
+        essential_monthly,
+        annual,
+        annual_timing,
+        replacement_percent,
+        survivor_percent,
+        bridge_healthcare: parse_bridge_healthcare(block, expected_inflation)?,
+        medicare: parse_medicare(block, expected_inflation)?,
     };
     
     Ok(expenses)
 }
 
-fn parse_retiree(input_yaml: &yaml_rust::Yaml) -> Result<Retiree, String> {
-    let name = parse_string(input_yaml, "name")?;
-    let life_expectency = parse_u32(input_yaml, "life_expectency")?;
-    let retirement_age = parse_u32(input_yaml, "retirement_age")?;
+fn parse_education_expense(input_yaml: &yaml_rust::Yaml) -> Result<EducationExpense, String> {
+    let year = parse_u32(input_yaml, "year")? as i32;
+    let years = match input_yaml["years"].is_badvalue() {
+        true => 1,
+        false => parse_u32(input_yaml, "years")?,
+    };
+    let annual_amount = parse_f32(input_yaml, "annual_amount")?;
 
-    let salary_annual = parse_f32(input_yaml, "wage_annual_salary")?;
-    let retirement_contribution_percent = parse_f32(input_yaml, "retirement_contribution_percent")?;
-    let social_security_age = parse_u32(input_yaml, "social_security_age")?;
-    let pension_age = parse_u32(input_yaml, "pension_age")?;
-    let pension_monthly_income = parse_f32(input_yaml, "pension_monthly_income")?;
-    let other_monthly_retirement_income = parse_f32(input_yaml, "other_monthly_retirement_income")?;
-    let social_security_amount_early = parse_f32(input_yaml, "social_security_amount_early")?;
-    let social_security_amount_full = parse_f32(input_yaml, "social_security_amount_full")?;
-    let social_security_amount_delayed = parse_f32(input_yaml, "social_security_amount_delayed")?;
+    let expense = EducationExpense {
+        year,
+        years,
+        annual_amount,
+    };
 
-    let date_of_birth = parse_string(input_yaml, "date_of_birth")?;
-    let date_of_birth = NaiveDate::parse_from_str(&date_of_birth, "%m/%d/%Y").map_err(|_| "Invalid date")?;
-    
-    let retiree = Retiree {
-        name,
-        date_of_birth,
-        life_expectency,
-        retirement_age,
-        salary_annual,
-        retirement_contribution_percent,
-        social_security_age,
-        pension_age,
-        pension_monthly_income,
-        other_monthly_retirement_income,
-        social_security_amount_early,
-        social_security_amount_full,
-        social_security_amount_delayed,
+    Ok(expense)
+}
+
+// the education block is optional
+fn parse_education(input_yaml: &yaml_rust::Yaml) -> Result<Education, String> {
+    let block = &input_yaml["education"];
+
+    let balance_529 = parse_f32_or(block, "balance_529", 0.0)?;
+    let mut expenses = Vec::new();
+    if let Some(vec) = block["expenses"].as_vec() {
+        for element in vec {
+            expenses.push(parse_education_expense(element)?);
+        }
+    }
+
+    let education = Education {
+        balance_529,
+        expenses,
     };
-    
-    Ok(retiree)
+
+    Ok(education)
 }
 
-fn parse_retirees(input_yaml: &yaml_rust::Yaml) -> Result<Vec<Retiree>, String> {
-    let mut retirees = Vec::new();
-    let block = &input_yaml["retirees"];
+// the relocation block is optional
+fn parse_relocation(input_yaml: &yaml_rust::Yaml) -> Result<Option<Relocation>, String> {
+    let block = &input_yaml["relocation"];
     if block.is_badvalue() {
-        return Err("retirees block missing".to_string());
+        return Ok(None);
     }
 
-    let vec = block.as_vec().ok_or("no retirees found")?;
-    for element in vec {
-        let retiree = parse_retiree(element);
-        match retiree {
-            Ok(v) => retirees.push(v),
-            Err(e) => return Err(e),
-        };
-    }
+    let age = parse_u32(block, "age")?;
+    let monthly_expenses = match block["monthly_expenses"].is_badvalue() {
+        true => None,
+        false => Some(parse_f32(block, "monthly_expenses")?),
+    };
+    let state_rate = match block["state_rate"].is_badvalue() {
+        true => None,
+        false => Some(parse_f32(block, "state_rate")?),
+    };
+    let home_equity = parse_f32_or(block, "home_equity", 0.0)?;
 
-    Ok(retirees)
+    let relocation = Relocation {
+        age,
+        monthly_expenses,
+        state_rate,
+        home_equity,
+    };
+
+    Ok(Some(relocation))
 }
 
-fn parse_tax_rate(input_yaml: &yaml_rust::Yaml) -> Result<TaxLevel, String> {
-    let income = parse_f32(input_yaml, "income")?;
-    let rate = parse_f32(input_yaml, "rate")?;
+fn parse_downsize(input_yaml: &yaml_rust::Yaml) -> Result<Option<Downsize>, String> {
+    let block = &input_yaml["downsize"];
+    if block.is_badvalue() {
+        return Ok(None);
+    }
 
-    let tax_rate = TaxLevel {
-        income,
-        rate,
+    let age = parse_u32(block, "age")?;
+    let new_value = parse_f32(block, "new_value")?;
+    let selling_costs_percent = parse_f32_or(block, "selling_costs_percent", 6.0)?;
+    if !(0.0..=100.0).contains(&selling_costs_percent) {
+        return Err("downsize selling_costs_percent must be 0 to 100".to_string());
+    }
+    let monthly_expenses = match block["monthly_expenses"].is_badvalue() {
+        true => None,
+        false => Some(parse_f32(block, "monthly_expenses")?),
     };
 
-    Ok(tax_rate)
+    let downsize = Downsize {
+        age,
+        new_value,
+        selling_costs_percent,
+        monthly_expenses,
+    };
+
+    Ok(Some(downsize))
 }
-    
-fn parse_tax_rates(input_yaml: &yaml_rust::Yaml) -> Result<TaxRates, String> {
-    let mut tax_levels = Vec::new();
-    let block = &input_yaml["tax_rates"];
+
+fn parse_reverse_mortgage(input_yaml: &yaml_rust::Yaml) -> Result<Option<ReverseMortgage>, String> {
+    let block = &input_yaml["reverse_mortgage"];
     if block.is_badvalue() {
-        return Err("tax_rates block missing".to_string());
+        return Ok(None);
     }
 
-    let standard_deduction = parse_f32(block, "standard_deduction")?;
+    let age = parse_u32_or(block, "age", 62)?;
+    if age < 62 {
+        return Err("reverse_mortgage age must be at least 62".to_string());
+    }
+    let credit_limit = parse_f32(block, "credit_limit")?;
+    if credit_limit < 0.0 {
+        return Err("reverse_mortgage credit_limit can't be negative".to_string());
+    }
+    let growth_rate = parse_f32_or(block, "growth_rate", 0.0)?;
 
-    let block = &block["levels"];
+    let reverse_mortgage = ReverseMortgage {
+        age,
+        credit_limit,
+        growth_rate,
+    };
+
+    Ok(Some(reverse_mortgage))
+}
+
+// the home block is optional
+fn parse_home(input_yaml: &yaml_rust::Yaml) -> Result<Option<Home>, String> {
+    let block = &input_yaml["home"];
     if block.is_badvalue() {
-        return Err("levels block missing".to_string());
+        return Ok(None);
     }
 
-    tax_levels.push( TaxLevel {income: 0.0, rate: 0.0});
-    let vec = block.as_vec().ok_or("no tax rates found")?;
-    for element in vec {
-        let tax_rate = parse_tax_rate(element);
-        match tax_rate {
-            Ok(v) => tax_levels.push(v),
-            Err(e) => return Err(e),
-        };
+    let value = parse_f32(block, "value")?;
+    if value <= 0.0 {
+        return Err("home value must be more than 0".to_string());
+    }
+    let appreciation = parse_f32_or(block, "appreciation", 0.0)?;
+    let property_tax_rate = parse_f32_or(block, "property_tax_rate", 0.0)?;
+    let insurance_annual = parse_f32_or(block, "insurance_annual", 0.0)?;
+    let downsize = parse_downsize(block)?;
+    let reverse_mortgage = parse_reverse_mortgage(block)?;
+    if downsize.is_some() && reverse_mortgage.is_some() {
+        return Err("home can't have both a downsize and a reverse_mortgage".to_string());
     }
 
-    //for (i, tax_rate) in tax_rates.iter().enumerate() {
-    for i in 1..tax_levels.len() {
-        if i < tax_levels.len() - 1 {
-            tax_levels[i].income = tax_levels[i + 1].income - 1.0;
-        }
-        else {
-            tax_levels[i].income = f32::MAX;
-        }
+    let home = Home {
+        value,
+        appreciation,
+        property_tax_rate,
+        insurance_annual,
+        downsize,
+        reverse_mortgage,
+    };
+
+    Ok(Some(home))
+}
+
+fn parse_windfall(input_yaml: &yaml_rust::Yaml) -> Result<Windfall, String> {
+    let name = parse_string(input_yaml, "name")?;
+    let amount = parse_f32(input_yaml, "amount")?;
+    let date = parse_date_or_none(input_yaml, "date")?.ok_or("Invalid value: date")?;
+    let probability = parse_f32_or(input_yaml, "probability", 100.0)?;
+    if !(0.0..=100.0).contains(&probability) {
+        return Err(format!("windfall {} probability must be 0 to 100", name));
     }
 
-    let tax_rates = TaxRates {
-        standard_deduction,
-        tax_levels,
+    let windfall = Windfall {
+        name,
+        amount,
+        date,
+        probability,
     };
 
-    Ok(tax_rates)
+    Ok(windfall)
 }
 
-fn parse_input_file(fname: &str) -> Result<Input, String> {
-    let file_str = fs::read_to_string(fname).unwrap();
-    
-    let docs = YamlLoader::load_from_str(&file_str).unwrap();
-    let doc = &docs[0];
+// the windfalls list is optional
+fn parse_windfalls(input_yaml: &yaml_rust::Yaml) -> Result<Vec<Windfall>, String> {
+    let mut windfalls = Vec::new();
+    if let Some(vec) = input_yaml["windfalls"].as_vec() {
+        for element in vec {
+            windfalls.push(parse_windfall(element)?);
+        }
+    }
+    Ok(windfalls)
+}
 
-    // Dump the YAML object
-    let mut out_str = String::new();
-    {
-        let mut emitter = YamlEmitter::new(&mut out_str);
-        emitter.dump(doc).unwrap(); // dump the YAML object to a String
-        // println!("{out_str}");
+// the date is either a date or an age of the first retiree
+fn parse_one_time_expense(input_yaml: &yaml_rust::Yaml, date_of_birth: &NaiveDate) -> Result<OneTimeExpense, String> {
+    let name = parse_string(input_yaml, "name")?;
+    let amount = parse_f32(input_yaml, "amount")?;
+    if amount < 0.0 {
+        return Err(format!("one-time expense {} amount can't be negative", name));
+    }
+    let date = match (input_yaml["date"].is_badvalue(), input_yaml["age"].is_badvalue()) {
+        (false, true) => parse_date_or_none(input_yaml, "date")?.ok_or("Invalid value: date")?,
+        (true, false) => utils::add_years(date_of_birth, parse_u32(input_yaml, "age")?),
+        _ => return Err(format!("one-time expense {} needs either a date or an age", name)),
+    };
+    let inflation_adjusted = parse_bool_or(input_yaml, "inflation_adjusted", true)?;
+    let every_years = match input_yaml["every_years"].is_badvalue() {
+        true => None,
+        false => Some(parse_u32(input_yaml, "every_years")?),
+    };
+    if every_years == Some(0) {
+        return Err(format!("one-time expense {} every_years must be at least 1", name));
     }
 
-    let portfolio = parse_portfolio(&doc)?;
-    let expenses = parse_expenses(&doc)?;
-    let retirees = parse_retirees(&doc)?;
-    let mut tax_rates = parse_tax_rates(&doc)?;
-    tax_rates.tax_levels.sort_unstable_by_key(|e| e.income as u32);
-    
-    let input = Input {
-        retirees,
-        portfolio,
-        expenses,
-        tax_rates,
+    let expense = OneTimeExpense {
+        name,
+        amount,
+        date,
+        inflation_adjusted,
+        every_years,
     };
 
-    Ok(input)
-        
+    Ok(expense)
 }
 
-///////////////////////////////////////////////////////////////////////////
-// Output results
-///////////////////////////////////////////////////////////////////////////
-
-fn format_table(table: Vec<Vec<String>>) -> String {
-    if table.is_empty() {
-        return "".to_string();
+// the tracking block is optional
+fn parse_tracking(input_yaml: &yaml_rust::Yaml, configured_balance: f32) -> Result<Option<Tracking>, String> {
+    let block = &input_yaml["tracking"];
+    if block.is_badvalue() {
+        return Ok(None);
     }
-    
-    // find max len of each column
-    let mut col_size: Vec<usize> = vec![0; table[0].len()];
-    for row in table.iter() {
-        for (i, cell) in row.iter().enumerate() {
-            if cell.len() > col_size[i] {
-                col_size[i] = cell.len();
-            }
-        }
+    let actuals_file = match &block["actuals_file"] {
+        yaml_rust::Yaml::BadValue => None,
+        _ => Some(parse_string(block, "actuals_file")?),
+    };
+    let success_floor = match &block["success_floor"] {
+        yaml_rust::Yaml::BadValue => None,
+        _ => Some(parse_f32(block, "success_floor")?),
+    };
+    if success_floor.is_some_and(|v| !(0.0..=100.0).contains(&v)) {
+        return Err("success_floor must be between 0 and 100".to_string());
     }
+    Ok(Some(Tracking {actuals_file, latest_balance: None, configured_balance, success_floor}))
+}
 
-    // format table
-    let mut str = String::new();
-    for row in table.iter() {
-        for (i, cell) in row.iter().enumerate() {
-            str.push_str(&format!("{:>width$} ", cell, width = col_size[i]));
+// the one_time_expenses list is optional
+fn parse_one_time_expenses(input_yaml: &yaml_rust::Yaml, date_of_birth: &NaiveDate) -> Result<Vec<OneTimeExpense>, String> {
+    let mut expenses = Vec::new();
+    if let Some(vec) = input_yaml["one_time_expenses"].as_vec() {
+        for element in vec {
+            expenses.push(parse_one_time_expense(element, date_of_birth)?);
         }
-        str.push_str("\n");
-    } 
+    }
+    Ok(expenses)
+}
 
-    str
+fn parse_rental_property(input_yaml: &yaml_rust::Yaml) -> Result<RentalProperty, String> {
+    let name = parse_string(input_yaml, "name")?;
+    let value = parse_f32(input_yaml, "value")?;
+    let appreciation = parse_f32_or(input_yaml, "appreciation", 0.0)?;
+    let monthly_rent = parse_f32(input_yaml, "monthly_rent")?;
+    let sale_year = match input_yaml["sale_year"].is_badvalue() {
+        true => None,
+        false => Some(parse_u32(input_yaml, "sale_year")? as i32),
+    };
+    let basis = parse_f32_or(input_yaml, "basis", value)?;
+    if value < 0.0 || basis < 0.0 {
+        return Err(format!("rental property {} value and basis can't be negative", name));
+    }
+
+    let property = RentalProperty {
+        name,
+        value,
+        appreciation,
+        monthly_rent,
+        sale_year,
+        basis,
+    };
+
+    Ok(property)
 }
 
-fn num_with_commas(num: u64) -> String
-{
-    num.to_formatted_string(&Locale::en)
+// the rental_properties list is optional
+fn parse_rental_properties(input_yaml: &yaml_rust::Yaml) -> Result<Vec<RentalProperty>, String> {
+    let mut properties = Vec::new();
+    if let Some(vec) = input_yaml["rental_properties"].as_vec() {
+        for element in vec {
+            properties.push(parse_rental_property(element)?);
+        }
+    }
+    Ok(properties)
 }
 
-fn print_simulation_results(simulation_results: &simulate::SimulationResults) {
-    let mut retire_printed = false;
+// the analysis block is optional, every value has a default
+fn parse_analysis(input_yaml: &yaml_rust::Yaml, retirees: &[Retiree]) -> Result<Analysis, String> {
+    let block = &input_yaml["analysis"];
 
-    let mut table: Vec<Vec<String>> = Vec::new();
+    let target_success_rate = parse_f32_or(block, "target_success_rate", 90.0)?;
+    let early_retirement_years = parse_u32_or(block, "early_retirement_years", 0)?;
+    let early_retirement_benefit_reduction = parse_f32_or(block, "early_retirement_benefit_reduction", 2.0)?;
+    if !(0.0..=100.0).contains(&early_retirement_benefit_reduction) {
+        return Err("early_retirement_benefit_reduction must be 0 to 100".to_string());
+    }
+    let retirement_curve_months = match block["retirement_curve"].as_str() {
+        None => 0,
+        Some("monthly") => 1,
+        Some("quarterly") => 3,
+        Some(v) => return Err(format!("Invalid value: retirement_curve {}", v)),
+    };
+    let retirement_curve_years = parse_u32_or(block, "retirement_curve_years", 5)?;
+    if retirement_curve_years == 0 {
+        return Err("retirement_curve_years must be at least 1".to_string());
+    }
+    let decade_presets = parse_bool_or(block, "decade_presets", false)?;
+    let perpetuity = parse_bool_or(block, "perpetuity", false)?;
+    let fixed_income_warning_percent = parse_f32_or(block, "fixed_income_warning_percent", 50.0)?;
+    if !(0.0..=100.0).contains(&fixed_income_warning_percent) {
+        return Err("fixed_income_warning_percent must be 0 to 100".to_string());
+    }
 
-    let heading = vec!["".to_string(), "Year".to_string(), "Age".to_string(),
-                       "Balance".to_string(), "Expenses".to_string(),
-                       "Income".to_string(), "Tax".to_string(),
-                       "Rate".to_string(), "Draw".to_string(),
-                       "Yield".to_string(), "".to_string()];
-                       table.push(heading);
-    
-    for (i, monthly_snapshot) in simulation_results.monthly_snapshot.iter().enumerate() {
-        if (i % 12) == 0 {
-            let mut row: Vec<String> = Vec::new();
-            
-            let age = utils::get_age(&simulation_results.retirees[0].date_of_birth, &monthly_snapshot.date);
-            row.push((i / 12).to_string());
-            row.push(monthly_snapshot.date.format("%Y").to_string());
-            row.push(age.to_string());
-            row.push(num_with_commas(monthly_snapshot.balance as u64));
-            row.push(format!("{:.0}", monthly_snapshot.expenses));
+    let crash = parse_crash(&block["crash"])?;
+    let mut stress_tests = Vec::new();
+    if let Some(vec) = block["stress_tests"].as_vec() {
+        for element in vec {
+            stress_tests.push(parse_stress_test(element)?);
+        }
+    }
+    let longevity_scenarios = parse_longevity_scenarios(&block["longevity_scenarios"], retirees)?;
+    let benchmarks = parse_bool_or(block, "benchmarks", true)?;
+    let asset_location = parse_bool_or(block, "asset_location", false)?;
+
+    let analysis = Analysis {
+        target_success_rate,
+        early_retirement_years,
+        early_retirement_benefit_reduction,
+        retirement_curve_months,
+        retirement_curve_years,
+        decade_presets,
+        perpetuity,
+        fixed_income_warning_percent,
+        crash,
+        stress_tests,
+        longevity_scenarios,
+        benchmarks,
+        asset_location,
+    };
+
+    Ok(analysis)
+}
+
+// longevity_scenarios is either true, for everyone living to 85, each
+// retiree on their own living to 95, and everyone living to 100, or a list
+// of scenarios. Each scenario is an age for everyone or a list with an age
+// for each retiree.
+fn parse_longevity_scenarios(value: &yaml_rust::Yaml, retirees: &[Retiree]) -> Result<Vec<Vec<u32>>, String> {
+    let mut scenarios = Vec::new();
+    if let Some(vec) = value.as_vec() {
+        for element in vec {
+            let ages = match (element.as_i64(), element.as_vec()) {
+                (Some(age), _) => vec![age as u32; retirees.len()],
+                (None, Some(ages)) if ages.len() == retirees.len() => ages.iter()
+                    .map(|v| v.as_i64().map(|v| v as u32).ok_or("Invalid value: longevity_scenarios".to_string()))
+                    .collect::<Result<Vec<_>, _>>()?,
+                (None, Some(_)) => return Err("longevity_scenarios need an age for each retiree".to_string()),
+                _ => return Err("Invalid value: longevity_scenarios".to_string()),
+            };
+            scenarios.push(ages);
+        }
+    }
+    else if value.as_bool() == Some(true) {
+        scenarios.push(vec![85; retirees.len()]);
+        if retirees.len() > 1 {
+            for i in 0..retirees.len() {
+                let mut ages = vec![85; retirees.len()];
+                ages[i] = 95;
+                scenarios.push(ages);
+            }
+        }
+        else {
+            scenarios.push(vec![95]);
+        }
+        scenarios.push(vec![100; retirees.len()]);
+    }
+    else if !value.is_badvalue() && value.as_bool().is_none() {
+        return Err("Invalid value: longevity_scenarios".to_string());
+    }
+    for ages in scenarios.iter() {
+        for (retiree, age) in retirees.iter().zip(ages.iter()) {
+            if *age <= retiree.retirement_age {
+                return Err(format!("longevity_scenarios age {} must be after {}'s retirement age", age, retiree.name));
+            }
+        }
+    }
+    Ok(scenarios)
+}
+
+// the crash block is optional, each asset class falls by a percentage
+fn parse_crash(block: &yaml_rust::Yaml) -> Result<Crash, String> {
+    let crash = Crash {
+        us_equity: parse_f32_or(block, "us_equity_percent", -40.0)?,
+        international_equity: parse_f32_or(block, "international_equity_percent", -40.0)?,
+        bonds: parse_f32_or(block, "bonds_percent", -10.0)?,
+    };
+    if [crash.us_equity, crash.international_equity, crash.bonds].iter().any(|v| !(-100.0..=0.0).contains(v)) {
+        return Err("crash percentages must be -100 to 0".to_string());
+    }
+    Ok(crash)
+}
+
+fn parse_stress_test(input_yaml: &yaml_rust::Yaml) -> Result<StressTest, String> {
+    let name = input_yaml["name"].as_str().map(|v| v.to_string());
+    let event = match input_yaml["kind"].as_str() {
+        Some("expense") => {
+            let amount = parse_f32(input_yaml, "amount")?;
+            if amount < 0.0 {
+                return Err("stress test expense amount can't be negative".to_string());
+            }
+            let year = match input_yaml["year"].is_badvalue() {
+                true => None,
+                false => Some(parse_u32(input_yaml, "year")? as i32),
+            };
+            StressEvent::Expense {amount, year}
+        },
+        Some("crash") => StressEvent::Crash(parse_crash(input_yaml)?),
+        Some("inflation") => {
+            let shock = InflationShock {
+                rate: parse_f32_or(input_yaml, "rate", 7.4)?,
+                years: parse_u32_or(input_yaml, "years", 10)?,
+            };
+            if shock.years == 0 {
+                return Err("stress test inflation years must be at least 1".to_string());
+            }
+            StressEvent::Inflation(shock)
+        },
+        Some(v) => return Err(format!("Invalid value: stress test kind {}", v)),
+        None => return Err("Invalid value: stress test kind".to_string()),
+    };
+    Ok(StressTest {name, event})
+}
+
+// the simulation block is optional. time_step is either monthly (the
+// default) or annual, which is faster but less accurate.
+fn parse_simulation_settings(input_yaml: &yaml_rust::Yaml) -> Result<SimulationSettings, String> {
+    let block = &input_yaml["simulation"];
+
+    let months_per_step = match block["time_step"].as_str() {
+        None | Some("monthly") => 1,
+        Some("annual") => 12,
+        Some(v) => return Err(format!("Invalid value: time_step {}", v)),
+    };
+
+    let allocation_check = match block["allocation_check"].as_str() {
+        None | Some("normalize") => AllocationCheck::Normalize,
+        Some("strict") => AllocationCheck::Strict,
+        Some(v) => return Err(format!("Invalid value: allocation_check {}", v)),
+    };
+
+    // without the parametric feature the returns can only be bootstrapped
+    let monte_carlo = match block["monte_carlo"].as_str() {
+        None if !cfg!(feature = "parametric") => MonteCarloMethod::Bootstrap,
+        Some("parametric") if !cfg!(feature = "parametric") => {
+            return Err("monte_carlo: parametric needs the parametric feature".to_string());
+        },
+        None | Some("parametric") => MonteCarloMethod::Parametric,
+        Some("bootstrap") => MonteCarloMethod::Bootstrap,
+        Some(v) => return Err(format!("Invalid value: monte_carlo {}", v)),
+    };
+
+    let mortality = match block["mortality"].as_str() {
+        None | Some("fixed") => Mortality::Fixed,
+        Some("life_table") => Mortality::LifeTable,
+        Some(v) => return Err(format!("Invalid value: mortality {}", v)),
+    };
+
+    let settings = SimulationSettings {
+        months_per_step,
+        allocation_check,
+        monte_carlo,
+        mortality,
+        check_invariants: false,
+        inflation_shock: None,
+    };
+
+    Ok(settings)
+}
+
+// optional date in mm/dd/yyyy
+fn parse_date_or_none(yaml: &yaml_rust::Yaml, field_name: &str) -> Result<Option<NaiveDate>, String> {
+    if yaml[field_name].is_badvalue() {
+        return Ok(None);
+    }
+    let value = parse_string(yaml, field_name)?;
+    let date = NaiveDate::parse_from_str(&value, "%m/%d/%Y")
+        .map_err(|_| format!("Invalid date: {} {}", field_name, value))?;
+    Ok(Some(date))
+}
+
+fn parse_part_time_work(input_yaml: &yaml_rust::Yaml) -> Result<PartTimeWork, String> {
+    let start_date = parse_date_or_none(input_yaml, "start_date")?.ok_or("Invalid value: start_date")?;
+    let end_date = parse_date_or_none(input_yaml, "end_date")?.ok_or("Invalid value: end_date")?;
+    if end_date < start_date {
+        return Err("part_time end_date is before its start_date".to_string());
+    }
+    let monthly_income = parse_f32(input_yaml, "monthly_income")?;
+
+    let part_time = PartTimeWork {
+        start_date,
+        end_date,
+        monthly_income,
+    };
+
+    Ok(part_time)
+}
+
+fn parse_compensation(input_yaml: &yaml_rust::Yaml) -> Result<Compensation, String> {
+    let date = parse_date_or_none(input_yaml, "date")?.ok_or("Invalid value: date")?;
+    let amount = parse_f32(input_yaml, "amount")?;
+    let repeat_months = parse_u32_or(input_yaml, "repeat_months", 0)?;
+    let end_date = match repeat_months {
+        0 => date,
+        _ => parse_date_or_none(input_yaml, "end_date")?.ok_or("compensation with repeat_months needs an end_date")?,
+    };
+    if end_date < date {
+        return Err("compensation end_date is before its date".to_string());
+    }
+    let tax_rate = match input_yaml["tax_rate"].is_badvalue() {
+        true => None,
+        false => Some(parse_f32(input_yaml, "tax_rate")?),
+    };
+    let savings_percent = parse_f32_or(input_yaml, "savings_percent", 0.0)?;
+    if !(0.0..=100.0).contains(&savings_percent) {
+        return Err("compensation savings_percent must be 0 to 100".to_string());
+    }
+
+    let compensation = Compensation {
+        date,
+        amount,
+        repeat_months,
+        end_date,
+        tax_rate,
+        savings_percent,
+    };
+
+    Ok(compensation)
+}
+
+fn parse_employer_match(input_yaml: &yaml_rust::Yaml) -> Result<Option<EmployerMatch>, String> {
+    let block = &input_yaml["employer_match"];
+    if block.is_badvalue() {
+        return Ok(None);
+    }
+
+    let percent = parse_f32(block, "percent")?;
+    let limit_percent = parse_f32(block, "limit_percent")?;
+    let hire_date = parse_date_or_none(block, "hire_date")?;
+    let vesting = match block["vesting"].as_str() {
+        None | Some("immediate") => Vesting::Immediate,
+        Some("cliff") => Vesting::Cliff,
+        Some("graded") => Vesting::Graded,
+        Some(v) => return Err(format!("Invalid value: vesting {}", v)),
+    };
+    let vesting_years = parse_u32_or(block, "vesting_years", 0)?;
+    if vesting != Vesting::Immediate && (hire_date.is_none() || vesting_years == 0) {
+        return Err("cliff and graded vesting need a hire_date and vesting_years".to_string());
+    }
+
+    let employer_match = EmployerMatch {
+        percent,
+        limit_percent,
+        hire_date,
+        vesting,
+        vesting_years,
+    };
+
+    Ok(Some(employer_match))
+}
+
+// the annuity block is optional
+fn parse_annuity(input_yaml: &yaml_rust::Yaml) -> Result<Option<Annuity>, String> {
+    let block = &input_yaml["annuity"];
+    if block.is_badvalue() {
+        return Ok(None);
+    }
+
+    let premium = parse_f32_or(block, "premium", 0.0)?;
+    let premium_percent = parse_f32_or(block, "premium_percent", 0.0)?;
+    if (premium > 0.0) == (premium_percent > 0.0) {
+        return Err("annuity needs either a premium or a premium_percent".to_string());
+    }
+    if premium_percent > 100.0 {
+        return Err("annuity premium_percent can't be more than 100".to_string());
+    }
+    let age = match block["age"].is_badvalue() {
+        true => None,
+        false => Some(parse_u32(block, "age")?),
+    };
+    let monthly_income = parse_f32_or(block, "monthly_income", 0.0)?;
+    let payout_percent = parse_f32_or(block, "payout_percent", 0.0)?;
+    if (monthly_income > 0.0) == (payout_percent > 0.0) {
+        return Err("annuity needs either a monthly_income or a payout_percent".to_string());
+    }
+    if premium_percent > 0.0 && monthly_income > 0.0 {
+        return Err("annuity with a premium_percent needs a payout_percent".to_string());
+    }
+    let cola = parse_bool_or(block, "cola", false)?;
+    let qlac = parse_bool_or(block, "qlac", false)?;
+    let qualified = parse_bool_or(block, "qualified", qlac)?;
+    if qlac && !qualified {
+        return Err("a QLAC is always qualified".to_string());
+    }
+    if qlac && premium > QLAC_LIMIT {
+        return Err(format!("annuity premium is over the QLAC limit of ${}", num_with_commas(QLAC_LIMIT as u64)));
+    }
+    match age {
+        Some(age) if qlac && age > QLAC_MAX_AGE => return Err(format!("QLAC payments must start by age {}", QLAC_MAX_AGE)),
+        None if qlac => return Err("a QLAC needs the age its payments start".to_string()),
+        _ => {},
+    }
+
+    let annuity = Annuity {
+        premium,
+        premium_percent,
+        age,
+        monthly_income,
+        payout_percent,
+        cola,
+        qualified,
+        qlac,
+    };
+
+    Ok(Some(annuity))
+}
+
+// the employer_stock block is optional
+fn parse_employer_stock(input_yaml: &yaml_rust::Yaml) -> Result<Option<EmployerStock>, String> {
+    let block = &input_yaml["employer_stock"];
+    if block.is_badvalue() {
+        return Ok(None);
+    }
+
+    let value = parse_f32(block, "value")?;
+    let basis = parse_f32(block, "basis")?;
+    if value <= 0.0 {
+        return Err("employer_stock value must be more than 0".to_string());
+    }
+    if !(0.0..=value).contains(&basis) {
+        return Err("employer_stock basis must be 0 to the value".to_string());
+    }
+    let nua = parse_bool_or(block, "nua", false)?;
+
+    let employer_stock = EmployerStock {
+        value,
+        basis,
+        nua,
+    };
+
+    Ok(Some(employer_stock))
+}
+
+// the bonus block is optional
+fn parse_bonus(input_yaml: &yaml_rust::Yaml) -> Result<Option<Bonus>, String> {
+    let block = &input_yaml["bonus"];
+    if block.is_badvalue() {
+        return Ok(None);
+    }
+
+    let amount = parse_f32_or(block, "amount", 0.0)?;
+    let percent = parse_f32_or(block, "percent", 0.0)?;
+    let month = match block["month"].is_badvalue() {
+        true => 12,
+        false => parse_u32(block, "month")?,
+    };
+    if !(1..=12).contains(&month) {
+        return Err(format!("Invalid value: bonus month {}", month));
+    }
+    let contribution_percent = parse_f32_or(block, "contribution_percent", 0.0)?;
+    let tax_rate = match block["tax_rate"].is_badvalue() {
+        true => None,
+        false => Some(parse_f32(block, "tax_rate")?),
+    };
+    let savings_percent = parse_f32_or(block, "savings_percent", 0.0)?;
+
+    let bonus = Bonus {
+        amount,
+        percent,
+        month,
+        contribution_percent,
+        tax_rate,
+        savings_percent,
+    };
+
+    Ok(Some(bonus))
+}
+
+fn parse_retiree(input_yaml: &yaml_rust::Yaml) -> Result<Retiree, String> {
+    let name = parse_string(input_yaml, "name")?;
+    let life_expectency = parse_u32(input_yaml, "life_expectency")?;
+    let retirement_age = parse_u32(input_yaml, "retirement_age")?;
+
+    let salary_annual = parse_f32(input_yaml, "wage_annual_salary")?;
+    let salary_growth_percent = parse_f32_or(input_yaml, "wage_growth_percent", 0.0)?;
+    let mut raises = Vec::new();
+    if let Some(vec) = input_yaml["wage_raises"].as_vec() {
+        for element in vec {
+            raises.push(Raise {
+                year: parse_u32(element, "year")? as i32,
+                percent: parse_f32(element, "percent")?,
+            });
+        }
+    }
+    // the percentage is optional when there is a fixed contribution
+    let retirement_contribution_monthly = parse_f32_or(input_yaml, "retirement_contribution_monthly", 0.0)?;
+    let retirement_contribution_percent = if retirement_contribution_monthly > 0.0 {
+        parse_f32_or(input_yaml, "retirement_contribution_percent", 0.0)?
+    }
+    else {
+        parse_f32(input_yaml, "retirement_contribution_percent")?
+    };
+    let contribution_inflation_indexed = parse_bool_or(input_yaml, "contribution_inflation_indexed", true)?;
+    let contribution_start_date = parse_date_or_none(input_yaml, "contribution_start_date")?;
+    let employer_match = parse_employer_match(input_yaml)?;
+    let bonus = parse_bonus(input_yaml)?;
+    let hsa_contribution_annual = parse_f32_or(input_yaml, "hsa_contribution_annual", 0.0)?;
+    let backdoor_roth_annual = parse_f32_or(input_yaml, "backdoor_roth_annual", 0.0)?;
+    let nondeductible_ira_annual = parse_f32_or(input_yaml, "nondeductible_ira_annual", 0.0)?;
+    let mega_backdoor_roth_annual = parse_f32_or(input_yaml, "mega_backdoor_roth_annual", 0.0)?;
+    let social_security_age = parse_u32(input_yaml, "social_security_age")?;
+    let social_security_months = parse_u32_or(input_yaml, "social_security_months", 0)?;
+    if social_security_months > 11 {
+        return Err("social_security_months must be 0 to 11".to_string());
+    }
+    let pension_age = parse_u32(input_yaml, "pension_age")?;
+    let pension_monthly_income = parse_f32(input_yaml, "pension_monthly_income")?;
+    let has_percent = !input_yaml["pension_cola_percent"].is_badvalue();
+    let has_cap = !input_yaml["pension_cola_cap"].is_badvalue();
+    if has_percent && has_cap {
+        return Err("pension_cola_percent and pension_cola_cap can't both be set".to_string());
+    }
+    if (has_percent || has_cap) && input_yaml["pension_cola"].as_bool() == Some(false) {
+        return Err("pension_cola is false, remove it to use pension_cola_percent or pension_cola_cap".to_string());
+    }
+    let pension_cola = if has_percent {
+        PensionCola::Fixed(parse_f32(input_yaml, "pension_cola_percent")?)
+    }
+    else if has_cap {
+        PensionCola::Capped(parse_f32(input_yaml, "pension_cola_cap")?)
+    }
+    else if parse_bool_or(input_yaml, "pension_cola", true)? {
+        PensionCola::Cpi
+    }
+    else {
+        PensionCola::None
+    };
+    let pension_survivor_percent = parse_f32_or(input_yaml, "pension_survivor_percent", 100.0)?;
+    if !(0.0..=100.0).contains(&pension_survivor_percent) {
+        return Err("pension_survivor_percent must be 0 to 100".to_string());
+    }
+    let pension_lump_sum = parse_f32_or(input_yaml, "pension_lump_sum", 0.0)?;
+    let pension_take_lump_sum = parse_bool_or(input_yaml, "pension_take_lump_sum", false)?;
+    if pension_take_lump_sum && pension_lump_sum <= 0.0 {
+        return Err("pension_take_lump_sum needs a pension_lump_sum".to_string());
+    }
+    let annuity = parse_annuity(input_yaml)?;
+    let employer_stock = parse_employer_stock(input_yaml)?;
+    let mut part_time = Vec::new();
+    if let Some(vec) = input_yaml["part_time"].as_vec() {
+        for element in vec {
+            part_time.push(parse_part_time_work(element)?);
+        }
+    }
+    let mut compensation = Vec::new();
+    if let Some(vec) = input_yaml["compensation"].as_vec() {
+        for element in vec {
+            compensation.push(parse_compensation(element)?);
+        }
+    }
+    let other_monthly_retirement_income = parse_f32(input_yaml, "other_monthly_retirement_income")?;
+    let social_security_amount_full = parse_f32(input_yaml, "social_security_amount_full")?;
+
+    let date_of_birth = parse_string(input_yaml, "date_of_birth")?;
+    let date_of_birth = NaiveDate::parse_from_str(&date_of_birth, "%m/%d/%Y").map_err(|_| "Invalid date")?;
+    let sex = match input_yaml["sex"].as_str() {
+        None => None,
+        Some("male") => Some(Sex::Male),
+        Some("female") => Some(Sex::Female),
+        Some(v) => return Err(format!("Invalid value: sex {}", v)),
+    };
+    // part-time work follows full-time work, the wages before retirement
+    // are the salary
+    let retirement_date = utils::add_years(&date_of_birth, retirement_age);
+    if part_time.iter().any(|v| v.start_date < retirement_date) {
+        return Err(format!("{}'s part_time work starts before retirement", name));
+    }
+    // compensation isn't paid after leaving the job, unvested equity is
+    // forfeited
+    if compensation.iter().any(|v| v.date >= retirement_date) {
+        return Err(format!("{}'s compensation starts on or after retirement", name));
+    }
+    
+    let retiree = Retiree {
+        name,
+        date_of_birth,
+        sex,
+        life_expectency,
+        retirement_age,
+        salary_annual,
+        salary_growth_percent,
+        raises,
+        retirement_contribution_percent,
+        retirement_contribution_monthly,
+        contribution_inflation_indexed,
+        contribution_start_date,
+        employer_match,
+        bonus,
+        compensation,
+        hsa_contribution_annual,
+        backdoor_roth_annual,
+        nondeductible_ira_annual,
+        mega_backdoor_roth_annual,
+        social_security_age,
+        social_security_months,
+        pension_age,
+        pension_monthly_income,
+        pension_cola,
+        pension_survivor_percent,
+        pension_lump_sum,
+        pension_take_lump_sum,
+        annuity,
+        employer_stock,
+        part_time,
+        other_monthly_retirement_income,
+        social_security_amount_full,
+    };
+    
+    Ok(retiree)
+}
+
+fn parse_retirees(input_yaml: &yaml_rust::Yaml) -> Result<Vec<Retiree>, String> {
+    let mut retirees = Vec::new();
+    let block = &input_yaml["retirees"];
+    if block.is_badvalue() {
+        return Err("retirees block missing".to_string());
+    }
+
+    let vec = block.as_vec().ok_or("no retirees found")?;
+    for element in vec {
+        let retiree = parse_retiree(element);
+        match retiree {
+            Ok(v) => retirees.push(v),
+            Err(e) => return Err(e),
+        };
+    }
+
+    Ok(retirees)
+}
+
+fn parse_tax_rate(input_yaml: &yaml_rust::Yaml) -> Result<TaxLevel, String> {
+    let income = parse_f32(input_yaml, "income")?;
+    let rate = parse_f32(input_yaml, "rate")?;
+
+    let tax_rate = TaxLevel {
+        income,
+        rate,
+    };
+
+    Ok(tax_rate)
+}
+    
+// converts the levels as they are written in the configuration into the
+// levels used to calculate the taxes
+fn build_tax_levels(levels: Vec<TaxLevel>) -> Vec<TaxLevel> {
+    let mut tax_levels = vec![TaxLevel {income: 0.0, rate: 0.0}];
+    tax_levels.extend(levels);
+
+    //for (i, tax_rate) in tax_rates.iter().enumerate() {
+    for i in 1..tax_levels.len() {
+        if i < tax_levels.len() - 1 {
+            tax_levels[i].income = tax_levels[i + 1].income - 1.0;
+        }
+        else {
+            tax_levels[i].income = f32::MAX;
+        }
+    }
+
+    tax_levels.sort_unstable_by_key(|e| e.income as u32);
+
+    tax_levels
+}
+
+fn parse_tax_levels(block: &yaml_rust::Yaml) -> Result<Vec<TaxLevel>, String> {
+    let mut tax_levels = Vec::new();
+    let block = &block["levels"];
+    if block.is_badvalue() {
+        return Err("levels block missing".to_string());
+    }
+
+    let vec = block.as_vec().ok_or("no tax rates found")?;
+    for element in vec {
+        let tax_rate = parse_tax_rate(element);
+        match tax_rate {
+            Ok(v) => tax_levels.push(v),
+            Err(e) => return Err(e),
+        };
+    }
+
+    Ok(build_tax_levels(tax_levels))
+}
+
+// optional table that replaces the current tax law starting in a given year
+fn parse_tax_sunset(block: &yaml_rust::Yaml) -> Result<Option<TaxSunset>, String> {
+    let block = &block["sunset"];
+    if block.is_badvalue() {
+        return Ok(None);
+    }
+
+    let year = parse_u32(block, "year")? as i32;
+    let standard_deduction = parse_f32(block, "standard_deduction")?;
+    let tax_levels = parse_tax_levels(block)?;
+
+    let sunset = TaxSunset {
+        year,
+        standard_deduction,
+        tax_levels,
+    };
+
+    Ok(Some(sunset))
+}
+
+// the payroll block is optional, defaults are the 2025 rates
+fn parse_payroll_taxes(block: &yaml_rust::Yaml) -> Result<PayrollTaxes, String> {
+    let block = &block["payroll"];
+
+    let social_security_rate = parse_f32_or(block, "social_security_rate", 6.2)?;
+    let social_security_wage_base = parse_f32_or(block, "social_security_wage_base", 176100.0)?;
+    let medicare_rate = parse_f32_or(block, "medicare_rate", 1.45)?;
+    let additional_medicare_rate = parse_f32_or(block, "additional_medicare_rate", 0.9)?;
+    let additional_medicare_threshold = parse_f32_or(block, "additional_medicare_threshold", 250000.0)?;
+
+    let payroll = PayrollTaxes {
+        social_security_rate,
+        social_security_wage_base,
+        medicare_rate,
+        additional_medicare_rate,
+        additional_medicare_threshold,
+    };
+
+    Ok(payroll)
+}
+
+// long term capital gains brackets. Unlike the ordinary income levels, each
+// income is where the rate starts. Optional, defaults are the 2025 rates for
+// married filing jointly.
+fn parse_capital_gains_levels(block: &yaml_rust::Yaml) -> Result<Vec<TaxLevel>, String> {
+    let block = &block["capital_gains"]["levels"];
+    if block.is_badvalue() {
+        return Ok(vec![
+            TaxLevel {income: 0.0, rate: 0.0},
+            TaxLevel {income: 96700.0, rate: 15.0},
+            TaxLevel {income: 600050.0, rate: 20.0},
+        ]);
+    }
+
+    let mut levels = vec![TaxLevel {income: 0.0, rate: 0.0}];
+    let vec = block.as_vec().ok_or("no capital gains rates found")?;
+    for element in vec {
+        levels.push(parse_tax_rate(element)?);
+    }
+    levels.sort_unstable_by_key(|e| e.income as u32);
+
+    Ok(levels)
+}
+
+// the irmaa block is optional, defaults are the 2025 Part B and Part D
+// surcharges for married filing jointly
+fn parse_irmaa_levels(block: &yaml_rust::Yaml) -> Result<Vec<IrmaaLevel>, String> {
+    let block = &block["irmaa"]["levels"];
+    if block.is_badvalue() {
+        return Ok(vec![
+            IrmaaLevel {income: 212000.0, monthly_surcharge: 87.70},
+            IrmaaLevel {income: 266000.0, monthly_surcharge: 220.30},
+            IrmaaLevel {income: 334000.0, monthly_surcharge: 352.90},
+            IrmaaLevel {income: 400000.0, monthly_surcharge: 485.50},
+            IrmaaLevel {income: 750000.0, monthly_surcharge: 529.70},
+        ]);
+    }
+
+    let mut levels = Vec::new();
+    let vec = block.as_vec().ok_or("no IRMAA levels found")?;
+    for element in vec {
+        let income = parse_f32(element, "income")?;
+        let monthly_surcharge = parse_f32(element, "monthly_surcharge")?;
+        levels.push(IrmaaLevel {income, monthly_surcharge});
+    }
+    levels.sort_unstable_by_key(|e| e.income as u32);
+
+    Ok(levels)
+}
+
+fn parse_tax_rates(input_yaml: &yaml_rust::Yaml) -> Result<TaxRates, String> {
+    let block = &input_yaml["tax_rates"];
+    if block.is_badvalue() {
+        return Err("tax_rates block missing".to_string());
+    }
+
+    let standard_deduction = parse_f32(block, "standard_deduction")?;
+    let senior_deduction = parse_f32_or(block, "senior_deduction", 1600.0)?;
+    let tax_levels = parse_tax_levels(block)?;
+    let sunset = parse_tax_sunset(block)?;
+    let payroll = parse_payroll_taxes(block)?;
+    let capital_gains_levels = parse_capital_gains_levels(block)?;
+    let state_rate = parse_f32_or(block, "state_rate", 0.0)?;
+    let irmaa_levels = parse_irmaa_levels(block)?;
+
+    let tax_rates = TaxRates {
+        standard_deduction,
+        senior_deduction,
+        tax_levels,
+        sunset,
+        payroll,
+        capital_gains_levels,
+        state_rate,
+        irmaa_levels,
+        tables: TaxTables::default(),
+    };
+
+    Ok(tax_rates)
+}
+
+fn parse_input_file(fname: &str) -> Result<Input, String> {
+    let file_str = fs::read_to_string(fname).map_err(|e| format!("Can't read {}: {}", fname, e))?;
+    parse_input(&file_str, fname)
+}
+
+// parses the text of a configuration file. The files it names are relative
+// to the file's name.
+fn parse_input(file_str: &str, fname: &str) -> Result<Input, String> {
+    let docs = YamlLoader::load_from_str(file_str).map_err(|e| format!("Can't parse {}: {}", fname, e))?;
+    let doc = docs.first().ok_or(format!("{} is empty", fname))?;
+
+    // Dump the YAML object. This normalizes the formatting and drops the
+    // comments, so it identifies the configuration for the run cache.
+    let mut config_text = String::new();
+    {
+        let mut emitter = YamlEmitter::new(&mut config_text);
+        emitter.dump(doc).map_err(|e| format!("Can't parse {}: {:?}", fname, e))?;
+    }
+
+    let (mut portfolio, asset_class_names) = parse_portfolio(doc)?;
+    let accounts = parse_accounts(&doc["portfolio"], &asset_class_names)?;
+    let expenses = parse_expenses(doc, portfolio.expected_inflation)?;
+    let education = parse_education(doc)?;
+    portfolio.education_balance = education.balance_529;
+    let retirees = parse_retirees(doc)?;
+    let relocation = parse_relocation(doc)?;
+    if let Some(relocation) = relocation.as_ref() {
+        if relocation.age < retirees[0].retirement_age {
+            return Err("relocation age must be at or after the retirement age".to_string());
+        }
+    }
+    let home = parse_home(doc)?;
+    let downsize = home.as_ref().and_then(|v| v.downsize.as_ref());
+    if relocation.as_ref().is_some_and(|v| v.monthly_expenses.is_some()) && downsize.is_some_and(|v| v.monthly_expenses.is_some()) {
+        return Err("relocation and home downsize can't both change the monthly_expenses".to_string());
+    }
+    let windfalls = parse_windfalls(doc)?;
+    let rental_properties = parse_rental_properties(doc)?;
+    let one_time_expenses = parse_one_time_expenses(doc, &retirees[0].date_of_birth)?;
+    let mut tax_rates = parse_tax_rates(doc)?;
+
+    // the brackets file is relative to the configuration file, and its
+    // contents are part of the configuration for the run cache
+    if let Some(brackets_file) = doc["tax_rates"]["brackets_file"].as_str() {
+        let path = Path::new(fname).parent().unwrap_or(Path::new("")).join(brackets_file);
+        let path_str = path.to_string_lossy();
+        let text = fs::read_to_string(&path).map_err(|e| format!("Can't read {}: {}", path_str, e))?;
+        tax_rates.tables = tax_tables::parse_tax_tables(&path_str, &text)?;
+        config_text.push('\n');
+        config_text.push_str(&text);
+    }
+
+    // like the brackets file, the actual balances are relative to the
+    // configuration file and part of the configuration for the run cache
+    let mut tracking = parse_tracking(doc, portfolio.balance)?;
+    if let Some(tracking) = tracking.as_mut() {
+        if let Some(actuals_file) = tracking.actuals_file.as_ref() {
+            let path = Path::new(fname).parent().unwrap_or(Path::new("")).join(actuals_file);
+            let path_str = path.to_string_lossy();
+            let text = fs::read_to_string(&path).map_err(|e| format!("Can't read {}: {}", path_str, e))?;
+            let (date, balance) = reconcile::get_latest_balance(&path_str, &text)?;
+            portfolio.set_balance(balance);
+            tracking.latest_balance = Some((date, balance));
+            config_text.push('\n');
+            config_text.push_str(&text);
+        }
+    }
+    let survivor_tax_rates = tax_rates.for_single();
+    let analysis = parse_analysis(doc, &retirees)?;
+    let simulation = parse_simulation_settings(doc)?;
+    
+    let input = Input {
+        retirees,
+        portfolio,
+        accounts,
+        asset_class_names,
+        expenses,
+        education,
+        relocation,
+        home,
+        windfalls,
+        rental_properties,
+        one_time_expenses,
+        tracking,
+        tax_rates,
+        survivor_tax_rates,
+        analysis,
+        simulation,
+        crash_balance: None,
+        config_text,
+    };
+
+    Ok(input)
+        
+}
+
+// checks for values that are allowed but are probably mistakes. Allocations
+// that are slightly off are scaled to 100% unless the allocation check is
+// strict, and allocations that are further off are rejected.
+fn validate_input(input: &mut Input) -> Result<Vec<Warning>, String> {
+    let mut warnings = Vec::new();
+    let mut warn = |field: &str, message: String| {
+        warnings.push(Warning {field: field.to_string(), message});
+    };
+
+    let allocation_check = input.simulation.allocation_check;
+    let num_glide_points = input.portfolio.num_glide_points;
+    let allocations: Vec<(String, &mut portfolio::Allocation)> = match num_glide_points {
+        0 => vec![
+            ("portfolio.pre-retirement_allocation".to_string(), &mut input.portfolio.pre_retirement_allocation),
+            ("portfolio.post-retirement_allocation".to_string(), &mut input.portfolio.post_retirement_allocation),
+        ],
+        _ => input.portfolio.glide_path[..num_glide_points].iter_mut()
+            .map(|v| (format!("portfolio.glide_path at age {}", v.age), &mut v.allocation))
+            .collect(),
+    };
+    for (field, allocation) in allocations {
+        let total = allocation.get_total();
+        if (total - 100.0).abs() <= 0.01 {
+            continue;
+        }
+        if allocation_check == AllocationCheck::Strict || (total - 100.0).abs() > ALLOCATION_TOLERANCE {
+            return Err(format!("{} adds up to {:.1}% instead of 100%", field, total));
+        }
+        for weight in allocation.weights.iter_mut() {
+            *weight *= 100.0 / total;
+        }
+        warn(&field, format!("{} adds up to {:.1}%, scaled to 100%", field, total));
+    }
+    if num_glide_points > 0 {
+        input.portfolio.pre_retirement_allocation = input.portfolio.glide_path[0].allocation;
+        input.portfolio.post_retirement_allocation = input.portfolio.glide_path[num_glide_points - 1].allocation;
+    }
+
+    if let Some(percent) = input.expenses.replacement_percent {
+        input.expenses.monthly = simulate::get_final_year_pay(input)? * percent / 100.0;
+    }
+
+    if input.expenses.survivor_percent < 100.0 && input.retirees.len() < 2 {
+        warn("expenses.survivor_expense_percent",
+             "survivor_expense_percent only applies to a couple".to_string());
+    }
+
+    if input.expenses.essential_monthly.is_some_and(|v| v > input.expenses.monthly) {
+        warn("expenses.essential_monthly",
+             "essential_monthly is more than the monthly expenses".to_string());
+    }
+
+    if input.expenses.healthcare_monthly > f32::max(input.expenses.monthly, 0.0) {
+        warn("expenses.healthcare_monthly",
+             "healthcare_monthly is more than the monthly expenses, only the monthly expenses are paid".to_string());
+    }
+
+    let today = chrono::Utc::now().naive_utc().date();
+    for retiree in input.retirees.iter() {
+        if input.simulation.mortality == Mortality::LifeTable && retiree.sex.is_none() {
+            return Err(format!("{} needs a sex for the life tables", retiree.name));
+        }
+        if retiree.pension_take_lump_sum && utils::add_years(&retiree.date_of_birth, retiree.pension_age) < today {
+            return Err(format!("{}'s pension has already started, the lump sum can't be taken", retiree.name));
+        }
+        if let Some(annuity) = retiree.annuity.as_ref() {
+            let retirement_date = utils::add_years(&retiree.date_of_birth, retiree.retirement_age);
+            if annuity.get_start_date(&retiree.date_of_birth, retirement_date) < retirement_date {
+                return Err(format!("{}'s annuity must start paying at or after retirement", retiree.name));
+            }
+            if retirement_date < today {
+                return Err(format!("{}'s annuity is bought at retirement, which has already passed", retiree.name));
+            }
+        }
+        if retiree.retirement_age >= retiree.life_expectency {
+            warn("retirees.retirement_age",
+                 format!("{} retires at {}, after their life expectancy of {}",
+                         retiree.name, retiree.retirement_age, retiree.life_expectency));
+        }
+        if !(62..=70).contains(&retiree.social_security_age) {
+            warn("retirees.social_security_age",
+                 format!("{} starts social security at {}, benefits are only calculated for ages 62 to 70",
+                         retiree.name, retiree.social_security_age));
+        }
+        // IRA limit including the catch-up contribution
+        if retiree.backdoor_roth_annual > 8000.0 {
+            warn("retirees.backdoor_roth_annual",
+                 format!("{}'s backdoor Roth contribution of ${} is above the IRA contribution limit",
+                         retiree.name, num_with_commas(retiree.backdoor_roth_annual as u64)));
+        }
+    }
+    for windfall in input.windfalls.iter().filter(|v| v.date < today) {
+        warn("windfalls",
+             format!("The {} on {} has already happened, it should be in the balances instead",
+                     windfall.name, windfall.date.format("%m/%d/%Y")));
+    }
+    if let Some((date, _)) = input.tracking.as_ref().and_then(|v| v.latest_balance) {
+        if utils::add_months(&date, 3) < today {
+            warn("tracking.actuals_file",
+                 format!("The latest actual balance is from {}, the simulation starts today",
+                         date.format("%m/%d/%Y")));
+        }
+    }
+    for expense in input.one_time_expenses.iter().filter(|v| v.date < today && v.every_years.is_none()) {
+        warn("one_time_expenses",
+             format!("The {} on {} has already been paid, it should be left out instead",
+                     expense.name, expense.date.format("%m/%d/%Y")));
+    }
+    for property in input.rental_properties.iter().filter(|v| v.sale_year.is_some_and(|v| v < chrono::Datelike::year(&today))) {
+        warn("rental_properties",
+             format!("{} was sold in {}, it should be left out instead", property.name, property.sale_year.unwrap()));
+    }
+
+    Ok(warnings)
+}
+
+///////////////////////////////////////////////////////////////////////////
+// Output results
+///////////////////////////////////////////////////////////////////////////
+
+fn format_table(table: Vec<Vec<String>>) -> String {
+    if table.is_empty() {
+        return "".to_string();
+    }
+    
+    // the headings and labels in the language of the report
+    let table: Vec<Vec<&str>> = table.iter()
+        .map(|row| row.iter().map(|cell| tr!(cell.as_str())).collect())
+        .collect();
+
+    // find max len of each column, in characters for the accented ones
+    let mut col_size: Vec<usize> = vec![0; table[0].len()];
+    for row in table.iter() {
+        for (i, cell) in row.iter().enumerate() {
+            if cell.chars().count() > col_size[i] {
+                col_size[i] = cell.chars().count();
+            }
+        }
+    }
+
+    // format table
+    let mut str = String::new();
+    for row in table.iter() {
+        for (i, cell) in row.iter().enumerate() {
+            str.push_str(&format!("{:>width$} ", cell, width = col_size[i]));
+        }
+        str.push('\n');
+    } 
+
+    str
+}
+
+fn num_with_commas(num: u64) -> String
+{
+    num.to_formatted_string(&Locale::en)
+}
+
+// like num_with_commas, for amounts that can be negative
+fn signed_with_commas(num: f32) -> String
+{
+    (num as i64).to_formatted_string(&Locale::en)
+}
+
+fn print_simulation_results(out: &mut String, simulation_results: &simulate::SimulationResults) {
+    // there's no retirement to mark when it happened before the simulation
+    let mut retire_printed = simulation_results.monthly_snapshot.first()
+        .is_some_and(|v| simulation_results.retirement_date < v.date);
+
+    let mut table: Vec<Vec<String>> = Vec::new();
+
+    let heading = vec!["".to_string(), "Year".to_string(), "Age".to_string(),
+                       "Balance".to_string(), "Expenses".to_string(),
+                       "Income".to_string(), "Tax".to_string(),
+                       "Fed".to_string(), "CapG".to_string(),
+                       "State".to_string(), "IRMAA".to_string(),
+                       "Rate".to_string(), "Draw".to_string(),
+                       "Yield".to_string(), "".to_string()];
+                       table.push(heading);
+    
+    let snapshots_per_year = simulation_results.snapshots_per_year();
+    for (i, monthly_snapshot) in simulation_results.monthly_snapshot.iter().enumerate() {
+        if (i % snapshots_per_year) == 0 {
+            let mut row: Vec<String> = Vec::new();
+            
+            let age = utils::get_age(&simulation_results.retirees[0].date_of_birth, &monthly_snapshot.date);
+            row.push((i / snapshots_per_year).to_string());
+            row.push(monthly_snapshot.date.format("%Y").to_string());
+            row.push(age.to_string());
+            row.push(num_with_commas(monthly_snapshot.balance as u64));
+            row.push(format!("{:.0}", monthly_snapshot.expenses));
             row.push(format!("{:.0}", monthly_snapshot.income));
             row.push(format!("{:.0}", monthly_snapshot.taxes));
+            row.push(format!("{:.0}", monthly_snapshot.federal_taxes));
+            row.push(format!("{:.0}", monthly_snapshot.capital_gains_taxes));
+            row.push(format!("{:.0}", monthly_snapshot.state_taxes));
+            row.push(format!("{:.0}", monthly_snapshot.irmaa));
             row.push(format!("{:.0}%", monthly_snapshot.tax_rate));
             row.push(format!("{:.2}%", monthly_snapshot.withdrawal_rate * 100.0));
             row.push(format!("{:.2}%", monthly_snapshot.annualized_return));
@@ -376,104 +2845,2109 @@ fn print_simulation_results(simulation_results: &simulate::SimulationResults) {
                 row.push("".to_string());
             }
 
-            table.push(row);
+            table.push(row);
+        }
+    }
+
+    outln!(out, "{}", format_table(table));
+    
+    outln!(out, "{}", tr!("Average return: {:.2}%", simulation_results.average_return));
+    if let Some(returns) = simulation_results.get_return_statistics() {
+        outln!(out, "{}", tr!("Geometric mean return: {:.2}%, volatility {:.2}%, best year {:.2}% ({}), worst year {:.2}% ({})",
+               returns.geometric_mean, returns.volatility,
+               returns.best_year.0, returns.best_year.1, returns.worst_year.0, returns.worst_year.1));
+    }
+
+    if let Some(first) = simulation_results.monthly_snapshot.first() {
+        if first.wages > 0.0 {
+            let take_home = first.wages - first.contributions - first.payroll_taxes - first.wage_taxes;
+            outln!(out, "{}", tr!("Take-home pay while working: ${} per month (wages ${}, contributions ${}, payroll tax ${}, income tax ${})",
+                     num_with_commas(take_home.max(0.0) as u64),
+                     num_with_commas(first.wages as u64),
+                     num_with_commas(first.contributions as u64),
+                     num_with_commas(first.payroll_taxes as u64),
+                     num_with_commas(first.wage_taxes as u64)));
+        }
+    }
+}
+    
+// pensions without a cost of living adjustment are fixed in nominal dollars,
+// so show how much they are really worth as inflation eats away at them
+// the tax bracket each year. The headroom is how much more ordinary income,
+// such as a Roth conversion, fits before the next bracket.
+fn print_tax_brackets(out: &mut String, simulation_results: &simulate::SimulationResults) {
+    let mut table: Vec<Vec<String>> = Vec::new();
+    table.push(vec!["Year".to_string(), "Age".to_string(), "Taxable".to_string(),
+                    "Bracket".to_string(), "Effective".to_string(), "Headroom".to_string()]);
+
+    let snapshots_per_year = simulation_results.snapshots_per_year();
+    for monthly_snapshot in simulation_results.monthly_snapshot.iter().step_by(snapshots_per_year) {
+        let age = utils::get_age(&simulation_results.retirees[0].date_of_birth, &monthly_snapshot.date);
+        // the top bracket has no limit
+        let headroom = if monthly_snapshot.bracket_headroom >= f32::MAX / 2.0 {"-".to_string()}
+            else {num_with_commas(monthly_snapshot.bracket_headroom as u64)};
+        table.push(vec![
+            monthly_snapshot.date.format("%Y").to_string(),
+            age.to_string(),
+            num_with_commas((monthly_snapshot.taxable_income * 12.0) as u64),
+            format!("{:.0}%", monthly_snapshot.bracket_rate),
+            format!("{:.1}%", monthly_snapshot.effective_tax_rate),
+            headroom,
+        ]);
+    }
+
+    outln!(out);
+    outln!(out, "{}", tr!("Tax brackets (annual taxable income before the standard deduction):"));
+    out.push_str(&format_table(table));
+}
+
+// what the household takes home each month in retirement, the income and
+// withdrawals less the taxes, averaged over each year of the plan
+fn print_paycheck(out: &mut String, simulation_results: &simulate::SimulationResults) {
+    let mut table: Vec<Vec<String>> = Vec::new();
+    table.push(vec!["Year".to_string(), "Age".to_string(), "Income".to_string(),
+                    "Withdrawals".to_string(), "Taxes".to_string(), "Paycheck".to_string()]);
+
+    let snapshots_per_year = simulation_results.snapshots_per_year();
+    for year in simulation_results.monthly_snapshot.chunks(snapshots_per_year) {
+        let first = &year[0];
+        if first.date < simulation_results.retirement_date {
+            continue;
+        }
+        let count = year.len() as f32;
+        let income = year.iter().map(|v| v.income).sum::<f32>() / count;
+        let withdrawals = year.iter().map(|v| v.withdrawals).sum::<f32>() / count;
+        let taxes = year.iter().map(|v| v.taxes).sum::<f32>() / count;
+        let age = utils::get_age(&simulation_results.retirees[0].date_of_birth, &first.date);
+        table.push(vec![
+            first.date.format("%Y").to_string(),
+            age.to_string(),
+            num_with_commas(income.max(0.0) as u64),
+            num_with_commas(withdrawals as u64),
+            num_with_commas(taxes as u64),
+            num_with_commas((income + withdrawals - taxes).max(0.0) as u64),
+        ]);
+    }
+    if table.len() == 1 {
+        return;
+    }
+
+    outln!(out);
+    outln!(out, "{}", tr!("Monthly paycheck in retirement (income plus withdrawals less taxes, average of each year):"));
+    out.push_str(&format_table(table));
+}
+
+// compares the income that lasts for life with the essential expenses in
+// each year of retirement. The years it falls short depend on the portfolio.
+fn print_income_floor(out: &mut String, input: &Input, simulation_results: &simulate::SimulationResults) {
+    let mut table: Vec<Vec<String>> = Vec::new();
+    table.push(vec!["Year".to_string(), "Age".to_string(), "Guaranteed".to_string(),
+                    "Essential".to_string(), "Shortfall".to_string()]);
+
+    let snapshots_per_year = simulation_results.snapshots_per_year();
+    let mut years = 0;
+    let mut short_years = 0;
+    let mut total_shortfall = 0.0;
+    for year in simulation_results.monthly_snapshot.chunks(snapshots_per_year) {
+        let first = &year[0];
+        if first.date < simulation_results.retirement_date {
+            continue;
+        }
+        let count = year.len() as f32;
+        let guaranteed = year.iter().map(|v| v.guaranteed_income).sum::<f32>() / count;
+        let essential = input.expenses.essential_monthly
+            .unwrap_or_else(|| year.iter().map(|v| v.expenses).sum::<f32>() / count);
+        let shortfall = f32::max(essential - guaranteed, 0.0);
+        years += 1;
+        if shortfall > 0.0 {
+            short_years += 1;
+            total_shortfall += shortfall * 12.0;
+        }
+        let age = utils::get_age(&simulation_results.retirees[0].date_of_birth, &first.date);
+        table.push(vec![
+            first.date.format("%Y").to_string(),
+            age.to_string(),
+            num_with_commas(guaranteed as u64),
+            num_with_commas(essential.max(0.0) as u64),
+            if shortfall > 0.0 {num_with_commas(shortfall as u64)} else {"".to_string()},
+        ]);
+    }
+    if years == 0 {
+        return;
+    }
+
+    outln!(out);
+    outln!(out, "{}", tr!("Guaranteed income (social security, pensions and annuities) against {} (monthly, average of each year):",
+             if input.expenses.essential_monthly.is_some() {tr!("essential expenses")} else {tr!("all expenses")}));
+    out.push_str(&format_table(table));
+    if short_years == 0 {
+        outln!(out, "{}", tr!("Guaranteed income covers the expenses in every year of retirement"));
+    }
+    else {
+        outln!(out, "{}", tr!("Guaranteed income falls short in {} of {} years, ${} in today's dollars in total that depends on the portfolio",
+                 short_years, years, num_with_commas(total_shortfall as u64)));
+    }
+}
+
+// the income each year in today's dollars next to what it is in the dollars
+// of that year, and the pensions and annuities that lose value to inflation
+// as a share of the expenses. Once that share has reached the warning
+// percentage, the years it's below it are marked.
+fn print_purchasing_power(out: &mut String, input: &Input, simulation_results: &simulate::SimulationResults) {
+    if simulation_results.monthly_snapshot.iter().all(|v| v.fixed_income == 0.0) {
+        return;
+    }
+    let inflation = input.portfolio.expected_inflation;
+    let warning_percent = input.analysis.fixed_income_warning_percent;
+    let start_date = simulation_results.monthly_snapshot[0].date;
+
+    let mut table: Vec<Vec<String>> = Vec::new();
+    table.push(vec!["Year".to_string(), "Age".to_string(), "Income".to_string(), "Nominal".to_string(),
+                    "Fixed".to_string(), "Expenses".to_string(), "Fixed share".to_string(), "".to_string()]);
+
+    let snapshots_per_year = simulation_results.snapshots_per_year();
+    let years = simulation_results.monthly_snapshot.chunks(snapshots_per_year)
+        .filter(|v| v[0].date >= simulation_results.retirement_date);
+    let mut has_reached = false;
+    let mut warnings = Vec::new();
+    for (year, (_, share)) in years.zip(simulation_results.get_fixed_income_shares()) {
+        let first = &year[0];
+        let count = year.len() as f32;
+        let income = year.iter().map(|v| v.income).sum::<f32>() / count;
+        let fixed_income = year.iter().map(|v| v.fixed_income).sum::<f32>() / count;
+        let expenses = year.iter().map(|v| v.expenses).sum::<f32>() / count;
+        let price_level = 1.0 / utils::get_inflation_discount(inflation, utils::months_between(&start_date, &first.date));
+        let age = utils::get_age(&simulation_results.retirees[0].date_of_birth, &first.date);
+        let is_below = has_reached && share.is_some_and(|v| v < warning_percent);
+        if is_below && table.last().is_some_and(|v| v[7].is_empty()) {
+            warnings.push(tr!("{} (age {})", first.date.format("%Y"), age));
+        }
+        has_reached |= share.is_some_and(|v| v >= warning_percent);
+        table.push(vec![
+            first.date.format("%Y").to_string(),
+            age.to_string(),
+            num_with_commas(income.max(0.0) as u64),
+            num_with_commas((income * price_level).max(0.0) as u64),
+            num_with_commas(fixed_income as u64),
+            num_with_commas(expenses.max(0.0) as u64),
+            share.map_or("-".to_string(), |v| format!("{:.0}%", v)),
+            if is_below {"Below".to_string()} else {"".to_string()},
+        ]);
+    }
+    if table.len() == 1 {
+        return;
+    }
+
+    outln!(out);
+    outln!(out, "{}", tr!("Income in today's dollars and nominal at {:.1}% inflation, and fixed pensions and annuities that lose value to inflation (monthly, average of each year):",
+             inflation));
+    out.push_str(&format_table(table));
+    for warning in warnings.iter() {
+        outln!(out, "{}", tr!("Warning: fixed income falls below {:.0}% of the expenses in {}", warning_percent, warning));
+    }
+}
+
+// the health insurance and out-of-pocket costs from retirement until
+// Medicare, by year, with the ACA premium tax credit against them
+fn print_healthcare_costs(out: &mut String, input: &Input, simulation_results: &simulate::SimulationResults) {
+    let bridge = input.expenses.bridge_healthcare.as_ref();
+    let medicare = input.expenses.medicare.as_ref();
+    if bridge.is_none() && medicare.is_none() {
+        return;
+    }
+    let aca_subsidy = bridge.is_some_and(|v| v.aca_subsidy);
+    let months = simulation_results.months_per_step as f32;
+    let mut table: Vec<Vec<String>> = Vec::new();
+    let mut heading = vec!["Year".to_string(), "Age".to_string(), "Taxable income".to_string()];
+    if bridge.is_some() {
+        heading.push("Before Medicare".to_string());
+    }
+    if aca_subsidy {
+        heading.push("ACA credit".to_string());
+    }
+    if medicare.is_some() {
+        heading.push("Medicare".to_string());
+        heading.push("IRMAA".to_string());
+    }
+    heading.push("Net cost".to_string());
+    table.push(heading);
+    let mut total_costs = 0.0;
+    let mut total_credit = 0.0;
+    for year in simulation_results.monthly_snapshot.chunks(simulation_results.snapshots_per_year()) {
+        let bridge_costs = year.iter().map(|v| v.bridge_healthcare).sum::<f32>() * months;
+        let premiums = year.iter().map(|v| v.medicare_premiums).sum::<f32>() * months;
+        let irmaa = match medicare {
+            Some(_) => year.iter().map(|v| v.irmaa).sum::<f32>() * months,
+            None => 0.0,
+        };
+        let costs = bridge_costs + premiums + irmaa;
+        if costs == 0.0 {
+            continue;
+        }
+        let credit = year.iter().map(|v| v.aca_credit).sum::<f32>() * months;
+        let first = &year[0];
+        let mut row = vec![
+            first.date.format("%Y").to_string(),
+            utils::get_age(&simulation_results.retirees[0].date_of_birth, &first.date).to_string(),
+            num_with_commas((year.iter().map(|v| v.taxable_income).sum::<f32>() * months).max(0.0) as u64),
+        ];
+        if bridge.is_some() {
+            row.push(num_with_commas(bridge_costs as u64));
+        }
+        if aca_subsidy {
+            row.push(num_with_commas(credit as u64));
+        }
+        if medicare.is_some() {
+            row.push(num_with_commas(premiums as u64));
+            row.push(num_with_commas(irmaa as u64));
+        }
+        row.push(num_with_commas((costs - credit) as u64));
+        table.push(row);
+        total_costs += costs;
+        total_credit += credit;
+    }
+    if table.len() == 1 {
+        return;
+    }
+
+    outln!(out);
+    outln!(out, "{}", tr!("Healthcare costs (annual, today's dollars):"));
+    if let Some(bridge) = bridge {
+        outln!(out, "{}", tr!("  Insurance and out-of-pocket costs before Medicare grow {:.1}% a year", bridge.inflation));
+    }
+    if let Some(medicare) = medicare {
+        outln!(out, "{}", tr!("  Part B, Part D and Medigap premiums from 65 grow {:.1}% a year, with IRMAA surcharges on top",
+               medicare.inflation));
+    }
+    out.push_str(&format_table(table));
+    match aca_subsidy {
+        true => outln!(out, "{}", tr!("Total ${}, ${} after the ACA premium tax credit", num_with_commas(total_costs as u64),
+                       num_with_commas((total_costs - total_credit) as u64))),
+        false => outln!(out, "Total ${}", num_with_commas(total_costs as u64)),
+    }
+}
+
+fn print_pension_erosion(out: &mut String, input: &Input) {
+    let inflation = input.portfolio.expected_inflation;
+    let today = chrono::Utc::now().naive_utc().date();
+
+    for retiree in input.retirees.iter() {
+        // only pensions that don't keep up with inflation lose value, but a
+        // fixed adjustment can also be more than inflation
+        let cola = retiree.pension_cola;
+        if cola.get_real_value(inflation, 12) == 1.0 || retiree.pension_monthly_income <= 0.0 ||
+            retiree.pension_take_lump_sum {
+            continue;
+        }
+
+        outln!(out);
+        outln!(out, "{}", tr!("Pension for {} has {}. Value in today's dollars at {:.1}% inflation:",
+                 retiree.name, cola.describe(), inflation));
+
+        let mut table: Vec<Vec<String>> = Vec::new();
+        table.push(vec!["Year".to_string(), "Age".to_string(),
+                        "Monthly".to_string(), "Value".to_string()]);
+
+        // the pension amount is entered in today's dollars and its adjustments
+        // start once it starts
+        let start_age = u32::max(retiree.pension_age, utils::get_age(&retiree.date_of_birth, &today));
+        for age in (start_age..=retiree.life_expectency).step_by(5) {
+            let date = utils::add_years(&retiree.date_of_birth, age);
+            let months = (age - start_age) * 12;
+            let discount = cola.get_real_value(inflation, months);
+            table.push(vec![
+                date.format("%Y").to_string(),
+                age.to_string(),
+                format!("{:.0}", retiree.pension_monthly_income * discount),
+                format!("{:.0}%", discount * 100.0),
+            ]);
+        }
+
+        out.push_str(&format_table(table));
+    }
+}
+    
+// success rate of the historical simulation if spending were higher or lower
+fn print_spending_sweep(out: &mut String, input: &Input, historical_scan: &mut HistoricalScan) -> Result<(), String> {
+    let percentages = [80.0, 90.0, 100.0, 110.0, 120.0];
+    let monthly_expenses: Vec<f32> = percentages.iter().map(|v| input.expenses.monthly * v / 100.0).collect();
+    let sweep_results = scan::run_spending_sweep(historical_scan, input, &monthly_expenses)?;
+
+    let mut table: Vec<Vec<String>> = Vec::new();
+    table.push(vec!["Expenses".to_string(), "".to_string(), "Success".to_string()]);
+    for ((percent, expenses), results) in percentages.iter().zip(monthly_expenses.iter()).zip(sweep_results.iter()) {
+        table.push(vec![
+            signed_with_commas(*expenses),
+            format!("({:.0}%)", percent),
+            format!("{:.1}%", results.success_rate()),
+        ]);
+    }
+
+    outln!(out);
+    outln!(out, "{}", tr!("Success rate by monthly expenses:"));
+    out.push_str(&format_table(table));
+
+    Ok(())
+}
+
+// the historical success rate when the retirees are forced to retire
+// earlier than planned, for each year up to the analysis setting
+fn print_early_retirement(out: &mut String, input: &Input, historical_scan: &mut HistoricalScan) -> Result<(), String> {
+    let max_years = input.analysis.early_retirement_years;
+    if max_years == 0 {
+        return Ok(());
+    }
+    let reduction = input.analysis.early_retirement_benefit_reduction;
+    let years: Vec<u32> = (0..=max_years).collect();
+    let sweep_results = scan::run_early_retirement_sweep(historical_scan, input, &years, reduction)?;
+
+    let context = simulate::PlanContext::new(input);
+    let mut table: Vec<Vec<String>> = Vec::new();
+    table.push(vec!["Retires".to_string(), "".to_string(), "Success".to_string(), "Median ending".to_string()]);
+    for (years_early, results) in years.iter().zip(sweep_results.iter()) {
+        let retirement_date = context.with_early_retirement(*years_early, reduction).retirement_date;
+        table.push(vec![
+            retirement_date.format("%m/%d/%Y").to_string(),
+            match years_early {
+                0 => tr!("(as planned)").to_string(),
+                1 => tr!("(1 year early)").to_string(),
+                v => tr!("({} years early)", v),
+            },
+            format!("{:.1}%", results.success_rate()),
+            num_with_commas(results.get_ending_balance_percentile(50.0) as u64),
+        ]);
+    }
+
+    outln!(out);
+    outln!(out, "{}", tr!("Success rate if forced to retire early, with social security {}% lower for each year:", reduction));
+    out.push_str(&format_table(table));
+
+    Ok(())
+}
+
+// the plan with a stress test's event added, and a description of the
+// event. An expense without a year is paid at the start of a random year in
+// retirement.
+fn get_stressed_input(input: &Input, event: &StressEvent, rng: &mut ChaCha12Rng) -> (Input, String) {
+    let mut stressed = input.clone();
+    let description = match *event {
+        StressEvent::Expense {amount, year} => {
+            let year = year.unwrap_or_else(|| {
+                let context = simulate::PlanContext::new(input);
+                let first_year = chrono::Datelike::year(&NaiveDate::max(context.retirement_date, context.start_date)) + 1;
+                let last_year = context.retirees.iter().map(|v| chrono::Datelike::year(&v.death_date)).max().unwrap() - 1;
+                rng.gen_range(first_year..=last_year.max(first_year))
+            });
+            stressed.one_time_expenses.push(OneTimeExpense {
+                name: "Stress test".to_string(),
+                amount,
+                date: NaiveDate::from_ymd_opt(year, 1, 1).unwrap(),
+                inflation_adjusted: true,
+                every_years: None,
+            });
+            tr!("${} expense in {}", num_with_commas(amount as u64), year)
+        },
+        StressEvent::Crash(crash) => {
+            crash_portfolio(&mut stressed, &crash);
+            tr!("Crash now, equities {:.0}%/{:.0}%, bonds {:.0}%", crash.us_equity, crash.international_equity, crash.bonds)
+        },
+        StressEvent::Inflation(shock) => {
+            stressed.simulation.inflation_shock = Some(shock);
+            tr!("{:.1}% inflation for {} years", shock.rate, shock.years)
+        },
+    };
+    (stressed, description)
+}
+
+// the historical and Monte Carlo scans rerun with each stress test's event
+// added on its own, and the change in the success rates. The random years
+// come from the Monte Carlo seed, so a run can be repeated.
+fn print_stress_tests(out: &mut String,
+                      summary: &mut ReportSummary,
+                      input: &Input,
+                      historical_scan: &mut HistoricalScan,
+                      monte_carlo_scan: &mut MonteCarloScan,
+                      historical_results: &scan::ScanResults,
+                      monte_carlo_results: &scan::ScanResults) -> Result<(), String> {
+    if input.analysis.stress_tests.is_empty() {
+        return Ok(());
+    }
+    let mut rng = ChaCha12Rng::seed_from_u64(monte_carlo_scan.seed());
+    let historical_baseline = historical_results.success_rate();
+    let monte_carlo_baseline = monte_carlo_results.success_rate();
+
+    let mut table: Vec<Vec<String>> = Vec::new();
+    table.push(["Stress test", "Event", "Historical", "Change", "Monte Carlo", "Change"]
+               .iter().map(|v| v.to_string()).collect());
+    table.push(vec!["Baseline".to_string(), "".to_string(), format!("{:.1}%", historical_baseline), "".to_string(),
+                    format!("{:.1}%", monte_carlo_baseline), "".to_string()]);
+    let mut tests_json = Vec::new();
+    for (i, test) in input.analysis.stress_tests.iter().enumerate() {
+        let (stressed, description) = get_stressed_input(input, &test.event, &mut rng);
+        let historical = historical_scan.run_scan(&stressed)?.success_rate();
+        let monte_carlo = monte_carlo_scan.run_scan(&stressed)?.success_rate();
+        let name = test.name.clone().unwrap_or_else(|| tr!("Test {}", i + 1));
+        table.push(vec![
+            name.clone(),
+            description.clone(),
+            format!("{:.1}%", historical),
+            format!("{:+.1}%", historical - historical_baseline),
+            format!("{:.1}%", monte_carlo),
+            format!("{:+.1}%", monte_carlo - monte_carlo_baseline),
+        ]);
+        tests_json.push(Json::object(vec![
+            ("name", Json::String(name)),
+            ("event", Json::String(description)),
+            ("historical_success_rate", Json::Number(historical as f64)),
+            ("historical_change", Json::Number((historical - historical_baseline) as f64)),
+            ("monte_carlo_success_rate", Json::Number(monte_carlo as f64)),
+            ("monte_carlo_change", Json::Number((monte_carlo - monte_carlo_baseline) as f64)),
+        ]));
+    }
+
+    outln!(out);
+    outln!(out, "{}", tr!("Success rates with each stress test added to the plan on its own:"));
+    out.push_str(&format_table(table));
+    summary.stress_tests = Some(Json::Array(tests_json));
+
+    Ok(())
+}
+
+// the annual withdrawal of the 4% rule benchmark, a percentage of the
+// balance at retirement
+const BENCHMARK_WITHDRAWAL_RATE: f32 = 4.0;
+
+// the plan with all of the portfolio in one allocation, before and after
+// retirement
+fn get_benchmark_input(input: &Input, us_equity: f32, bonds: f32) -> Input {
+    let mut benchmark = input.clone();
+    let mut weights = [0.0; portfolio::MAX_ASSET_CLASSES];
+    weights[portfolio::US_EQUITY] = us_equity;
+    weights[portfolio::BONDS] = bonds;
+    benchmark.portfolio.pre_retirement_allocation = portfolio::Allocation {weights};
+    benchmark.portfolio.post_retirement_allocation = portfolio::Allocation {weights};
+    benchmark.portfolio.num_glide_points = 0;
+    benchmark.portfolio.num_accounts = 0;
+    benchmark
+}
+
+// the historical and Monte Carlo scans rerun with two reference strategies
+// on the same inputs: all in bonds with the plan's expenses, and the classic
+// 60/40 spending 4% of the balance at retirement each year, so the plan can
+// be judged against them
+fn print_benchmarks(out: &mut String,
+                    summary: &mut ReportSummary,
+                    input: &Input,
+                    historical_scan: &mut HistoricalScan,
+                    monte_carlo_scan: &mut MonteCarloScan,
+                    historical_results: &scan::ScanResults,
+                    monte_carlo_results: &scan::ScanResults) -> Result<(), String> {
+    if !input.analysis.benchmarks {
+        return Ok(());
+    }
+
+    let all_bonds = get_benchmark_input(input, 0.0, 100.0);
+    // the 4% rule replaces the monthly expenses, and none of it can be cut.
+    // The balance at retirement is the uniform simulation's.
+    let mut sixty_forty = get_benchmark_input(input, 60.0, 40.0);
+    let retirement_balance = simulate::run_simulation(&sixty_forty)?.retirement_balance
+        .unwrap_or(sixty_forty.portfolio.balance);
+    sixty_forty.expenses.monthly = retirement_balance * BENCHMARK_WITHDRAWAL_RATE / 100.0 / 12.0;
+    sixty_forty.expenses.essential_monthly = None;
+
+    let mut table: Vec<Vec<String>> = Vec::new();
+    table.push(["Strategy", "Monthly", "Historical", "Monte Carlo", "Median ending"]
+               .iter().map(|v| v.to_string()).collect());
+    let mut benchmarks_json = Vec::new();
+    let mut add_row = |name: &str, label: &str, input: &Input, historical: &scan::ScanResults, monte_carlo: &scan::ScanResults| {
+        let median_ending = historical.get_ending_balance_percentile(50.0);
+        table.push(vec![
+            label.to_string(),
+            num_with_commas(input.expenses.monthly.max(0.0) as u64),
+            format!("{:.1}%", historical.success_rate()),
+            format!("{:.1}%", monte_carlo.success_rate()),
+            num_with_commas(median_ending as u64),
+        ]);
+        benchmarks_json.push(Json::object(vec![
+            ("name", Json::string(name)),
+            ("monthly_expenses", Json::Number(input.expenses.monthly as f64)),
+            ("historical_success_rate", Json::Number(historical.success_rate() as f64)),
+            ("monte_carlo_success_rate", Json::Number(monte_carlo.success_rate() as f64)),
+            ("historical_median_ending_balance", Json::Number(median_ending as f64)),
+        ]));
+    };
+    add_row("plan", "This plan", input, historical_results, monte_carlo_results);
+    for (name, label, benchmark) in [("all_bonds", "100% bonds", &all_bonds), ("sixty_forty", "60/40, 4% rule", &sixty_forty)] {
+        let historical = historical_scan.run_scan(benchmark)?;
+        let monte_carlo = monte_carlo_scan.run_scan(benchmark)?;
+        add_row(name, label, benchmark, &historical, &monte_carlo);
+    }
+
+    outln!(out);
+    outln!(out, "{}", tr!("Compared with simple strategies on the same inputs, with the historical median ending balance:"));
+    out.push_str(&format_table(table));
+    summary.benchmarks = Some(Json::Array(benchmarks_json));
+
+    Ok(())
+}
+
+// a longevity scenario as "Both to 85" or each retiree's age
+fn describe_longevity(input: &Input, ages: &[u32]) -> String {
+    if ages.iter().all(|v| *v == ages[0]) {
+        return match ages.len() {
+            1 => tr!("To {}", ages[0]),
+            2 => tr!("Both to {}", ages[0]),
+            _ => tr!("All to {}", ages[0]),
+        };
+    }
+    input.retirees.iter().zip(ages.iter())
+        .map(|(retiree, age)| tr!("{} to {}", retiree.name, age))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// the success rate with the retirees living to other ages, so couples can
+// see how much a long life for one or both of them matters
+fn print_longevity_scenarios(out: &mut String, input: &Input, historical_scan: &mut HistoricalScan) -> Result<(), String> {
+    if input.analysis.longevity_scenarios.is_empty() {
+        return Ok(());
+    }
+    let mut scenarios = vec![input.retirees.iter().map(|v| v.life_expectency).collect::<Vec<u32>>()];
+    scenarios.extend(input.analysis.longevity_scenarios.iter().cloned());
+    let sweep_results = scan::run_longevity_sweep(historical_scan, input, &scenarios)?;
+
+    let mut table: Vec<Vec<String>> = Vec::new();
+    table.push(vec!["Lives to".to_string(), "".to_string(), "Success".to_string(), "Median ending".to_string()]);
+    for (i, (ages, results)) in scenarios.iter().zip(sweep_results.iter()).enumerate() {
+        table.push(vec![
+            describe_longevity(input, ages),
+            if i == 0 {"(as planned)".to_string()} else {"".to_string()},
+            format!("{:.1}%", results.success_rate()),
+            num_with_commas(results.get_ending_balance_percentile(50.0) as u64),
+        ]);
+    }
+
+    outln!(out);
+    outln!(out, "{}", tr!("Success rate if the retirees live to other ages:"));
+    out.push_str(&format_table(table));
+
+    Ok(())
+}
+
+// decades with fewer years of data than this aren't used as presets
+const MIN_DECADE_YEARS: usize = 5;
+
+// the uniform simulation rerun with the average returns and inflation of
+// each historical decade, between the single set of expected returns and a
+// full scan
+fn print_decade_presets(out: &mut String, input: &Input, historical_scan: &HistoricalScan) -> Result<(), String> {
+    if !input.analysis.decade_presets {
+        return Ok(());
+    }
+    let portfolio = &input.portfolio;
+    let mut presets = vec![("Expected".to_string(), portfolio.get_expected_returns(), portfolio.expected_inflation)];
+    for decade in historical_scan.get_decade_averages(MIN_DECADE_YEARS) {
+        let name = match decade.last_year - decade.first_year {
+            9 => format!("{}s", decade.first_year),
+            _ => format!("{}-{}", decade.first_year, decade.last_year),
+        };
+        presets.push((name, decade.returns.get_returns(portfolio), decade.returns.inflation));
+    }
+
+    let mut header: Vec<String> = ["Returns", "US equity", "International", "Bonds", "Cash"]
+        .iter().map(|v| v.to_string()).collect();
+    header.extend(input.asset_class_names[portfolio::NUM_BUILT_IN_ASSET_CLASSES..].iter().cloned());
+    header.extend(["Inflation", "Result", "Ending balance"].iter().map(|v| v.to_string()));
+    let mut table: Vec<Vec<String>> = vec![header];
+    for (name, returns, inflation) in presets.iter() {
+        let results = simulate::run_simulation_with_returns(input, returns, *inflation)?;
+        let mut row = vec![name.clone()];
+        row.extend(returns[..portfolio.num_asset_classes].iter().map(|v| format!("{:.1}%", v)));
+        row.push(format!("{:.1}%", inflation));
+        row.push(match results.get_depletion_age() {
+            Some(age) => tr!("Runs out at {}", age),
+            None => "Succeeds".to_string(),
+        });
+        row.push(num_with_commas(results.monthly_snapshot.last().map_or(0.0, |v| v.balance) as u64));
+        table.push(row);
+    }
+
+    outln!(out);
+    outln!(out, "{}", tr!("Uniform returns with the average real returns and inflation of each decade (annualized):"));
+    out.push_str(&format_table(table));
+    Ok(())
+}
+
+// the shift of the retirement date on the curve, such as "(1 year 3 months
+// later)"
+fn format_retirement_shift(months: i32) -> String {
+    if months == 0 {
+        return tr!("(as planned)").to_string();
+    }
+    let plural = |n: u32, unit: &str, units: &str| format!("{} {}", n, if n == 1 { tr!(unit) } else { tr!(units) });
+    let (years, remainder) = (months.unsigned_abs() / 12, months.unsigned_abs() % 12);
+    let mut parts = Vec::new();
+    if years > 0 {
+        parts.push(plural(years, "year", "years"));
+    }
+    if remainder > 0 {
+        parts.push(plural(remainder, "month", "months"));
+    }
+    match months < 0 {
+        true => tr!("({} earlier)", parts.join(" ")),
+        false => tr!("({} later)", parts.join(" ")),
+    }
+}
+
+// the historical success rate for retirement dates either side of the
+// planned one, in monthly or quarterly steps, with a bar for each so the
+// steepness of the curve shows. Shifts that leave every retirement date
+// where it was, because no one retires before today, are left out.
+fn print_retirement_curve(out: &mut String, input: &Input, historical_scan: &mut HistoricalScan) -> Result<(), String> {
+    let step = input.analysis.retirement_curve_months as i32;
+    if step == 0 {
+        return Ok(());
+    }
+    let context = simulate::PlanContext::new(input);
+    if context.retirees.iter().all(|v| v.retirement_date <= context.start_date) {
+        return Ok(());
+    }
+
+    let max_months = (input.analysis.retirement_curve_years * 12) as i32;
+    let mut shifts: Vec<i32> = Vec::new();
+    let mut previous_dates = Vec::new();
+    for months in (-max_months..=max_months).step_by(step as usize) {
+        let dates: Vec<NaiveDate> = context.with_retirement_shift(months).retirees.iter()
+            .map(|v| v.retirement_date)
+            .collect();
+        if dates != previous_dates {
+            shifts.push(months);
+            previous_dates = dates;
+        }
+    }
+    let curve_results = scan::run_retirement_curve(historical_scan, input, &shifts)?;
+
+    let mut table: Vec<Vec<String>> = Vec::new();
+    table.push(vec!["Retires".to_string(), "".to_string(), "Success".to_string(),
+                    "Median ending".to_string(), "".to_string()]);
+    for (months, results) in shifts.iter().zip(curve_results.iter()) {
+        let retirement_date = context.with_retirement_shift(*months).retirement_date;
+        let success_rate = results.success_rate();
+        table.push(vec![
+            retirement_date.format("%m/%d/%Y").to_string(),
+            format_retirement_shift(*months),
+            format!("{:.1}%", success_rate),
+            num_with_commas(results.get_ending_balance_percentile(50.0) as u64),
+            "#".repeat((success_rate / 2.0).round() as usize),
+        ]);
+    }
+
+    outln!(out);
+    outln!(out, "{}", tr!("Success rate by retirement date:"));
+    out.push_str(&format_table(table));
+
+    Ok(())
+}
+
+// compares the ending balances of the Monte Carlo simulation with the
+// historical ones at the same percentiles, to check that the expected returns
+// and standard deviations are consistent with history. Each historical
+// percentile is labelled with the run closest to it.
+fn print_scan_comparison(out: &mut String, historical_results: &scan::ScanResults, monte_carlo_results: &scan::ScanResults) {
+    let percentiles = [5.0, 10.0, 25.0, 50.0, 75.0, 90.0, 95.0];
+
+    let mut table: Vec<Vec<String>> = Vec::new();
+    table.push(vec!["Percentile".to_string(), "Historical".to_string(), "Years".to_string(),
+                    "Monte Carlo".to_string(), "Difference".to_string()]);
+    for percentile in percentiles.iter() {
+        let historical = historical_results.get_ending_balance_percentile(*percentile);
+        let monte_carlo = monte_carlo_results.get_ending_balance_percentile(*percentile);
+        let sorted = &historical_results.sorted_indices;
+        let closest = sorted[((sorted.len() - 1) as f32 * percentile / 100.0).round() as usize];
+        let scenario = &historical_results.scenario_results[closest];
+        table.push(vec![
+            format!("{:.0}%", percentile),
+            num_with_commas(historical as u64),
+            format!("{}-{}", scenario.starting_year, scenario.ending_year),
+            num_with_commas(monte_carlo as u64),
+            signed_with_commas(monte_carlo - historical),
+        ]);
+    }
+    table.push(vec![
+        "Success".to_string(),
+        format!("{:.1}%", historical_results.success_rate()),
+        "".to_string(),
+        format!("{:.1}%", monte_carlo_results.success_rate()),
+        format!("{:+.1}%", monte_carlo_results.success_rate() - historical_results.success_rate()),
+    ]);
+
+    outln!(out);
+    outln!(out, "{}", tr!("Monte Carlo compared with historical ending balances:"));
+    out.push_str(&format_table(table));
+}
+
+// whether the uniform run lived off the real returns
+fn print_principal(out: &mut String, simulation_results: &simulate::SimulationResults) {
+    let Some(principal) = simulation_results.retirement_balance else {
+        return;
+    };
+    let ending_balance = simulation_results.monthly_snapshot.last().map_or(0.0, |v| v.balance);
+    outln!(out, "{}", tr!("Principal {}: ${} at retirement, ${} at the end",
+           if simulation_results.keeps_principal() {tr!("kept")} else {tr!("spent")},
+           num_with_commas(principal as u64), num_with_commas(ending_balance as u64)));
+}
+
+// what the expense ratios and the advisor fee cost the uniform run, from
+// the same run without them
+fn print_fees(out: &mut String, input: &Input, simulation_results: &simulate::SimulationResults) {
+    let portfolio = &input.portfolio;
+    let asset_classes = &portfolio.asset_classes[..portfolio.num_asset_classes];
+    if portfolio.advisor_fee_percent == 0.0 && asset_classes.iter().all(|v| v.expense_ratio == 0.0) {
+        return;
+    }
+    let mut no_fees = input.clone();
+    no_fees.portfolio.advisor_fee_percent = 0.0;
+    for asset_class in no_fees.portfolio.asset_classes.iter_mut() {
+        asset_class.expense_ratio = 0.0;
+    }
+    let Ok(no_fees_results) = simulate::run_simulation(&no_fees) else {
+        return;
+    };
+    let ending_balance = simulation_results.monthly_snapshot.last().map_or(0.0, |v| v.balance);
+    let no_fees_balance = no_fees_results.monthly_snapshot.last().map_or(0.0, |v| v.balance);
+    outln!(out, "{}", tr!("Investment fees: ${} less at the end than without fees",
+           num_with_commas((no_fees_balance - ending_balance).max(0.0) as u64)));
+}
+
+// how much of the uniform run was paid with the reverse mortgage line of
+// credit, if any of it was
+fn print_reverse_mortgage(out: &mut String, simulation_results: &simulate::SimulationResults) {
+    let Some(first_draw) = simulation_results.monthly_snapshot.iter().find(|v| v.credit_draws > 0.0) else {
+        return;
+    };
+    let last = simulation_results.monthly_snapshot.last().unwrap();
+    let months = simulation_results.months_per_step as f32;
+    let drawn: f32 = simulation_results.monthly_snapshot.iter().map(|v| v.credit_draws * months).sum();
+    outln!(out, "{}", tr!("Reverse mortgage: ${} drawn from age {}, ${} owed and ${} of credit left at the end",
+           num_with_commas(drawn as u64),
+           utils::get_age(&simulation_results.retirees[0].date_of_birth, &first_draw.date),
+           num_with_commas(last.reverse_mortgage_balance as u64),
+           num_with_commas(last.credit_available as u64)));
+}
+
+// one row per simulation, so the results can be compared without scrolling
+// back through each section
+fn print_run_summary(out: &mut String,
+                     input: &Input,
+                     uniform_results: &simulate::SimulationResults,
+                     historical_results: &scan::ScanResults,
+                     monte_carlo_results: &scan::ScanResults) {
+    let depletion_age = |v: Option<u32>| v.map_or("-".to_string(), |v| v.to_string());
+    let perpetuity = input.analysis.perpetuity;
+
+    let mut table: Vec<Vec<String>> = Vec::new();
+    let mut heading = vec!["Simulation".to_string(), "Success".to_string(), "Median ending".to_string(),
+                           "Worst ending".to_string(), "Earliest depletion age".to_string()];
+    if perpetuity {
+        heading.push("Principal kept".to_string());
+    }
+    table.push(heading);
+    let ending_balance = uniform_results.monthly_snapshot.last().map_or(0.0, |v| v.balance);
+    let mut row = vec![
+        "Uniform".to_string(),
+        format!("{:.1}%", if uniform_results.is_depleted() { 0.0 } else { 100.0 }),
+        num_with_commas(ending_balance as u64),
+        num_with_commas(ending_balance as u64),
+        depletion_age(uniform_results.get_depletion_age()),
+    ];
+    if perpetuity {
+        row.push(format!("{:.1}%", if uniform_results.keeps_principal() { 100.0 } else { 0.0 }));
+    }
+    table.push(row);
+    for (name, results) in [("Historical", historical_results), ("Monte Carlo", monte_carlo_results)] {
+        let earliest_depletion = results.scenario_results.iter()
+            .filter_map(|v| v.simulation_results.get_depletion_age())
+            .min();
+        let mut row = vec![
+            name.to_string(),
+            format!("{:.1}%", results.success_rate()),
+            num_with_commas(results.get_ending_balance_percentile(50.0) as u64),
+            num_with_commas(results.get_ending_balance_percentile(0.0) as u64),
+            depletion_age(earliest_depletion),
+        ];
+        if perpetuity {
+            row.push(format!("{:.1}%", results.principal_kept_rate()));
+        }
+        table.push(row);
+    }
+
+    outln!(out);
+    outln!(out, "{}", tr!("-= Summary =-"));
+    outln!(out);
+    out.push_str(&format_table(table));
+    print_outcome_bands(out, historical_results, monte_carlo_results);
+}
+
+// the actual balance the simulations started from, and whether the
+// historical success rate is still at or above the floor. Returns true when
+// it's below.
+fn print_tracking(out: &mut String, tracking: &Tracking, historical_results: &scan::ScanResults) -> bool {
+    outln!(out);
+    outln!(out, "{}", tr!("-= Plan tracking =-"));
+    outln!(out);
+    if let (Some(file), Some((date, balance))) = (&tracking.actuals_file, tracking.latest_balance) {
+        outln!(out, "{}", tr!("Started from the actual balance of ${} on {} in {}, {:+.1}% from the ${} in the configuration",
+               num_with_commas(balance as u64), date.format("%m/%d/%Y"), file,
+               (balance / tracking.configured_balance - 1.0) * 100.0, num_with_commas(tracking.configured_balance as u64)));
+    }
+    let Some(floor) = tracking.success_floor else {
+        return false;
+    };
+    let success_rate = historical_results.success_rate();
+    if success_rate < floor {
+        outln!(out, "{}", tr!("Alert: the historical success rate of {:.1}% is below the floor of {:.1}%, time to revisit the plan",
+               success_rate, floor));
+        return true;
+    }
+    outln!(out, "{}", tr!("The historical success rate of {:.1}% is at or above the floor of {:.1}%", success_rate, floor));
+    false
+}
+
+// how many runs of each scan ran out of money at what ages, and how much the
+// others had left
+fn print_outcome_bands(out: &mut String,
+                       historical_results: &scan::ScanResults,
+                       monte_carlo_results: &scan::ScanResults) {
+    let scans = [historical_results, monte_carlo_results];
+    let counts = scans.map(|v| v.get_outcome_counts());
+    let cell = |results: &scan::ScanResults, count: usize| match results.num_simulations {
+        0 => "-".to_string(),
+        n => format!("{} ({:.1}%)", count, count as f32 / n as f32 * 100.0),
+    };
+
+    let mut table: Vec<Vec<String>> = Vec::new();
+    table.push(vec!["Outcome".to_string(), "Historical".to_string(), "Monte Carlo".to_string()]);
+    for (i, (_, label)) in scan::OUTCOME_BANDS.iter().enumerate() {
+        table.push(vec![
+            label.to_string(),
+            cell(historical_results, counts[0][i] as usize),
+            cell(monte_carlo_results, counts[1][i] as usize),
+        ]);
+    }
+    if scans.iter().any(|v| !v.errors.is_empty()) {
+        table.push(vec![
+            "Stopped with an error".to_string(),
+            cell(historical_results, historical_results.errors.len()),
+            cell(monte_carlo_results, monte_carlo_results.errors.len()),
+        ]);
+    }
+
+    outln!(out);
+    outln!(out, "{}", tr!("Outcomes:"));
+    out.push_str(&format_table(table));
+}
+
+// runs the historical simulation for every combination of social security
+// claiming ages, and prints the ones with the best success rate and median
+// ending balance
+fn print_claiming_ages(out: &mut String, input: &Input) -> Result<(), String> {
+    // ages that have already passed can't be chosen any more, unless the
+    // retiree already claimed at that age
+    let today = chrono::Utc::now().naive_utc().date();
+    let mut claiming_ages: Vec<Vec<u32>> = vec![Vec::new()];
+    for retiree in input.retirees.iter() {
+        let current_age = utils::get_age(&retiree.date_of_birth, &today);
+        let choices: Vec<u32> = (62..=70)
+            .filter(|v| *v >= current_age || *v == retiree.social_security_age)
+            .collect();
+        claiming_ages = claiming_ages.iter()
+            .flat_map(|ages| choices.iter().map(move |age| [ages.clone(), vec![*age]].concat()))
+            .collect();
+    }
+
+    let mut historical_scan = HistoricalScan::new()
+        .map_err(|err| format!("Error parsing historical returns: {}", err))?;
+    let sweep_results = scan::run_social_security_sweep(&mut historical_scan, input, &claiming_ages)
+        .map_err(|err| format!("Error running historical simulation: {}", err))?;
+
+    // best first, by success rate and then median ending balance
+    let mut ranked: Vec<(&Vec<u32>, f32, f32)> = claiming_ages.iter().zip(sweep_results.iter())
+        .map(|(ages, results)| (ages, results.success_rate(), results.get_ending_balance_percentile(50.0)))
+        .collect();
+    ranked.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap().then_with(|| b.2.partial_cmp(&a.2).unwrap()));
+
+    let current: Vec<u32> = input.retirees.iter().map(|v| v.social_security_age).collect();
+    let mut table: Vec<Vec<String>> = Vec::new();
+    let mut heading: Vec<String> = input.retirees.iter().map(|v| v.name.clone()).collect();
+    heading.extend(["Success".to_string(), "Median ending".to_string(), "".to_string()]);
+    table.push(heading);
+    for (i, (ages, success_rate, median)) in ranked.iter().enumerate() {
+        // the top results and the configured ages
+        if i >= 10 && **ages != current {
+            continue;
         }
+        let mut row: Vec<String> = ages.iter().map(|v| v.to_string()).collect();
+        row.push(format!("{:.1}%", success_rate));
+        row.push(num_with_commas(*median as u64));
+        row.push(if **ages == current {"Current".to_string()} else {"".to_string()});
+        table.push(row);
     }
 
-    println!("{}", format_table(table));
-    
-    println!("Average return: {:.2}%", simulation_results.average_return);
+    outln!(out, "-= Social security claiming ages =-");
+    outln!(out);
+    outln!(out, "Historical simulation of the claiming ages from 62 to 70, best first:");
+    out.push_str(&format_table(table));
+
+    let (best, success_rate, median) = ranked[0];
+    outln!(out);
+    outln!(out, "Best claiming ages: {} (success rate {:.1}%, median ending balance ${})",
+           input.retirees.iter().zip(best.iter()).map(|(r, age)| format!("{} {}", r.name, age)).collect::<Vec<_>>().join(", "),
+           success_rate,
+           num_with_commas(median as u64));
+
+    Ok(())
+}
+
+// the Monte Carlo scan with the configured method, bootstrapping from the
+// historical years if it's set
+fn get_monte_carlo_scan(input: &Input, seed: Option<u64>, historical_scan: &HistoricalScan) -> MonteCarloScan {
+    let monte_carlo_scan = MonteCarloScan::new(seed);
+    match input.simulation.monte_carlo {
+        MonteCarloMethod::Parametric => monte_carlo_scan,
+        MonteCarloMethod::Bootstrap => monte_carlo_scan.with_bootstrap(historical_scan.get_return_paths().to_vec()),
+    }
+}
+
+// compares taking each pension that offers a lump sum as an annuity or as the
+// lump sum, changing one pension at a time
+fn print_pension_lump_sum(out: &mut String, input: &Input, seed: Option<u64>) -> Result<(), String> {
+    let today = chrono::Utc::now().naive_utc().date();
+    let mut historical_scan = HistoricalScan::new()
+        .map_err(|err| format!("Error parsing historical returns: {}", err))?;
+    let mut monte_carlo_scan = get_monte_carlo_scan(input, seed, &historical_scan);
+
+    outln!(out, "-= Pension lump sum or annuity =-");
+
+    let mut found = false;
+    for (i, retiree) in input.retirees.iter().enumerate() {
+        if retiree.pension_lump_sum <= 0.0 || retiree.pension_monthly_income <= 0.0 ||
+            utils::add_years(&retiree.date_of_birth, retiree.pension_age) < today {
+            continue;
+        }
+        found = true;
+
+        let mut table: Vec<Vec<String>> = Vec::new();
+        table.push(vec!["".to_string(), "Historical".to_string(), "Median ending".to_string(),
+                        "Monte Carlo".to_string(), "".to_string()]);
+        for take_lump_sum in [false, true] {
+            let mut choice = input.clone();
+            choice.retirees[i].pension_take_lump_sum = take_lump_sum;
+            let historical_results = historical_scan.run_scan(&choice)
+                .map_err(|err| format!("Error running historical simulation: {}", err))?;
+            let monte_carlo_results = monte_carlo_scan.run_scan(&choice)
+                .map_err(|err| format!("Error running monte carlo simulation: {}", err))?;
+
+            let label = match take_lump_sum {
+                false => format!("Annuity ${} per month", num_with_commas(retiree.pension_monthly_income as u64)),
+                true => format!("Lump sum ${}", num_with_commas(retiree.pension_lump_sum as u64)),
+            };
+            table.push(vec![
+                label,
+                format!("{:.1}%", historical_results.success_rate()),
+                num_with_commas(historical_results.get_ending_balance_percentile(50.0) as u64),
+                format!("{:.1}%", monte_carlo_results.success_rate()),
+                if take_lump_sum == retiree.pension_take_lump_sum {"Current".to_string()} else {"".to_string()},
+            ]);
+        }
+
+        outln!(out);
+        outln!(out, "{}'s pension, starting at {}. The annuity pays {:.1}% of the lump sum a year{}:",
+               retiree.name,
+               retiree.pension_age,
+               retiree.pension_monthly_income * 12.0 / retiree.pension_lump_sum * 100.0,
+               if retiree.pension_cola == PensionCola::Cpi {", adjusted for inflation"} else {""});
+        out.push_str(&format_table(table));
+    }
+
+    if !found {
+        return Err("No pension that hasn't started has a pension_lump_sum to compare".to_string());
+    }
+    outln!(out);
+    outln!(out, "Monte Carlo seed: {}", monte_carlo_scan.seed());
+
+    Ok(())
+}
+
+// compares rolling over the employer stock of each retiree who hasn't
+// retired yet with distributing it with NUA treatment, changing one retiree
+// at a time
+fn print_nua(out: &mut String, input: &Input, seed: Option<u64>) -> Result<(), String> {
+    let today = chrono::Utc::now().naive_utc().date();
+    let context = simulate::PlanContext::new(input);
+    let mut historical_scan = HistoricalScan::new()
+        .map_err(|err| format!("Error parsing historical returns: {}", err))?;
+    let mut monte_carlo_scan = get_monte_carlo_scan(input, seed, &historical_scan);
+
+    outln!(out, "-= Employer stock rollover or NUA =-");
+
+    let mut found = false;
+    for (i, (retiree, retiree_info)) in input.retirees.iter().zip(context.retirees.iter()).enumerate() {
+        let Some(stock) = retiree.employer_stock.as_ref() else {
+            continue;
+        };
+        if retiree_info.retirement_date < today {
+            continue;
+        }
+        found = true;
+
+        let mut table: Vec<Vec<String>> = Vec::new();
+        table.push(vec!["".to_string(), "Historical".to_string(), "Median ending".to_string(),
+                        "Monte Carlo".to_string(), "".to_string()]);
+        for nua in [false, true] {
+            let mut choice = input.clone();
+            choice.retirees[i].employer_stock.as_mut().unwrap().nua = nua;
+            let historical_results = historical_scan.run_scan(&choice)
+                .map_err(|err| format!("Error running historical simulation: {}", err))?;
+            let monte_carlo_results = monte_carlo_scan.run_scan(&choice)
+                .map_err(|err| format!("Error running monte carlo simulation: {}", err))?;
+
+            table.push(vec![
+                if nua {"NUA".to_string()} else {"Roll over".to_string()},
+                format!("{:.1}%", historical_results.success_rate()),
+                num_with_commas(historical_results.get_ending_balance_percentile(50.0) as u64),
+                format!("{:.1}%", monte_carlo_results.success_rate()),
+                if nua == stock.nua {"Current".to_string()} else {"".to_string()},
+            ]);
+        }
+
+        outln!(out);
+        outln!(out, "{}'s employer stock, ${} with a basis of ${}. NUA taxes the basis as income at retirement and ${} as capital gains:",
+               retiree.name,
+               num_with_commas(stock.value as u64),
+               num_with_commas(stock.basis as u64),
+               num_with_commas((stock.value - stock.basis) as u64));
+        out.push_str(&format_table(table));
+    }
+
+    if !found {
+        return Err("No retiree who hasn't retired has employer_stock to compare".to_string());
+    }
+    outln!(out);
+    outln!(out, "Monte Carlo seed: {}", monte_carlo_scan.seed());
+
+    Ok(())
+}
+
+// the amount of each asset class in each kind of account now and with the
+// suggested location, and what the suggested location changes in the
+// uniform simulation
+fn print_location_report(out: &mut String, summary: &mut ReportSummary, report: &location::LocationReport, input: &Input) {
+    outln!(out, "{}", tr!("Least taxed asset classes in the taxable accounts, highest expected returns in the Roth accounts and HSAs:"));
+    let mut table: Vec<Vec<String>> = vec![["Asset class", "Taxable now", "Taxable", "Tax-deferred", "Tax-free"]
+                                           .iter().map(|v| v.to_string()).collect()];
+    let mut holdings_json = Vec::new();
+    for (i, name) in input.asset_class_names.iter().enumerate() {
+        if (0..location::NUM_LOCATIONS).all(|v| report.current[v][i] + report.suggested[v][i] <= 0.0) {
+            continue;
+        }
+        table.push(vec![
+            input.get_asset_class_description(i).to_string(),
+            num_with_commas(report.current[location::TAXABLE][i] as u64),
+            num_with_commas(report.suggested[location::TAXABLE][i] as u64),
+            num_with_commas(report.suggested[location::TAX_DEFERRED][i] as u64),
+            num_with_commas(report.suggested[location::TAX_FREE][i] as u64),
+        ]);
+        holdings_json.push(Json::object(vec![
+            ("asset_class", Json::string(name)),
+            ("taxable_now", Json::Number(report.current[location::TAXABLE][i] as f64)),
+            ("taxable", Json::Number(report.suggested[location::TAXABLE][i] as f64)),
+            ("tax_deferred", Json::Number(report.suggested[location::TAX_DEFERRED][i] as f64)),
+            ("tax_free", Json::Number(report.suggested[location::TAX_FREE][i] as f64)),
+        ]));
+    }
+    out.push_str(&format_table(table));
+    outln!(out);
+    let signed = |v: f32| format!("{}${}", if v < 0.0 {"-"} else {""}, num_with_commas(v.abs() as u64));
+    outln!(out, "{}", tr!("The suggested location changes the ending balance by {} and the lifetime taxes by {}",
+           signed(report.suggested_ending_balance - report.ending_balance),
+           signed(report.suggested_taxes - report.taxes)));
+    summary.asset_location = Some(Json::object(vec![
+        ("holdings", Json::Array(holdings_json)),
+        ("ending_balance", Json::Number(report.ending_balance as f64)),
+        ("suggested_ending_balance", Json::Number(report.suggested_ending_balance as f64)),
+        ("lifetime_taxes", Json::Number(report.taxes as f64)),
+        ("suggested_lifetime_taxes", Json::Number(report.suggested_taxes as f64)),
+    ]));
+}
+
+fn print_glide_report(out: &mut String, report: &glide::GlideReport, input: &Input) {
+    let Some(required_starting_balance) = report.required_starting_balance else {
+        outln!(out, "{}", tr!("Warning: no balance today reaches a {:.0}% historical success rate, the savings glide report is skipped",
+               report.target_success_rate));
+        return;
+    };
+    outln!(out, "{}", tr!("Balance needed today for a {:.0}% historical success rate: ${}",
+             report.target_success_rate,
+             num_with_commas(required_starting_balance as u64)));
+    outln!(out);
+
+    let mut table: Vec<Vec<String>> = Vec::new();
+    table.push(vec!["Year".to_string(), "Age".to_string(), "Projected".to_string(),
+                    "Required".to_string(), "Gap".to_string(), "".to_string()]);
+
+    for year in report.years.iter() {
+        let age = utils::get_age(&input.retirees[0].date_of_birth, &year.date);
+        let gap = year.projected_balance - year.required_balance;
+        let status = match year.status {
+            glide::GlideStatus::OnTrack => "On track",
+            glide::GlideStatus::Watch => "Watch",
+            glide::GlideStatus::Behind => "Behind",
+        };
+        table.push(vec![
+            year.date.format("%Y").to_string(),
+            age.to_string(),
+            num_with_commas(year.projected_balance as u64),
+            num_with_commas(year.required_balance as u64),
+            format!("{}{}", if gap < 0.0 {"-"} else {""}, num_with_commas(gap.abs() as u64)),
+            status.to_string(),
+        ]);
+    }
+
+    out.push_str(&format_table(table));
 }
     
 ///////////////////////////////////////////////////////////////////////////
 // Running simulations
 ///////////////////////////////////////////////////////////////////////////
 
-fn run_scan<S: scan::Scannable>(input: &Input, scanner: &mut S) -> Result<scan::ScanResults, String> {
-    let results = scanner.run_scan(&input)?; 
+fn run_scan<S: scan::Scannable>(out: &mut String, input: &Input, scanner: &mut S) -> Result<scan::ScanResults, String> {
+    let results = scanner.run_scan(input)?; 
         
-    println!("Successful runs: {} of {} ({:.1}%)", results.num_successful,
+    outln!(out, "{}", tr!("Successful runs: {} of {} ({:.1}%)", results.num_successful,
              results.num_simulations,
-             results.num_successful as f32/(results.num_simulations as f32) * 100.0);
-    println!("Lowest ending balance: ${}", num_with_commas(results.min_balance as u64));
-    println!("Highest ending balance: ${}", num_with_commas(results.max_balance as u64));
+             results.num_successful as f32/(results.num_simulations as f32) * 100.0));
+    // the successful runs funded the full lifestyle, and the others may
+    // still have paid for the essentials by cutting the discretionary
+    // spending
+    if input.expenses.essential_monthly.is_some() {
+        outln!(out, "{}", tr!("Floor funded: {} of {} ({:.1}%), {} runs only cut discretionary spending", results.num_floor_funded,
+               results.num_simulations, results.floor_funded_rate(), results.num_floor_funded - results.num_successful));
+    }
+    if input.analysis.perpetuity {
+        outln!(out, "{}", tr!("Principal kept: {} of {} ({:.1}%)", results.num_principal_kept,
+               results.num_simulations, results.principal_kept_rate()));
+    }
+    outln!(out, "{}", tr!("Lowest ending balance: ${}", num_with_commas(results.min_balance as u64)));
+    outln!(out, "{}", tr!("Highest ending balance: ${}", num_with_commas(results.max_balance as u64)));
+    print_failure_depth(out, &results, input);
+    print_scenario_errors(out, &results);
+    print_anomalies(out, &results);
 
     Ok(results)
 }
 
-fn print_historical_result_details(results: &scan::ScanResults) {
-    println!();
-    println!("Scenarios (sorted by worst to best):");
+fn print_scenario_errors(out: &mut String, results: &scan::ScanResults) {
+    if let Some(first) = results.errors.first() {
+        outln!(out, "{}", tr!("Runs with errors (counted as failed): {}, first error: {}", results.errors.len(), first.message));
+    }
+}
+
+// notes about unusual returns, details are in the JSON output
+fn print_anomalies(out: &mut String, results: &scan::ScanResults) {
+    let count = results.count_anomalous_scenarios(scan::AnomalyKind::DataWrapAround);
+    if count > 0 {
+        outln!(out, "{}", tr!("Note: {} runs reached the end of the historical returns and continued from the start", count));
+    }
+    let count = results.count_anomalous_scenarios(scan::AnomalyKind::ReturnBelowTotalLoss);
+    if count > 0 {
+        outln!(out, "{}", tr!("Note: {} runs sampled annual returns below -100%, these were limited to -100%", count));
+    }
+}
+
+fn print_events(out: &mut String, events: &[report::Event]) {
+    outln!(out);
+    outln!(out, "{}", tr!("Events:"));
+    for event in events.iter() {
+        outln!(out, "    {} {}", event.date.format("%m/%d/%Y"), event.description);
+    }
+}
+
+// how badly the failed scenarios missed, measured by the expenses left unpaid
+// after the money ran out
+fn print_failure_depth(out: &mut String, results: &scan::ScanResults, input: &Input) {
+    if results.shortfalls.is_empty() {
+        return;
+    }
+
+    // there are no years of spending to compare to when expenses are negative
+    let annual_expenses = input.expenses.monthly * 12.0;
+    let describe = |amount: f32| {
+        if annual_expenses <= 0.0 {
+            return format!("${}", num_with_commas(amount as u64));
+        }
+        tr!("${} ({:.1} years of spending)", num_with_commas(amount as u64), amount / annual_expenses)
+    };
+
+    outln!(out, "{}", tr!("Unfunded expenses in failed runs:"));
+    outln!(out, "{}", tr!("    median {}", describe(utils::get_percentile(&results.shortfalls, 50.0))));
+    outln!(out, "{}", tr!("    90th percentile {}", describe(utils::get_percentile(&results.shortfalls, 90.0))));
+    outln!(out, "{}", tr!("    worst {}", describe(utils::get_percentile(&results.shortfalls, 100.0))));
+}
+
+fn print_historical_result_details(out: &mut String, results: &scan::ScanResults) {
+    outln!(out);
+    outln!(out, "{}", tr!("Scenarios (sorted by worst to best):"));
+    // the sparklines share the length of the longest scenario, so the ones
+    // that ran out of money end early
+    let length = results.scenario_results.iter()
+        .map(|v| v.simulation_results.monthly_snapshot.len())
+        .max()
+        .unwrap_or(0);
     for index in results.sorted_indices.iter() {
-        println!("    years {} to {}, ending balance ${}",
-                results.scenario_results[*index].starting_year,
-                results.scenario_results[*index].ending_year,
-                num_with_commas(results.scenario_results[*index].simulation_results.monthly_snapshot.last().unwrap().balance as u64));
+        let scenario = &results.scenario_results[*index];
+        let balances: Vec<f32> = scenario.simulation_results.monthly_snapshot.iter().map(|v| v.balance).collect();
+        outln!(out, "{}", tr!("    years {} to {}, ending balance {} {}",
+                scenario.starting_year,
+                scenario.ending_year,
+                format!("{:<12}", format!("${}", num_with_commas(balances.last().unwrap().max(0.0) as u64))),
+                utils::get_sparkline(&balances, length, 30)));
     }
 
     let worst_index = results.sorted_indices[0];
-    println!();
-    println!("Worst result was years {} to {}",
+    outln!(out);
+    outln!(out, "{}", tr!("Worst result was years {} to {}",
             results.scenario_results[worst_index].starting_year,
-            results.scenario_results[worst_index].ending_year);
+            results.scenario_results[worst_index].ending_year));
 }
 
-fn main() {
-    println!("Retirement Simulator!!!");
-    println!("Version {}", env!("CARGO_PKG_VERSION"));
-    println!();
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OutputFormat {
+    Text,
+    Json,
+    // a record for each scenario of the main scans as it finishes, then
+    // the JSON report
+    JsonLines,
+}
 
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        println!("Usage: retirement-simulator <input file>");
-        println!("Example: retirement-simulator retirement.yaml");
+// what to print, the full report unless a subcommand is given
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Command {
+    Report,
+    // the simulated tax return for a year
+    TaxReport(i32),
+    // the best social security claiming ages
+    ClaimingAges,
+    // taking the pensions as annuities or lump sums
+    PensionLumpSum,
+    // rolling over employer stock or distributing it with NUA treatment
+    Nua,
+    // checks the dataset and configuration
+    Doctor,
+    // combines the JSON reports of several runs
+    Merge,
+    // compares the actual balances with the projection of a JSON report
+    Reconcile,
+}
+
+// command line options
+#[derive(Debug)]
+struct Options {
+    command: Command,
+    // empty for merge and reconcile, which read reports instead
+    input_file: String,
+    // the JSON reports to combine, for merge, or the report and the CSV of
+    // actual balances, for reconcile
+    reports: Vec<String>,
+    force: bool,
+    seed: Option<u64>,
+    format: OutputFormat,
+    export_returns: Option<String>,
+    // checks the invariants of every simulation
+    check: bool,
+    // applies the configured crash to the balance before the simulations
+    crash: bool,
+    // the language of the text report
+    language: i18n::Language,
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut files = Vec::new();
+    let mut force = false;
+    let mut seed = None;
+    let mut format = OutputFormat::Text;
+    let mut export_returns = None;
+    let mut check = false;
+    let mut crash = false;
+    let mut language = i18n::Language::English;
+
+    let mut iter = args.iter().skip(1).peekable();
+    let command = match iter.peek().map(|v| v.as_str()) {
+        Some("tax-report") => {
+            iter.next();
+            let value = iter.next().ok_or("tax-report requires a year")?;
+            Command::TaxReport(value.parse::<i32>().map_err(|_| format!("Invalid year: {}", value))?)
+        },
+        Some("claiming-ages") => {
+            iter.next();
+            Command::ClaimingAges
+        },
+        Some("pension-lump-sum") => {
+            iter.next();
+            Command::PensionLumpSum
+        },
+        Some("nua") => {
+            iter.next();
+            Command::Nua
+        },
+        Some(command @ ("doctor" | "merge")) if !cfg!(feature = "tools") => {
+            return Err(format!("{} needs the tools feature", command));
+        },
+        Some("doctor") => {
+            iter.next();
+            Command::Doctor
+        },
+        Some("merge") => {
+            iter.next();
+            Command::Merge
+        },
+        Some("reconcile") => {
+            iter.next();
+            Command::Reconcile
+        },
+        _ => Command::Report,
+    };
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--force" => force = true,
+            "--check" => check = true,
+            "--seed" => {
+                let value = iter.next().ok_or("--seed requires a value")?;
+                seed = Some(value.parse::<u64>().map_err(|_| format!("Invalid seed: {}", value))?);
+            },
+            "--format" => {
+                format = match iter.next().map(|v| v.as_str()) {
+                    Some("text") => OutputFormat::Text,
+                    Some("json") => OutputFormat::Json,
+                    Some("jsonl") => OutputFormat::JsonLines,
+                    _ => return Err("--format must be text, json or jsonl".to_string()),
+                };
+            },
+            "--export-returns" => {
+                let value = iter.next().ok_or("--export-returns requires a file name")?;
+                export_returns = Some(value.to_string());
+            },
+            "--crash" => {
+                match iter.next().map(|v| v.as_str()) {
+                    Some("now") => crash = true,
+                    _ => return Err("--crash must be now".to_string()),
+                }
+            },
+            "--language" => {
+                let value = iter.next().ok_or("--language requires a value")?;
+                language = i18n::Language::parse(value)?;
+            },
+            _ if arg.starts_with("--") => return Err(format!("Unknown option: {}", arg)),
+            _ => files.push(arg.to_string()),
+        }
+    }
+
+    if command != Command::Report && format != OutputFormat::Text {
+        return Err("tax-report, claiming-ages, pension-lump-sum, nua, doctor, merge and reconcile only have text output".to_string());
+    }
+    if command != Command::Report && check {
+        return Err("--check only applies to the simulation report".to_string());
+    }
+    if command != Command::Report && crash {
+        return Err("--crash only applies to the simulation report".to_string());
+    }
+    if command != Command::Report && language != i18n::Language::English {
+        return Err("--language only applies to the simulation report".to_string());
+    }
+
+    if command == Command::Merge {
+        if files.len() < 2 {
+            return Err("merge requires at least two reports".to_string());
+        }
+        return Ok(Options {command, input_file: String::new(), reports: files, force, seed, format, export_returns, check, crash, language});
+    }
+    if command == Command::Reconcile {
+        if files.len() != 2 {
+            return Err("reconcile requires a JSON report and a CSV file of actual balances".to_string());
+        }
+        return Ok(Options {command, input_file: String::new(), reports: files, force, seed, format, export_returns, check, crash, language});
+    }
+
+    // the doctor checks the template when no configuration is given
+    if command == Command::Doctor && files.is_empty() {
+        files.push("input.yaml".to_string());
+    }
+    if files.len() > 1 {
+        return Err(format!("Unexpected argument: {}", files[1]));
+    }
+    let input_file = files.pop().ok_or("No input file")?;
+    Ok(Options {command, input_file, reports: Vec::new(), force, seed, format, export_returns, check, crash, language})
+}
+
+// the returns applied in each step of a simulation
+fn get_returns_json(input: &Input, simulation_results: &simulate::SimulationResults) -> Json {
+    Json::Array(simulation_results.monthly_snapshot.iter().map(|v| {
+        let mut fields = vec![("date", Json::String(v.date.to_string()))];
+        for (name, value) in input.asset_class_names.iter().zip(simulation_results.get_step_returns(v).iter()) {
+            fields.push((name.as_str(), Json::Number(*value as f64)));
+        }
+        Json::object(fields)
+    }).collect())
+}
+
+// the balance projected at the start of each step of a simulation and the
+// deposits less withdrawals in it, for comparing with the actual balances
+// later. The balances are in the dollars of the start, so the inflation
+// they're adjusted with is saved too.
+fn get_projection_json(input: &Input, simulation_results: &simulate::SimulationResults) -> Json {
+    let months = simulation_results.months_per_step as f32;
+    let mut balance = input.portfolio.balance;
+    let mut steps = Vec::new();
+    for v in simulation_results.monthly_snapshot.iter() {
+        steps.push(Json::object(vec![
+            ("date", Json::String(v.date.to_string())),
+            ("balance", Json::Number(balance as f64)),
+            ("net_deposits", Json::Number((v.net_deposits * months) as f64)),
+        ]));
+        balance = v.balance;
+    }
+    Json::object(vec![
+        ("inflation", Json::Number(input.portfolio.expected_inflation as f64)),
+        ("steps", Json::Array(steps)),
+    ])
+}
+
+// adds the returns applied in each step of a simulation to a CSV export
+fn add_returns_to_csv(csv: &mut String, run: &str, scenario: &str, simulation_results: &simulate::SimulationResults,
+                      num_asset_classes: usize) {
+    for v in simulation_results.monthly_snapshot.iter() {
+        let returns: Vec<String> = simulation_results.get_step_returns(v)[..num_asset_classes].iter().map(|r| r.to_string()).collect();
+        outln!(csv, "{},{},{},{}", run, scenario, v.date, returns.join(","));
+    }
+}
+
+// scenarios formatted by each thread at a time when exporting returns
+const RETURNS_CSV_CHUNK: usize = 64;
+
+// writes the returns of a run's scenarios to a CSV export. A large scan has
+// millions of rows, so they're formatted on every core a batch of scenarios
+// at a time and written in order as each batch finishes, rather than held in
+// memory until the end.
+fn write_returns_csv(writer: &mut impl Write, run: &str, num_asset_classes: usize,
+                     scenarios: &[(String, &simulate::SimulationResults)]) -> io::Result<()> {
+    let num_threads = thread::available_parallelism().map_or(1, |v| v.get());
+    for batch in scenarios.chunks(num_threads * RETURNS_CSV_CHUNK) {
+        let chunks: Vec<String> = thread::scope(|s| {
+            let handles: Vec<_> = batch.chunks(RETURNS_CSV_CHUNK).map(|chunk| s.spawn(move || {
+                let mut csv = String::new();
+                for (scenario, simulation_results) in chunk.iter() {
+                    add_returns_to_csv(&mut csv, run, scenario, simulation_results, num_asset_classes);
+                }
+                csv
+            })).collect();
+            handles.into_iter().map(|v| v.join().unwrap()).collect()
+        });
+        for csv in chunks.iter() {
+            writer.write_all(csv.as_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+// results of a scan for the machine-readable output
+struct ScanSummary {
+    num_simulations: u32,
+    num_successful: u32,
+    success_rate: f32,
+    num_principal_kept: u32,
+    num_floor_funded: u32,
+    floor_funded_rate: f32,
+    outcome_counts: [u32; scan::OUTCOME_BANDS.len()],
+    anomalies: Vec<scan::Anomaly>,
+    errors: Vec<scan::ScenarioError>,
+    scenarios: Vec<Json>,
+}
+
+impl ScanSummary {
+    // the scenarios are left out when they were already streamed
+    fn new(results: &scan::ScanResults, has_scenarios: bool) -> Self {
+        ScanSummary {
+            num_simulations: results.num_simulations,
+            num_successful: results.num_successful,
+            success_rate: results.success_rate(),
+            num_principal_kept: results.num_principal_kept,
+            num_floor_funded: results.num_floor_funded,
+            floor_funded_rate: results.floor_funded_rate(),
+            outcome_counts: results.get_outcome_counts(),
+            anomalies: results.anomalies.clone(),
+            errors: results.errors.clone(),
+            scenarios: if has_scenarios {
+                results.scenario_results.iter().map(|v| v.to_json()).collect()
+            }
+            else {
+                Vec::new()
+            },
+        }
+    }
+
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("simulations", Json::Number(self.num_simulations as f64)),
+            ("successful", Json::Number(self.num_successful as f64)),
+            ("success_rate", Json::Number(self.success_rate as f64)),
+            ("principal_kept", Json::Number(self.num_principal_kept as f64)),
+            ("floor_funded", Json::Number(self.num_floor_funded as f64)),
+            ("floor_funded_rate", Json::Number(self.floor_funded_rate as f64)),
+            ("outcomes", Json::object(scan::OUTCOME_BANDS.iter().zip(self.outcome_counts)
+                .map(|((name, _), count)| (*name, Json::Number(count as f64)))
+                .collect())),
+            ("anomalies", Json::Array(self.anomalies.iter().map(|v| v.to_json()).collect())),
+            ("errors", Json::Array(self.errors.iter().map(|v| v.to_json()).collect())),
+            ("scenarios", Json::Array(self.scenarios.clone())),
+        ])
+    }
+}
+
+// a failed invariant check and the simulation it was found in
+struct CheckedViolation {
+    simulation: String,
+    violation: invariants::Violation,
+}
+
+impl CheckedViolation {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("simulation", Json::string(&self.simulation)),
+            ("violation", self.violation.to_json()),
+        ])
+    }
+}
+
+// the parts of the report collected for the machine-readable output
+#[derive(Default)]
+struct ReportSummary {
+    events: Vec<report::Event>,
+    uniform_ending_balance: Option<f32>,
+    uniform_principal_kept: bool,
+    uniform_returns: Option<Json>,
+    uniform_projection: Option<Json>,
+    // the actual balance and success floor, None without tracking
+    tracking: Option<Json>,
+    // the accounts and their allocations, None unless they're listed
+    accounts: Option<Json>,
+    // the balance before and after the crash, None without --crash now
+    crash: Option<Json>,
+    // the success rates with each stress test, None without them
+    stress_tests: Option<Json>,
+    // the plan and the benchmarks, None if they're turned off
+    benchmarks: Option<Json>,
+    // the suggested asset location and its outcome, None unless it's
+    // turned on and there's anything to move
+    asset_location: Option<Json>,
+    // the success rate fell below the floor
+    tracking_alert: bool,
+    historical: Option<ScanSummary>,
+    monte_carlo: Option<ScanSummary>,
+    // the failed invariant checks, None unless run with --check
+    violations: Option<Vec<CheckedViolation>>,
+    // number of simulations checked
+    num_checked: usize,
+}
+
+// applies the configured crash to the starting balance, with the allocation
+// for retirement if the household has already retired. Every simulation
+// starts from the crashed balance, so the runs aren't cached with the ones
+// without it.
+fn apply_crash(input: &mut Input) {
+    let crash = input.analysis.crash;
+    input.crash_balance = Some(input.portfolio.balance);
+    crash_portfolio(input, &crash);
+    input.config_text.push_str("\n# --crash now\n");
+}
+
+fn crash_portfolio(input: &mut Input, crash: &Crash) {
+    let context = simulate::PlanContext::new(input);
+    let is_retired = context.retirement_date <= context.start_date;
+    input.portfolio.age = utils::get_age_months(&input.retirees[0].date_of_birth, &context.start_date) as f32 / 12.0;
+    let mut returns = [0.0; portfolio::MAX_ASSET_CLASSES];
+    returns[portfolio::US_EQUITY] = crash.us_equity;
+    returns[portfolio::INTERNATIONAL_EQUITY] = crash.international_equity;
+    returns[portfolio::BONDS] = crash.bonds;
+    input.portfolio.grow(&returns, is_retired, 12);
+}
+
+// the accounts that make up the starting balance and their allocations, if
+// they're listed, with the portfolio they add up to
+fn print_accounts(out: &mut String, summary: &mut ReportSummary, input: &Input) {
+    if input.accounts.is_empty() {
         return;
-    } 
+    }
+    let portfolio = &input.portfolio;
+    let has_allocations = input.accounts[0].allocation.is_some();
+    let mut header: Vec<String> = ["Account", "Type", "Balance"].iter().map(|v| v.to_string()).collect();
+    if has_allocations {
+        header.extend(["US equity", "International", "Bonds", "Cash"].iter().map(|v| v.to_string()));
+        header.extend(input.asset_class_names[portfolio::NUM_BUILT_IN_ASSET_CLASSES..].iter().cloned());
+    }
+    let mut table: Vec<Vec<String>> = vec![header];
+    let mut add_row = |name: &str, account_type: &str, balance: f32, allocation: Option<&portfolio::Allocation>| {
+        let mut row = vec![name.to_string(), account_type.to_string(), num_with_commas(balance as u64)];
+        if let Some(allocation) = allocation {
+            row.extend(allocation.weights[..portfolio.num_asset_classes].iter().map(|v| format!("{:.0}%", v)));
+        }
+        table.push(row);
+    };
+    for account in input.accounts.iter() {
+        add_row(&account.name, account.account_type.get_description(), account.balance, account.allocation.as_ref());
+    }
+    // the accounts' allocations together, weighted by their balances
+    add_row("Total", "", portfolio.balance, has_allocations.then_some(&portfolio.pre_retirement_allocation));
 
-    let input = parse_input_file(&args[1]);
-    let input = match input {
-        Ok(v) => v,
-        Err(e) => {println!("{e}"); process::exit(1);}
+    outln!(out, "{}", tr!("-= Accounts =-"));
+    outln!(out);
+    out.push_str(&format_table(table));
+    outln!(out);
+    summary.accounts = Some(Json::Array(input.accounts.iter().map(|v| Json::object(vec![
+        ("name", Json::string(&v.name)),
+        ("type", Json::string(v.account_type.get_name())),
+        ("balance", Json::Number(v.balance as f64)),
+        ("allocation", v.allocation.map_or(Json::Null, |allocation| Json::object(
+            input.asset_class_names.iter().zip(allocation.weights)
+                .map(|(name, weight)| (name.as_str(), Json::Number(weight as f64)))
+                .collect()))),
+    ])).collect()));
+}
+
+fn print_crash(out: &mut String, summary: &mut ReportSummary, input: &Input) {
+    let Some(balance) = input.crash_balance else {
+        return;
     };
-    
-    println!("-= Simulation using uniform returns =-");
-    println!();
-    let simulation_results = simulate::run_simulation(&input).unwrap_or_else(|err| {
-        println!("Error running simulation: {}", err);
-        process::exit(1);
-    });
-    if simulation_results.monthly_snapshot[simulation_results.monthly_snapshot.len() - 1].balance == 0.0 {
-        println!("Retirement failed");
+    let crash = &input.analysis.crash;
+    outln!(out, "{}", tr!("-= Market crash now =-"));
+    outln!(out);
+    outln!(out, "{}", tr!("US equities {:.0}%, international equities {:.0}%, bonds {:.0}%",
+           crash.us_equity, crash.international_equity, crash.bonds));
+    outln!(out, "{}", tr!("The balance falls from ${} to ${} ({:.1}%) before every simulation", num_with_commas(balance as u64),
+           num_with_commas(input.portfolio.balance as u64),
+           if balance > 0.0 {(input.portfolio.balance / balance - 1.0) * 100.0} else {0.0}));
+    outln!(out);
+    summary.crash = Some(Json::object(vec![
+        ("balance_before", Json::Number(balance as f64)),
+        ("balance_after", Json::Number(input.portfolio.balance as f64)),
+    ]));
+}
+
+// runs all the simulations and adds their results to the report
+fn run_report(out: &mut String,
+              summary: &mut ReportSummary,
+              input: &Input,
+              options: &Options) -> Result<(), String> {
+    print_accounts(out, summary, input);
+    print_crash(out, summary, input);
+    outln!(out, "{}", tr!("-= Simulation using uniform returns =-"));
+    outln!(out);
+    let simulation_results = simulate::run_simulation(input)
+        .map_err(|err| format!("Error running simulation: {}", err))?;
+    let ending_balance = simulation_results.monthly_snapshot[simulation_results.monthly_snapshot.len() - 1].balance;
+    summary.uniform_ending_balance = Some(ending_balance);
+    summary.uniform_principal_kept = simulation_results.keeps_principal();
+    if simulation_results.retirement_date < simulation_results.monthly_snapshot[0].date {
+        outln!(out, "{}", tr!("Retired on {}, the simulation starts with withdrawals from the portfolio",
+               simulation_results.retirement_date.format("%m/%d/%Y")));
+    }
+    summary.uniform_returns = Some(get_returns_json(input, &simulation_results));
+    summary.uniform_projection = Some(get_projection_json(input, &simulation_results));
+    // written as each run finishes, it can be large
+    let export_error = |err: io::Error| format!("Can't write {}: {}", options.export_returns.as_ref().unwrap(), err);
+    let mut returns_csv = options.export_returns.as_ref()
+        .map(|fname| fs::File::create(fname).map(io::BufWriter::new))
+        .transpose()
+        .map_err(export_error)?;
+    let num_asset_classes = input.portfolio.num_asset_classes;
+    if let Some(csv) = returns_csv.as_mut() {
+        let header = format!("run,scenario,date,{}\n", input.asset_class_names.join(","));
+        csv.write_all(header.as_bytes()).map_err(export_error)?;
+        write_returns_csv(csv, "uniform", num_asset_classes, &[("0".to_string(), &simulation_results)]).map_err(export_error)?;
+    }
+    if simulation_results.is_depleted() {
+        outln!(out, "{}", tr!("Retirement failed"));
+        if input.expenses.essential_monthly.is_some() && simulation_results.is_floor_funded() {
+            outln!(out, "{}", tr!("The essential expenses were still funded by cutting the discretionary spending"));
+        }
     }
     else {
-        println!("Retirement succeeded!");
+        outln!(out, "{}", tr!("Retirement succeeded!"));
+    }
+    if input.analysis.perpetuity {
+        print_principal(out, &simulation_results);
+    }
+    print_reverse_mortgage(out, &simulation_results);
+    print_fees(out, input, &simulation_results);
+    print_simulation_results(out, &simulation_results);
+    if let Some(percent) = input.expenses.replacement_percent {
+        outln!(out, "{}", tr!("Retirement expenses: {:.0}% of the pay in the last year before retirement, after savings and payroll taxes, ${} per month",
+                 percent, num_with_commas(input.expenses.monthly.max(0.0) as u64)));
+    }
+    print_tax_brackets(out, &simulation_results);
+    print_paycheck(out, &simulation_results);
+    print_income_floor(out, input, &simulation_results);
+    print_purchasing_power(out, input, &simulation_results);
+    print_healthcare_costs(out, input, &simulation_results);
+    summary.events = report::get_events(&simulate::PlanContext::new(input), &simulation_results);
+    print_events(out, &summary.events);
+    print_pension_erosion(out, input);
+
+    if input.analysis.asset_location {
+        let location_report = location::run_location_report(input)
+            .map_err(|err| format!("Error running asset location report: {}", err))?;
+        outln!(out);
+        outln!(out, "{}", tr!("-= Asset location =-"));
+        outln!(out);
+        match location_report {
+            Some(report) => print_location_report(out, summary, &report, input),
+            None => outln!(out, "{}", tr!("Nothing to move, the balance is all in one of taxable, tax-deferred and tax-free accounts")),
+        }
+    }
+
+    outln!(out);
+    outln!(out, "{}", tr!("-= Historical simulation =-"));
+    outln!(out);
+    let mut historical_scan = HistoricalScan::new()
+        .map_err(|err| format!("Error parsing historical returns: {}", err))?;
+    // only the main scans are streamed, not the sweeps that rerun them
+    let is_streamed = options.format == OutputFormat::JsonLines;
+    if is_streamed {
+        historical_scan.set_stream(Some(scan::ScenarioStream::new("historical", Box::new(io::stdout()))));
+    }
+    let historical_results = run_scan(out, input, &mut historical_scan)
+        .map_err(|err| format!("Error running historical simulation: {}", err))?;
+    historical_scan.set_stream(None);
+    summary.historical = Some(ScanSummary::new(&historical_results, !is_streamed));
+    if let Some(csv) = returns_csv.as_mut() {
+        let scenarios: Vec<_> = historical_results.scenario_results.iter()
+            .map(|v| (v.starting_year.to_string(), &v.simulation_results))
+            .collect();
+        write_returns_csv(csv, "historical", num_asset_classes, &scenarios).map_err(export_error)?;
+    }
+    print_historical_result_details(out, &historical_results);
+    print_spending_sweep(out, input, &mut historical_scan)
+        .map_err(|err| format!("Error running spending sweep: {}", err))?;
+    print_early_retirement(out, input, &mut historical_scan)
+        .map_err(|err| format!("Error running early retirement stress test: {}", err))?;
+    print_longevity_scenarios(out, input, &mut historical_scan)
+        .map_err(|err| format!("Error running longevity scenarios: {}", err))?;
+    print_retirement_curve(out, input, &mut historical_scan)
+        .map_err(|err| format!("Error running retirement date curve: {}", err))?;
+    print_decade_presets(out, input, &historical_scan)
+        .map_err(|err| format!("Error running decade presets: {}", err))?;
+
+    let glide_report = glide::run_glide_report(input, &mut historical_scan)
+        .map_err(|err| format!("Error running savings glide report: {}", err))?;
+    if let Some(report) = glide_report {
+        outln!(out);
+        outln!(out, "{}", tr!("-= Savings glide report =-"));
+        outln!(out);
+        print_glide_report(out, &report, input);
+    }
+    
+    outln!(out);
+    outln!(out, "{}", tr!("-= Monte Carlo Simulation =-"));
+    outln!(out);
+    let mut monte_carlo_scan = get_monte_carlo_scan(input, options.seed, &historical_scan);
+    if input.simulation.monte_carlo == MonteCarloMethod::Bootstrap {
+        outln!(out, "{}", tr!("Returns drawn from historical years with replacement"));
+        outln!(out);
+    }
+    if input.simulation.mortality == Mortality::LifeTable {
+        outln!(out, "{}", tr!("Lifetimes drawn from the life tables"));
+        outln!(out);
+    }
+    if is_streamed {
+        monte_carlo_scan.set_stream(Some(scan::ScenarioStream::new("monte_carlo", Box::new(io::stdout()))));
+    }
+    let monte_carlo_results = run_scan(out, input, &mut monte_carlo_scan)
+        .map_err(|err| format!("Error running monte carlo simulation: {}", err))?;
+    monte_carlo_scan.set_stream(None);
+    summary.monte_carlo = Some(ScanSummary::new(&monte_carlo_results, !is_streamed));
+    if let Some(csv) = returns_csv.as_mut() {
+        let scenarios: Vec<_> = monte_carlo_results.scenario_results.iter()
+            .map(|v| (v.index.to_string(), &v.simulation_results))
+            .collect();
+        write_returns_csv(csv, "monte_carlo", num_asset_classes, &scenarios).map_err(export_error)?;
+    }
+
+    let worst = &monte_carlo_results.scenario_results[monte_carlo_results.sorted_indices[0]];
+    outln!(out);
+    outln!(out, "{}", tr!("Worst year (scenario {} of seed {}):", worst.index, monte_carlo_scan.seed()));
+    print_simulation_results(out, &worst.simulation_results);
+
+    print_stress_tests(out, summary, input, &mut historical_scan, &mut monte_carlo_scan,
+                       &historical_results, &monte_carlo_results)
+        .map_err(|err| format!("Error running stress tests: {}", err))?;
+    print_scan_comparison(out, &historical_results, &monte_carlo_results);
+    print_run_summary(out, input, &simulation_results, &historical_results, &monte_carlo_results);
+    print_benchmarks(out, summary, input, &mut historical_scan, &mut monte_carlo_scan,
+                     &historical_results, &monte_carlo_results)
+        .map_err(|err| format!("Error running benchmarks: {}", err))?;
+    if let Some(tracking) = input.tracking.as_ref() {
+        summary.tracking_alert = print_tracking(out, tracking, &historical_results);
+        let optional_number = |v: Option<f32>| v.map_or(Json::Null, |v| Json::Number(v as f64));
+        summary.tracking = Some(Json::object(vec![
+            ("balance_date", tracking.latest_balance.map_or(Json::Null, |v| Json::String(v.0.to_string()))),
+            ("balance", optional_number(tracking.latest_balance.map(|v| v.1))),
+            ("success_floor", optional_number(tracking.success_floor)),
+            ("success_rate", Json::Number(historical_results.success_rate() as f64)),
+            ("alert", Json::Bool(summary.tracking_alert)),
+        ]));
+    }
+
+    if options.check {
+        let mut violations = Vec::new();
+        add_violations(&mut violations, "Uniform returns".to_string(), &simulation_results);
+        for scenario in historical_results.scenario_results.iter() {
+            add_violations(&mut violations, format!("Historical {}", scenario.starting_year), &scenario.simulation_results);
+        }
+        for scenario in monte_carlo_results.scenario_results.iter() {
+            add_violations(&mut violations, format!("Monte Carlo scenario {} of seed {}", scenario.index, monte_carlo_scan.seed()),
+                           &scenario.simulation_results);
+        }
+        summary.num_checked = 1 + historical_results.scenario_results.len() + monte_carlo_results.scenario_results.len();
+        print_violations(out, &violations, summary.num_checked);
+        summary.violations = Some(violations);
+    }
+
+    if let Some(mut csv) = returns_csv {
+        csv.flush().map_err(export_error)?;
     }
-    print_simulation_results(&simulation_results);
 
-    println!();
-    println!("-= Historical simulation =-");
-    println!();
-    let mut historical_scan = HistoricalScan::new().unwrap_or_else(|err| {
-        println!("Error parsing historical returns: {}", err);
+    Ok(())
+}
+
+fn add_violations(violations: &mut Vec<CheckedViolation>, simulation: String, results: &simulate::SimulationResults) {
+    for violation in results.violations.iter() {
+        violations.push(CheckedViolation {simulation: simulation.clone(), violation: violation.clone()});
+    }
+}
+
+// the failed invariant checks, each with the simulation and the month it
+// was found in. Only the first few are printed, they are all in the JSON
+// output.
+fn print_violations(out: &mut String, violations: &[CheckedViolation], num_checked: usize) {
+    const MAX_PRINTED: usize = 50;
+
+    outln!(out);
+    outln!(out, "{}", tr!("-= Invariant check =-"));
+    outln!(out);
+    if violations.is_empty() {
+        outln!(out, "{}", tr!("Checked {} simulations, no violations", num_checked));
+        return;
+    }
+    let mut simulations: Vec<&str> = violations.iter().map(|v| v.simulation.as_str()).collect();
+    simulations.dedup();
+    outln!(out, "{}", tr!("Checked {} simulations, {} violations in {} of them:", num_checked, violations.len(), simulations.len()));
+    for v in violations.iter().take(MAX_PRINTED) {
+        outln!(out, "    {}, {}: {}: {}", v.simulation, v.violation.date.format("%m/%d/%Y"), v.violation.check, v.violation.message);
+    }
+    if violations.len() > MAX_PRINTED {
+        outln!(out, "{}", tr!("    ... and {} more", violations.len() - MAX_PRINTED));
+    }
+}
+
+// the whole report as a JSON document. The text report is included as is.
+fn format_json_report(warnings: &[Warning],
+                      summary: &ReportSummary,
+                      text: &str,
+                      error: Option<&str>) -> String {
+    let optional_scan = |v: &Option<ScanSummary>| v.as_ref().map_or(Json::Null, |v| v.to_json());
+    let json = Json::object(vec![
+        ("version", Json::string(env!("CARGO_PKG_VERSION"))),
+        ("error", error.map_or(Json::Null, Json::string)),
+        ("warnings", Json::Array(warnings.iter().map(|v| v.to_json()).collect())),
+        ("events", Json::Array(summary.events.iter().map(|v| v.to_json()).collect())),
+        ("uniform", summary.uniform_ending_balance.map_or(Json::Null, |v| Json::object(vec![
+            ("succeeded", Json::Bool(v > 0.0)),
+            ("ending_balance", Json::Number(v as f64)),
+            ("principal_kept", Json::Bool(summary.uniform_principal_kept)),
+            ("returns", summary.uniform_returns.clone().unwrap_or(Json::Null)),
+            ("projection", summary.uniform_projection.clone().unwrap_or(Json::Null)),
+        ]))),
+        ("historical", optional_scan(&summary.historical)),
+        ("monte_carlo", optional_scan(&summary.monte_carlo)),
+        ("tracking", summary.tracking.clone().unwrap_or(Json::Null)),
+        ("accounts", summary.accounts.clone().unwrap_or(Json::Null)),
+        ("crash", summary.crash.clone().unwrap_or(Json::Null)),
+        ("stress_tests", summary.stress_tests.clone().unwrap_or(Json::Null)),
+        ("benchmarks", summary.benchmarks.clone().unwrap_or(Json::Null)),
+        ("asset_location", summary.asset_location.clone().unwrap_or(Json::Null)),
+        ("violations", summary.violations.as_ref().map_or(Json::Null, |v| Json::Array(v.iter().map(|v| v.to_json()).collect()))),
+        ("report", Json::string(text)),
+    ]);
+    format!("{}\n", json)
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let options = parse_args(&args).unwrap_or_else(|err| {
+        println!("{}", err);
+        println!("Usage: retirement-simulator [--force] [--seed <number>] [--format text|json|jsonl] [--export-returns <csv file>] [--check] [--crash now] [--language en|es] <input file>");
+        println!("       retirement-simulator tax-report <year> <input file>");
+        println!("       retirement-simulator claiming-ages <input file>");
+        println!("       retirement-simulator pension-lump-sum [--seed <number>] <input file>");
+        println!("       retirement-simulator nua [--seed <number>] <input file>");
+        println!("       retirement-simulator doctor [<input file>]");
+        println!("       retirement-simulator merge <json report> <json report>...");
+        println!("       retirement-simulator reconcile <json report> <actual balances csv>");
+        println!("Example: retirement-simulator retirement.yaml");
         process::exit(1);
     });
-    let historical_results = run_scan(&input, &mut historical_scan).unwrap_or_else(|err| {
-        println!("Error running historical simulation: {}", err);
-        process::exit(1);
+    // JSON Lines ends with the JSON report, so it's otherwise the same
+    let is_json = options.format != OutputFormat::Text;
+
+    // the doctor reports problems with the configuration itself, so it runs
+    // before the configuration is read
+    #[cfg(feature = "tools")]
+    if options.command == Command::Doctor {
+        let mut text = String::new();
+        let is_ok = doctor::run_doctor(&mut text, &options.input_file);
+        print!("{}", text);
+        process::exit(if is_ok {0} else {1});
+    }
+
+    // merge only reads the reports of earlier runs
+    #[cfg(feature = "tools")]
+    if options.command == Command::Merge {
+        let mut text = String::new();
+        let result = merge::run_merge(&mut text, &options.reports);
+        print!("{}", text);
+        if let Err(err) = result {
+            println!("{}", err);
+            process::exit(1);
+        }
+        return;
+    }
+
+    // reconcile only reads the report of an earlier run and the balances
+    // since
+    if options.command == Command::Reconcile {
+        let mut text = String::new();
+        let result = reconcile::run_reconcile(&mut text, &options.reports[0], &options.reports[1]);
+        print!("{}", text);
+        if let Err(err) = result {
+            println!("{}", err);
+            process::exit(1);
+        }
+        return;
+    }
+
+    if !is_json {
+        println!("Retirement Simulator!!!");
+        println!("Version {}", env!("CARGO_PKG_VERSION"));
+        println!();
+    }
+
+    let input = parse_input_file(&options.input_file)
+        .and_then(|mut v| validate_input(&mut v).map(|warnings| (v, warnings)));
+    let input = input.map(|(mut v, warnings)| {
+        v.simulation.check_invariants = options.check;
+        if options.crash {
+            apply_crash(&mut v);
+        }
+        (v, warnings)
     });
-    print_historical_result_details(&historical_results);
-    
-    println!();
-    println!("-= Monte Carlo Simulation =-");
-    println!();
-    let mut monte_carlo_scan = MonteCarloScan::new();
-    let monte_carlo_results = run_scan(&input, &mut monte_carlo_scan).unwrap_or_else(|err| {
-        println!("Error running monte carlo simulation: {}", err);
+    let (input, warnings) = match input {
+        Ok(v) => v,
+        Err(e) => {
+            if is_json {
+                print!("{}", format_json_report(&[], &ReportSummary::default(), "", Some(&e)));
+            }
+            else {
+                println!("{e}");
+            }
+            process::exit(1);
+        }
+    };
+
+    if !is_json {
+        for warning in warnings.iter() {
+            println!("Warning: {}", warning.message);
+        }
+        if !warnings.is_empty() {
+            println!();
+        }
+    }
+
+    // the subcommands are quick enough to not need the cache
+    if options.command != Command::Report {
+        let mut text = String::new();
+        let result = match options.command {
+            Command::TaxReport(year) => simulate::run_simulation(&input)
+                .map_err(|err| format!("Error running simulation: {}", err))
+                .and_then(|results| tax_report::print_tax_report(&mut text, &input, &results, year)),
+            Command::PensionLumpSum => print_pension_lump_sum(&mut text, &input, options.seed),
+            Command::Nua => print_nua(&mut text, &input, options.seed),
+            _ => print_claiming_ages(&mut text, &input),
+        };
+        print!("{}", text);
+        if let Err(err) = result {
+            println!("{}", err);
+            process::exit(1);
+        }
+        return;
+    }
+
+    // Monte Carlo results can only be repeated when they are seeded
+    // the invariants are only checked when the simulations run, and so is
+    // the tracking floor. The streamed records of JSON Lines aren't saved.
+    let has_success_floor = input.tracking.as_ref().is_some_and(|v| v.success_floor.is_some());
+    let run_hash = options.seed.filter(|_| !options.check && !has_success_floor)
+        .filter(|_| options.format != OutputFormat::JsonLines)
+        .map(|seed| cache::get_run_hash(&input.config_text, seed, is_json, options.language));
+    if let Some(hash) = &run_hash {
+        // the export is only written when the simulations run
+        if !options.force && options.export_returns.is_none() {
+            if let Some(report) = cache::load_report(hash) {
+                // keep the JSON output a valid document
+                if is_json {
+                    eprintln!("(Results of an identical earlier run, use --force to run the simulations again)");
+                }
+                else {
+                    println!("(Results of an identical earlier run, use --force to run the simulations again)");
+                    println!();
+                }
+                print!("{}", report);
+                return;
+            }
+        }
+    }
+
+    i18n::set_language(options.language);
+    let mut text = String::new();
+    let mut summary = ReportSummary::default();
+    let result = run_report(&mut text, &mut summary, &input, &options);
+    let out = if is_json {
+        format_json_report(&warnings, &summary, &text, result.as_ref().err().map(|v| v.as_str()))
+    }
+    else {
+        text
+    };
+    print!("{}", out);
+    if let Err(err) = result {
+        if !is_json {
+            println!("{}", err);
+        }
         process::exit(1);
-    });
+    }
 
-    println!();
-    println!("Worst year:");
-    print_simulation_results(&monte_carlo_results.scenario_results[monte_carlo_results.sorted_indices[0]].simulation_results);
+    if let Some(hash) = &run_hash {
+        if let Err(err) = cache::save_report(hash, &out) {
+            eprintln!("Warning: can't save results for later runs: {}", err);
+        }
+    }
+    if summary.violations.is_some_and(|v| !v.is_empty()) || summary.tracking_alert {
+        process::exit(1);
+    }
 }
-
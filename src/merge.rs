@@ -0,0 +1,223 @@
+/**************************************************************************
+* merge.rs
+*
+* Combines the historical and Monte Carlo results saved in the JSON reports
+* of several runs, such as Monte Carlo batches run with different seeds on
+* different machines, into one set of results. The success rate and
+* percentiles are worked out again from all the scenarios, rather than
+* averaged.
+**************************************************************************/
+
+use std::fs;
+use yaml_rust::{Yaml, YamlLoader};
+use crate::{format_table, num_with_commas, utils};
+
+const PERCENTILES: [f32; 7] = [5.0, 10.0, 25.0, 50.0, 75.0, 90.0, 95.0];
+
+// a scan from one report, with what's needed to combine it with others
+#[derive(Debug, Clone)]
+struct SavedScan {
+    file: String,
+    // a batch is identified by how its returns were made. Historical scans
+    // have no seed, so they are all the same batch.
+    return_model: String,
+    seed: Option<String>,
+    // the plan the scenarios ran with, which has to match to combine them
+    monthly_expenses: f64,
+    months_per_step: u32,
+    num_simulations: u32,
+    num_successful: u32,
+    ending_balances: Vec<f32>,
+    num_errors: usize,
+    num_anomalies: usize,
+}
+
+// JSON numbers without a decimal point are integers
+fn get_number(yaml: &Yaml) -> Option<f64> {
+    match yaml {
+        Yaml::Integer(v) => Some(*v as f64),
+        v => v.as_f64(),
+    }
+}
+
+fn parse_scan(file: &str, yaml: &Yaml) -> Result<Option<SavedScan>, String> {
+    if yaml.is_null() || yaml.is_badvalue() {
+        return Ok(None);
+    }
+    let invalid = |field: &str| format!("{}: invalid value: {}", file, field);
+    let scenarios = yaml["scenarios"].as_vec()
+        .ok_or(format!("{}: no scenarios, it was saved by an older version", file))?;
+    let mut ending_balances = Vec::new();
+    for scenario in scenarios.iter() {
+        ending_balances.push(get_number(&scenario["ending_balance"]).ok_or(invalid("ending_balance"))? as f32);
+    }
+
+    // every scenario of a scan has the same metadata except for the death
+    // dates drawn from the life tables
+    let metadata = &scenarios.first().ok_or(format!("{}: no scenarios", file))?["metadata"];
+    Ok(Some(SavedScan {
+        file: file.to_string(),
+        return_model: metadata["return_model"].as_str().ok_or(invalid("return_model"))?.to_string(),
+        seed: metadata["seed"].as_str().map(|v| v.to_string()),
+        monthly_expenses: get_number(&metadata["monthly_expenses"]).ok_or(invalid("monthly_expenses"))?,
+        months_per_step: get_number(&metadata["months_per_step"]).ok_or(invalid("months_per_step"))? as u32,
+        num_simulations: get_number(&yaml["simulations"]).ok_or(invalid("simulations"))? as u32,
+        num_successful: get_number(&yaml["successful"]).ok_or(invalid("successful"))? as u32,
+        ending_balances,
+        num_errors: yaml["errors"].as_vec().map_or(0, |v| v.len()),
+        num_anomalies: yaml["anomalies"].as_vec().map_or(0, |v| v.len()),
+    }))
+}
+
+// the historical and Monte Carlo scans of a report written with
+// --format json
+fn parse_report(file: &str, text: &str) -> Result<(String, Option<SavedScan>, Option<SavedScan>), String> {
+    let docs = YamlLoader::load_from_str(text).map_err(|e| format!("Can't parse {}: {}", file, e))?;
+    let doc = docs.first().ok_or(format!("{} is empty", file))?;
+    if let Some(error) = doc["error"].as_str() {
+        return Err(format!("{} is the report of a run that failed: {}", file, error));
+    }
+    let version = doc["version"].as_str().ok_or(format!("{} isn't a JSON report", file))?;
+    let historical = parse_scan(file, &doc["historical"])?;
+    let monte_carlo = parse_scan(file, &doc["monte_carlo"])?;
+    Ok((version.to_string(), historical, monte_carlo))
+}
+
+// the scans to combine, leaving out any that repeat a batch that is already
+// included, with a note for each Monte Carlo batch left out. Every report
+// has the same historical scenarios, so only the first is used without a
+// note. Scans of different plans can't be combined.
+fn merge_scans(scans: Vec<SavedScan>, notes: &mut Vec<String>) -> Result<Vec<SavedScan>, String> {
+    let mut merged: Vec<SavedScan> = Vec::new();
+    for scan in scans.into_iter() {
+        if let Some(first) = merged.first() {
+            if scan.monthly_expenses != first.monthly_expenses || scan.months_per_step != first.months_per_step {
+                return Err(format!("{} and {} ran different plans", first.file, scan.file));
+            }
+        }
+        if let Some(same) = merged.iter().find(|v| v.return_model == scan.return_model && v.seed == scan.seed) {
+            if scan.seed.is_some() {
+                notes.push(format!("Left out the {} scenarios of {}, the same as {}", scan.return_model, scan.file, same.file));
+            }
+            continue;
+        }
+        merged.push(scan);
+    }
+    Ok(merged)
+}
+
+// a column of the results table for the combined scans
+fn get_column(scans: &[SavedScan]) -> Vec<String> {
+    if scans.is_empty() {
+        return vec!["".to_string(); PERCENTILES.len() + 6];
+    }
+    let num_simulations: u32 = scans.iter().map(|v| v.num_simulations).sum();
+    let num_successful: u32 = scans.iter().map(|v| v.num_successful).sum();
+    let ending_balances: Vec<f32> = scans.iter().flat_map(|v| v.ending_balances.iter().copied()).collect();
+
+    let mut column = vec![
+        scans.len().to_string(),
+        num_simulations.to_string(),
+        num_successful.to_string(),
+        format!("{:.1}%", num_successful as f32 / num_simulations as f32 * 100.0),
+    ];
+    for percentile in PERCENTILES.iter() {
+        column.push(num_with_commas(utils::get_percentile(&ending_balances, *percentile) as u64));
+    }
+    column.push(scans.iter().map(|v| v.num_errors).sum::<usize>().to_string());
+    column.push(scans.iter().map(|v| v.num_anomalies).sum::<usize>().to_string());
+    column
+}
+
+pub fn run_merge(out: &mut String, files: &[String]) -> Result<(), String> {
+    let mut versions = Vec::new();
+    let mut historical = Vec::new();
+    let mut monte_carlo = Vec::new();
+    for file in files.iter() {
+        let text = fs::read_to_string(file).map_err(|e| format!("Can't read {}: {}", file, e))?;
+        let (version, historical_scan, monte_carlo_scan) = parse_report(file, &text)?;
+        versions.push(version);
+        historical.extend(historical_scan);
+        monte_carlo.extend(monte_carlo_scan);
+    }
+
+    let mut notes = Vec::new();
+    versions.sort();
+    versions.dedup();
+    if versions.len() > 1 {
+        notes.push(format!("The reports are from different versions: {}", versions.join(", ")));
+    }
+    let historical = merge_scans(historical, &mut notes)?;
+    let monte_carlo = merge_scans(monte_carlo, &mut notes)?;
+    if let (Some(h), Some(m)) = (historical.first(), monte_carlo.first()) {
+        if h.monthly_expenses != m.monthly_expenses || h.months_per_step != m.months_per_step {
+            return Err(format!("{} and {} ran different plans", h.file, m.file));
+        }
+    }
+
+    let mut labels = vec!["Batches".to_string(), "Simulations".to_string(),
+                          "Successful".to_string(), "Success rate".to_string()];
+    labels.extend(PERCENTILES.iter().map(|v| format!("{:.0}% ending", v)));
+    labels.push("Errors".to_string());
+    labels.push("Anomalies".to_string());
+    let historical_column = get_column(&historical);
+    let monte_carlo_column = get_column(&monte_carlo);
+
+    let mut table: Vec<Vec<String>> = Vec::new();
+    table.push(vec!["".to_string(), "Historical".to_string(), "Monte Carlo".to_string()]);
+    for (i, label) in labels.into_iter().enumerate() {
+        table.push(vec![label, historical_column[i].clone(), monte_carlo_column[i].clone()]);
+    }
+
+    outln!(out, "Merged {} reports", files.len());
+    for note in notes.iter() {
+        outln!(out, "Note: {}", note);
+    }
+    outln!(out);
+    out.push_str(&format_table(table));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_scan(file: &str, seed: Option<&str>, ending_balances: Vec<f32>) -> SavedScan {
+        SavedScan {
+            file: file.to_string(),
+            return_model: "parametric".to_string(),
+            seed: seed.map(|v| v.to_string()),
+            monthly_expenses: 9000.0,
+            months_per_step: 1,
+            num_simulations: ending_balances.len() as u32,
+            num_successful: ending_balances.iter().filter(|v| **v > 0.0).count() as u32,
+            ending_balances,
+            num_errors: 0,
+            num_anomalies: 0,
+        }
+    }
+
+    #[test]
+    fn test_merge_scans() {
+        let mut notes = Vec::new();
+        let scans = vec![
+            get_scan("a.json", Some("1"), vec![0.0, 100.0]),
+            get_scan("b.json", Some("2"), vec![200.0, 300.0, 400.0]),
+            get_scan("c.json", Some("1"), vec![0.0, 100.0]),
+        ];
+        let merged = merge_scans(scans, &mut notes).unwrap();
+        assert_eq!(merged.len(), 2);
+        assert_eq!(notes.len(), 1);
+
+        // the success rate and median come from all the scenarios, not the
+        // average of the batches' 50% and 100%
+        let column = get_column(&merged);
+        assert_eq!(column[1], "5");
+        assert_eq!(column[3], "80.0%");
+        assert_eq!(column[4 + 3], "200");
+
+        let mut other_plan = get_scan("d.json", Some("3"), vec![100.0]);
+        other_plan.monthly_expenses = 8000.0;
+        assert!(merge_scans(vec![merged[0].clone(), other_plan], &mut notes).is_err());
+    }
+}
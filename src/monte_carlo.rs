@@ -4,59 +4,261 @@
 * Run a large number of random simulations
 **************************************************************************/
 
-use rand_distr::{Normal, Distribution};
-use crate::{Input, scan, simulate};
+use rand::{Rng, SeedableRng};
+use rand::rngs::OsRng;
+use rand_chacha::ChaCha12Rng;
+#[cfg(feature = "parametric")]
+use rand_distr::{Normal, StandardNormal};
+use chrono::NaiveDate;
+use crate::{mortality, scan, simulate, Input, Mortality};
+use crate::historical_scan::ReturnPath;
+use crate::portfolio::MAX_ASSET_CLASSES;
+#[cfg(feature = "parametric")]
+use crate::portfolio::{BONDS, CASH, INTERNATIONAL_EQUITY, US_EQUITY};
+
+// first of the ChaCha12 streams used for the lifetimes, far from the
+// scenarios' streams
+const MORTALITY_STREAMS: u64 = 1 << 63;
+
+// first of the streams used for whether the windfalls happen
+const WINDFALL_STREAMS: u64 = (1 << 63) + (1 << 62);
 
 pub struct MonteCarloScan {
+    seed: u64,
+
+    // historical years to draw from instead of the normal distributions
+    bootstrap_years: Option<Vec<ReturnPath>>,
+
+    // where each scenario is written as it finishes, if anywhere
+    stream: Option<scan::ScenarioStream>,
 }
 
 impl MonteCarloScan {
-    pub fn new() -> Self {
-        MonteCarloScan {}
+    // the same seed always produces the same scenarios. Without one a seed
+    // is taken from the operating system, so the scenarios are different
+    // every run.
+    pub fn new(seed: Option<u64>) -> Self {
+        let seed = seed.unwrap_or_else(|| OsRng.gen());
+        MonteCarloScan {seed, bootstrap_years: None, stream: None}
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    // each scenario has its own ChaCha12 stream, selected by the scenario
+    // index, so a scenario's returns only depend on the seed and its index
+    // and not on how many scenarios run or in what order
+    fn get_scenario_rng(&self, index: usize) -> ChaCha12Rng {
+        let mut rng = ChaCha12Rng::seed_from_u64(self.seed);
+        rng.set_stream(index as u64);
+        rng
+    }
+
+    // draws each year's returns from these historical years, with
+    // replacement. The asset classes of a year are drawn together, so their
+    // co-movement is kept without assuming a distribution. The returns are
+    // real, so the year's inflation comes with them.
+    pub fn with_bootstrap(mut self, years: Vec<ReturnPath>) -> Self {
+        self.bootstrap_years = Some(years);
+        self
+    }
+
+    // the scenarios of the following scans are also written to the
+    // stream, until it's set back to None
+    pub fn set_stream(&mut self, stream: Option<scan::ScenarioStream>) {
+        self.stream = stream;
     }
 
-    fn run_scenario(&mut self,
-                    input: &Input) -> Result<scan::Scenario, String> {
-        let mut simulation = simulate::Simulation::new(input);
+    // an annual return from a z-score drawn from the standard normal
+    // distribution. A return below -100% isn't possible so it's limited to
+    // -100% and recorded as an anomaly.
+    #[cfg(feature = "parametric")]
+    fn sample_return(zscore: f32,
+                     distribution: &Normal<f32>,
+                     name: &str,
+                     scenario: usize,
+                     year: u32,
+                     anomalies: &mut Vec<scan::Anomaly>) -> f32 {
+        let annual_return = distribution.from_zscore(zscore);
+        if annual_return < -100.0 {
+            anomalies.push(scan::Anomaly {
+                scenario,
+                kind: scan::AnomalyKind::ReturnBelowTotalLoss,
+                year,
+                message: format!("Sampled {} return of {:.1}% limited to -100%", name, annual_return),
+            });
+            return -100.0;
+        }
+        annual_return
+    }
+
+    // the lifetimes have their own streams so that drawing them doesn't
+    // change the returns of a scenario
+    fn get_mortality_rng(&self, index: usize) -> ChaCha12Rng {
+        let mut rng = ChaCha12Rng::seed_from_u64(self.seed);
+        rng.set_stream(MORTALITY_STREAMS + index as u64);
+        rng
+    }
+
+    fn get_windfall_rng(&self, index: usize) -> ChaCha12Rng {
+        let mut rng = ChaCha12Rng::seed_from_u64(self.seed);
+        rng.set_stream(WINDFALL_STREAMS + index as u64);
+        rng
+    }
 
-        let us_distribution = Normal::new(input.portfolio.us_equity_expected_returns,
-                                          input.portfolio.us_equity_standard_deviation).unwrap();
-        let international_distribution = Normal::new(input.portfolio.international_equity_expected_returns,
-                                          input.portfolio.international_equity_standard_deviation).unwrap();
-        let bonds_distribution = Normal::new(input.portfolio.bonds_expected_returns,
-                                          input.portfolio.bonds_standard_deviation).unwrap();
+    fn run_scenario(&self,
+                    index: usize,
+                    context: &simulate::PlanContext) -> Result<scan::Scenario, String> {
+        let input = context.input;
+        let mut sampled_context = None;
+        if input.simulation.mortality == Mortality::LifeTable {
+            let mut rng = self.get_mortality_rng(index);
+            let death_dates: Vec<NaiveDate> = input.retirees.iter()
+                .map(|v| mortality::sample_death_date(&mut rng, v.sex.unwrap(), &v.date_of_birth, &context.start_date))
+                .collect();
+            sampled_context = Some(context.with_death_dates(&death_dates));
+        }
+        // each windfall that isn't certain happens in its share of the
+        // scenarios
+        if input.windfalls.iter().any(|v| !v.is_certain()) {
+            let mut rng = self.get_windfall_rng(index);
+            let happens: Vec<bool> = input.windfalls.iter()
+                .map(|v| rng.gen::<f32>() * 100.0 < v.probability)
+                .collect();
+            sampled_context = Some(sampled_context.as_ref().unwrap_or(context).with_windfalls(&happens));
+        }
+        let context = sampled_context.as_ref().unwrap_or(context);
+        let mut simulation = simulate::Simulation::new(context);
+
+        let return_model = match self.bootstrap_years {
+            Some(_) => scan::ReturnModel::Bootstrap,
+            None => scan::ReturnModel::Parametric,
+        };
+        let mut rng = self.get_scenario_rng(index);
+        let mut anomalies = Vec::new();
+        let mut year = 0;
 
         'outer: loop {
-            let us_returns = us_distribution.sample(&mut rand::thread_rng());
-            let international_returns = international_distribution.sample(&mut rand::thread_rng());
-            let bonds_returns = bonds_distribution.sample(&mut rand::thread_rng());
-            for _ in 0..12 {
-                let is_finished = simulation.run_simulation_one_month(
-                    us_returns,
-                    international_returns,
-                    bonds_returns)?;
+            let returns = match &self.bootstrap_years {
+                Some(years) => {
+                    let path = years[rng.gen_range(0..years.len())];
+                    simulation.set_inflation(path.inflation);
+                    path.get_returns(&input.portfolio)
+                },
+                None => Self::sample_parametric(&mut rng, &mut simulation, input, index, year, &mut anomalies)?,
+            };
+            for _ in 0..simulation.steps_per_year() {
+                let is_finished = simulation.run_simulation_one_step(&returns)?;
                 if is_finished {
                     break 'outer;
                 }
             }
+            year += 1;
         }
 
         Ok(scan::Scenario {
+            index,
             simulation_results: simulation.simulation_results_,
             starting_year: 0,
             ending_year: 0,
+            anomalies,
+            metadata: scan::ScenarioMetadata::new(return_model, Some(self.seed), context),
         })
     }
+
+    // a year's returns drawn from the normal distributions of the asset
+    // classes, and its inflation if that varies too
+    #[cfg(feature = "parametric")]
+    fn sample_parametric(rng: &mut ChaCha12Rng,
+                         simulation: &mut simulate::Simulation,
+                         input: &Input,
+                         index: usize,
+                         year: u32,
+                         anomalies: &mut Vec<scan::Anomaly>) -> Result<[f32; MAX_ASSET_CLASSES], String> {
+        let portfolio = &input.portfolio;
+        let asset_classes = &portfolio.asset_classes[..portfolio.num_asset_classes];
+
+        // the stocks and bonds are drawn first, since inflation is
+        // correlated with them. The other asset classes are drawn after it,
+        // on their own, unless they're riskless.
+        let mut zscores = [0.0; MAX_ASSET_CLASSES];
+        for zscore in zscores[..CASH].iter_mut() {
+            *zscore = rng.sample(StandardNormal);
+        }
+        if portfolio.inflation_standard_deviation > 0.0 {
+            let distribution = Normal::new(portfolio.expected_inflation, portfolio.inflation_standard_deviation).unwrap();
+            let zscore = get_inflation_zscore(
+                portfolio.inflation_equity_correlation,
+                portfolio.inflation_bonds_correlation,
+                zscores[US_EQUITY], zscores[INTERNATIONAL_EQUITY], zscores[BONDS], rng.sample(StandardNormal));
+            simulation.set_inflation(distribution.from_zscore(zscore));
+        }
+        let mut returns = portfolio.get_expected_returns();
+        for (i, asset_class) in asset_classes.iter().enumerate() {
+            if i >= CASH {
+                if asset_class.standard_deviation == 0.0 {
+                    continue;
+                }
+                zscores[i] = rng.sample(StandardNormal);
+            }
+            let distribution = Normal::new(asset_class.expected_returns, asset_class.standard_deviation).unwrap();
+            returns[i] = Self::sample_return(zscores[i], &distribution, input.get_asset_class_description(i),
+                                             index, year, anomalies);
+        }
+        Ok(returns)
+    }
+
+    #[cfg(not(feature = "parametric"))]
+    fn sample_parametric(_rng: &mut ChaCha12Rng,
+                         _simulation: &mut simulate::Simulation,
+                         _input: &Input,
+                         _index: usize,
+                         _year: u32,
+                         _anomalies: &mut Vec<scan::Anomaly>) -> Result<[f32; MAX_ASSET_CLASSES], String> {
+        Err("The parametric Monte Carlo method needs the parametric feature".to_string())
+    }
+}
+
+// z-score of a year's inflation correlated with the z-scores of the asset
+// classes. The asset classes are independent, so mixing in each of them with
+// its correlation as the weight, and independent noise for the rest of the
+// variance, gives exactly those correlations.
+#[cfg(feature = "parametric")]
+fn get_inflation_zscore(equity_correlation: f32,
+                        bonds_correlation: f32,
+                        us_zscore: f32,
+                        international_zscore: f32,
+                        bonds_zscore: f32,
+                        own_zscore: f32) -> f32 {
+    let own_weight = f32::max(
+        1.0 - 2.0 * equity_correlation * equity_correlation - bonds_correlation * bonds_correlation, 0.0).sqrt();
+    equity_correlation * (us_zscore + international_zscore) + bonds_correlation * bonds_zscore +
+        own_weight * own_zscore
 }
 
 impl scan::Scannable for MonteCarloScan {
-    fn run_scan(&mut self, input: &Input) -> Result<scan::ScanResults, String> {
+    fn run_scan_with_context(&mut self, context: &simulate::PlanContext) -> Result<scan::ScanResults, String> {
         let mut results = scan::ScanResults::new();
 
+        // an error in one scenario, for example from an unusual combination
+        // of returns, doesn't stop the rest of the scan
         for index in 0..1000 {
-            let scenario = self.run_scenario(
-                input)?;
-            scan::add_scenario_to_results(&mut results, scenario, index);
+            let scenario = self.run_scenario(index, context);
+            if let Some(stream) = self.stream.as_mut() {
+                match &scenario {
+                    Ok(scenario) => stream.write_scenario(scenario)?,
+                    Err(err) => stream.write_error(index, err)?,
+                }
+            }
+            match scenario {
+                Ok(scenario) => scan::add_scenario_to_results(&mut results, scenario),
+                Err(err) => scan::add_error_to_results(&mut results, index, err),
+            }
+        }
+
+        if results.scenario_results.is_empty() {
+            return Err(format!("Every scenario failed, first error: {}", results.errors[0].message));
         }
 
         results.sort_results();
@@ -65,3 +267,43 @@ impl scan::Scannable for MonteCarloScan {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scenario_streams() {
+        let scan = MonteCarloScan::new(Some(42));
+        let first: u64 = scan.get_scenario_rng(7).gen();
+        // a scenario's stream doesn't depend on the ones drawn before it
+        let _: u64 = scan.get_scenario_rng(6).gen();
+        assert_eq!(scan.get_scenario_rng(7).gen::<u64>(), first);
+        assert_eq!(MonteCarloScan::new(Some(42)).get_scenario_rng(7).gen::<u64>(), first);
+        assert_ne!(scan.get_scenario_rng(8).gen::<u64>(), first);
+        assert_ne!(MonteCarloScan::new(Some(43)).get_scenario_rng(7).gen::<u64>(), first);
+    }
+
+    #[test]
+    #[cfg(feature = "parametric")]
+    fn test_inflation_correlation() {
+        let mut rng = MonteCarloScan::new(Some(42)).get_scenario_rng(0);
+
+        // sample correlations of 20,000 years are within a few hundredths
+        let count = 20000;
+        let (mut us_sum, mut bonds_sum) = (0.0, 0.0);
+        for _ in 0..count {
+            let us: f32 = rng.sample(StandardNormal);
+            let international: f32 = rng.sample(StandardNormal);
+            let bonds: f32 = rng.sample(StandardNormal);
+            let inflation = get_inflation_zscore(-0.1, -0.3, us, international, bonds, rng.sample(StandardNormal));
+            us_sum += us * inflation;
+            bonds_sum += bonds * inflation;
+        }
+        assert!((us_sum / count as f32 + 0.1).abs() < 0.03);
+        assert!((bonds_sum / count as f32 + 0.3).abs() < 0.03);
+
+        // all of inflation's variance from bonds
+        assert_eq!(get_inflation_zscore(0.0, -1.0, 0.5, 0.2, 1.5, 2.0), -1.5);
+    }
+}
@@ -0,0 +1,85 @@
+/**************************************************************************
+* mortality.rs
+*
+* Samples how long each retiree lives from actuarial life tables, as an
+* alternative to a fixed life expectancy.
+**************************************************************************/
+
+use chrono::NaiveDate;
+use rand::Rng;
+use rand_chacha::ChaCha12Rng;
+use crate::Sex;
+use crate::utils::*;
+
+// probability of dying within a year at every 5th age, rounded from the
+// Social Security Administration's period life table. Ages in between are
+// interpolated and no one lives past the last age.
+const TABLE_AGES: [u32; 17] = [40, 45, 50, 55, 60, 65, 70, 75, 80, 85, 90, 95, 100, 105, 110, 115, 120];
+const MALE_DEATH_PROBABILITIES: [f32; 17] = [
+    0.0030, 0.0042, 0.0058, 0.0087, 0.0125, 0.0170, 0.0246, 0.0378, 0.0598,
+    0.0980, 0.1630, 0.2600, 0.3600, 0.4600, 0.5600, 0.6600, 1.0];
+const FEMALE_DEATH_PROBABILITIES: [f32; 17] = [
+    0.0018, 0.0026, 0.0035, 0.0053, 0.0077, 0.0108, 0.0163, 0.0259, 0.0423,
+    0.0718, 0.1250, 0.2120, 0.3100, 0.4200, 0.5300, 0.6400, 1.0];
+
+// probability of dying before the next birthday. The probabilities grow
+// about exponentially with age, so they are interpolated on a log scale.
+pub fn get_death_probability(sex: Sex, age: u32) -> f32 {
+    let probabilities = match sex {
+        Sex::Male => &MALE_DEATH_PROBABILITIES,
+        Sex::Female => &FEMALE_DEATH_PROBABILITIES,
+    };
+    let last = TABLE_AGES.len() - 1;
+    if age >= TABLE_AGES[last] {
+        return 1.0;
+    }
+    if age <= TABLE_AGES[0] {
+        return probabilities[0];
+    }
+    let i = TABLE_AGES.iter().rposition(|v| *v <= age).unwrap();
+    let fraction = (age - TABLE_AGES[i]) as f32 / (TABLE_AGES[i + 1] - TABLE_AGES[i]) as f32;
+    (probabilities[i].ln() + (probabilities[i + 1].ln() - probabilities[i].ln()) * fraction).exp()
+}
+
+// draws the first day a retiree is no longer alive, after the start date.
+// Each year of age is survived with the table's probability, and the death
+// is spread over the months of the year it happens in.
+pub fn sample_death_date(rng: &mut ChaCha12Rng,
+                         sex: Sex,
+                         date_of_birth: &NaiveDate,
+                         start_date: &NaiveDate) -> NaiveDate {
+    let mut age = get_age(date_of_birth, start_date);
+    while rng.gen::<f32>() >= get_death_probability(sex, age) {
+        age += 1;
+    }
+    let months = age * 12 + rng.gen_range(0..12);
+    let death_date = date_of_birth.checked_add_months(chrono::Months::new(months)).unwrap();
+    NaiveDate::max(death_date, start_date.checked_add_months(chrono::Months::new(1)).unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_death_probability() {
+        assert!((get_death_probability(Sex::Male, 65) - 0.0170).abs() < 1e-6);
+        assert_eq!(get_death_probability(Sex::Female, 30), 0.0018);
+        assert_eq!(get_death_probability(Sex::Female, 120), 1.0);
+        let between = get_death_probability(Sex::Male, 67);
+        assert!(between > 0.0170 && between < 0.0246);
+
+        // remaining life expectancy at 65 is close to the table's 18 years
+        // for men and 20.5 for women
+        for (sex, expected) in [(Sex::Male, 17.6), (Sex::Female, 20.4)] {
+            let mut alive = 1.0;
+            let mut years = 0.0;
+            for age in 65..=120 {
+                let probability = get_death_probability(sex, age);
+                years += alive * (1.0 - probability / 2.0);
+                alive *= 1.0 - probability;
+            }
+            assert!((years - expected).abs() < 0.1);
+        }
+    }
+}
@@ -1,18 +1,168 @@
 /**************************************************************************
 * portfolio.rs
 *
-* Tracks the portfolio holdings and balance. Right now assume an asset
-* allocation and continuous rebalancing.
+* Tracks the portfolio holdings and balance. The holdings either follow the
+* asset allocation exactly (continuous rebalancing) or drift with the
+* returns, with the cash flows going to the asset classes furthest from it.
+*
+* The asset classes are the four built-in ones, US equities, international
+* equities, bonds and cash, followed by any defined in the configuration.
+* Everything about them is kept in fixed-size arrays indexed by asset class
+* so the portfolio stays cheap to copy for every simulation.
+*
+* Selling to rebalance the taxable accounts realizes capital gains, which
+* are collected for the simulation to tax.
+*
+* An optional cash bucket holds a few years of withdrawals outside the
+* allocation. It pays the withdrawals while the rest of the portfolio is down
+* and is refilled from it in good times.
+*
+* The allocation either switches from the pre-retirement one to the
+* post-retirement one at retirement, or follows a glide path of allocations
+* at ages of the first retiree.
+*
+* The balance is split into the kinds of accounts by how they're taxed. They
+* all hold the allocation unless accounts are listed with their own, in which
+* case each of those grows with its own and the kinds of accounts add them up.
 **************************************************************************/
 
+use crate::historical_scan::HistoricalColumn;
 use crate::utils::*;
 
-// all values are percentages (0-100.0)
-#[derive(Debug, Clone, Copy)]
+// the most asset classes a portfolio can have, the built-in ones included
+pub const MAX_ASSET_CLASSES: usize = 8;
+
+// indexes of the built-in asset classes
+pub const US_EQUITY: usize = 0;
+pub const INTERNATIONAL_EQUITY: usize = 1;
+pub const BONDS: usize = 2;
+// money market funds and T-bills
+pub const CASH: usize = 3;
+pub const NUM_BUILT_IN_ASSET_CLASSES: usize = 4;
+
+// names of the built-in asset classes in the exported returns
+pub const BUILT_IN_ASSET_CLASS_NAMES: [&str; NUM_BUILT_IN_ASSET_CLASSES] =
+    ["us_equity", "international_equity", "bonds", "cash"];
+
+// all values are percentages (0-100.0), one for each asset class
+#[derive(Debug, Clone, Copy, Default)]
 pub struct Allocation {
-    pub us_equities: f32,
-    pub international: f32,
-    pub bonds: f32,
+    pub weights: [f32; MAX_ASSET_CLASSES],
+}
+
+impl Allocation {
+    pub fn get_total(&self) -> f32 {
+        self.weights.iter().sum()
+    }
+}
+
+// the most accounts that can be listed
+pub const MAX_ACCOUNTS: usize = 8;
+
+// how an account is taxed, which decides the part of the portfolio's
+// balance it's in
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum AccountType {
+    #[default]
+    Taxable,
+    TaxDeferred,
+    // a traditional IRA, tax-deferred with an after-tax basis
+    Ira,
+    Roth,
+    Hsa,
+}
+
+pub const NUM_ACCOUNT_TYPES: usize = 5;
+pub const ACCOUNT_TYPES: [AccountType; NUM_ACCOUNT_TYPES] =
+    [AccountType::Taxable, AccountType::TaxDeferred, AccountType::Ira, AccountType::Roth, AccountType::Hsa];
+
+impl AccountType {
+    // as it's given in the configuration
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            AccountType::Taxable => "taxable",
+            AccountType::TaxDeferred => "tax_deferred",
+            AccountType::Ira => "ira",
+            AccountType::Roth => "roth",
+            AccountType::Hsa => "hsa",
+        }
+    }
+
+    pub fn get_description(&self) -> &'static str {
+        match self {
+            AccountType::Taxable => "taxable",
+            AccountType::TaxDeferred => "tax-deferred",
+            AccountType::Ira => "IRA",
+            AccountType::Roth => "Roth",
+            AccountType::Hsa => "HSA",
+        }
+    }
+}
+
+// an account with its own allocation. The deposits and withdrawals of its
+// kind of account are shared among the accounts of that kind in proportion
+// to their balances, and each grows with the returns of its allocation.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Account {
+    pub account_type: AccountType,
+    pub balance: f32,
+    pub allocation: Allocation,
+}
+
+// the most points a glide path can have
+pub const MAX_GLIDE_POINTS: usize = 12;
+
+// the allocation at an age of the first retiree on a glide path
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GlidePoint {
+    pub age: f32,
+    pub allocation: Allocation,
+}
+
+// what's expected of an asset class. Returns and yields are annual
+// percentages, and the returns are real.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AssetClass {
+    pub expected_returns: f32,
+    pub standard_deviation: f32,
+    // yields paid out by the holdings. Dividends are assumed to be
+    // qualified and interest is taxed as ordinary income.
+    pub dividend_yield: f32,
+    pub interest_yield: f32,
+    // the column of the historical returns used for it in the historical
+    // simulation and bootstrap Monte Carlo, the expected returns every year
+    // if None
+    pub historical: Option<HistoricalColumn>,
+    // inflation-protected bonds such as TIPS and I bonds. They earn a fixed
+    // real yield, the expected returns, whatever the inflation, but the
+    // inflation adjustment is taxed as interest.
+    pub inflation_linked: bool,
+    // annual percentage of the holdings paid to the funds, taken from the
+    // returns
+    pub expense_ratio: f32,
+}
+
+impl AssetClass {
+    // the annual interest taxed, the inflation adjustment of the principal
+    // is taxed with the interest of inflation-linked bonds even though it
+    // isn't paid out. Deflation reduces it.
+    pub fn get_interest_yield(&self, inflation: f32) -> f32 {
+        match self.inflation_linked {
+            true => f32::max(self.interest_yield + inflation, 0.0),
+            false => self.interest_yield,
+        }
+    }
+}
+
+// how the holdings are kept at the asset allocation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rebalancing {
+    // the holdings are at the allocation every step
+    Continuous,
+    // contributions buy the underweight asset classes and withdrawals sell
+    // the overweight ones, with a full rebalance only when an asset class
+    // drifts outside the band
+    CashFlow,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -22,50 +172,617 @@ pub struct Portfolio {
     pub pre_retirement_allocation: Allocation,
     pub post_retirement_allocation: Allocation,
     
-    pub us_equity_expected_returns: f32,
-    pub us_equity_standard_deviation: f32,
-    pub international_equity_expected_returns: f32,
-    pub international_equity_standard_deviation: f32,
-    pub bonds_expected_returns: f32,
-    pub bonds_standard_deviation: f32,
+    // the first num_asset_classes are used, the built-in ones first. Cash
+    // earns the short-term rate, with no other risk.
+    pub asset_classes: [AssetClass; MAX_ASSET_CLASSES],
+    pub num_asset_classes: usize,
     pub expected_inflation: f32,
+    // the expected returns were given in nominal terms and have been turned
+    // into real ones with the expected inflation. Only the doctor shows it.
+    #[cfg_attr(not(feature = "tools"), allow(dead_code))]
+    pub nominal_returns: bool,
+
+    // the parametric Monte Carlo simulation draws each year's inflation when
+    // the standard deviation isn't 0. The correlations are with the real
+    // returns of each stock asset class and of bonds.
+    #[cfg_attr(not(any(feature = "parametric", feature = "tools")), allow(dead_code))]
+    pub inflation_standard_deviation: f32,
+    #[cfg_attr(not(feature = "parametric"), allow(dead_code))]
+    pub inflation_equity_correlation: f32,
+    #[cfg_attr(not(feature = "parametric"), allow(dead_code))]
+    pub inflation_bonds_correlation: f32,
+
+    // portion of the balance held in taxable brokerage accounts. The rest is
+    // in tax-deferred retirement accounts.
+    pub taxable_balance: f32,
+
+    // after-tax money in the taxable accounts that comes out tax free, such
+    // as the proceeds of employer stock distributed with NUA treatment. Like
+    // the IRA basis it doesn't grow.
+    pub taxable_basis: f32,
+
+    // portion of the balance held in health savings accounts
+    pub hsa_balance: f32,
+
+    // portion of the balance held in Roth accounts. Withdrawals are tax free.
+    pub roth_balance: f32,
+
+    // portion of the tax-deferred balance held in traditional IRAs, and the
+    // after-tax basis in them from non-deductible contributions. The basis
+    // doesn't grow, and comes out tax free in proportion to the IRA balance
+    // (the pro-rata rule).
+    pub ira_balance: f32,
+    pub ira_basis: f32,
+
+    // 529 education savings. These are only used for education expenses, so
+    // they aren't part of the balance.
+    pub education_balance: f32,
+
+    // percentage of the losses in the taxable accounts that are harvested
+    // in down years, 0 for no tax-loss harvesting
+    pub tax_loss_harvest_percent: f32,
+
+    // percentage of what's sold to rebalance the taxable accounts that is a
+    // capital gain, and the gains realized since the simulation last took
+    // them to be taxed
+    pub rebalance_gain_percent: f32,
+    pub rebalance_gains: f32,
+
+    // annual percentage of the balance paid to an advisor, taken from the
+    // returns of every asset class like the expense ratios
+    pub advisor_fee_percent: f32,
+
+    // with cash-flow rebalancing, the percentage points an asset class can
+    // drift from the allocation before everything is rebalanced
+    pub rebalancing: Rebalancing,
+    pub rebalance_band: f32,
+
+    // the amount in each asset class after the last growth, all 0 until
+    // then. With continuous rebalancing only used for what's sold to get
+    // back to the allocation.
+    pub holdings: [f32; MAX_ASSET_CLASSES],
+
+    // the years of withdrawals kept in the cash bucket, 0 for none, and the
+    // amount in it. The bucket is part of the balance but not of the
+    // allocation, and earns the return of cash.
+    pub cash_bucket_years: f32,
+    pub cash_bucket: f32,
+
+    // the monthly growth of the rest of the portfolio over the last year,
+    // oldest first, to tell a down market. Only kept with a cash bucket.
+    pub recent_growth: [f32; 12],
+
+    // the first num_glide_points are the glide path, in order of age, used
+    // instead of the pre- and post-retirement allocations if there are any.
+    // The age is the first retiree's, kept up to date by the simulation.
+    pub glide_path: [GlidePoint; MAX_GLIDE_POINTS],
+    pub num_glide_points: usize,
+    pub age: f32,
+
+    // the first num_accounts are the accounts listed with their own
+    // allocations, none if they aren't listed or all hold the portfolio's.
+    // The balances are as of the last growth. Money in a kind of account
+    // with none listed, such as contributions to an HSA when no HSA is
+    // listed, holds the target allocation.
+    pub accounts: [Account; MAX_ACCOUNTS],
+    pub num_accounts: usize,
+
+    // deposits less withdrawals since it was last set to 0, for the
+    // invariant check. Growth isn't included.
+    pub net_flows: f32,
 }
 
 impl Portfolio {
+    // the expected returns of each asset class
+    pub fn get_expected_returns(&self) -> [f32; MAX_ASSET_CLASSES] {
+        self.asset_classes.map(|v| v.expected_returns)
+    }
+
+    // replaces the balance with a new one, such as an actual balance, keeping
+    // the share of each kind of account. The IRA basis is what was
+    // contributed, so it doesn't change unless the IRAs are worth less.
+    pub fn set_balance(&mut self, balance: f32) {
+        let ratio = if self.balance > 0.0 {balance / self.balance} else {0.0};
+        self.taxable_balance *= ratio;
+        self.hsa_balance *= ratio;
+        self.roth_balance *= ratio;
+        self.ira_balance *= ratio;
+        self.ira_basis = self.ira_basis.min(self.ira_balance);
+        self.balance = balance;
+    }
+
+    // deposits into the tax-deferred accounts
     pub fn deposit(&mut self, amount: f32) {
         self.balance += amount;
+        self.net_flows += amount;
+    }
+
+    // deposits into the taxable accounts
+    pub fn deposit_taxable(&mut self, amount: f32) {
+        self.balance += amount;
+        self.net_flows += amount;
+        self.taxable_balance += amount;
+    }
+
+    // deposits after-tax money into the taxable accounts
+    pub fn deposit_taxable_basis(&mut self, amount: f32) {
+        self.deposit_taxable(amount);
+        self.taxable_basis += amount;
+    }
+
+    // deposits into the health savings accounts
+    pub fn deposit_hsa(&mut self, amount: f32) {
+        self.balance += amount;
+        self.net_flows += amount;
+        self.hsa_balance += amount;
+    }
+
+    // deposits into the Roth accounts
+    pub fn deposit_roth(&mut self, amount: f32) {
+        self.balance += amount;
+        self.net_flows += amount;
+        self.roth_balance += amount;
+    }
+
+    // non-deductible contributions to the traditional IRAs
+    pub fn deposit_nondeductible_ira(&mut self, amount: f32) {
+        self.balance += amount;
+        self.net_flows += amount;
+        self.ira_balance += amount;
+        self.ira_basis += amount;
+    }
+
+    // a backdoor Roth contribution: a non-deductible IRA contribution that is
+    // converted to Roth right away. The conversion is taxed pro-rata over all
+    // the traditional IRAs, returns the taxable part.
+    pub fn convert_backdoor_roth(&mut self, amount: f32) -> f32 {
+        if amount <= 0.0 {
+            return 0.0;
+        }
+        let ira_total = self.ira_balance + amount;
+        let basis_total = self.ira_basis + amount;
+        let nontaxable = amount * basis_total / ira_total;
+        self.ira_basis = basis_total - nontaxable;
+        self.deposit_roth(amount);
+        amount - nontaxable
+    }
+
+    // share of a withdrawal from the taxable and tax-deferred accounts that
+    // is IRA or taxable basis, and not taxed
+    pub fn get_nontaxable_fraction(&self) -> f32 {
+        let other_balance = self.get_other_balance();
+        if other_balance <= 0.0 {
+            return 0.0;
+        }
+        f32::min((self.ira_basis + self.taxable_basis) / other_balance, 1.0)
     }
 
+    // balance of the taxable and tax-deferred accounts
+    pub fn get_other_balance(&self) -> f32 {
+        f32::max(self.balance - self.hsa_balance - self.roth_balance, 0.0)
+    }
+
+    // withdraws from the taxable and tax-deferred accounts in proportion to
+    // their balances. The Roth accounts are only used once these run out,
+    // and the health savings accounts after that.
     pub fn withdraw(&mut self, amount: f32) {
+        let other_balance = self.get_other_balance();
+        if amount > other_balance {
+            self.taxable_balance = 0.0;
+            self.taxable_basis = 0.0;
+            self.ira_balance = 0.0;
+            self.ira_basis = 0.0;
+            self.balance = self.hsa_balance + self.roth_balance;
+            self.net_flows -= other_balance;
+            let remaining = amount - other_balance;
+            let roth_amount = self.withdraw_roth(remaining);
+            self.withdraw_hsa(remaining - roth_amount);
+            return;
+        }
+        if other_balance > 0.0 {
+            let remaining = (other_balance - amount) / other_balance;
+            self.taxable_balance *= remaining;
+            self.taxable_basis *= remaining;
+            self.ira_balance *= remaining;
+            self.ira_basis *= remaining;
+        }
         self.balance -= amount;
-        if self.balance < 0.0 {
-            self.balance = 0.0;
+        self.net_flows -= amount;
+    }
+
+    // withdraws from the taxable accounts only, returns the amount withdrawn
+    pub fn withdraw_taxable(&mut self, amount: f32) -> f32 {
+        let amount = f32::min(amount, self.taxable_balance);
+        if self.taxable_balance > 0.0 {
+            self.taxable_basis *= (self.taxable_balance - amount) / self.taxable_balance;
+        }
+        self.taxable_balance -= amount;
+        self.balance = f32::max(self.balance - amount, 0.0);
+        self.net_flows -= amount;
+        amount
+    }
+
+    // withdraws from the tax-deferred accounts only, taking from the IRAs in
+    // proportion. Returns the amount withdrawn.
+    pub fn withdraw_tax_deferred(&mut self, amount: f32) -> f32 {
+        let deferred_balance = f32::max(self.get_other_balance() - self.taxable_balance, 0.0);
+        let amount = f32::min(amount, deferred_balance);
+        if deferred_balance > 0.0 {
+            let remaining = (deferred_balance - amount) / deferred_balance;
+            self.ira_balance *= remaining;
+            self.ira_basis *= remaining;
+        }
+        self.balance = f32::max(self.balance - amount, 0.0);
+        self.net_flows -= amount;
+        amount
+    }
+
+    // withdraws from the health savings accounts, returns the amount withdrawn
+    pub fn withdraw_hsa(&mut self, amount: f32) -> f32 {
+        let amount = f32::min(amount, self.hsa_balance);
+        self.hsa_balance -= amount;
+        self.balance = f32::max(self.balance - amount, 0.0);
+        self.net_flows -= amount;
+        amount
+    }
+
+    // withdraws from the Roth accounts, returns the amount withdrawn
+    pub fn withdraw_roth(&mut self, amount: f32) -> f32 {
+        let amount = f32::min(amount, self.roth_balance);
+        self.roth_balance -= amount;
+        self.balance = f32::max(self.balance - amount, 0.0);
+        self.net_flows -= amount;
+        amount
+    }
+
+    // the part of the balance that follows the allocation, everything but
+    // the cash bucket
+    pub fn get_invested_balance(&self) -> f32 {
+        f32::max(self.balance - self.cash_bucket, 0.0)
+    }
+
+    // whether the invested part of the portfolio has lost value over the
+    // last year
+    pub fn is_down_market(&self) -> bool {
+        self.recent_growth.iter().product::<f32>() < 1.0
+    }
+
+    // called with a step's withdrawals once they're made. In a down market
+    // they came out of the cash bucket, otherwise they came out of the rest
+    // of the portfolio, which then refills the bucket to the years of
+    // withdrawals at this rate.
+    pub fn update_cash_bucket(&mut self, withdrawn: f32, monthly_withdrawals: f32) {
+        if self.cash_bucket_years <= 0.0 {
+            return;
+        }
+        self.cash_bucket = match self.is_down_market() {
+            true => f32::max(self.cash_bucket - withdrawn, 0.0),
+            false => monthly_withdrawals * 12.0 * self.cash_bucket_years,
+        };
+        self.cash_bucket = f32::min(self.cash_bucket, self.balance);
+    }
+
+    // withdraws from the 529 accounts, returns the amount withdrawn
+    pub fn withdraw_education(&mut self, amount: f32) -> f32 {
+        let amount = f32::min(amount, self.education_balance);
+        self.education_balance -= amount;
+        amount
+    }
+
+    // all the accounts grow at the same rate
+    fn scale_balances(&mut self, old_balance: f32) {
+        if old_balance > 0.0 {
+            self.taxable_balance *= self.balance / old_balance;
+            self.hsa_balance *= self.balance / old_balance;
+            self.roth_balance *= self.balance / old_balance;
+            self.ira_balance *= self.balance / old_balance;
+        }
+        // the basis doesn't grow, but losses can leave less than it
+        self.taxable_basis = f32::min(self.taxable_basis, self.taxable_balance);
+    }
+
+    // the balance of each kind of account, in the order of ACCOUNT_TYPES.
+    // The tax-deferred balance is what's left of the balance outside the
+    // others.
+    pub fn get_account_type_balances(&self) -> [f32; NUM_ACCOUNT_TYPES] {
+        let others = self.taxable_balance + self.ira_balance + self.roth_balance + self.hsa_balance;
+        [self.taxable_balance, f32::max(self.balance - self.cash_bucket - others, 0.0), self.ira_balance,
+         self.roth_balance, self.hsa_balance]
+    }
+
+    fn has_accounts(&self, account_type: AccountType) -> bool {
+        self.accounts[..self.num_accounts].iter().any(|v| v.account_type == account_type)
+    }
+
+    // the balance of each account now: its share of its kind of account in
+    // proportion to the balances at the last growth, or an even share if
+    // they were all empty
+    pub fn get_account_balances(&self) -> [f32; MAX_ACCOUNTS] {
+        let type_balances = self.get_account_type_balances();
+        let accounts = &self.accounts[..self.num_accounts];
+        std::array::from_fn(|i| {
+            let Some(account) = accounts.get(i) else {
+                return 0.0;
+            };
+            let type_balance = type_balances[account.account_type as usize];
+            let (count, total) = accounts.iter().filter(|v| v.account_type == account.account_type)
+                .fold((0, 0.0), |(count, total), v| (count + 1, total + v.balance));
+            match total > 0.0 {
+                true => account.balance / total * type_balance,
+                false => type_balance / count as f32,
+            }
+        })
+    }
+
+    // the amount of each asset class held in a kind of account, at the
+    // allocation given if it has no accounts listed
+    pub fn get_type_holdings(&self, account_type: AccountType, allocation: &Allocation) -> [f32; MAX_ASSET_CLASSES] {
+        if !self.has_accounts(account_type) {
+            let balance = self.get_account_type_balances()[account_type as usize];
+            return allocation.weights.map(|v| v / 100.0 * balance);
+        }
+        let mut holdings = [0.0; MAX_ASSET_CLASSES];
+        let balances = self.get_account_balances();
+        for (account, balance) in self.accounts[..self.num_accounts].iter().zip(balances) {
+            if account.account_type == account_type {
+                for (holding, weight) in holdings.iter_mut().zip(account.allocation.weights) {
+                    *holding += weight / 100.0 * balance;
+                }
+            }
+        }
+        holdings
+    }
+
+    // grows each account with the returns of its own allocation, and the
+    // money in kinds of accounts with none listed with the target allocation.
+    // The accounts are rebalanced continuously, so what the growth moves
+    // away from each allocation is sold, and taxed in the taxable accounts.
+    // Returns the annualized average return.
+    fn grow_accounts(
+        &mut self,
+        annual_returns: &[f32; MAX_ASSET_CLASSES],
+        growth: &[f32; MAX_ASSET_CLASSES],
+        target: &Allocation) -> f32 {
+        let grow_amount = |amount: f32, allocation: &Allocation| -> [f32; MAX_ASSET_CLASSES] {
+            std::array::from_fn(|i| amount * allocation.weights[i] / 100.0 * growth[i])
+        };
+        let get_return = |allocation: &Allocation| -> f32 {
+            annual_returns.iter().zip(allocation.weights.iter()).map(|(r, w)| r * w / 100.0).sum()
+        };
+
+        let account_balances = self.get_account_balances();
+        let type_balances = self.get_account_type_balances();
+        let mut new_type_balances = [0.0; NUM_ACCOUNT_TYPES];
+        let mut holdings = [0.0; MAX_ASSET_CLASSES];
+        let mut total_return = 0.0;
+        let mut sold = 0.0;
+        let mut add = |account_type: AccountType, amount: f32, allocation: &Allocation| -> f32 {
+            let amounts = grow_amount(amount, allocation);
+            let grown: f32 = amounts.iter().sum();
+            if account_type == AccountType::Taxable {
+                sold += (0..MAX_ASSET_CLASSES)
+                    .map(|i| f32::max(amounts[i] - grown * allocation.weights[i] / 100.0, 0.0))
+                    .sum::<f32>();
+            }
+            for (holding, amount) in holdings.iter_mut().zip(amounts) {
+                *holding += amount;
+            }
+            new_type_balances[account_type as usize] += grown;
+            total_return += amount * get_return(allocation);
+            grown
+        };
+        for (account_type, balance) in ACCOUNT_TYPES.iter().zip(type_balances) {
+            if !self.has_accounts(*account_type) {
+                add(*account_type, balance, target);
+            }
+        }
+        for (account, balance) in self.accounts[..self.num_accounts].iter_mut().zip(account_balances) {
+            account.balance = add(account.account_type, balance, &account.allocation);
+        }
+
+        let invested: f32 = type_balances.iter().sum();
+        self.rebalance_gains += sold * self.rebalance_gain_percent / 100.0;
+        self.holdings = holdings;
+        self.balance = new_type_balances.iter().sum();
+        self.taxable_balance = new_type_balances[AccountType::Taxable as usize];
+        self.ira_balance = new_type_balances[AccountType::Ira as usize];
+        self.roth_balance = new_type_balances[AccountType::Roth as usize];
+        self.hsa_balance = new_type_balances[AccountType::Hsa as usize];
+        self.taxable_basis = f32::min(self.taxable_basis, self.taxable_balance);
+        if invested <= 0.0 {
+            return get_return(target);
+        }
+        total_return / invested
+    }
+
+    // the allocation at an age on the glide path, interpolated between the
+    // points around it. Before the first point and after the last the
+    // nearest one's is held.
+    pub fn get_glide_allocation(&self, age: f32) -> Allocation {
+        let points = &self.glide_path[..self.num_glide_points];
+        match points.iter().position(|v| v.age > age) {
+            Some(0) => points[0].allocation,
+            None => points[points.len() - 1].allocation,
+            Some(i) => {
+                let (before, after) = (points[i - 1], points[i]);
+                let fraction = (age - before.age) / (after.age - before.age);
+                Allocation {
+                    weights: std::array::from_fn(|j| before.allocation.weights[j] +
+                        (after.allocation.weights[j] - before.allocation.weights[j]) * fraction),
+                }
+            },
+        }
+    }
+
+    // the allocation the holdings are kept at, from the glide path if there
+    // is one
+    pub fn get_target_allocation(&self, use_post_retirement: bool) -> Allocation {
+        if self.num_glide_points > 0 {
+            return self.get_glide_allocation(self.age);
         }
+        if use_post_retirement {self.post_retirement_allocation} else {self.pre_retirement_allocation}
+    }
+
+    // the share of each asset class held now, in percent. That's the
+    // allocation unless the holdings are drifting with cash-flow rebalancing,
+    // or the accounts have their own allocations.
+    pub fn get_current_allocation(&self, use_post_retirement: bool) -> Allocation {
+        let allocation = self.get_target_allocation(use_post_retirement);
+        let holdings = match self.num_accounts > 0 {
+            true => self.get_holdings(&allocation),
+            false => self.holdings,
+        };
+        let total: f32 = holdings.iter().sum();
+        if (self.rebalancing == Rebalancing::Continuous && self.num_accounts == 0) || total <= 0.0 {
+            return allocation;
+        }
+        Allocation {
+            weights: holdings.map(|v| v / total * 100.0),
+        }
+    }
+
+    // the amount of each asset class held in all the kinds of accounts
+    fn get_holdings(&self, allocation: &Allocation) -> [f32; MAX_ASSET_CLASSES] {
+        let mut holdings = [0.0; MAX_ASSET_CLASSES];
+        for account_type in ACCOUNT_TYPES {
+            for (holding, amount) in holdings.iter_mut().zip(self.get_type_holdings(account_type, allocation)) {
+                *holding += amount;
+            }
+        }
+        holdings
+    }
+
+    // puts the deposits and withdrawals since the last growth into the
+    // holdings, buying what's furthest below the allocation and selling
+    // what's furthest above it, then rebalances fully if an asset class is
+    // still outside the band
+    fn rebalance_cash_flows(&mut self, allocation: &Allocation) {
+        let invested = self.get_invested_balance();
+        let targets = allocation.weights.map(|v| v / 100.0 * invested);
+        let held: f32 = self.holdings.iter().sum();
+        let flow = invested - held;
+        if held <= 0.0 || invested <= 0.0 {
+            self.holdings = targets;
+            return;
+        }
+        // the gaps add up to at least the flow, since the targets add up to
+        // the holdings plus the flow
+        let gaps: [f32; MAX_ASSET_CLASSES] = match flow >= 0.0 {
+            true => std::array::from_fn(|i| f32::max(targets[i] - self.holdings[i], 0.0)),
+            false => std::array::from_fn(|i| f32::max(self.holdings[i] - targets[i], 0.0)),
+        };
+        let total_gap: f32 = gaps.iter().sum();
+        if total_gap > 0.0 {
+            for (holding, gap) in self.holdings.iter_mut().zip(gaps) {
+                *holding += flow * gap / total_gap;
+            }
+        }
+        let is_outside_band = (0..MAX_ASSET_CLASSES)
+            .any(|i| (self.holdings[i] - targets[i]).abs() / invested * 100.0 > self.rebalance_band);
+        if is_outside_band {
+            let sold = (0..MAX_ASSET_CLASSES).map(|i| f32::max(self.holdings[i] - targets[i], 0.0)).sum();
+            self.realize_rebalance_gains(sold);
+            self.holdings = targets;
+        }
+    }
+
+    // the gains on an amount sold to rebalance, in the taxable accounts'
+    // share of it. Selling in the other accounts isn't taxed.
+    fn realize_rebalance_gains(&mut self, sold: f32) {
+        if self.balance > 0.0 {
+            self.rebalance_gains += sold * self.taxable_balance / self.balance * self.rebalance_gain_percent / 100.0;
+        }
+    }
+
+    // monthly qualified dividends and interest paid by the taxable accounts,
+    // with the annual inflation for the inflation-linked asset classes. The
+    // cash bucket is spread over the accounts like the rest, and taxable
+    // accounts listed with their own allocations hold those.
+    pub fn get_taxable_yields(&self, use_post_retirement: bool, inflation: f32) -> (f32, f32) {
+        let mut allocation = self.get_current_allocation(use_post_retirement);
+        if self.has_accounts(AccountType::Taxable) && self.taxable_balance > 0.0 {
+            let holdings = self.get_type_holdings(AccountType::Taxable, &allocation);
+            allocation.weights = holdings.map(|v| v / self.taxable_balance * 100.0);
+        }
+        let bucket_share = if self.balance > 0.0 {self.cash_bucket / self.balance} else {0.0};
+        let mut dividends = 0.0;
+        let mut interest = bucket_share * 100.0 * self.asset_classes[CASH].interest_yield;
+        for (weight, asset_class) in allocation.weights.iter().zip(self.asset_classes.iter()) {
+            dividends += weight * (1.0 - bucket_share) * asset_class.dividend_yield;
+            interest += weight * (1.0 - bucket_share) * asset_class.get_interest_yield(inflation);
+        }
+        (self.taxable_balance / 12.0 * dividends / 100.0 / 100.0,
+         self.taxable_balance / 12.0 * interest / 100.0 / 100.0)
     }
     
-    // grows the balance and returns annualized average return
+    // the annual returns of each asset class after the expense ratio and
+    // the advisor fee
+    pub fn get_net_returns(&self, annual_returns: &[f32; MAX_ASSET_CLASSES]) -> [f32; MAX_ASSET_CLASSES] {
+        std::array::from_fn(|i| {
+            let fees = self.asset_classes[i].expense_ratio + self.advisor_fee_percent;
+            match fees > 0.0 {
+                true => ((1.0 + annual_returns[i] / 100.0) * (1.0 - fees / 100.0) - 1.0) * 100.0,
+                false => annual_returns[i],
+            }
+        })
+    }
+
+    // grows the balance over a number of months with the annual returns of
+    // each asset class, less the fees, and returns annualized average
+    // return. The cash bucket grows with the return of cash, and accounts
+    // listed with their own allocations grow with those.
     pub fn grow(
         &mut self,
-        us_equity_expected_returns: f32,
-        international_equity_expected_returns: f32,
-        bonds_expected_returns: f32,
-        use_post_retirement: bool) -> f32 {
-        let &allocation = if use_post_retirement {&self.post_retirement_allocation}
-            else {&self.pre_retirement_allocation};
-        let mut us_equity = self.balance * allocation.us_equities / 100.0;
-        let mut international_equity = self.balance * allocation.international / 100.0;
-        let mut bonds = self.balance * allocation.bonds / 100.0;
-
-        us_equity *= get_monthly_rate(us_equity_expected_returns / 100.0) + 1.0;
-        international_equity *= get_monthly_rate(international_equity_expected_returns / 100.0) + 1.0;
-        bonds *= get_monthly_rate(bonds_expected_returns / 100.0) + 1.0;
-
-        self.balance = us_equity + international_equity + bonds;
+        annual_returns: &[f32; MAX_ASSET_CLASSES],
+        use_post_retirement: bool,
+        months: u32) -> f32 {
+        let annual_returns = &self.get_net_returns(annual_returns);
+        let target = self.get_target_allocation(use_post_retirement);
+        let growth = annual_returns.map(|v| (get_monthly_rate(v / 100.0) + 1.0).powi(months as i32));
+
+        // the 529 accounts are kept at the allocation
+        self.education_balance *= target.weights.iter().zip(growth.iter()).map(|(w, g)| w * g).sum::<f32>() / 100.0;
+
+        if self.num_accounts > 0 {
+            return self.grow_accounts(annual_returns, &growth, &target);
+        }
+        if self.rebalancing == Rebalancing::CashFlow {
+            self.rebalance_cash_flows(&target);
+        }
+        let allocation = self.get_current_allocation(use_post_retirement);
+        let invested = self.get_invested_balance();
+        // continuous rebalancing sells what drifted above the allocation since
+        // the last growth
+        let held: f32 = self.holdings.iter().sum();
+        if self.rebalancing == Rebalancing::Continuous && held > 0.0 {
+            let sold: f32 = (0..MAX_ASSET_CLASSES)
+                .map(|i| f32::max(self.holdings[i] / held - allocation.weights[i] / 100.0, 0.0))
+                .sum();
+            self.realize_rebalance_gains(sold * invested);
+        }
+        let amounts: [f32; MAX_ASSET_CLASSES] =
+            std::array::from_fn(|i| invested * allocation.weights[i] / 100.0 * growth[i]);
+
+        let old_balance = self.balance;
+        let old_cash_bucket = self.cash_bucket;
+        self.cash_bucket *= growth[CASH];
+        self.balance = amounts.iter().sum::<f32>() + self.cash_bucket;
+        self.scale_balances(old_balance);
+        self.holdings = amounts;
+        if self.cash_bucket_years > 0.0 && invested > 0.0 {
+            let monthly_growth = (amounts.iter().sum::<f32>() / invested).powf(1.0 / months as f32);
+            for _ in 0..months.min(12) {
+                self.recent_growth.rotate_left(1);
+                self.recent_growth[11] = monthly_growth;
+            }
+        }
 
         // return annualized return
-        us_equity_expected_returns * allocation.us_equities / 100.0 +
-            international_equity_expected_returns * allocation.international / 100.0 +
-            bonds_expected_returns * allocation.bonds / 100.0
+        let invested_return: f32 = annual_returns.iter().zip(allocation.weights.iter()).map(|(r, w)| r * w / 100.0).sum();
+        if old_cash_bucket <= 0.0 {
+            return invested_return;
+        }
+        (invested_return * invested + annual_returns[CASH] * old_cash_bucket) / (invested + old_cash_bucket)
     }
 }
 
@@ -0,0 +1,293 @@
+/**************************************************************************
+* reconcile.rs
+*
+* Compares the actual balances of the portfolio, kept in a CSV file as the
+* years go by, with the projection saved in the JSON report of an earlier
+* run. The actual balances are nominal, so they're brought back to the
+* dollars of the report with the inflation it assumed. The difference at
+* each date is split into the part from depositing or withdrawing more or
+* less than planned and the part from everything else, mostly returns, to
+* show which assumptions need recalibrating.
+**************************************************************************/
+
+use std::fs;
+use chrono::NaiveDate;
+use yaml_rust::{Yaml, YamlLoader};
+use crate::{format_table, num_with_commas, signed_with_commas, utils};
+
+// the uniform projection saved in a report
+#[derive(Debug)]
+struct Projection {
+    // annual percentage the amounts are adjusted with
+    inflation: f32,
+    // the date of each step, the balance at its start, and the deposits less
+    // withdrawals in it
+    steps: Vec<(NaiveDate, f32, f32)>,
+}
+
+// a line of the CSV file, in nominal dollars
+#[derive(Debug, PartialEq)]
+struct Actual {
+    date: NaiveDate,
+    balance: f32,
+    // deposits less withdrawals since the previous line, or since the report
+    // for the first one. None if not recorded.
+    net_deposits: Option<f32>,
+}
+
+// an actual balance compared with the projection, in the dollars of the
+// report
+#[derive(Debug)]
+struct Comparison {
+    date: NaiveDate,
+    projected: f32,
+    actual: f32,
+    projected_deposits: f32,
+    actual_deposits: Option<f32>,
+    // the change in the difference since the previous line not explained by
+    // the deposits
+    from_returns: Option<f32>,
+}
+
+// JSON numbers without a decimal point are integers
+fn get_number(yaml: &Yaml) -> Option<f64> {
+    match yaml {
+        Yaml::Integer(v) => Some(*v as f64),
+        v => v.as_f64(),
+    }
+}
+
+fn parse_report(file: &str, text: &str) -> Result<Projection, String> {
+    let docs = YamlLoader::load_from_str(text).map_err(|e| format!("Can't parse {}: {}", file, e))?;
+    let doc = docs.first().ok_or(format!("{} is empty", file))?;
+    if let Some(error) = doc["error"].as_str() {
+        return Err(format!("{} is the report of a run that failed: {}", file, error));
+    }
+    doc["version"].as_str().ok_or(format!("{} isn't a JSON report", file))?;
+    let projection = &doc["uniform"]["projection"];
+    let steps = projection["steps"].as_vec()
+        .ok_or(format!("{}: no projection, it was saved by an older version", file))?;
+
+    let invalid = |field: &str| format!("{}: invalid value: {}", file, field);
+    let mut parsed = Vec::new();
+    for step in steps.iter() {
+        let date = step["date"].as_str()
+            .and_then(|v| NaiveDate::parse_from_str(v, "%Y-%m-%d").ok())
+            .ok_or(invalid("date"))?;
+        let balance = get_number(&step["balance"]).ok_or(invalid("balance"))? as f32;
+        let net_deposits = get_number(&step["net_deposits"]).ok_or(invalid("net_deposits"))? as f32;
+        parsed.push((date, balance, net_deposits));
+    }
+    if parsed.is_empty() {
+        return Err(format!("{}: the projection has no steps", file));
+    }
+    Ok(Projection {
+        inflation: get_number(&projection["inflation"]).ok_or(invalid("inflation"))? as f32,
+        steps: parsed,
+    })
+}
+
+// lines of date,balance[,net deposits] with the date as mm/dd/yyyy. A
+// header line and lines starting with # are skipped.
+fn parse_actuals(file: &str, text: &str) -> Result<Vec<Actual>, String> {
+    let mut actuals: Vec<Actual> = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || (i == 0 && line.to_lowercase().starts_with("date")) {
+            continue;
+        }
+        let invalid = |field: &str| format!("{} line {}: invalid {}: {}", file, i + 1, field, line);
+        let fields: Vec<&str> = line.split(',').map(|v| v.trim()).collect();
+        if fields.len() < 2 || fields.len() > 3 {
+            return Err(format!("{} line {}: expected date,balance[,net deposits]: {}", file, i + 1, line));
+        }
+        let date = NaiveDate::parse_from_str(fields[0], "%m/%d/%Y").map_err(|_| invalid("date"))?;
+        let balance = fields[1].parse::<f32>().ok().filter(|v| *v >= 0.0).ok_or(invalid("balance"))?;
+        let net_deposits = match fields.get(2) {
+            Some(v) if !v.is_empty() => Some(v.parse::<f32>().map_err(|_| invalid("net deposits"))?),
+            _ => None,
+        };
+        if actuals.last().is_some_and(|v| v.date >= date) {
+            return Err(format!("{} line {}: the dates must be in order: {}", file, i + 1, line));
+        }
+        actuals.push(Actual {date, balance, net_deposits});
+    }
+    if actuals.is_empty() {
+        return Err(format!("{} has no balances", file));
+    }
+    Ok(actuals)
+}
+
+// the date and amount of the last balance in a CSV file of actual balances
+pub fn get_latest_balance(file: &str, text: &str) -> Result<(NaiveDate, f32), String> {
+    let actuals = parse_actuals(file, text)?;
+    let latest = actuals.last().unwrap();
+    Ok((latest.date, latest.balance))
+}
+
+// the step of the projection a date falls in, None if it's outside
+fn get_step(projection: &Projection, date: &NaiveDate) -> Option<usize> {
+    if date <= &projection.steps[0].0 || date > &projection.steps.last().unwrap().0 {
+        return None;
+    }
+    projection.steps.iter().rposition(|v| v.0 <= *date)
+}
+
+fn compare(projection: &Projection, actuals: &[Actual]) -> Result<Vec<Comparison>, String> {
+    let start = projection.steps[0].0;
+    let mut comparisons = Vec::new();
+    let mut previous_step = 0;
+    let mut previous_difference = 0.0;
+    for actual in actuals.iter() {
+        let step = get_step(projection, &actual.date).ok_or(format!(
+            "{} is outside the projection, which runs from {} to {}", actual.date.format("%m/%d/%Y"),
+            start.format("%m/%d/%Y"), projection.steps.last().unwrap().0.format("%m/%d/%Y")))?;
+        let discount = utils::get_inflation_discount(projection.inflation, utils::months_between(&start, &actual.date));
+        let projected = projection.steps[step].1;
+        let projected_deposits: f32 = projection.steps[previous_step..step].iter().map(|v| v.2).sum();
+        let actual_balance = actual.balance * discount;
+        let actual_deposits = actual.net_deposits.map(|v| v * discount);
+        let difference = actual_balance - projected;
+        comparisons.push(Comparison {
+            date: actual.date,
+            projected,
+            actual: actual_balance,
+            projected_deposits,
+            actual_deposits,
+            from_returns: actual_deposits.map(|v| difference - previous_difference - (v - projected_deposits)),
+        });
+        previous_step = step;
+        previous_difference = difference;
+    }
+    Ok(comparisons)
+}
+
+// the annual return of a balance that started at start_balance and had
+// deposits made along the way, counting the deposits as made halfway
+// through (the Modified Dietz method)
+fn get_annual_return(start_balance: f32, end_balance: f32, deposits: f32, months: u32) -> Option<f32> {
+    let invested = start_balance + deposits / 2.0;
+    if invested <= 0.0 || months == 0 {
+        return None;
+    }
+    let total = (end_balance - start_balance - deposits) / invested;
+    if total <= -1.0 {
+        return None;
+    }
+    Some(((1.0 + total).powf(12.0 / months as f32) - 1.0) * 100.0)
+}
+
+fn format_difference(amount: f32) -> String {
+    signed_with_commas(amount.round())
+}
+
+pub fn run_reconcile(out: &mut String, report_file: &str, actuals_file: &str) -> Result<(), String> {
+    let text = fs::read_to_string(report_file).map_err(|e| format!("Can't read {}: {}", report_file, e))?;
+    let projection = parse_report(report_file, &text)?;
+    let text = fs::read_to_string(actuals_file).map_err(|e| format!("Can't read {}: {}", actuals_file, e))?;
+    let actuals = parse_actuals(actuals_file, &text)?;
+    let comparisons = compare(&projection, &actuals)?;
+
+    let (start, start_balance, _) = projection.steps[0];
+    outln!(out, "Reconciliation of {} with the projection of {} from {}",
+           actuals_file, report_file, start.format("%m/%d/%Y"));
+    outln!(out, "Amounts in the dollars of {}, adjusted for {}% inflation", start.format("%m/%Y"), projection.inflation);
+    outln!(out);
+
+    let mut table: Vec<Vec<String>> = Vec::new();
+    table.push(["Date", "Projected", "Actual", "Difference", "Difference %",
+                "Projected deposits", "Actual deposits", "From returns"].iter().map(|v| v.to_string()).collect());
+    for v in comparisons.iter() {
+        let optional = |amount: Option<f32>| amount.map_or("-".to_string(), format_difference);
+        table.push(vec![
+            v.date.format("%m/%d/%Y").to_string(),
+            num_with_commas(v.projected as u64),
+            num_with_commas(v.actual as u64),
+            format_difference(v.actual - v.projected),
+            if v.projected > 0.0 {format!("{:.1}%", (v.actual - v.projected) / v.projected * 100.0)} else {"-".to_string()},
+            format_difference(v.projected_deposits),
+            optional(v.actual_deposits),
+            optional(v.from_returns),
+        ]);
+    }
+    out.push_str(&format_table(table));
+    outln!(out);
+
+    // the returns can only be compared when every deposit was recorded
+    let last = comparisons.last().unwrap();
+    let months = utils::months_between(&start, &last.date);
+    let actual_deposits: Option<f32> = comparisons.iter().map(|v| v.actual_deposits).sum();
+    let projected_deposits: f32 = comparisons.iter().map(|v| v.projected_deposits).sum();
+    let projected_return = get_annual_return(start_balance, last.projected, projected_deposits, months);
+    let actual_return = actual_deposits.and_then(|v| get_annual_return(start_balance, last.actual, v, months));
+    match (actual_return, projected_return) {
+        (Some(actual), Some(projected)) => {
+            outln!(out, "Real return since {}: {:.1}% a year, projected {:.1}%",
+                   start.format("%m/%d/%Y"), actual, projected);
+            if (actual - projected).abs() >= 1.0 {
+                outln!(out, "The returns have been {:.1} points a year {} the projection's. If that's expected to last, adjust the expected returns or inflation.",
+                       (actual - projected).abs(), if actual < projected {"below"} else {"above"});
+            }
+        },
+        _ if actual_deposits.is_none() => {
+            outln!(out, "The returns can't be compared without the net deposits on every line");
+        },
+        _ => (),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_projection() -> Projection {
+        // 1,000,000 growing 1% a month with 1,000 deposited every month
+        let start = NaiveDate::from_ymd_opt(2024, 1, 17).unwrap();
+        let mut balance = 1_000_000.0;
+        let mut steps = Vec::new();
+        for i in 0..24 {
+            steps.push((utils::add_months(&start, i), balance, 1000.0));
+            balance = (balance + 1000.0) * 1.01;
+        }
+        Projection {inflation: 0.0, steps}
+    }
+
+    #[test]
+    fn test_parse_actuals() {
+        let text = "date,balance,net_deposits\n# after the move\n03/01/2024,1020000,2000\n06/01/2024, 1050000 ,\n";
+        let actuals = parse_actuals("actuals.csv", text).unwrap();
+        assert_eq!(actuals.len(), 2);
+        assert_eq!(actuals[0], Actual {date: NaiveDate::from_ymd_opt(2024, 3, 1).unwrap(), balance: 1_020_000.0, net_deposits: Some(2000.0)});
+        assert_eq!(actuals[1].net_deposits, None);
+        assert_eq!(get_latest_balance("actuals.csv", text).unwrap(), (NaiveDate::from_ymd_opt(2024, 6, 1).unwrap(), 1_050_000.0));
+
+        assert!(parse_actuals("actuals.csv", "06/01/2024,1050000\n03/01/2024,1020000\n").is_err());
+        assert!(parse_actuals("actuals.csv", "2024-03-01,1020000\n").is_err());
+    }
+
+    #[test]
+    fn test_compare() {
+        let projection = get_projection();
+        let date = NaiveDate::from_ymd_opt(2024, 7, 17).unwrap();
+        let projected = projection.steps[6].1;
+
+        // 5,000 less deposited than planned and 10,000 less from returns
+        let actuals = vec![Actual {date, balance: projected - 15_000.0, net_deposits: Some(1000.0)}];
+        let comparisons = compare(&projection, &actuals).unwrap();
+        assert_eq!(comparisons[0].projected_deposits, 6000.0);
+        assert!((comparisons[0].from_returns.unwrap() + 10_000.0).abs() < 1.0);
+
+        // the actual balances are nominal
+        let projection = Projection {inflation: 3.0, ..get_projection()};
+        let comparisons = compare(&projection, &actuals).unwrap();
+        assert!(comparisons[0].actual < projected - 15_000.0);
+
+        // the same growth as projected
+        let return_rate = get_annual_return(1_000_000.0, projected, 6000.0, 6).unwrap();
+        assert!((return_rate - (1.01f32.powi(12) - 1.0) * 100.0).abs() < 0.1);
+
+        let before = vec![Actual {date: NaiveDate::from_ymd_opt(2023, 12, 1).unwrap(), balance: 1.0, net_deposits: None}];
+        assert!(compare(&projection, &before).is_err());
+    }
+}
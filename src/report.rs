@@ -0,0 +1,337 @@
+/**************************************************************************
+* report.rs
+*
+* Structured parts of the report (warnings, events, and scenario
+* anomalies) and a small JSON writer for the machine-readable output.
+**************************************************************************/
+
+use std::fmt;
+use chrono::NaiveDate;
+use crate::{num_with_commas, simulate};
+
+///////////////////////////////////////////////////////////////////////////
+// JSON
+///////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    // keeps the order the fields were added in
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn object(fields: Vec<(&str, Json)>) -> Json {
+        Json::Object(fields.into_iter().map(|(k, v)| (k.to_string(), v)).collect())
+    }
+
+    pub fn string(s: &str) -> Json {
+        Json::String(s.to_string())
+    }
+}
+
+fn write_json_string(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(v) => write!(f, "{}", v),
+            // JSON has no NaN or infinity
+            Json::Number(v) if !v.is_finite() => write!(f, "null"),
+            Json::Number(v) => write!(f, "{}", v),
+            Json::String(v) => write_json_string(f, v),
+            Json::Array(values) => {
+                write!(f, "[")?;
+                for (i, v) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", v)?;
+                }
+                write!(f, "]")
+            },
+            Json::Object(fields) => {
+                write!(f, "{{")?;
+                for (i, (k, v)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_json_string(f, k)?;
+                    write!(f, ":{}", v)?;
+                }
+                write!(f, "}}")
+            },
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////
+// Warnings
+///////////////////////////////////////////////////////////////////////////
+
+// a configuration value that is allowed but probably a mistake
+#[derive(Debug, Clone)]
+pub struct Warning {
+    pub field: String,
+    pub message: String,
+}
+
+impl Warning {
+    pub fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("field", Json::string(&self.field)),
+            ("message", Json::string(&self.message)),
+        ])
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////
+// Event log
+///////////////////////////////////////////////////////////////////////////
+
+// a date when something changes in the plan
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub date: NaiveDate,
+    pub kind: &'static str,
+    pub retiree: Option<String>,
+    pub description: String,
+}
+
+impl Event {
+    pub fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("date", Json::String(self.date.to_string())),
+            ("kind", Json::string(self.kind)),
+            ("retiree", self.retiree.as_ref().map_or(Json::Null, |v| Json::string(v))),
+            ("description", Json::string(&self.description)),
+        ])
+    }
+}
+
+// the events of a plan that happen during the simulation, in date order
+pub fn get_events(context: &simulate::PlanContext, results: &simulate::SimulationResults) -> Vec<Event> {
+    let input = context.input;
+    let mut events = Vec::new();
+
+    events.push(Event {
+        date: context.retirement_date,
+        kind: "retirement",
+        retiree: None,
+        description: "Retirement, expenses are paid from the portfolio".to_string(),
+    });
+
+    if let Some(date) = context.relocation_date {
+        events.push(Event {
+            date,
+            kind: "relocation",
+            retiree: None,
+            description: "Move, expenses and state taxes change".to_string(),
+        });
+    }
+
+    if let (Some(home), Some(date)) = (input.home.as_ref(), context.downsize_date) {
+        let equity = home.get_downsize_equity(&context.start_date, &date);
+        events.push(Event {
+            date,
+            kind: "downsize",
+            retiree: None,
+            description: match equity >= 0.0 {
+                true => format!("Downsize, ${} from the home saved, expenses change", num_with_commas(equity as u64)),
+                false => format!("Downsize, ${} more for the new home, expenses change", num_with_commas(-equity as u64)),
+            },
+        });
+    }
+
+    let end_date = context.retirees.iter().map(|v| v.death_date).max().unwrap_or(context.start_date);
+    for expense in input.one_time_expenses.iter() {
+        for date in expense.get_dates(&context.start_date, &end_date) {
+            let amount = expense.get_amount(input.portfolio.expected_inflation, &context.start_date, &date);
+            events.push(Event {
+                date,
+                kind: "one_time_expense",
+                retiree: None,
+                description: format!("{}, ${} withdrawn", expense.name, num_with_commas(amount as u64)),
+            });
+        }
+    }
+
+    for windfall in context.windfalls.iter() {
+        events.push(Event {
+            date: windfall.date,
+            kind: "windfall",
+            retiree: None,
+            description: format!("{}, ${} saved", windfall.name, num_with_commas(windfall.amount as u64)),
+        });
+    }
+
+    for property in input.rental_properties.iter() {
+        if let Some(date) = property.get_sale_date() {
+            events.push(Event {
+                date,
+                kind: "rental_sale",
+                retiree: None,
+                description: format!("{} sold for ${}, the rent stops", property.name,
+                                     num_with_commas(property.get_value(&context.start_date, &date) as u64)),
+            });
+        }
+    }
+
+    for (retiree, retiree_info) in input.retirees.iter().zip(context.retirees.iter()) {
+        let name = Some(retiree.name.clone());
+        events.push(Event {
+            date: retiree_info.retirement_date,
+            kind: "stop_working",
+            retiree: name.clone(),
+            description: format!("{} stops working, wages and contributions stop", retiree.name),
+        });
+        if retiree.employer_stock.as_ref().is_some_and(|v| v.nua) {
+            events.push(Event {
+                date: retiree_info.retirement_date,
+                kind: "nua",
+                retiree: name.clone(),
+                description: format!("{} distributes the employer stock with NUA treatment", retiree.name),
+            });
+        }
+        for part_time in retiree.part_time.iter() {
+            events.push(Event {
+                date: part_time.start_date,
+                kind: "part_time_start",
+                retiree: name.clone(),
+                description: format!("{} starts part-time work, ${} per month", retiree.name,
+                                     num_with_commas(part_time.monthly_income as u64)),
+            });
+            events.push(Event {
+                date: part_time.end_date,
+                kind: "part_time_end",
+                retiree: name.clone(),
+                description: format!("{} stops part-time work", retiree.name),
+            });
+        }
+        events.push(Event {
+            date: retiree_info.social_security_date,
+            kind: "social_security",
+            retiree: name.clone(),
+            description: format!("{} starts social security", retiree.name),
+        });
+        if retiree.pension_monthly_income > 0.0 {
+            events.push(Event {
+                date: retiree_info.pension_date,
+                kind: "pension",
+                retiree: name.clone(),
+                description: match retiree.pension_take_lump_sum {
+                    true => format!("{} takes the pension as a lump sum", retiree.name),
+                    false => format!("{} starts a pension", retiree.name),
+                },
+            });
+        }
+        if let Some(annuity) = retiree.annuity.as_ref() {
+            events.push(Event {
+                date: annuity.get_start_date(&retiree.date_of_birth, retiree_info.retirement_date),
+                kind: "annuity",
+                retiree: name.clone(),
+                description: format!("{} starts annuity payments", retiree.name),
+            });
+        }
+        events.push(Event {
+            date: retiree_info.medicare_date,
+            kind: "medicare",
+            retiree: name.clone(),
+            description: format!("{} starts Medicare", retiree.name),
+        });
+        events.push(Event {
+            date: retiree_info.death_date,
+            kind: "life_expectancy",
+            retiree: name,
+            description: format!("{} reaches the end of their life expectancy", retiree.name),
+        });
+    }
+
+    // the pensions and annuities that lose value to inflation falling below
+    // a share of the expenses, each time they do
+    let warning_percent = context.input.analysis.fixed_income_warning_percent;
+    let mut was_above = false;
+    for (date, share) in results.get_fixed_income_shares() {
+        let Some(share) = share else {
+            continue;
+        };
+        if share < warning_percent && was_above {
+            events.push(Event {
+                date,
+                kind: "purchasing_power",
+                retiree: None,
+                description: format!("Pensions and annuities without full inflation adjustments fall below {:.0}% of the expenses",
+                                     warning_percent),
+            });
+        }
+        was_above = share >= warning_percent;
+    }
+
+    if let Some(date) = context.reverse_mortgage_date {
+        events.push(Event {
+            date: NaiveDate::max(date, context.start_date),
+            kind: "reverse_mortgage",
+            retiree: None,
+            description: "Reverse mortgage line of credit opens".to_string(),
+        });
+    }
+    if let Some(first_draw) = results.monthly_snapshot.iter().find(|v| v.credit_draws > 0.0) {
+        events.push(Event {
+            date: first_draw.date,
+            kind: "credit_draw",
+            retiree: None,
+            description: "Portfolio runs out, expenses are drawn from the reverse mortgage".to_string(),
+        });
+    }
+    if results.is_depleted() {
+        let last = results.monthly_snapshot.last().unwrap();
+        events.push(Event {
+            date: last.date,
+            kind: "depleted",
+            retiree: None,
+            description: match context.reverse_mortgage_date {
+                Some(_) => "Portfolio and reverse mortgage run out of money".to_string(),
+                None => "Portfolio runs out of money".to_string(),
+            },
+        });
+    }
+
+    // only events during the simulation
+    events.retain(|v| v.date >= context.start_date);
+    events.sort_by_key(|v| v.date);
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json() {
+        let json = Json::object(vec![
+            ("name", Json::string("a \"b\"\n")),
+            ("values", Json::Array(vec![Json::Number(1.5), Json::Bool(true), Json::Null])),
+            ("bad", Json::Number(f64::NAN)),
+        ]);
+        assert_eq!(json.to_string(), r#"{"name":"a \"b\"\n","values":[1.5,true,null],"bad":null}"#);
+    }
+}
@@ -4,14 +4,184 @@
 * Common functions and traits for scanning a series of simulations 
 **************************************************************************/
 
-use crate::{Input, simulate};
+use chrono::NaiveDate;
+use crate::{Input, simulate, utils};
+use crate::report::Json;
+use std::io::Write;
+
+// Something unusual in the returns used by a scenario. The scenario still
+// runs, but its results may be less meaningful.
+#[derive(Debug, Clone)]
+pub struct Anomaly {
+    pub scenario: usize,
+    pub kind: AnomalyKind,
+    // year of the simulation, starting at 0
+    pub year: u32,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AnomalyKind {
+    // a sampled annual return was below -100%, it is limited to -100%
+    ReturnBelowTotalLoss,
+    // the scenario ran past the last year of history and reused the first years
+    DataWrapAround,
+}
+
+impl AnomalyKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AnomalyKind::ReturnBelowTotalLoss => "return_below_total_loss",
+            AnomalyKind::DataWrapAround => "data_wrap_around",
+        }
+    }
+}
+
+impl Anomaly {
+    pub fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("scenario", Json::Number(self.scenario as f64)),
+            ("kind", Json::string(self.kind.name())),
+            ("year", Json::Number(self.year as f64)),
+            ("message", Json::string(&self.message)),
+        ])
+    }
+}
+
+// A scenario that couldn't be simulated. It counts as a failed scenario.
+#[derive(Debug, Clone)]
+pub struct ScenarioError {
+    pub scenario: usize,
+    pub message: String,
+}
+
+impl ScenarioError {
+    pub fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("scenario", Json::Number(self.scenario as f64)),
+            ("message", Json::string(&self.message)),
+        ])
+    }
+}
+
+// where a scenario's returns came from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReturnModel {
+    // consecutive historical years
+    Historical,
+    // normal distributions around the expected returns
+    Parametric,
+    // historical years drawn at random
+    Bootstrap,
+}
+
+impl ReturnModel {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ReturnModel::Historical => "historical",
+            ReturnModel::Parametric => "parametric",
+            ReturnModel::Bootstrap => "bootstrap",
+        }
+    }
+}
+
+// What a scenario was run with, so its results can still be interpreted, or
+// the scenario re-created, after they're saved or compared with other runs.
+// Sweeps change the plan between scans, so it's taken from the plan context
+// the scenario ran with.
+#[derive(Debug, Clone)]
+pub struct ScenarioMetadata {
+    pub return_model: ReturnModel,
+    // the Monte Carlo seed, None for historical scenarios
+    pub seed: Option<u64>,
+    pub monthly_expenses: f32,
+    pub months_per_step: u32,
+    // one for each retiree
+    pub social_security_dates: Vec<NaiveDate>,
+    pub death_dates: Vec<NaiveDate>,
+    // the names of the windfalls that happen
+    pub windfalls: Vec<String>,
+}
+
+impl ScenarioMetadata {
+    pub fn new(return_model: ReturnModel, seed: Option<u64>, context: &simulate::PlanContext) -> Self {
+        ScenarioMetadata {
+            return_model,
+            seed,
+            monthly_expenses: context.monthly_expenses,
+            months_per_step: context.input.simulation.months_per_step,
+            social_security_dates: context.retirees.iter().map(|v| v.social_security_date).collect(),
+            death_dates: context.retirees.iter().map(|v| v.death_date).collect(),
+            windfalls: context.windfalls.iter().map(|v| v.name.clone()).collect(),
+        }
+    }
+
+    pub fn to_json(&self) -> Json {
+        let dates = |dates: &[NaiveDate]| Json::Array(dates.iter()
+            .map(|v| Json::string(&v.format("%Y-%m-%d").to_string()))
+            .collect());
+        Json::object(vec![
+            ("return_model", Json::string(self.return_model.name())),
+            ("seed", self.seed.map_or(Json::Null, |v| Json::string(&v.to_string()))),
+            ("monthly_expenses", Json::Number(self.monthly_expenses as f64)),
+            ("months_per_step", Json::Number(self.months_per_step as f64)),
+            ("social_security_dates", dates(&self.social_security_dates)),
+            ("death_dates", dates(&self.death_dates)),
+            ("windfalls", Json::Array(self.windfalls.iter().map(|v| Json::string(v)).collect())),
+        ])
+    }
+}
 
 // A scenario is a particular simulation (one retirement cycle) in a scan.
 #[derive(Debug)]
 pub struct Scenario {
+    // the scenario's number in the scan. A Monte Carlo scenario can be
+    // re-created from it and the seed.
+    pub index: usize,
     pub simulation_results: simulate::SimulationResults,
     pub starting_year: u32,
     pub ending_year: u32,
+    pub anomalies: Vec<Anomaly>,
+    pub metadata: ScenarioMetadata,
+}
+
+impl Scenario {
+    pub fn to_json(&self) -> Json {
+        let ending_balance = self.simulation_results.monthly_snapshot.last().map_or(0.0, |v| v.balance);
+        Json::object(vec![
+            ("scenario", Json::Number(self.index as f64)),
+            ("starting_year", Json::Number(self.starting_year as f64)),
+            ("ending_year", Json::Number(self.ending_year as f64)),
+            ("ending_balance", Json::Number(ending_balance as f64)),
+            ("floor_funded", Json::Bool(self.simulation_results.is_floor_funded())),
+            ("returns", self.simulation_results.get_return_statistics().map_or(Json::Null, |v| v.to_json())),
+            ("metadata", self.metadata.to_json()),
+        ])
+    }
+}
+
+// the ranges the outcomes of the scenarios are counted in, by the first
+// retiree's age when the money ran out or the ending balance in today's
+// dollars. Each has a name for the machine-readable output and a label.
+pub const OUTCOME_BANDS: [(&str, &str); 6] = [
+    ("ran_out_before_80", "Ran out before 80"),
+    ("ran_out_80_to_89", "Ran out at 80 to 89"),
+    ("ran_out_after_90", "Ran out at 90 or later"),
+    ("under_250k", "Succeeded with under $250,000"),
+    ("250k_to_1m", "Succeeded with $250,000 to $1,000,000"),
+    ("over_1m", "Succeeded with over $1,000,000"),
+];
+
+// index in OUTCOME_BANDS of a scenario's outcome
+pub fn get_outcome_band(depletion_age: Option<u32>, ending_balance: f32) -> usize {
+    match depletion_age {
+        Some(age) if age < 80 => 0,
+        Some(age) if age < 90 => 1,
+        Some(_) => 2,
+        None if ending_balance < 250000.0 => 3,
+        None if ending_balance <= 1000000.0 => 4,
+        None => 5,
+    }
 }
 
 // Information for a vector element intended for sorting
@@ -28,9 +198,19 @@ pub struct ScanResults {
     pub scenario_results: Vec<Scenario>,
     pub num_simulations: u32,
     pub num_successful: u32,
+    // scenarios that ended with at least the balance they retired with
+    pub num_principal_kept: u32,
+    // scenarios that paid the essential expenses for the whole plan,
+    // including the successful ones
+    pub num_floor_funded: u32,
     pub min_balance: f32,
     pub max_balance: f32,
     pub sorted_indices: Vec<usize>,
+
+    // unfunded expenses of each failed scenario, in today's dollars
+    pub shortfalls: Vec<f32>,
+    pub anomalies: Vec<Anomaly>,
+    pub errors: Vec<ScenarioError>,
     sorting_info: Vec<ScenarioSortingInfo>,
 }
 
@@ -40,13 +220,70 @@ impl ScanResults {
             scenario_results: Vec::new(),
             num_simulations: 0,
             num_successful: 0,
+            num_principal_kept: 0,
+            num_floor_funded: 0,
             min_balance: f32::MAX,
             max_balance: 0.0,
             sorted_indices: Vec::new(),
+            shortfalls: Vec::new(),
+            anomalies: Vec::new(),
+            errors: Vec::new(),
             sorting_info: Vec::new(),
         }
     }
 
+    // number of scenarios with at least one anomaly of the given kind
+    pub fn count_anomalous_scenarios(&self, kind: AnomalyKind) -> usize {
+        let mut scenarios: Vec<usize> = self.anomalies.iter()
+            .filter(|v| v.kind == kind)
+            .map(|v| v.scenario)
+            .collect();
+        scenarios.dedup();
+        scenarios.len()
+    }
+
+    // percentage of scenarios that never ran out of money
+    pub fn success_rate(&self) -> f32 {
+        if self.num_simulations == 0 {
+            return 0.0;
+        }
+        self.num_successful as f32 / self.num_simulations as f32 * 100.0
+    }
+
+    // percentage of scenarios that paid the essential expenses
+    pub fn floor_funded_rate(&self) -> f32 {
+        if self.num_simulations == 0 {
+            return 0.0;
+        }
+        self.num_floor_funded as f32 / self.num_simulations as f32 * 100.0
+    }
+
+    // percentage of scenarios that kept their principal
+    pub fn principal_kept_rate(&self) -> f32 {
+        if self.num_simulations == 0 {
+            return 0.0;
+        }
+        self.num_principal_kept as f32 / self.num_simulations as f32 * 100.0
+    }
+
+    // number of scenarios in each of OUTCOME_BANDS. Scenarios that stopped
+    // with an error aren't in any of them.
+    pub fn get_outcome_counts(&self) -> [u32; OUTCOME_BANDS.len()] {
+        let mut counts = [0; OUTCOME_BANDS.len()];
+        for scenario in self.scenario_results.iter() {
+            let results = &scenario.simulation_results;
+            let ending_balance = results.monthly_snapshot.last().map_or(0.0, |v| v.balance);
+            counts[get_outcome_band(results.get_depletion_age(), ending_balance)] += 1;
+        }
+        counts
+    }
+
+    // ending balance at a percentile (0-100) of the scenarios
+    pub fn get_ending_balance_percentile(&self, percentile: f32) -> f32 {
+        let balances: Vec<f32> = self.sorting_info.iter().map(|v| v.ending_balance).collect();
+        utils::get_percentile(&balances, percentile)
+    }
+
     pub fn add_sorting_info(&mut self, index: usize, num_months: usize, ending_balance: f32) {
         self.sorting_info.push(ScenarioSortingInfo{index, num_months, ending_balance});
     }
@@ -65,10 +302,92 @@ impl ScanResults {
 }
 
 pub trait Scannable {
-    fn run_scan(&mut self, input: &Input) -> Result<ScanResults, String>;
+    fn run_scan_with_context(&mut self, context: &simulate::PlanContext) -> Result<ScanResults, String>;
+
+    fn run_scan(&mut self, input: &Input) -> Result<ScanResults, String> {
+        self.run_scan_with_context(&simulate::PlanContext::new(input))
+    }
+}
+
+// runs the scan once for each monthly expense amount. Only the spending
+// changes between runs, so the plan context is derived once and the
+// scanner's return data is reused.
+pub fn run_spending_sweep<S: Scannable>(
+    scanner: &mut S,
+    input: &Input,
+    monthly_expenses: &[f32]) -> Result<Vec<ScanResults>, String> {
+    let context = simulate::PlanContext::new(input);
+    let mut sweep_results = Vec::new();
+    for expenses in monthly_expenses.iter() {
+        let cell_context = context.with_monthly_expenses(*expenses);
+        sweep_results.push(scanner.run_scan_with_context(&cell_context)?);
+    }
+    Ok(sweep_results)
+}
+
+// runs the scan once for each combination of social security claiming
+// ages, each with an age for every retiree
+pub fn run_social_security_sweep<S: Scannable>(
+    scanner: &mut S,
+    input: &Input,
+    claiming_ages: &[Vec<u32>]) -> Result<Vec<ScanResults>, String> {
+    let context = simulate::PlanContext::new(input);
+    let mut sweep_results = Vec::new();
+    for ages in claiming_ages.iter() {
+        let cell_context = context.with_social_security_ages(ages);
+        sweep_results.push(scanner.run_scan_with_context(&cell_context)?);
+    }
+    Ok(sweep_results)
+}
+
+// runs the scan once for each number of years the retirees are forced to
+// retire early
+pub fn run_early_retirement_sweep<S: Scannable>(
+    scanner: &mut S,
+    input: &Input,
+    years: &[u32],
+    benefit_reduction_percent: f32) -> Result<Vec<ScanResults>, String> {
+    let context = simulate::PlanContext::new(input);
+    let mut sweep_results = Vec::new();
+    for years_early in years.iter() {
+        let cell_context = context.with_early_retirement(*years_early, benefit_reduction_percent);
+        sweep_results.push(scanner.run_scan_with_context(&cell_context)?);
+    }
+    Ok(sweep_results)
+}
+
+// runs the scan once for each number of months the retirees retire later
+// than planned, negative for earlier
+pub fn run_retirement_curve<S: Scannable>(
+    scanner: &mut S,
+    input: &Input,
+    shifts: &[i32]) -> Result<Vec<ScanResults>, String> {
+    let context = simulate::PlanContext::new(input);
+    let mut curve_results = Vec::new();
+    for months in shifts.iter() {
+        let cell_context = context.with_retirement_shift(*months);
+        curve_results.push(scanner.run_scan_with_context(&cell_context)?);
+    }
+    Ok(curve_results)
+}
+
+// runs the scan once for each set of ages the retirees live to, one for
+// each retiree
+pub fn run_longevity_sweep<S: Scannable>(
+    scanner: &mut S,
+    input: &Input,
+    longevity_ages: &[Vec<u32>]) -> Result<Vec<ScanResults>, String> {
+    let context = simulate::PlanContext::new(input);
+    let mut sweep_results = Vec::new();
+    for ages in longevity_ages.iter() {
+        let cell_context = context.with_life_expectancies(ages);
+        sweep_results.push(scanner.run_scan_with_context(&cell_context)?);
+    }
+    Ok(sweep_results)
 }
 
-pub fn add_scenario_to_results(results: &mut ScanResults, scenario: Scenario, index: usize) {
+pub fn add_scenario_to_results(results: &mut ScanResults, mut scenario: Scenario) {
+    let index = results.scenario_results.len();
     results.num_simulations += 1;
     let last_index = scenario.simulation_results.monthly_snapshot.len() - 1;
     let last_balance = scenario.simulation_results.monthly_snapshot[last_index].balance;
@@ -76,11 +395,87 @@ pub fn add_scenario_to_results(results: &mut ScanResults, scenario: Scenario, in
     results.max_balance = f32::max(results.max_balance, last_balance);
     results.add_sorting_info(
         index,
-        scenario.simulation_results.monthly_snapshot.len(),
+        scenario.simulation_results.monthly_snapshot.len() * scenario.simulation_results.months_per_step as usize,
         last_balance,
     );
-    if last_balance > 0.0 {
+    if !scenario.simulation_results.is_depleted() {
         results.num_successful += 1;
     }
+    else {
+        results.shortfalls.push(scenario.simulation_results.unfunded_expenses);
+    }
+    if scenario.simulation_results.keeps_principal() {
+        results.num_principal_kept += 1;
+    }
+    if scenario.simulation_results.is_floor_funded() {
+        results.num_floor_funded += 1;
+    }
+    results.anomalies.append(&mut scenario.anomalies);
     results.scenario_results.push(scenario);
 }
+
+// records a scenario that failed with an error, so the rest of the scan can
+// continue
+pub fn add_error_to_results(results: &mut ScanResults, scenario: usize, message: String) {
+    results.num_simulations += 1;
+    results.errors.push(ScenarioError {scenario, message});
+}
+
+// Writes a JSON Lines record for each scenario of a scan as soon as it has
+// run, so a consumer can follow a long scan without waiting for the report
+pub struct ScenarioStream {
+    // the scan the records belong to, such as historical or monte_carlo
+    scan: &'static str,
+    writer: Box<dyn Write>,
+}
+
+impl ScenarioStream {
+    pub fn new(scan: &'static str, writer: Box<dyn Write>) -> Self {
+        ScenarioStream {scan, writer}
+    }
+
+    pub fn write_scenario(&mut self, scenario: &Scenario) -> Result<(), String> {
+        self.write_record("scenario", scenario.to_json())
+    }
+
+    pub fn write_error(&mut self, scenario: usize, message: &str) -> Result<(), String> {
+        self.write_record("error", ScenarioError {scenario, message: message.to_string()}.to_json())
+    }
+
+    fn write_record(&mut self, record: &str, value: Json) -> Result<(), String> {
+        let json = Json::object(vec![
+            ("record", Json::string(record)),
+            ("scan", Json::string(self.scan)),
+            (record, value),
+        ]);
+        writeln!(self.writer, "{}", json)
+            .and_then(|_| self.writer.flush())
+            .map_err(|err| format!("Can't write the scenario records: {}", err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scenario_errors_count_as_failed() {
+        let mut results = ScanResults::new();
+        add_error_to_results(&mut results, 3, "error".to_string());
+        assert_eq!(results.num_simulations, 1);
+        assert_eq!(results.num_successful, 0);
+        assert_eq!(results.success_rate(), 0.0);
+        assert_eq!(results.errors[0].scenario, 3);
+        assert_eq!(results.get_outcome_counts(), [0; OUTCOME_BANDS.len()]);
+    }
+
+    #[test]
+    fn test_outcome_bands() {
+        assert_eq!(get_outcome_band(Some(79), 0.0), 0);
+        assert_eq!(get_outcome_band(Some(80), 0.0), 1);
+        assert_eq!(get_outcome_band(Some(95), 0.0), 2);
+        assert_eq!(get_outcome_band(None, 249999.0), 3);
+        assert_eq!(get_outcome_band(None, 1000000.0), 4);
+        assert_eq!(get_outcome_band(None, 1000001.0), 5);
+    }
+}
@@ -4,11 +4,35 @@
 * Performs the simulation of a retirement scenario
 **************************************************************************/
 
-use crate::{Input, TaxLevel};
-use chrono;
-use chrono::NaiveDate;
+use crate::{Input, IrmaaLevel, PayrollTaxes, Retiree, TaxLevel, TaxRates, Windfall};
+use chrono::{Datelike, NaiveDate};
 use crate::utils::*;
-use crate::portfolio::Portfolio;
+use crate::portfolio::{Portfolio, MAX_ASSET_CLASSES};
+use crate::invariants;
+use crate::report::Json;
+
+// capital losses can offset this much ordinary income a year
+const MAX_LOSS_DEDUCTION: f32 = 3000.0;
+
+// the 2025 federal poverty line for one person and for each additional
+// person, which the 2026 ACA premium tax credit is based on. It's indexed to
+// inflation, so it stays the same in today's dollars.
+const POVERTY_LINE: f32 = 15650.0;
+const POVERTY_LINE_PER_PERSON: f32 = 5500.0;
+
+// the share of income expected to go to the benchmark premium in 2026, by
+// income as a percentage of the poverty line. The share rises linearly from
+// the start to the end of each band, and above 400% there's no credit.
+// 3.14% is the IRS figure, not an approximation of pi
+#[allow(clippy::approx_constant)]
+const ACA_APPLICABLE_PERCENTAGES: [(f32, f32, f32, f32); 6] = [
+    (0.0, 133.0, 2.10, 2.10),
+    (133.0, 150.0, 3.14, 4.19),
+    (150.0, 200.0, 4.19, 6.60),
+    (200.0, 250.0, 6.60, 8.44),
+    (250.0, 300.0, 8.44, 9.96),
+    (300.0, 400.0, 9.96, 9.96),
+];
 
 // stores results of each month of the simulation
 #[derive(Debug)]
@@ -17,41 +41,605 @@ pub struct MonthlySnapshot {
     pub balance: f32,
     pub expenses: f32,
     pub income: f32,
+    pub wages: f32,
+    pub contributions: f32,
     pub tax_rate: f32,
     pub taxes: f32,
+    pub payroll_taxes: f32,
+    pub wage_taxes: f32,
+
+    // ordinary taxable income before the standard deduction, including the
+    // withdrawals to pay the taxes
+    pub taxable_income: f32,
+    // federal income and capital gains taxes as a percentage of the
+    // taxable income plus dividends
+    pub effective_tax_rate: f32,
+    // federal marginal rate after the withdrawals to pay the taxes, and the
+    // annual income that can be added before the next bracket
+    pub bracket_rate: f32,
+    pub bracket_headroom: f32,
+
+    // taxable income by type for the tax report. Withdrawals include the
+    // withdrawals to pay the taxes, social security is the full benefit, and
+    // the standard deduction is annual.
+    pub taxable_wages: f32,
+    pub social_security: f32,
+    pub other_income: f32,
+    // social security, pensions and annuities
+    pub guaranteed_income: f32,
+    // the pensions and annuities that lose value to inflation
+    pub fixed_income: f32,
+    // the part of the expenses that is one-time expenses
+    pub one_time_expenses: f32,
+    // health insurance and out-of-pocket costs before Medicare, and the ACA
+    // premium tax credit that reduces them. The expenses include the costs
+    // less the credit.
+    pub bridge_healthcare: f32,
+    pub aca_credit: f32,
+    // Part B, Part D and Medigap premiums, included in the expenses. The
+    // IRMAA surcharges on top of them are counted with the taxes.
+    pub medicare_premiums: f32,
+    pub roth_conversions: f32,
+    pub interest: f32,
+    pub dividends: f32,
+    // capital gains realized by rebalancing the taxable accounts
+    pub rebalance_gains: f32,
+    pub taxable_withdrawals: f32,
+    pub loss_deduction: f32,
+    pub standard_deduction: f32,
+
+    // breakdown of the taxes other than payroll taxes
+    pub federal_taxes: f32,
+    pub capital_gains_taxes: f32,
+    pub state_taxes: f32,
+    pub irmaa: f32,
+
+    // taken from the portfolio for expenses and taxes, not including the
+    // 529 accounts
+    pub withdrawals: f32,
+    // everything deposited in the portfolio less everything taken out
+    pub net_deposits: f32,
     pub withdrawal_rate: f32,
     pub annualized_return: f32,
+
+    // drawn from the reverse mortgage line of credit for expenses and
+    // taxes, and the credit left and the loan balance at the end of the step
+    pub credit_draws: f32,
+    pub credit_available: f32,
+    pub reverse_mortgage_balance: f32,
+
+    // monthly returns (percent) applied to each asset class in this step
+    pub returns: [f32; MAX_ASSET_CLASSES],
 }
     
-// values collected for each retiree during simulation to make
-// reporting easier
-#[derive(Debug)]
+// values derived for each retiree before the simulation starts to make
+// the simulation faster and reporting easier
+#[derive(Debug, Clone, Copy)]
 pub struct RetireeInfo {
-    pub name: String,
     pub social_security_date: NaiveDate,
     pub date_of_birth: NaiveDate,
+    pub pension_date: NaiveDate,
+    pub medicare_date: NaiveDate,
+
+    // wages and contributions stop on this date
+    pub retirement_date: NaiveDate,
+
+    // first day past the retiree's life expectancy
+    pub death_date: NaiveDate,
     social_security_income: f32,
+
+    // benefit based on the spouse's record, paid once both have claimed
+    spousal_date: NaiveDate,
+    spousal_income: f32,
+
+    // the benefits are recalculated at full retirement age to credit the
+    // months withheld by the earnings test
+    full_retirement_date: NaiveDate,
+    social_security_income_fra: f32,
+    spousal_income_fra: f32,
+}
+
+// annual earnings test limits in today's dollars. Benefits claimed before
+// full retirement age are withheld $1 for every $2 of earnings over the
+// limit, and in the year full retirement age is reached $1 for every $3 over
+// the higher limit, counting only the earnings before it.
+const EARNINGS_TEST_LIMIT: f32 = 23400.0;
+const EARNINGS_TEST_LIMIT_FRA_YEAR: f32 = 62160.0;
+
+// benefits withheld in a year for the earnings in it
+fn get_earnings_test_withholding(earnings: f32, is_fra_year: bool) -> f32 {
+    match is_fra_year {
+        true => f32::max(earnings - EARNINGS_TEST_LIMIT_FRA_YEAR, 0.0) / 3.0,
+        false => f32::max(earnings - EARNINGS_TEST_LIMIT, 0.0) / 2.0,
+    }
+}
+
+// monthly benefit withheld by the earnings test from a retiree still
+// working after claiming. Wages stop at the retiree's retirement, and months
+// after that are paid in full even if the year's earnings are over the
+// limit, so the year's withholding is spread over the months worked.
+fn get_earnings_test_monthly(
+    retiree: &Retiree,
+    info: &RetireeInfo,
+    start_date: &NaiveDate,
+    date: &NaiveDate,
+    benefit: f32) -> f32 {
+
+    let is_fra_year = info.full_retirement_date.year() == date.year();
+    let mut year_end = NaiveDate::from_ymd_opt(date.year() + 1, 1, 1).unwrap();
+    if is_fra_year {
+        year_end = NaiveDate::min(year_end, info.full_retirement_date);
+    }
+    let end = NaiveDate::min(info.retirement_date, year_end);
+    let is_working = *date < end || (*date < year_end && retiree.get_part_time_income(date) > 0.0);
+    if *date < info.social_security_date || !is_working {
+        return 0.0;
+    }
+
+    // the earnings of each month of the year that counts, a partial month
+    // of full-time work counts as a month worked
+    let mut earnings = 0.0;
+    let mut full_time_months = 0;
+    let mut months_worked = 0;
+    for month in 1..=12 {
+        let month_start = NaiveDate::from_ymd_opt(date.year(), month, 1).unwrap();
+        if month_start >= year_end {
+            break;
+        }
+        let part_time = retiree.get_part_time_income(&month_start);
+        if month_start < end {
+            earnings += retiree.get_salary(start_date, &month_start) / 12.0;
+            full_time_months += 1;
+        }
+        earnings += part_time;
+        if month_start < end || part_time > 0.0 {
+            months_worked += 1;
+        }
+    }
+    if let Some(bonus) = retiree.bonus.as_ref() {
+        if bonus.month <= full_time_months {
+            earnings += bonus.amount + retiree.get_salary(start_date, date) * bonus.percent / 100.0;
+        }
+    }
+    let year_start = NaiveDate::from_ymd_opt(date.year(), 1, 1).unwrap();
+    earnings += retiree.compensation.iter().map(|v| v.get_amount(&year_start, &end)).sum::<f32>();
+    let withheld = get_earnings_test_withholding(earnings, is_fra_year) / u32::max(months_worked, 1) as f32;
+    f32::min(withheld, benefit)
+}
+
+// the social security claiming age of each retiree in months
+fn get_claim_months(input: &Input) -> Vec<u32> {
+    input.retirees.iter()
+        .map(|v| v.social_security_age * 12 + v.social_security_months)
+        .collect()
+}
+
+// derives the social security dates and benefits of each retiree from
+// their claiming ages in months
+fn set_social_security(retirees: &mut [RetireeInfo], input: &Input, start_date: &NaiveDate, claim_months: &[u32]) {
+    let mut claim_months = claim_months.to_vec();
+    for ((info, retiree), months) in retirees.iter_mut().zip(input.retirees.iter()).zip(claim_months.iter_mut()) {
+        (info.social_security_date, *months) = get_social_security_start(&retiree.date_of_birth, *months);
+        info.social_security_income = get_social_security_monthly_income(
+            *months,
+            retiree.date_of_birth.year(),
+            retiree.social_security_amount_full);
+        info.spousal_date = info.social_security_date;
+        info.spousal_income = 0.0;
+    }
+
+    // spousal benefits are up to half of the other retiree's full
+    // benefit, and start when the later of the two claims
+    if retirees.len() == 2 {
+        let spousal_date = NaiveDate::max(retirees[0].social_security_date, retirees[1].social_security_date);
+        for i in 0..2 {
+            retirees[i].spousal_date = spousal_date;
+            retirees[i].spousal_income = get_spousal_monthly_income(
+                claim_months[i],
+                input.retirees[i].date_of_birth.year(),
+                input.retirees[1 - i].social_security_amount_full);
+        }
+    }
+
+    // at full retirement age the benefits are recalculated as if they were
+    // claimed later by the number of months the earnings test withheld,
+    // including months of part-time work after retiring
+    for (i, (info, retiree)) in retirees.iter_mut().zip(input.retirees.iter()).enumerate() {
+        let full_months = get_full_retirement_age_months(retiree.date_of_birth.year());
+        info.full_retirement_date = get_month_at_age(&retiree.date_of_birth, full_months);
+
+        let mut withheld_months = 0.0;
+        let mut date = info.social_security_date;
+        while date < info.full_retirement_date {
+            let benefit = info.get_benefit(&date, false);
+            if benefit > 0.0 {
+                withheld_months += get_earnings_test_monthly(retiree, info, start_date, &date, benefit) / benefit;
+            }
+            date = date.checked_add_months(chrono::Months::new(1)).unwrap();
+        }
+
+        let credited_months = u32::min(claim_months[i] + withheld_months.round() as u32, u32::max(full_months, claim_months[i]));
+        info.social_security_income_fra = get_social_security_monthly_income(
+            credited_months,
+            retiree.date_of_birth.year(),
+            retiree.social_security_amount_full);
+        info.spousal_income_fra = 0.0;
+        if input.retirees.len() == 2 {
+            info.spousal_income_fra = get_spousal_monthly_income(
+                credited_months,
+                retiree.date_of_birth.year(),
+                input.retirees[1 - i].social_security_amount_full);
+        }
+    }
+}
+
+impl RetireeInfo {
+    pub fn is_alive(&self, date: &NaiveDate) -> bool {
+        *date < self.death_date
+    }
+
+    // the retiree's own benefit on a date, and with the spousal benefit the
+    // larger of the two, before the earnings test
+    fn get_benefit(&self, date: &NaiveDate, include_spousal: bool) -> f32 {
+        let (own, spousal) = match *date >= self.full_retirement_date {
+            true => (self.social_security_income_fra, self.spousal_income_fra),
+            false => (self.social_security_income, self.spousal_income),
+        };
+        if include_spousal && *date > self.spousal_date {
+            f32::max(own, spousal)
+        }
+        else if *date > self.social_security_date {
+            own
+        }
+        else {
+            0.0
+        }
+    }
+}
+
+// everything derived from the input that is the same for every scenario.
+// Scans create this once and share it with all their simulations.
+#[derive(Debug, Clone)]
+pub struct PlanContext<'a> {
+    pub input: &'a Input,
+    pub start_date: NaiveDate,
+    pub retirement_date: NaiveDate,
+    pub retirees: Vec<RetireeInfo>,
+    pub monthly_expenses: f32,
+
+    // date of the move, if any, and the monthly expenses after it if they
+    // change
+    pub relocation_date: Option<NaiveDate>,
+    pub relocation_expenses: Option<f32>,
+
+    // date of downsizing the home, if any, and the monthly expenses after
+    // it if they change
+    pub downsize_date: Option<NaiveDate>,
+    pub downsize_expenses: Option<f32>,
+
+    // date the reverse mortgage line of credit opens, if there is one
+    pub reverse_mortgage_date: Option<NaiveDate>,
+
+    // the windfalls that happen
+    pub windfalls: Vec<&'a Windfall>,
+}
+
+// the first day past living to an age, the plan covers the whole year of it
+fn get_death_date(date_of_birth: &NaiveDate, age: u32) -> NaiveDate {
+    date_of_birth.checked_add_months(chrono::Months::new((age + 1) * 12)).unwrap()
+}
+
+impl<'a> PlanContext<'a> {
+    pub fn new(input: &'a Input) -> Self {
+        Self::with_start_date(input, chrono::Utc::now().naive_utc().date())
+    }
+
+    // the context of a plan that starts at a date other than today
+    pub fn with_start_date(input: &'a Input, start_date: NaiveDate) -> Self {
+        let mut retirees = Vec::new();
+        for retiree in input.retirees.iter() {
+            let retiree_info = RetireeInfo {
+                social_security_date: retiree.date_of_birth,
+                date_of_birth: retiree.date_of_birth,
+                pension_date: add_years(&retiree.date_of_birth, retiree.pension_age),
+                medicare_date: get_month_at_age(&retiree.date_of_birth, 65 * 12),
+                retirement_date: add_years(&retiree.date_of_birth, retiree.retirement_age),
+                death_date: get_death_date(&retiree.date_of_birth, retiree.life_expectency),
+                social_security_income: 0.0,
+                spousal_date: retiree.date_of_birth,
+                spousal_income: 0.0,
+                full_retirement_date: retiree.date_of_birth,
+                social_security_income_fra: 0.0,
+                spousal_income_fra: 0.0,
+            };
+            retirees.push(retiree_info);
+        }
+        set_social_security(&mut retirees, input, &start_date, &get_claim_months(input));
+
+        // the household retires, and starts paying the expenses from the
+        // portfolio, when the first retiree stops working
+        let retirement_date = retirees.iter().map(|v| v.retirement_date).min().unwrap();
+
+        let relocation = input.relocation.as_ref();
+        let downsize = input.home.as_ref().and_then(|v| v.downsize.as_ref());
+        PlanContext {
+            input,
+            start_date,
+            retirement_date,
+            retirees,
+            monthly_expenses: input.expenses.monthly,
+            relocation_date: relocation.map(|v| add_years(&input.retirees[0].date_of_birth, v.age)),
+            relocation_expenses: relocation.and_then(|v| v.monthly_expenses),
+            downsize_date: downsize.map(|v| add_years(&input.retirees[0].date_of_birth, v.age)),
+            downsize_expenses: downsize.and_then(|v| v.monthly_expenses),
+            reverse_mortgage_date: input.home.as_ref().and_then(|v| v.reverse_mortgage.as_ref())
+                .map(|v| add_years(&input.retirees[0].date_of_birth, v.age)),
+            windfalls: input.windfalls.iter().filter(|v| v.is_certain()).collect(),
+        }
+    }
+
+    // copy of the context with different social security claiming ages, one
+    // for each retiree, for sweeps
+    pub fn with_social_security_ages(&self, ages: &[u32]) -> Self {
+        let mut context = self.clone();
+        let claim_months: Vec<u32> = ages.iter().map(|v| v * 12).collect();
+        set_social_security(&mut context.retirees, self.input, &self.start_date, &claim_months);
+        context
+    }
+
+    // copy of the context with the retirees still working forced to retire
+    // some years earlier than planned, such as after a layoff, for the early
+    // retirement stress test. Social security is worked out again for the
+    // new dates, less a percentage for each year retired early since fewer
+    // years of earnings count towards it.
+    pub fn with_early_retirement(&self, years: u32, benefit_reduction_percent: f32) -> Self {
+        let mut context = self.clone();
+        let mut factors = Vec::new();
+        for info in context.retirees.iter_mut() {
+            let planned_date = info.retirement_date;
+            if planned_date > self.start_date {
+                let date = planned_date.checked_sub_months(chrono::Months::new(years * 12)).unwrap();
+                info.retirement_date = NaiveDate::max(date, self.start_date);
+            }
+            let years_early = months_between(&info.retirement_date, &planned_date) as f32 / 12.0;
+            factors.push(f32::max(1.0 - years_early * benefit_reduction_percent / 100.0, 0.0));
+        }
+        context.retirement_date = context.retirees.iter().map(|v| v.retirement_date).min().unwrap();
+
+        set_social_security(&mut context.retirees, self.input, &self.start_date, &get_claim_months(self.input));
+        for (i, info) in context.retirees.iter_mut().enumerate() {
+            info.social_security_income *= factors[i];
+            info.social_security_income_fra *= factors[i];
+            // the spousal benefit is based on the other retiree's earnings
+            if let Some(factor) = factors.get(1 - i) {
+                info.spousal_income *= factor;
+                info.spousal_income_fra *= factor;
+            }
+        }
+        context
+    }
+
+    // copy of the context with the retirees still working retiring the given
+    // number of months later than planned, or earlier if it's negative, for
+    // the retirement date curve. Unlike the stress test social security
+    // isn't reduced, and no one retires before today.
+    pub fn with_retirement_shift(&self, months: i32) -> Self {
+        let mut context = self.clone();
+        for info in context.retirees.iter_mut() {
+            let planned_date = info.retirement_date;
+            if planned_date > self.start_date {
+                let shift = chrono::Months::new(months.unsigned_abs());
+                let date = if months < 0 {
+                    planned_date.checked_sub_months(shift).unwrap()
+                } else {
+                    planned_date.checked_add_months(shift).unwrap()
+                };
+                info.retirement_date = NaiveDate::max(date, self.start_date);
+            }
+        }
+        context.retirement_date = context.retirees.iter().map(|v| v.retirement_date).min().unwrap();
+        set_social_security(&mut context.retirees, self.input, &self.start_date, &get_claim_months(self.input));
+        context
+    }
+
+    // copy of the context with different dates the retirees die, one for
+    // each retiree
+    pub fn with_death_dates(&self, death_dates: &[NaiveDate]) -> Self {
+        let mut context = self.clone();
+        for (info, date) in context.retirees.iter_mut().zip(death_dates.iter()) {
+            info.death_date = *date;
+        }
+        context
+    }
+
+    // copy of the context with the retirees living to other ages, one for
+    // each retiree
+    pub fn with_life_expectancies(&self, ages: &[u32]) -> Self {
+        let death_dates: Vec<NaiveDate> = self.input.retirees.iter().zip(ages.iter())
+            .map(|(retiree, age)| get_death_date(&retiree.date_of_birth, *age))
+            .collect();
+        self.with_death_dates(&death_dates)
+    }
+
+    // copy of the context with the windfalls that happen in a scenario, one
+    // for each windfall in the input
+    pub fn with_windfalls(&self, happens: &[bool]) -> Self {
+        PlanContext {
+            windfalls: self.input.windfalls.iter().zip(happens.iter())
+                .filter(|(_, happens)| **happens)
+                .map(|(windfall, _)| windfall)
+                .collect(),
+            ..self.clone()
+        }
+    }
+
+    // copy of the context with different spending, for sweeps. The expenses
+    // after a move or downsizing change by the same percentage.
+    pub fn with_monthly_expenses(&self, monthly_expenses: f32) -> Self {
+        let mut scale = 1.0;
+        if self.input.expenses.monthly != 0.0 {
+            scale = monthly_expenses / self.input.expenses.monthly;
+        }
+        PlanContext {
+            monthly_expenses,
+            relocation_expenses: self.relocation_expenses.map(|v| v * scale),
+            downsize_expenses: self.downsize_expenses.map(|v| v * scale),
+            ..self.clone()
+        }
+    }
 }
 
 #[derive(Debug, Default)]
 pub struct SimulationResults {
     pub retirement_date: NaiveDate,
-    pub retirement_age: u32,
     pub retirees: Vec<RetireeInfo>,
     pub monthly_snapshot: Vec<MonthlySnapshot>,
     pub average_return: f32,
+
+    // expenses in today's dollars left unpaid from when the money ran out
+    // until the end of the plan, 0 if the money lasted
+    pub unfunded_expenses: f32,
+    // the part of them that's essential spending. All of it is unless the
+    // expenses have an essential amount, the rest is discretionary spending
+    // that can be cut.
+    pub unfunded_essential_expenses: f32,
+
+    // each snapshot covers this many months, 1 unless the coarse annual
+    // time step is used
+    pub months_per_step: u32,
+
+    // failed invariant checks, only checked with --check
+    pub violations: Vec<invariants::Violation>,
+
+    // the balance when retirement starts, None if the plan ended before
+    pub retirement_balance: Option<f32>,
+}
+
+// the portfolio returns a simulation ran with, as annual percentages, for
+// comparing the paths against the expected returns
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReturnStatistics {
+    pub geometric_mean: f32,
+    // standard deviation of the returns of each full year
+    pub volatility: f32,
+    // return and calendar year of the best and worst full years
+    pub best_year: (f32, i32),
+    pub worst_year: (f32, i32),
 }
 
-fn is_everyone_dead(current_date: &NaiveDate, input: &Input) -> bool {
-    for retiree in input.retirees.iter() {
-        if get_age(&retiree.date_of_birth, &current_date) <= retiree.life_expectency {
-            return false;
+impl ReturnStatistics {
+    pub fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("geometric_mean", Json::Number(self.geometric_mean as f64)),
+            ("volatility", Json::Number(self.volatility as f64)),
+            ("best_year", Json::Number(self.best_year.1 as f64)),
+            ("best_year_return", Json::Number(self.best_year.0 as f64)),
+            ("worst_year", Json::Number(self.worst_year.1 as f64)),
+            ("worst_year_return", Json::Number(self.worst_year.0 as f64)),
+        ])
+    }
+}
+
+impl SimulationResults {
+    pub fn snapshots_per_year(&self) -> usize {
+        (12 / self.months_per_step) as usize
+    }
+
+    // the returns (percent) of each asset class over a step, the monthly
+    // returns compounded over its months
+    pub fn get_step_returns(&self, snapshot: &MonthlySnapshot) -> [f32; MAX_ASSET_CLASSES] {
+        match self.months_per_step {
+            1 => snapshot.returns,
+            months => snapshot.returns.map(|v| ((1.0 + v / 100.0).powi(months as i32) - 1.0) * 100.0),
+        }
+    }
+
+    // whether the plan lived off the real returns, ending with at least the
+    // balance it retired with. The amounts are in today's dollars, so the
+    // principal is kept after inflation.
+    pub fn keeps_principal(&self) -> bool {
+        let ending_balance = self.monthly_snapshot.last().map_or(0.0, |v| v.balance);
+        self.retirement_balance.is_some_and(|v| ending_balance > 0.0 && ending_balance >= v)
+    }
+
+    // the statistics of the returns of each step, compounded into years
+    // from the start of the simulation. A last year that ends early only
+    // counts towards the geometric mean. None if there wasn't a full year.
+    pub fn get_return_statistics(&self) -> Option<ReturnStatistics> {
+        let step_growth = |v: &MonthlySnapshot| (1.0 + v.annualized_return as f64 / 100.0).powf(self.months_per_step as f64 / 12.0);
+        let total_growth: f64 = self.monthly_snapshot.iter().map(step_growth).product();
+        let years = (self.monthly_snapshot.len() * self.months_per_step as usize) as f64 / 12.0;
+
+        let mut annual_returns = Vec::new();
+        for year in self.monthly_snapshot.chunks_exact(self.snapshots_per_year()) {
+            let growth: f64 = year.iter().map(step_growth).product();
+            annual_returns.push(((growth - 1.0) * 100.0, year[0].date.year()));
+        }
+        if annual_returns.is_empty() {
+            return None;
+        }
+
+        let mean = annual_returns.iter().map(|v| v.0).sum::<f64>() / annual_returns.len() as f64;
+        let mut variance = 0.0;
+        if annual_returns.len() > 1 {
+            variance = annual_returns.iter().map(|v| (v.0 - mean).powi(2)).sum::<f64>() / (annual_returns.len() - 1) as f64;
+        }
+        let best = annual_returns.iter().fold(annual_returns[0], |a, b| if b.0 > a.0 {*b} else {a});
+        let worst = annual_returns.iter().fold(annual_returns[0], |a, b| if b.0 < a.0 {*b} else {a});
+        Some(ReturnStatistics {
+            geometric_mean: ((total_growth.powf(1.0 / years) - 1.0) * 100.0) as f32,
+            volatility: variance.sqrt() as f32,
+            best_year: (best.0 as f32, best.1),
+            worst_year: (worst.0 as f32, worst.1),
+        })
+    }
+
+    // the pensions and annuities that lose value to inflation as a
+    // percentage of the expenses, averaged over each year of retirement, with
+    // the first date of the year. None for a year without expenses.
+    pub fn get_fixed_income_shares(&self) -> Vec<(NaiveDate, Option<f32>)> {
+        let mut shares = Vec::new();
+        for year in self.monthly_snapshot.chunks(self.snapshots_per_year()) {
+            if year[0].date < self.retirement_date {
+                continue;
+            }
+            // one-time expenses would make a year with a new roof look like
+            // the income had fallen behind
+            let fixed_income: f32 = year.iter().map(|v| v.fixed_income).sum();
+            let expenses: f32 = year.iter().map(|v| v.expenses - v.one_time_expenses).sum();
+            shares.push((year[0].date, (expenses > 0.0).then(|| fixed_income / expenses * 100.0)));
+        }
+        shares
+    }
+
+    // whether the money ran out before the end of the plan. A plan living on
+    // a reverse mortgage line of credit after the portfolio is empty hasn't
+    // run out until the credit has too.
+    pub fn is_depleted(&self) -> bool {
+        self.monthly_snapshot.last().is_some_and(|v| v.balance == 0.0 && v.credit_available == 0.0)
+    }
+
+    // the essential spending was paid for the whole plan, even if the
+    // discretionary spending had to be cut after the money ran out
+    pub fn is_floor_funded(&self) -> bool {
+        !self.is_depleted() || self.unfunded_essential_expenses < 1.0
+    }
+
+    // the first retiree's age when the money ran out, if it did
+    pub fn get_depletion_age(&self) -> Option<u32> {
+        if !self.is_depleted() {
+            return None;
         }
+        let last = self.monthly_snapshot.last()?;
+        Some(get_age(&self.retirees[0].date_of_birth, &last.date))
     }
-    return true;
 }
 
-fn get_taxes(mut monthly_income: f32, standard_deduction: f32, tax_rates: &Vec<TaxLevel>) -> (f32, f32) {
+fn is_everyone_dead(current_date: &NaiveDate, retirees: &[RetireeInfo]) -> bool {
+    !retirees.iter().any(|v| v.is_alive(current_date))
+}
+
+fn get_taxes(mut monthly_income: f32, standard_deduction: f32, tax_rates: &[TaxLevel]) -> (f32, f32) {
     let mut total_tax: f32 = 0.0;
     if monthly_income > standard_deduction / 12.0 {
         monthly_income -= standard_deduction / 12.0;
@@ -71,220 +659,1460 @@ fn get_taxes(mut monthly_income: f32, standard_deduction: f32, tax_rates: &Vec<T
     }
     panic!("Tax rate too high!");
 }
+
+// returns the marginal rate and how much more annual income fits in the
+// current bracket, using the brackets the same way as get_taxes
+pub fn get_tax_bracket(mut monthly_income: f32, standard_deduction: f32, tax_rates: &[TaxLevel]) -> (f32, f32) {
+    monthly_income = f32::max(monthly_income - standard_deduction / 12.0, 0.0);
+
+    for tax_rate in tax_rates.iter() {
+        if monthly_income * 12.0 <= tax_rate.income {
+            return (tax_rate.rate, tax_rate.income - monthly_income * 12.0)
+        }
+        monthly_income -= tax_rate.income / 12.0;
+    }
+    panic!("Tax rate too high!");
+}
+
+// returns the rate and the amount of annual income taxed at that rate for
+// each bracket the income reaches, using the brackets the same way as
+// get_taxes
+pub fn get_brackets_filled(annual_income: f32, standard_deduction: f32, tax_rates: &[TaxLevel]) -> Vec<(f32, f32)> {
+    let mut income = f32::max(annual_income - standard_deduction, 0.0);
+    let mut brackets = Vec::new();
+    for tax_rate in tax_rates.iter() {
+        if income <= 0.0 {
+            break;
+        }
+        let amount = f32::min(income, tax_rate.income);
+        if amount > 0.0 {
+            brackets.push((tax_rate.rate, amount));
+        }
+        income -= amount;
+    }
+    brackets
+}
+
+// tax on a year of long term capital gains or qualified dividends. These
+// are stacked on top of the ordinary taxable income (after deductions) to
+// find which capital gains brackets they fall in. Each level's income is
+// where its rate starts.
+pub fn get_capital_gains_taxes(ordinary_income: f32, gains: f32, levels: &[TaxLevel]) -> f32 {
+    let start = ordinary_income;
+    let end = ordinary_income + gains;
+    let mut taxes = 0.0;
+    for (i, level) in levels.iter().enumerate() {
+        let next_income = levels.get(i + 1).map_or(f32::MAX, |v| v.income);
+        let taxed = f32::min(end, next_income) - f32::max(start, level.income);
+        if taxed > 0.0 {
+            taxes += taxed * level.rate / 100.0;
+        }
+    }
+    taxes
+}
+
+// harvested capital losses carried forward, applied to a step. They offset
+// the step's realized gains first, then up to $3,000 a year of the ordinary
+// income. Returns the gains left to tax, the ordinary income deducted (both
+// monthly, like the amounts of the step) and the losses still carried
+// forward.
+pub fn apply_harvested_losses(carryforward: f32, gains: f32, ordinary_income: f32, months: f32) -> (f32, f32, f32) {
+    let gains_offset = f32::min(gains, carryforward);
+    let carryforward = carryforward - gains_offset;
+    let deduction = f32::min(
+        f32::min(carryforward / months, MAX_LOSS_DEDUCTION / 12.0),
+        f32::max(ordinary_income, 0.0));
+    ((gains - gains_offset) / months, deduction, carryforward - deduction * months)
+}
     
-// this is an estimate. The IRS has a big table for retirement income based on
-// age and retirement date. This routine uses the values from the last row of
-// the table, for younger retirees. The user will enter their personal values
-// from the IRS web site and this routine will interpolate the rest. In the future
-// the whole table should be entered.
-fn get_social_security_monthly_income(
-    retirement_age: u32,
-    benefit_early: f32,
-    benefit_full: f32,
-    benefit_delayed: f32) -> f32 {
-
-    let min_age = 62;
-    let normal_age = 67;
-    let max_age = 70;
-    
-    if retirement_age >= max_age {
-        return benefit_delayed;
+// social security and medicare taxes on one year of wages. The additional
+// medicare tax applies to the household's combined wages.
+fn get_payroll_taxes(annual_salary: f32, household_salary: f32, payroll: &PayrollTaxes) -> f32 {
+    let social_security = f32::min(annual_salary, payroll.social_security_wage_base) *
+        payroll.social_security_rate / 100.0;
+    let medicare = annual_salary * payroll.medicare_rate / 100.0;
+    let mut additional_medicare = 0.0;
+    if household_salary > payroll.additional_medicare_threshold {
+        // split the household surcharge in proportion to each salary
+        additional_medicare = (household_salary - payroll.additional_medicare_threshold) *
+            payroll.additional_medicare_rate / 100.0 * annual_salary / household_salary;
     }
-    else if retirement_age < min_age {
+    social_security + medicare + additional_medicare
+}
+
+// monthly Medicare premium surcharge for one person with the given annual
+// income
+pub fn get_irmaa(annual_income: f32, levels: &[IrmaaLevel]) -> f32 {
+    levels.iter()
+        .rev()
+        .find(|v| annual_income > v.income)
+        .map_or(0.0, |v| v.monthly_surcharge)
+}
+    
+// the annual ACA premium tax credit for a household with the given annual
+// income: the benchmark premium less the share of income expected to go to
+// it. Below the poverty line, where there's Medicaid in most states instead,
+// the lowest share is used.
+pub fn get_premium_tax_credit(annual_income: f32, household_size: usize, annual_benchmark: f32) -> f32 {
+    let poverty_line = POVERTY_LINE + POVERTY_LINE_PER_PERSON * household_size.saturating_sub(1) as f32;
+    let percent = f32::max(annual_income, 0.0) / poverty_line * 100.0;
+    let Some((from, to, from_rate, to_rate)) = ACA_APPLICABLE_PERCENTAGES.iter().find(|v| percent <= v.1) else {
         return 0.0;
+    };
+    let rate = from_rate + (to_rate - from_rate) * (f32::max(percent, *from) - from) / (to - from);
+    f32::max(annual_benchmark - annual_income.max(0.0) * rate / 100.0, 0.0)
+}
+
+// month benefits start for a claiming age in months, and the age in months
+// the benefit is figured for. Benefits start in the month the age is reached,
+// except that a retiree has to be 62 for the whole month, so claiming at 62
+// starts at 62 and 1 month unless the birthday is on the 1st or 2nd.
+fn get_social_security_start(date_of_birth: &NaiveDate, claim_months: u32) -> (NaiveDate, u32) {
+    let date = get_month_at_age(date_of_birth, claim_months);
+    let earliest_months = 62 * 12;
+    if claim_months == earliest_months && get_age_months(date_of_birth, &date.succ_opt().unwrap()) < earliest_months {
+        return (add_months(&date, 1), earliest_months + 1);
+    }
+    (date, claim_months)
+}
+
+// full retirement age in months for a birth year. People born on January 1st
+// use the year before, which is ignored here.
+fn get_full_retirement_age_months(birth_year: i32) -> u32 {
+    match birth_year {
+        ..=1937 => 65 * 12,
+        1938..=1942 => 65 * 12 + (birth_year - 1937) as u32 * 2,
+        1943..=1954 => 66 * 12,
+        1955..=1959 => 66 * 12 + (birth_year - 1954) as u32 * 2,
+        _ => 67 * 12,
+    }
+}
+
+// annual delayed retirement credit (percent) for claiming after full
+// retirement age
+fn get_delayed_credit_rate(birth_year: i32) -> f32 {
+    match birth_year {
+        ..=1932 => 5.0,
+        1933..=1934 => 5.5,
+        1935..=1936 => 6.0,
+        1937..=1938 => 6.5,
+        1939..=1940 => 7.0,
+        1941..=1942 => 7.5,
+        _ => 8.0,
+    }
+}
+
+// percentage reduction for claiming a number of months before full
+// retirement age. The first 36 months reduce by the first rate (percent per
+// month), and each month before that by the second.
+fn get_early_reduction(months_early: u32, first_rate: f32, second_rate: f32) -> f32 {
+    u32::min(months_early, 36) as f32 * first_rate +
+        months_early.saturating_sub(36) as f32 * second_rate
+}
+
+// benefit for claiming at an age in months, using the SSA formulas. Claiming
+// before full retirement age reduces the benefit by 5/9% per month for the
+// first 36 months and 5/12% for each month before that. Claiming after it
+// adds the delayed retirement credit for each month up to age 70. Benefits
+// can't start before 62.
+fn get_social_security_monthly_income(
+    claim_months: u32,
+    birth_year: i32,
+    benefit_full: f32) -> f32 {
+
+    let min_months = 62 * 12;
+    let max_months = 70 * 12;
+    let full_months = get_full_retirement_age_months(birth_year);
+
+    if claim_months < min_months {
+        0.0
     }
-    else if retirement_age >= normal_age {
-        return benefit_full +
-            (benefit_delayed - benefit_full) *
-            (retirement_age - normal_age) as f32/
-            (max_age - normal_age) as f32;
+    else if claim_months < full_months {
+        let reduction = get_early_reduction(full_months - claim_months, 5.0 / 9.0, 5.0 / 12.0);
+        benefit_full * (1.0 - reduction / 100.0)
     }
     else {
-        return benefit_early +
-            (benefit_full - benefit_early) *
-            (retirement_age - min_age) as f32 /
-            (normal_age - min_age) as f32;
+        let months_late = u32::min(claim_months, max_months) - full_months;
+        benefit_full * (1.0 + months_late as f32 * get_delayed_credit_rate(birth_year) / 12.0 / 100.0)
+    }
+}
+
+// spousal benefit for claiming at an age in months. It is half of the
+// spouse's full retirement age benefit, reduced by 25/36% per month for the
+// first 36 months before full retirement age and 5/12% for each month before
+// that. Unlike the retiree's own benefit, it doesn't grow after full
+// retirement age.
+fn get_spousal_monthly_income(claim_months: u32, birth_year: i32, spouse_benefit_full: f32) -> f32 {
+    let min_months = 62 * 12;
+    let full_months = get_full_retirement_age_months(birth_year);
+
+    if claim_months < min_months {
+        return 0.0;
     }
+    let months_early = full_months - u32::min(claim_months, full_months);
+    let reduction = get_early_reduction(months_early, 25.0 / 36.0, 5.0 / 12.0);
+    spouse_benefit_full * 0.5 * (1.0 - reduction / 100.0)
+}
+
+// the bonuses paid in a step, as monthly amounts like the other cash flows
+#[derive(Debug, Default)]
+struct BonusPayments {
+    wages: f32,
+    contributions: f32,
+    federal_taxes: f32,
+    state_taxes: f32,
+    payroll_taxes: f32,
+}
+
+// the one-time expenses paid in a step, as monthly amounts like the other
+// cash flows. Unpaid is the total the portfolio couldn't pay.
+#[derive(Debug, Default)]
+struct OneTimePayments {
+    expenses: f32,
+    taxable_withdrawals: f32,
+    federal_taxes: f32,
+    state_taxes: f32,
+    unpaid: f32,
+}
+
+// a bonus or other compensation paid to a retiree in a step
+struct LumpSum<'a> {
+    retiree: &'a Retiree,
+    amount: f32,
+    contribution: f32,
+    tax_rate: Option<f32>,
+    savings_percent: f32,
 }
 
 // represents a simulation run
 pub struct Simulation<'a> {
     pub simulation_results_: SimulationResults,
    
+    context_: &'a PlanContext<'a>,
     input_: &'a Input,
     current_date_: NaiveDate,
     portfolio_: Portfolio,
     expenses_: f32,
-    tax_rates_: Vec<TaxLevel>,
     sum_of_returns_: f32,
+    // harvested capital losses not deducted yet, and the growth of the
+    // taxable accounts so far this year that they're harvested from
+    loss_carryforward_: f32,
+    taxable_growth_: f32,
+    months_per_step_: u32,
+
+    // annual inflation of the current year of returns, and the price level
+    // now and at the last social security cost of living adjustment, both
+    // relative to the start
+    inflation_: f32,
+    price_level_: f32,
+    cola_price_level_: f32,
+
+    // employer matches of each retiree so far, and the part of them that has
+    // vested and been deposited
+    accrued_matches_: Vec<f32>,
+    vested_matches_: Vec<f32>,
+
+    // premium paid for each retiree's annuity and its monthly payment in
+    // today's dollars at the purchase, 0 before it's bought
+    annuity_premiums_: Vec<f32>,
+    annuity_incomes_: Vec<f32>,
+
+    // reverse mortgage credit left to draw and the loan balance, both 0
+    // until the line of credit opens
+    credit_available_: f32,
+    reverse_mortgage_balance_: f32,
 }
     
 impl<'a> Simulation<'a> {
-    pub fn new(input: &'a Input) -> Self {
-        let retirement_date = add_years(&input.retirees[0].date_of_birth, input.retirees[0].retirement_age);
-        let current_date: NaiveDate = chrono::Utc::now().naive_utc().date();
-
-        let mut simulation_results = SimulationResults {
-            retirement_date,
-            retirement_age: input.retirees[0].retirement_age,
-            retirees: Vec::new(),
+    pub fn new(context: &'a PlanContext<'a>) -> Self {
+        let input = context.input;
+        let simulation_results = SimulationResults {
+            retirement_date: context.retirement_date,
+            retirees: context.retirees.clone(),
             monthly_snapshot: Vec::new(),
             average_return: 0.0,
+            unfunded_expenses: 0.0,
+            unfunded_essential_expenses: 0.0,
+            months_per_step: input.simulation.months_per_step,
+            violations: Vec::new(),
+            retirement_balance: None,
         };
-        
-        for retiree in input.retirees.iter() {
-            let retiree_result = RetireeInfo {
-                name: retiree.name.to_string(),
-                social_security_date: add_years(&retiree.date_of_birth, retiree.social_security_age),
-                date_of_birth: retiree.date_of_birth.clone(),
-                social_security_income: get_social_security_monthly_income(
-                    retiree.social_security_age,
-                    retiree.social_security_amount_early,
-                    retiree.social_security_amount_full,
-                    retiree.social_security_amount_delayed),
-            };
-            simulation_results.retirees.push(retiree_result);
-        }
-
-        let portfolio = input.portfolio.clone();
-        let expenses = input.expenses.monthly;
-        let tax_rates = input.tax_rates.tax_levels.to_vec();
 
         Self {
             simulation_results_: simulation_results,
+            context_: context,
             input_: input,
-            current_date_: current_date,
-            portfolio_: portfolio,
-            expenses_: expenses,
-            tax_rates_: tax_rates,
+            current_date_: context.start_date,
+            portfolio_: input.portfolio,
+            expenses_: context.monthly_expenses,
             sum_of_returns_: 0.0,
+            loss_carryforward_: 0.0,
+            taxable_growth_: 0.0,
+            months_per_step_: input.simulation.months_per_step,
+            inflation_: input.portfolio.expected_inflation,
+            price_level_: 1.0,
+            cola_price_level_: 1.0,
+            accrued_matches_: vec![0.0; input.retirees.len()],
+            vested_matches_: vec![0.0; input.retirees.len()],
+            annuity_premiums_: vec![0.0; input.retirees.len()],
+            annuity_incomes_: vec![0.0; input.retirees.len()],
+            credit_available_: 0.0,
+            reverse_mortgage_balance_: 0.0,
         }
     }
 
-    // returns true if simulation finished
-    pub fn run_simulation_one_month(
-        &mut self,
-        us_equity_expected_returns: f32,
-        international_equity_expected_returns: f32,
-        bonds_expected_returns: f32) -> Result<bool, String> {
-        
-        if is_everyone_dead(&self.current_date_, &self.input_) {
-            return Ok(true);
+    // number of times run_simulation_one_step is called for each year of returns
+    pub fn steps_per_year(&self) -> u32 {
+        12 / self.months_per_step_
+    }
+
+    // sets the inflation for the following steps, the expected inflation is
+    // used if this isn't called. Returns are real, so this only changes
+    // amounts that aren't adjusted for inflation continuously.
+    pub fn set_inflation(&mut self, annual_inflation: f32) {
+        self.inflation_ = annual_inflation;
+    }
+
+    // social security, pensions, and other retirement income for a month.
+    // Returns the income and the portion of it that is taxable.
+    // social security: before or after retirement. Benefits stop when a
+    // retiree dies, and the survivor keeps the larger of their own benefit
+    // and the one the spouse was receiving.
+    fn get_social_security_income(&self, date: &NaiveDate) -> f32 {
+        let retirees = &self.context_.retirees;
+        let mut income = 0.0;
+        for (i, retiree) in retirees.iter().enumerate() {
+            if !retiree.is_alive(date) {
+                continue;
+            }
+
+            // a retiree gets the larger of their own and the spousal benefit
+            let mut benefit = retiree.get_benefit(date, true);
+
+            if retirees.len() == 2 {
+                let spouse = &retirees[1 - i];
+                if !spouse.is_alive(date) && spouse.death_date > spouse.social_security_date {
+                    benefit = f32::max(benefit, spouse.get_benefit(&spouse.death_date, false));
+                }
+            }
+
+            // benefits claimed while still working can be withheld
+            benefit -= get_earnings_test_monthly(&self.input_.retirees[i], retiree, &self.context_.start_date, date, benefit);
+            income += benefit;
         }
-        
-        // pre-retirement contributions
-        for retiree in self.input_.retirees.iter() {
-            if self.current_date_ < self.simulation_results_.retirement_date {
-                let contribution = retiree.salary_annual * retiree.retirement_contribution_percent / 100.0;
-                self.portfolio_.deposit(contribution / 12.0);
+
+        // benefits are in today's dollars and keep their value at each COLA,
+        // but lose value to inflation until the next one
+        income * self.cola_price_level_ / self.price_level_
+    }
+
+    // the pension of the retiree at an index being paid on a date, 0 if
+    // there isn't one
+    fn get_pension(&self, i: usize, date: &NaiveDate) -> f32 {
+        let retiree = &self.input_.retirees[i];
+        let retiree_info = &self.context_.retirees[i];
+        let pension_date = retiree_info.pension_date;
+        if *date < pension_date || retiree.pension_take_lump_sum {
+            return 0.0;
+        }
+        let mut pension = retiree.pension_monthly_income;
+        // payments that don't keep up with inflation lose value from
+        // the first check
+        let start_date = NaiveDate::max(pension_date, self.context_.start_date);
+        let months = months_between(&start_date, date);
+        pension *= retiree.pension_cola.get_real_value(self.input_.portfolio.expected_inflation, months);
+        // and more while a stress test's inflation shock lasts
+        if let Some((rate, shock_months)) = self.get_inflation_shock(&start_date, date) {
+            pension *= retiree.pension_cola.get_real_value(rate, shock_months) /
+                retiree.pension_cola.get_real_value(self.input_.portfolio.expected_inflation, shock_months);
+        }
+        // a joint and survivor pension continues at a reduced amount
+        // for the spouse
+        if !retiree_info.is_alive(date) {
+            pension *= retiree.pension_survivor_percent / 100.0;
+        }
+        pension
+    }
+
+    // pensions being paid on a date, and the portion that is taxable
+    fn get_pension_income(&self, date: &NaiveDate) -> (f32, f32) {
+        let income: f32 = (0..self.input_.retirees.len()).map(|i| self.get_pension(i, date)).sum();
+        (income, income)
+    }
+
+    // the annuity payment of the retiree at an index on a date, 0 if there
+    // isn't one. Annuities pay for the retiree's life.
+    fn get_annuity_payment(&self, i: usize, date: &NaiveDate) -> f32 {
+        let retiree = &self.input_.retirees[i];
+        let retiree_info = &self.context_.retirees[i];
+        let Some(annuity) = retiree.annuity.as_ref() else {
+            return 0.0;
+        };
+        let retirement_date = retiree_info.retirement_date;
+        let start_date = annuity.get_start_date(&retiree.date_of_birth, retirement_date);
+        if *date < start_date || !retiree_info.is_alive(date) {
+            return 0.0;
+        }
+        let mut payment = self.annuity_incomes_[i];
+        if !annuity.cola {
+            let purchase_date = NaiveDate::max(retirement_date, self.context_.start_date);
+            let months = months_between(&purchase_date, date);
+            payment *= get_inflation_discount(self.input_.portfolio.expected_inflation, months);
+            if let Some((rate, shock_months)) = self.get_inflation_shock(&purchase_date, date) {
+                payment *= get_inflation_discount(rate, shock_months) /
+                    get_inflation_discount(self.input_.portfolio.expected_inflation, shock_months);
             }
         }
+        payment
+    }
+
+    // the rate of a stress test's inflation shock and the months of it
+    // between two dates, None without one
+    fn get_inflation_shock(&self, start_date: &NaiveDate, date: &NaiveDate) -> Option<(f32, u32)> {
+        let shock = self.input_.simulation.inflation_shock?;
+        let end_date = add_years(&self.context_.start_date, shock.years);
+        Some((shock.rate, months_between(start_date, &NaiveDate::min(*date, end_date))))
+    }
+
+    // the annual inflation of the current step, a stress test's inflation
+    // shock replaces the scenario's while it lasts
+    fn get_inflation(&self) -> f32 {
+        match self.input_.simulation.inflation_shock {
+            Some(shock) if self.current_date_ < add_years(&self.context_.start_date, shock.years) => shock.rate,
+            _ => self.inflation_,
+        }
+    }
+
+    // annuity payments on a date, and the portion that is taxable
+    fn get_annuity_income(&self, date: &NaiveDate) -> (f32, f32) {
+        let mut income = 0.0;
+        let mut taxable_income = 0.0;
+        for (i, (retiree, retiree_info)) in self.input_.retirees.iter().zip(self.context_.retirees.iter()).enumerate() {
+            let Some(annuity) = retiree.annuity.as_ref() else {
+                continue;
+            };
+            let payment = self.get_annuity_payment(i, date);
+            let start_date = annuity.get_start_date(&retiree.date_of_birth, retiree_info.retirement_date);
+            let expected_months = months_between(&start_date, &retiree_info.death_date);
+            income += payment;
+            taxable_income += payment * annuity.get_taxable_fraction(
+                self.annuity_premiums_[i], self.annuity_incomes_[i], expected_months);
+        }
+        (income, taxable_income)
+    }
 
-        // social security: before or after retirement
+    // the pensions and annuities that lose value to inflation, because they
+    // have no cost of living adjustment or one that can fall behind
+    fn get_fixed_income(&self, date: &NaiveDate) -> f32 {
+        let inflation = self.input_.portfolio.expected_inflation;
         let mut income = 0.0;
-        for (i, _retiree) in self.input_.retirees.iter().enumerate() {
-            if self.current_date_ > self.simulation_results_.retirees[i].social_security_date {
-                income += self.simulation_results_.retirees[i].social_security_income;
+        for (i, retiree) in self.input_.retirees.iter().enumerate() {
+            if retiree.pension_cola.get_real_value(inflation, 12) < 1.0 {
+                income += self.get_pension(i, date);
+            }
+            if retiree.annuity.as_ref().is_some_and(|v| !v.cola) {
+                income += self.get_annuity_payment(i, date);
             }
         }
+        income
+    }
+
+    // income that lasts for life whatever the returns: social security,
+    // pensions and annuities
+    fn get_guaranteed_income(&self, date: &NaiveDate) -> f32 {
+        self.get_social_security_income(date) + self.get_pension_income(date).0 + self.get_annuity_income(date).0
+    }
+
+    fn get_retirement_income(&self, date: &NaiveDate) -> (f32, f32) {
+        let mut income = self.get_social_security_income(date);
 
         // social security is usually 85% taxable (ignore lower incomes)
         let mut taxable_income = income * 0.85;
-        
+
         // pension income, before or after retirement
-        for retiree in self.input_.retirees.iter() {
-            let pension_date = add_years(&retiree.date_of_birth, retiree.pension_age);
-            if self.current_date_ >= pension_date {
-                income += retiree.pension_monthly_income;
-                taxable_income += retiree.pension_monthly_income;
-            }
-        }
+        let (pension, taxable_pension) = self.get_pension_income(date);
+        income += pension;
+        taxable_income += taxable_pension;
 
-        // other retirement income
-        for retiree in self.input_.retirees.iter() {
-            if self.current_date_ >= self.simulation_results_.retirement_date {
+        let (annuity, taxable_annuity) = self.get_annuity_income(date);
+        income += annuity;
+        taxable_income += taxable_annuity;
+
+        // other retirement income starts when each retiree retires
+        for (retiree, retiree_info) in self.input_.retirees.iter().zip(self.context_.retirees.iter()) {
+            if *date >= retiree_info.retirement_date {
                 income += retiree.other_monthly_retirement_income;
                 taxable_income += retiree.other_monthly_retirement_income;
             }
         }
 
-        // required withdrawals, only after retirement
-        let mut withdrawals = 0.0;
-        if self.current_date_ >= self.simulation_results_.retirement_date {
-            if income < self.expenses_ {
-                withdrawals = self.expenses_ - income;
-            }
+        // rent until each rental property is sold
+        for property in self.input_.rental_properties.iter().filter(|v| v.is_held(date)) {
+            income += property.monthly_rent;
+            taxable_income += property.monthly_rent;
         }
 
-        // tax on income and withdrawals. tax rate on ss will be higher, but ignore that for now
-        let (mut taxes, tax_rate) = get_taxes(
-            withdrawals + taxable_income,
-            self.input_.tax_rates.standard_deduction,
-            &self.tax_rates_);
+        // negative expenses are net profit, such as from a rental or side
+        // work, and are taxed like other income
+        let expenses = self.get_monthly_expenses(date);
+        if *date >= self.simulation_results_.retirement_date && expenses < 0.0 {
+            income -= expenses;
+            taxable_income -= expenses;
+        }
+
+        (income, taxable_income)
+    }
+
+    // monthly expenses to pay for in a step of a number of months starting at
+    // a date, including the annual expenses and the home's costs. Negative
+    // expenses are counted as income instead.
+    fn get_spending(&self, date: &NaiveDate, months: u32) -> f32 {
+        f32::max(self.get_monthly_expenses(date), 0.0) +
+            self.input_.expenses.get_annual_expenses(date.month(), months) +
+            self.get_home_costs(date) +
+            self.get_bridge_healthcare(date).0 +
+            self.get_medicare_premiums(date) +
+            f32::max(-self.get_home_equity(date, months), 0.0) / months as f32
+    }
+
+    // how much healthcare costs growing at their own annual rate are worth at
+    // a date in today's dollars. Prices follow the scenario's inflation until
+    // now and the expected inflation after.
+    fn get_healthcare_growth(&self, inflation: f32, date: &NaiveDate) -> f32 {
+        let years = months_between(&self.context_.start_date, date) as f32 / 12.0;
+        let price_level = self.price_level_ /
+            get_inflation_discount(self.input_.portfolio.expected_inflation, months_between(&self.current_date_, date));
+        (1.0 + inflation / 100.0).powf(years) / price_level
+    }
+
+    // the monthly health insurance premiums and out-of-pocket costs of the
+    // retirees who have retired and aren't on Medicare yet
+    fn get_bridge_healthcare(&self, date: &NaiveDate) -> (f32, f32) {
+        let Some(bridge) = self.input_.expenses.bridge_healthcare.as_ref() else {
+            return (0.0, 0.0);
+        };
+        let retirement_date = self.simulation_results_.retirement_date;
+        let covered = self.context_.retirees.iter()
+            .filter(|v| v.is_alive(date) && *date >= retirement_date.max(v.retirement_date) && *date < v.medicare_date)
+            .count();
+        if covered == 0 {
+            return (0.0, 0.0);
+        }
+        let growth = self.get_healthcare_growth(bridge.inflation, date);
+        let premiums = bridge.monthly_premium * covered as f32 * growth;
+        (premiums + bridge.monthly_out_of_pocket * covered as f32 * growth, premiums)
+    }
+
+    // the monthly Part B, Part D and Medigap premiums of the retirees on
+    // Medicare once the household has retired, before any IRMAA surcharges.
+    // Before then they're paid out of the pay like the other expenses.
+    fn get_medicare_premiums(&self, date: &NaiveDate) -> f32 {
+        let Some(medicare) = self.input_.expenses.medicare.as_ref() else {
+            return 0.0;
+        };
+        let retirement_date = self.simulation_results_.retirement_date;
+        let covered = self.context_.retirees.iter()
+            .filter(|v| v.is_alive(date) && *date >= retirement_date && *date >= v.medicare_date)
+            .count();
+        if covered == 0 {
+            return 0.0;
+        }
+        medicare.get_monthly_premiums() * covered as f32 * self.get_healthcare_growth(medicare.inflation, date)
+    }
+
+    // the IRMAA surcharges grow with the Medicare premiums when they're
+    // modeled, and with inflation otherwise
+    fn get_irmaa_growth(&self, date: &NaiveDate) -> f32 {
+        match self.input_.expenses.medicare.as_ref() {
+            Some(medicare) => self.get_healthcare_growth(medicare.inflation, date),
+            None => 1.0,
+        }
+    }
+
+    // the monthly ACA premium tax credit against the bridge premiums for the
+    // year's income, 0 without the subsidy
+    fn get_aca_credit(&self, date: &NaiveDate, annual_income: f32) -> f32 {
+        if !self.input_.expenses.bridge_healthcare.as_ref().is_some_and(|v| v.aca_subsidy) {
+            return 0.0;
+        }
+        let (_, premiums) = self.get_bridge_healthcare(date);
+        if premiums == 0.0 {
+            return 0.0;
+        }
+        let household_size = self.context_.retirees.iter().filter(|v| v.is_alive(date)).count();
+        get_premium_tax_credit(annual_income, household_size, premiums * 12.0) / 12.0
+    }
+
+    // monthly property taxes and insurance on the home lived in at a date
+    fn get_home_costs(&self, date: &NaiveDate) -> f32 {
+        let Some(home) = self.input_.home.as_ref() else {
+            return 0.0;
+        };
+        home.get_monthly_costs(home.get_value(&self.context_.start_date, date, self.context_.downsize_date))
+    }
+
+    // the home equity from a move or downsizing in a step of a number of
+    // months starting at a date, 0 if there's neither in it
+    fn get_home_equity(&self, date: &NaiveDate, months: u32) -> f32 {
+        let end = date.checked_add_months(chrono::Months::new(months)).unwrap();
+        let in_step = |v: Option<NaiveDate>| v.is_some_and(|v| v >= *date && v < end);
+        let mut equity = 0.0;
+        if let Some(relocation) = self.input_.relocation.as_ref().filter(|_| in_step(self.context_.relocation_date)) {
+            equity += relocation.home_equity;
+        }
+        if let Some(home) = self.input_.home.as_ref().filter(|_| in_step(self.context_.downsize_date)) {
+            equity += home.get_downsize_equity(&self.context_.start_date, &self.context_.downsize_date.unwrap());
+        }
+        equity
+    }
+
+    // sells the rental properties sold in a step and saves the proceeds
+    // after taxes. The gain is taxed at the capital gains rates on top of
+    // the step's income, as if it were all the year's, and at the state rate.
+    // Returns the federal and state taxes.
+    fn sell_rental_properties(&mut self, end_date: &NaiveDate, ordinary_income: f32, dividends: f32, state_rate: f32) -> (f32, f32) {
+        let input = self.input_;
+        let levels = &self.get_tax_rates(&self.current_date_).capital_gains_levels;
+        let mut federal_taxes = 0.0;
+        let mut state_taxes = 0.0;
+        let mut proceeds = 0.0;
+        for property in input.rental_properties.iter() {
+            let Some(sale_date) = property.get_sale_date() else {
+                continue;
+            };
+            if sale_date < self.current_date_ || sale_date >= *end_date {
+                continue;
+            }
+            let price = property.get_value(&self.context_.start_date, &sale_date);
+            let gain = f32::max(price - property.basis, 0.0);
+            let federal = get_capital_gains_taxes(ordinary_income * 12.0, dividends * 12.0 + gain, levels) -
+                get_capital_gains_taxes(ordinary_income * 12.0, dividends * 12.0, levels);
+            federal_taxes += federal;
+            state_taxes += gain * state_rate;
+            proceeds += price - federal - gain * state_rate;
+        }
+        self.portfolio_.deposit_taxable(proceeds);
+        (federal_taxes, state_taxes)
+    }
+
+    // distributes the employer stock of each retiree who takes NUA treatment
+    // when they retire in a step. The shares are sold right away and the
+    // proceeds saved after the taxes: ordinary income tax on the basis and
+    // capital gains tax on the appreciation, on top of the step's income as
+    // if it were all the year's, and the state rate on both. Returns the
+    // federal income, capital gains and state taxes.
+    fn distribute_employer_stock(&mut self, end_date: &NaiveDate, taxable_income: f32, dividends: f32, state_rate: f32) -> (f32, f32, f32) {
+        let input = self.input_;
+        let context = self.context_;
+        let (standard_deduction, tax_levels) = self.get_tax_law(&self.current_date_);
+        let capital_gains_levels = &self.get_tax_rates(&self.current_date_).capital_gains_levels;
+        let (mut federal_taxes, mut capital_gains_taxes, mut state_taxes) = (0.0, 0.0, 0.0);
+        let mut stacked_income = taxable_income * 12.0;
+        for (retiree, retiree_info) in input.retirees.iter().zip(context.retirees.iter()) {
+            let Some(stock) = retiree.employer_stock.as_ref().filter(|v| v.nua) else {
+                continue;
+            };
+            let date = retiree_info.retirement_date;
+            if date < self.current_date_ || date >= *end_date {
+                continue;
+            }
+
+            // the tax-deferred accounts may have less than the stock's value
+            // if the plan is running out of money
+            let value = self.portfolio_.withdraw_tax_deferred(stock.value);
+            let basis = stock.basis * value / stock.value;
+            let gain = value - basis;
+            let (before, _) = get_taxes(stacked_income / 12.0, standard_deduction, tax_levels);
+            let (after, _) = get_taxes((stacked_income + basis) / 12.0, standard_deduction, tax_levels);
+            stacked_income += basis;
+            let federal = (after - before) * 12.0;
+            let ordinary_income = f32::max(stacked_income - standard_deduction, 0.0);
+            let capital_gains = get_capital_gains_taxes(ordinary_income, dividends * 12.0 + gain, capital_gains_levels) -
+                get_capital_gains_taxes(ordinary_income, dividends * 12.0, capital_gains_levels);
+            let state = value * state_rate;
+
+            // the proceeds have been taxed, so they come out tax free
+            self.portfolio_.deposit_taxable_basis(f32::max(value - federal - capital_gains - state, 0.0));
+            federal_taxes += federal;
+            capital_gains_taxes += capital_gains;
+            state_taxes += state;
+        }
+        (federal_taxes, capital_gains_taxes, state_taxes)
+    }
+
+    // the one-time expenses paid in a step of a number of months starting at
+    // a date, in today's dollars
+    fn get_one_time_expenses(&self, date: &NaiveDate, months: u32) -> f32 {
+        let end = date.checked_add_months(chrono::Months::new(months)).unwrap();
+        let inflation = self.input_.portfolio.expected_inflation;
+        let mut expenses = 0.0;
+        for expense in self.input_.one_time_expenses.iter() {
+            for payment_date in expense.get_dates(date, &end) {
+                expenses += expense.get_amount(inflation, &self.context_.start_date, &payment_date);
+            }
+        }
+        expenses
+    }
+
+    // withdraws the one-time expenses paid in a step and the taxes on them.
+    // The taxable part of the withdrawal is taxed on top of the step's income
+    // as if it were all the year's, and at the state rate, and the
+    // withdrawals to pay the taxes are taxed at the same rate.
+    fn pay_one_time_expenses(&mut self, taxable_income: f32, state_rate: f32) -> OneTimePayments {
+        let expenses = self.get_one_time_expenses(&self.current_date_, self.months_per_step_);
+        if expenses <= 0.0 {
+            return OneTimePayments::default();
+        }
+        let (standard_deduction, tax_levels) = self.get_tax_law(&self.current_date_);
+        let taxable = expenses * (1.0 - self.portfolio_.get_nontaxable_fraction());
+        let (before, _) = get_taxes(taxable_income, standard_deduction, tax_levels);
+        let (after, _) = get_taxes(taxable_income + taxable / 12.0, standard_deduction, tax_levels);
+        let federal_taxes = (after - before) * 12.0;
+        let state_taxes = taxable * state_rate;
+        let gross_up = 1.0 / (1.0 - (federal_taxes + state_taxes) / expenses);
+        let (federal_taxes, state_taxes) = (federal_taxes * gross_up, state_taxes * gross_up);
+
+        let total = expenses + federal_taxes + state_taxes;
+        let unpaid = f32::max(total - self.portfolio_.balance, 0.0);
+        self.portfolio_.withdraw(total);
+        let months = self.months_per_step_ as f32;
+        OneTimePayments {
+            expenses: expenses / months,
+            taxable_withdrawals: taxable * gross_up / months,
+            federal_taxes: federal_taxes / months,
+            state_taxes: state_taxes / months,
+            unpaid,
+        }
+    }
+
+    fn has_moved(&self, date: &NaiveDate) -> bool {
+        self.context_.relocation_date.is_some_and(|v| *date >= v)
+    }
+
+    // the monthly expenses, which can change with a move or downsizing and go
+    // down after the first death
+    fn get_monthly_expenses(&self, date: &NaiveDate) -> f32 {
+        let has_downsized = self.context_.downsize_date.is_some_and(|v| *date >= v);
+        let expenses = match (self.context_.downsize_expenses, self.context_.relocation_expenses) {
+            (Some(expenses), _) if has_downsized => expenses,
+            (_, Some(expenses)) if self.has_moved(date) => expenses,
+            _ => self.expenses_,
+        };
+        match self.is_survivor(date) {
+            true => expenses * self.input_.expenses.survivor_percent / 100.0,
+            false => expenses,
+        }
+    }
+
+    // returns the flat state tax rate (percent), and the state standard
+    // deduction and brackets if they apply instead. A move with a new rate
+    // replaces both.
+    fn get_state_law(&self, date: &NaiveDate) -> (f32, Option<(f32, &'a [TaxLevel])>) {
+        let input: &'a Input = self.input_;
+        match input.relocation.as_ref().and_then(|v| v.state_rate) {
+            Some(rate) if self.has_moved(date) => (rate, None),
+            _ => (input.tax_rates.state_rate, self.get_tax_rates(date).state_for_year(date.year())),
+        }
+    }
+
+    // the monthly expenses beyond the essential ones, which can be cut when
+    // the money runs out. None of them are without an essential amount.
+    fn get_discretionary_expenses(&self, date: &NaiveDate) -> f32 {
+        match self.input_.expenses.essential_monthly {
+            Some(essential) => f32::max(self.get_monthly_expenses(date) - essential, 0.0),
+            None => 0.0,
+        }
+    }
+
+    // the expenses, and the taxes on the withdrawals that would pay for them,
+    // that can't be funded from the portfolio after it runs out of money, and
+    // the part of them left once the discretionary expenses are cut. The
+    // simulation stops when the money runs out, so this walks the remaining
+    // months until the end of the plan.
+    fn get_unfunded_expenses(&self) -> (f32, f32) {
+        let mut unfunded = 0.0;
+        let mut unfunded_essential = 0.0;
+        let mut date = self
+            .current_date_
+            .checked_add_months(chrono::Months::new(self.months_per_step_))
+            .unwrap();
+        while !is_everyone_dead(&date, &self.context_.retirees) {
+            if date < self.simulation_results_.retirement_date {
+                date = date.checked_add_months(chrono::Months::new(1)).unwrap();
+                continue;
+            }
+            let (income, taxable_income) = self.get_retirement_income(&date);
+            let spending = self.get_spending(&date, 1);
+            let spending = spending
+                - self.get_aca_credit(
+                    &date,
+                    (taxable_income + f32::max(spending - income, 0.0)) * 12.0,
+                );
+            let (standard_deduction, tax_levels) = self.get_tax_law(&date);
+            let get_unfunded = |spending: f32| {
+                let withdrawals = f32::max(spending - income, 0.0);
+                let (taxes, tax_rate) =
+                    get_taxes(withdrawals + taxable_income, standard_deduction, tax_levels);
+                let taxes = taxes / (1.0 - tax_rate / 100.0);
+                f32::max(withdrawals + taxes - f32::max(income - spending, 0.0), 0.0)
+            };
+            let one_time = self.get_one_time_expenses(&date, 1);
+            unfunded += get_unfunded(spending) + one_time;
+            unfunded_essential +=
+                get_unfunded(spending - self.get_discretionary_expenses(&date)) + one_time;
+            date = date.checked_add_months(chrono::Months::new(1)).unwrap();
+        }
+        (unfunded, unfunded_essential)
+    }
+
+    // whether one of a couple has died by a date
+    fn is_survivor(&self, date: &NaiveDate) -> bool {
+        let retirees = &self.context_.retirees;
+        retirees.len() > 1 && retirees.iter().any(|v| !v.is_alive(date))
+    }
+
+    // the survivor of a couple files jointly in the year of the death, and as
+    // a single from the next year
+    fn get_tax_rates(&self, date: &NaiveDate) -> &'a TaxRates {
+        let input: &'a Input = self.input_;
+        let retirees = &self.context_.retirees;
+        let widowed = retirees.len() > 1 && retirees.iter().any(|v| v.death_date.year() < date.year());
+        match widowed {
+            true => &input.survivor_tax_rates,
+            false => &input.tax_rates,
+        }
+    }
+
+    // returns the standard deduction and brackets for a date. The deduction
+    // goes up for each living retiree who is 65 by the end of the year.
+    fn get_tax_law(&self, date: &NaiveDate) -> (f32, &'a [TaxLevel]) {
+        let tax_rates = self.get_tax_rates(date);
+        let (standard_deduction, tax_levels) = tax_rates.for_year(date.year());
+        let seniors = self.context_.retirees.iter()
+            .filter(|v| v.medicare_date.year() <= date.year() && *date < v.death_date)
+            .count();
+        (standard_deduction + seniors as f32 * tax_rates.senior_deduction, tax_levels)
+    }
+
+    // a retiree's salary in this step
+    fn get_salary(&self, retiree: &Retiree) -> f32 {
+        retiree.get_salary(&self.context_.start_date, &self.current_date_)
+    }
+
+    // the retirees still working on a date
+    fn get_working_retirees(&self, date: NaiveDate) -> impl Iterator<Item = (usize, &'a Retiree, &RetireeInfo)> {
+        let input: &'a Input = self.input_;
+        input.retirees.iter().zip(self.context_.retirees.iter()).enumerate()
+            .filter(move |(_, (_, info))| date < info.retirement_date)
+            .map(|(i, (retiree, info))| (i, retiree, info))
+    }
+
+    // the bonuses and other compensation paid to the working retirees in
+    // this step, with the amount contributed to the retirement accounts
+    fn get_lump_sums(&self) -> Vec<LumpSum<'a>> {
+        let mut lump_sums = Vec::new();
+        let step_end = add_months(&self.current_date_, self.months_per_step_);
+        for (_, retiree, info) in self.get_working_retirees(self.current_date_) {
+            // an annual step includes every month
+            if let Some(bonus) = &retiree.bonus {
+                if self.months_per_step_ >= 12 || self.current_date_.month() == bonus.month {
+                    let amount = bonus.amount + self.get_salary(retiree) * bonus.percent / 100.0;
+                    lump_sums.push(LumpSum {
+                        retiree,
+                        amount,
+                        contribution: amount * bonus.contribution_percent / 100.0,
+                        tax_rate: bonus.tax_rate,
+                        savings_percent: bonus.savings_percent,
+                    });
+                }
+            }
+            // payments after retirement are forfeited
+            let end = NaiveDate::min(step_end, info.retirement_date);
+            for compensation in retiree.compensation.iter() {
+                let amount = compensation.get_amount(&self.current_date_, &end);
+                if amount > 0.0 {
+                    lump_sums.push(LumpSum {
+                        retiree,
+                        amount,
+                        contribution: 0.0,
+                        tax_rate: compensation.tax_rate,
+                        savings_percent: compensation.savings_percent,
+                    });
+                }
+            }
+        }
+        lump_sums
+    }
+
+    // pays the bonuses and other compensation for this step, if any. They
+    // are paid until each retiree retires, they are taxed on top of the
+    // household's taxable wages, and the taxes are paid from them.
+    fn pay_bonuses(&mut self, taxable_wages: f32) -> BonusPayments {
+        let mut payments = BonusPayments::default();
+        let lump_sums = self.get_lump_sums();
+        if lump_sums.is_empty() {
+            return payments;
+        }
+
+        let months = self.months_per_step_ as f32;
+        let (standard_deduction, tax_levels) = self.get_tax_law(&self.current_date_);
+        let (state_rate, state_law) = self.get_state_law(&self.current_date_);
+        let state_rate = state_rate / 100.0;
+        let household_salary: f32 = self.get_working_retirees(self.current_date_).map(|v| self.get_salary(v.1)).sum();
+        // the year's bonuses and compensation, for the additional medicare
+        // tax threshold
+        let year_end = add_months(&self.current_date_, 12);
+        let household_bonus: f32 = self.get_working_retirees(self.current_date_)
+            .map(|(_, r, info)| {
+                let end = NaiveDate::min(year_end, info.retirement_date);
+                r.bonus.as_ref().map_or(0.0, |b| b.amount + self.get_salary(r) * b.percent / 100.0) +
+                    r.compensation.iter().map(|v| v.get_amount(&self.current_date_, &end)).sum::<f32>()
+            })
+            .sum();
+
+        // taxable wages for the year, lump sums are added as they are paid
+        let mut stacked_wages = taxable_wages * 12.0;
+        for lump_sum in lump_sums {
+            let salary = self.get_salary(lump_sum.retiree);
+            let amount = lump_sum.amount;
+            let contribution = lump_sum.contribution;
+            let taxable = amount - contribution;
+            let federal_taxes = match lump_sum.tax_rate {
+                Some(rate) => taxable * rate / 100.0,
+                None => {
+                    let (before, _) = get_taxes(stacked_wages / 12.0, standard_deduction, tax_levels);
+                    let (after, _) = get_taxes((stacked_wages + taxable) / 12.0, standard_deduction, tax_levels);
+                    (after - before) * 12.0
+                },
+            };
+            let state_taxes = match state_law {
+                Some((state_deduction, state_levels)) => {
+                    let (before, _) = get_taxes(stacked_wages / 12.0, state_deduction, state_levels);
+                    let (after, _) = get_taxes((stacked_wages + taxable) / 12.0, state_deduction, state_levels);
+                    (after - before) * 12.0
+                },
+                None => taxable * state_rate,
+            };
+            stacked_wages += taxable;
+            let payroll = &self.input_.tax_rates.payroll;
+            let payroll_taxes =
+                get_payroll_taxes(salary + amount, household_salary + household_bonus, payroll) -
+                get_payroll_taxes(salary, household_salary + household_bonus, payroll);
+
+            self.portfolio_.deposit(contribution);
+            let take_home = amount - contribution - federal_taxes - state_taxes - payroll_taxes;
+            self.portfolio_.deposit_taxable(f32::max(take_home, 0.0) * lump_sum.savings_percent / 100.0);
+
+            payments.wages += amount / months;
+            payments.contributions += contribution / months;
+            payments.federal_taxes += federal_taxes / months;
+            payments.state_taxes += state_taxes / months;
+            payments.payroll_taxes += payroll_taxes / months;
+        }
+
+        payments
+    }
+
+    // runs a step with the annual real returns of each asset class, returns
+    // true if simulation finished
+    pub fn run_simulation_one_step(&mut self, annual_returns: &[f32; MAX_ASSET_CLASSES]) -> Result<bool, String> {
+        
+        if is_everyone_dead(&self.current_date_, &self.context_.retirees) {
+            return Ok(true);
+        }
+        let start_balance = self.portfolio_.balance;
+        self.portfolio_.net_flows = 0.0;
+        self.portfolio_.age = get_age_months(&self.input_.retirees[0].date_of_birth, &self.current_date_) as f32 / 12.0;
+        if self.simulation_results_.retirement_balance.is_none() && self.current_date_ >= self.context_.retirement_date {
+            self.simulation_results_.retirement_balance = Some(start_balance);
+        }
+
+        // the reverse mortgage line of credit opens with the whole limit
+        // available, at the start if the age has already been reached
+        let end_date = self.current_date_.checked_add_months(chrono::Months::new(self.months_per_step_)).unwrap();
+        let opens = self.context_.reverse_mortgage_date
+            .is_some_and(|v| v < end_date && (v >= self.current_date_ || self.current_date_ == self.context_.start_date));
+        if let Some(reverse_mortgage) = self.input_.home.as_ref().and_then(|v| v.reverse_mortgage.as_ref()).filter(|_| opens) {
+            self.credit_available_ = reverse_mortgage.credit_limit;
+        }
+
+        // social security gets a cost of living adjustment every January for
+        // the inflation since the last one
+        if self.months_per_step_ == 12 || self.current_date_.month() == 1 {
+            self.cola_price_level_ = self.price_level_;
+        }
+
+        // all amounts below are monthly, the cash flows into and out of the
+        // portfolio are multiplied by the number of months in the step
+        let months = self.months_per_step_ as f32;
+        
+        // wages and contributions until each retiree retires. Contributions
+        // are pre-tax, except for Roth contributions, and the taxes on wages
+        // are paid from the paycheck, not the portfolio.
+        let mut wages = 0.0;
+        let mut contributions = 0.0;
+        let mut roth_contributions = 0.0;
+        let mut conversion_income = 0.0;
+        let mut payroll_taxes = 0.0;
+        let working: Vec<(usize, &'a Retiree, RetireeInfo)> = self.get_working_retirees(self.current_date_)
+            .map(|(i, retiree, info)| (i, retiree, *info))
+            .collect();
+        let part_time: Vec<f32> = self.input_.retirees.iter().zip(self.context_.retirees.iter())
+            .map(|(retiree, info)| match info.is_alive(&self.current_date_) {
+                true => retiree.get_part_time_income(&self.current_date_),
+                false => 0.0,
+            })
+            .collect();
+        let household_salary: f32 = working.iter().map(|v| self.get_salary(v.1)).sum::<f32>() +
+            part_time.iter().sum::<f32>() * 12.0;
+        for (i, retiree, retiree_info) in working {
+            let salary = self.get_salary(retiree);
+
+            // a fixed contribution that isn't indexed loses value to
+            // inflation. Contributions can't be more than the salary.
+            let mut fixed_contribution = retiree.retirement_contribution_monthly;
+            if !retiree.contribution_inflation_indexed {
+                let months_since_start = months_between(&self.context_.start_date, &self.current_date_);
+                fixed_contribution *= get_inflation_discount(self.input_.portfolio.expected_inflation, months_since_start);
+            }
+            let mut contribution = f32::min(
+                salary / 12.0 * retiree.retirement_contribution_percent / 100.0 + fixed_contribution,
+                salary / 12.0);
+            if retiree.contribution_start_date.is_some_and(|v| self.current_date_ < v) {
+                contribution = 0.0;
+            }
+            self.portfolio_.deposit(contribution * months);
+            contributions += contribution;
+
+            // the employer match is deposited as it vests. Matches that
+            // haven't vested by retirement are forfeited, and they don't
+            // grow until they vest.
+            if let Some(employer_match) = retiree.employer_match.as_ref() {
+                let limit = salary / 12.0 * employer_match.limit_percent / 100.0;
+                self.accrued_matches_[i] += f32::min(contribution, limit) * employer_match.percent / 100.0 * months;
+                let vested = self.accrued_matches_[i] * employer_match.get_vested_fraction(&self.current_date_);
+                self.portfolio_.deposit(vested - self.vested_matches_[i]);
+                self.vested_matches_[i] = vested;
+            }
+
+            // HSA contributions are made through payroll, so they also
+            // avoid payroll taxes. They stop once Medicare starts.
+            let mut hsa_contribution = 0.0;
+            if self.current_date_ < retiree_info.medicare_date {
+                hsa_contribution = retiree.hsa_contribution_annual;
+                self.portfolio_.deposit_hsa(hsa_contribution / 12.0 * months);
+                contributions += hsa_contribution / 12.0;
+            }
+
+            // backdoor Roth IRA contributions and after-tax 401(k)
+            // contributions converted to Roth are made with taxed wages.
+            // The conversions are assumed to happen right away, so there
+            // are no gains to tax. Backdoor conversions are still taxed
+            // pro-rata when there are pre-tax IRA balances.
+            conversion_income += self.portfolio_.convert_backdoor_roth(retiree.backdoor_roth_annual / 12.0 * months) / months;
+            self.portfolio_.deposit_roth(retiree.mega_backdoor_roth_annual / 12.0 * months);
+            roth_contributions += (retiree.backdoor_roth_annual + retiree.mega_backdoor_roth_annual) / 12.0;
+
+            // non-deductible IRA contributions stay in the IRA
+            self.portfolio_.deposit_nondeductible_ira(retiree.nondeductible_ira_annual / 12.0 * months);
+            roth_contributions += retiree.nondeductible_ira_annual / 12.0;
+
+            wages += salary / 12.0;
+            payroll_taxes += get_payroll_taxes(
+                salary - hsa_contribution,
+                household_salary,
+                &self.input_.tax_rates.payroll) / 12.0;
+        }
+
+        // part-time work after retiring is paid and taxed like wages, but
+        // without contributions
+        for income in part_time.iter().filter(|v| **v > 0.0) {
+            wages += income;
+            payroll_taxes += get_payroll_taxes(income * 12.0, household_salary, &self.input_.tax_rates.payroll) / 12.0;
+        }
+        let taxable_wages = wages - contributions;
+        let bonus = self.pay_bonuses(taxable_wages);
+
+        // annuities are bought when each retiree retires, a qualified one with
+        // a tax-free transfer from the tax-deferred accounts. If the accounts
+        // can't pay the whole premium a smaller annuity is bought.
+        let balance = self.portfolio_.balance;
+        for (i, (retiree, retiree_info)) in self.input_.retirees.iter().zip(self.context_.retirees.iter()).enumerate() {
+            let retirement_date = retiree_info.retirement_date;
+            if retirement_date >= self.current_date_ && retirement_date < end_date {
+                let Some(annuity) = retiree.annuity.as_ref() else {
+                    continue;
+                };
+                let mut premium = match annuity.premium_percent > 0.0 {
+                    true => balance * annuity.premium_percent / 100.0,
+                    false => annuity.premium,
+                };
+                if annuity.qlac {
+                    premium = f32::min(premium, crate::QLAC_LIMIT);
+                }
+                let paid = match annuity.qualified {
+                    true => self.portfolio_.withdraw_tax_deferred(premium),
+                    false => self.portfolio_.withdraw_taxable(premium),
+                };
+                self.annuity_premiums_[i] = paid;
+                self.annuity_incomes_[i] = annuity.get_monthly_income(paid);
+            }
+        }
+
+        let (income, retirement_income) = self.get_retirement_income(&self.current_date_);
+        let social_security = self.get_social_security_income(&self.current_date_);
+        let mut taxable_income = retirement_income + conversion_income;
+
+        // dividends and interest in the taxable accounts are reinvested, but
+        // the taxes on them are paid from the portfolio
+        let is_retired = self.current_date_ >= self.simulation_results_.retirement_date;
+        let (qualified_dividends, interest) = self.portfolio_.get_taxable_yields(is_retired, self.get_inflation());
+        taxable_income += interest;
+
+        // the gains realized by rebalancing the taxable accounts since the
+        // last step are taxed at the capital gains rates like the dividends.
+        // Harvested losses offset them first, then the ordinary income below.
+        let (rebalance_gains, _, loss_carryforward) = apply_harvested_losses(
+            self.loss_carryforward_, self.portfolio_.rebalance_gains, 0.0, months);
+        self.loss_carryforward_ = loss_carryforward;
+        self.portfolio_.rebalance_gains = 0.0;
+        let dividends = qualified_dividends + rebalance_gains;
+
+        // a pension taken as a lump sum is rolled over into the tax-deferred
+        // accounts when it would have started
+        for (retiree, retiree_info) in self.input_.retirees.iter().zip(self.context_.retirees.iter()) {
+            let pension_date = retiree_info.pension_date;
+            if retiree.pension_take_lump_sum && pension_date >= self.current_date_ && pension_date < end_date {
+                self.portfolio_.deposit(retiree.pension_lump_sum);
+            }
+        }
+
+        // windfalls are saved, they aren't taxed
+        for windfall in self.context_.windfalls.iter() {
+            if windfall.date >= self.current_date_ && windfall.date < end_date {
+                self.portfolio_.deposit_taxable(windfall.amount);
+            }
+        }
+
+        // the cash from selling the home when moving or downsizing is saved, a more
+        // expensive home is paid for like the other expenses
+        let home_equity = self.get_home_equity(&self.current_date_, self.months_per_step_);
+        self.portfolio_.deposit_taxable(f32::max(home_equity, 0.0));
+
+        // the taxes on wages are paid from the paycheck. Wages fill the lower
+        // brackets first, the rest of the income is taxed on top of them.
+        let (standard_deduction, tax_levels) = self.get_tax_law(&self.current_date_);
+        let (wage_taxes, _) = get_taxes(taxable_wages, standard_deduction, tax_levels);
+        let (flat_state_rate, state_law) = self.get_state_law(&self.current_date_);
+        let state_wage_taxes = match state_law {
+            Some((state_deduction, state_levels)) => get_taxes(taxable_wages, state_deduction, state_levels).0,
+            None => f32::max(taxable_wages - standard_deduction / 12.0, 0.0) * flat_state_rate / 100.0,
+        };
+
+        // once the household retires, the take-home pay of a retiree who is
+        // still working goes towards the expenses
+        let mut take_home_wages = 0.0;
+        if is_retired {
+            take_home_wages = f32::max(
+                wages - contributions - roth_contributions - payroll_taxes - wage_taxes - state_wage_taxes, 0.0);
+        }
+
+        // required withdrawals, only after retirement
+        let mut spending = self.get_spending(&self.current_date_, self.months_per_step_);
+
+        // the ACA premium tax credit is based on the year's income, including
+        // the withdrawals for the full premiums. The credit makes the
+        // withdrawals smaller, which would raise it a little.
+        let (bridge_healthcare, _) = self.get_bridge_healthcare(&self.current_date_);
+        let mut aca_credit = 0.0;
+        if bridge_healthcare > 0.0 {
+            let withdrawals = f32::max(spending - income - take_home_wages, 0.0) * (1.0 - self.portfolio_.get_nontaxable_fraction());
+            aca_credit = self.get_aca_credit(&self.current_date_,
+                                             (taxable_wages + taxable_income + dividends + withdrawals) * 12.0);
+            spending -= aca_credit;
+        }
+        let mut withdrawals = 0.0;
+        if is_retired && income + take_home_wages < spending {
+            withdrawals = spending - income - take_home_wages;
+        }
+
+        // healthcare expenses are paid from the HSA first, tax free
+        let hsa_withdrawals = f32::min(
+            f32::min(withdrawals, self.input_.expenses.healthcare_monthly),
+            self.portfolio_.hsa_balance / months);
+        withdrawals -= hsa_withdrawals;
+
+        // education expenses are paid from the 529 accounts, anything they
+        // can't cover is withdrawn from the portfolio like other expenses
+        let education_expenses = self.input_.education.get_monthly_expenses(self.current_date_.year());
+        let education_withdrawals = f32::min(education_expenses, self.portfolio_.education_balance / months);
+        withdrawals += education_expenses - education_withdrawals;
+
+        // once the taxable and tax-deferred accounts run out, the rest comes
+        // from the Roth accounts, tax free
+        let roth_withdrawals = f32::min(
+            f32::max(withdrawals - self.portfolio_.get_other_balance() / months, 0.0),
+            self.portfolio_.roth_balance / months);
+        withdrawals -= roth_withdrawals;
+
+        // what the portfolio can't pay is drawn from the reverse mortgage
+        // line of credit, if it's open. Loan proceeds aren't taxed.
+        let spending_draws = f32::min(
+            f32::max(withdrawals * months - self.portfolio_.get_other_balance(), 0.0),
+            self.credit_available_);
+        withdrawals -= spending_draws / months;
+        self.credit_available_ -= spending_draws;
+
+        // IRA basis in the withdrawals isn't taxed
+        let taxable_withdrawals = withdrawals * (1.0 - self.portfolio_.get_nontaxable_fraction());
+
+        // the harvested losses left after the gains offset up to $3,000 a
+        // year of the ordinary income, wages included, and the rest is
+        // carried forward
+        let (_, loss_deduction, loss_carryforward) = apply_harvested_losses(
+            self.loss_carryforward_, 0.0, taxable_wages + taxable_withdrawals + taxable_income, months);
+        self.loss_carryforward_ = loss_carryforward;
+        taxable_income -= loss_deduction;
+
+        // tax on income and withdrawals. tax rate on ss will be higher, but ignore that for now.
+        let (total_taxes, tax_rate) = get_taxes(
+            taxable_wages + taxable_withdrawals + taxable_income,
+            standard_deduction,
+            tax_levels);
+
+        // the part of the deduction beyond the other income lowers the taxes
+        // withheld from the wages, and the refund is saved
+        let federal_taxes = f32::max(total_taxes - wage_taxes, 0.0);
+        let tax_refund = f32::max(wage_taxes - total_taxes, 0.0);
+
+        // qualified dividends are taxed at capital gains rates, on top of the ordinary income
+        let ordinary_income = f32::max(taxable_wages + taxable_withdrawals + taxable_income - standard_deduction / 12.0, 0.0);
+        let capital_gains_taxes = get_capital_gains_taxes(
+            ordinary_income * 12.0,
+            dividends * 12.0,
+            &self.get_tax_rates(&self.current_date_).capital_gains_levels) / 12.0;
+
+        // state tax is on the ordinary income and dividends, using the state
+        // brackets if there are any and a flat rate otherwise. Like the
+        // federal tax, the part on wages is paid from the paycheck.
+        let (state_taxes, state_rate) = match state_law {
+            Some((state_deduction, state_levels)) => {
+                let (total_taxes, rate) = get_taxes(
+                    taxable_wages + taxable_withdrawals + taxable_income + dividends,
+                    state_deduction,
+                    state_levels);
+                (total_taxes - state_wage_taxes, rate / 100.0)
+            },
+            None => {
+                let state_rate = flat_state_rate / 100.0;
+                ((ordinary_income + dividends) * state_rate - state_wage_taxes, state_rate)
+            },
+        };
+
+        let (sale_federal_taxes, sale_state_taxes) = self.sell_rental_properties(&end_date, ordinary_income, dividends, state_rate);
+        let (sale_federal_taxes, sale_state_taxes) = (sale_federal_taxes / months, sale_state_taxes / months);
+        let (nua_federal_taxes, nua_capital_gains_taxes, nua_state_taxes) = self.distribute_employer_stock(
+            &end_date, taxable_wages + taxable_withdrawals + taxable_income, dividends, state_rate);
+        let lump_sum_taxes = sale_federal_taxes + sale_state_taxes +
+            (nua_federal_taxes + nua_capital_gains_taxes + nua_state_taxes) / months;
+
+        // one-time expenses are withdrawn in the month they're paid
+        let one_time = self.pay_one_time_expenses(taxable_wages + taxable_withdrawals + taxable_income, state_rate);
+        let one_time_taxes = one_time.federal_taxes + one_time.state_taxes;
+
+        // Medicare premiums go up with income for everyone on Medicare
+        let num_on_medicare = self.context_.retirees.iter()
+            .filter(|v| self.current_date_ >= v.medicare_date && self.current_date_ < v.death_date)
+            .count();
+        let irmaa = get_irmaa(
+            (taxable_wages + taxable_withdrawals + taxable_income + dividends) * 12.0,
+            &self.get_tax_rates(&self.current_date_).irmaa_levels) * num_on_medicare as f32 *
+            self.get_irmaa_growth(&self.current_date_);
+        let medicare_premiums = self.get_medicare_premiums(&self.current_date_);
 
         // we need to withdraw more cash to cover taxes. But these withdrawals
         // will cost more taxes, causing more withdrawals, and more taxes and so
         // on. This can be calculated as an infinite power series.
-        let tax_on_tax = taxes / (1.0 - tax_rate / 100.0);
-        taxes = tax_on_tax;
+        let gross_up = 1.0 / (1.0 - (tax_rate / 100.0 + state_rate));
+        let taxes = (federal_taxes + capital_gains_taxes + state_taxes + irmaa) * gross_up;
+
+        // the withdrawals to pay the taxes are taxable too
+        let total_taxable_income = taxable_wages + taxable_withdrawals + taxable_income + taxes;
+        let (bracket_rate, bracket_headroom) = get_tax_bracket(total_taxable_income, standard_deduction, tax_levels);
+        let income_taxes = federal_taxes * gross_up + wage_taxes + capital_gains_taxes * gross_up;
+        let mut effective_tax_rate = 0.0;
+        if total_taxable_income + dividends > 0.0 {
+            effective_tax_rate = income_taxes / (total_taxable_income + dividends) * 100.0;
+        }
         
         let mut withdrawal_rate = 0.0;
         if self.portfolio_.balance > 0.0 {
-            withdrawal_rate = (withdrawals + taxes) * 12.0 / self.portfolio_.balance;
+            withdrawal_rate = (withdrawals + roth_withdrawals + hsa_withdrawals + taxes) * 12.0 / self.portfolio_.balance;
         }
             
-        if income > self.expenses_ {
-            self.portfolio_.deposit(income - self.expenses_);
-        }
-        if self.portfolio_.balance > taxes {
-            self.portfolio_.withdraw(taxes);
-        }
-        else {
-            self.portfolio_.balance = 0.0
-        }
-        if self.portfolio_.balance > withdrawals {
-            self.portfolio_.withdraw(withdrawals);
-        }
-        else {
-            self.portfolio_.balance = 0.0
+        // extra income is saved in the taxable accounts
+        if income + take_home_wages > spending {
+            self.portfolio_.deposit_taxable((income + take_home_wages - spending) * months);
         }
+        self.portfolio_.deposit_taxable(tax_refund * months);
+        let mut unfunded = f32::max((taxes + withdrawals + roth_withdrawals + hsa_withdrawals) * months - self.portfolio_.balance, 0.0) +
+            one_time.unpaid;
 
+        // the taxes the portfolio can't pay are drawn from the line of
+        // credit too, through the taxable accounts
+        let tax_draws = f32::min(unfunded, self.credit_available_);
+        self.portfolio_.deposit_taxable_basis(tax_draws);
+        unfunded -= tax_draws;
+        self.credit_available_ -= tax_draws;
+        self.reverse_mortgage_balance_ += spending_draws + tax_draws;
+        self.portfolio_.withdraw_hsa(hsa_withdrawals * months);
+        self.portfolio_.withdraw_roth(roth_withdrawals * months);
+        self.portfolio_.withdraw_education(education_withdrawals * months);
+        self.portfolio_.withdraw(taxes * months);
+        self.portfolio_.withdraw(withdrawals * months);
+        self.portfolio_.update_cash_bucket((taxes + withdrawals + roth_withdrawals + hsa_withdrawals) * months,
+                                           withdrawals + roth_withdrawals + hsa_withdrawals + taxes);
+
+        let taxable_balance = self.portfolio_.taxable_balance;
+        let pre_growth_balance = self.portfolio_.balance;
         let annualized_return = self.portfolio_.grow(
-            us_equity_expected_returns,
-            international_equity_expected_returns,
-            bonds_expected_returns,
-            self.current_date_ >= self.simulation_results_.retirement_date);
+            annual_returns,
+            self.current_date_ >= self.simulation_results_.retirement_date,
+            self.months_per_step_);
         self.sum_of_returns_ += annualized_return;
+
+        // the unused credit grows at the same rate as the loan
+        if let Some(reverse_mortgage) = self.input_.home.as_ref().and_then(|v| v.reverse_mortgage.as_ref()) {
+            let growth = (1.0 + reverse_mortgage.growth_rate / 100.0).powf(months / 12.0);
+            self.credit_available_ *= growth;
+            self.reverse_mortgage_balance_ *= growth;
+        }
+
+        // in a down year some of the losses in the taxable accounts can be sold
+        // and replaced with similar investments to realize the loss. The loss
+        // is the year's, so the months that go up make up for the ones that
+        // go down.
+        self.taxable_growth_ += self.portfolio_.taxable_balance - taxable_balance;
+        if self.months_per_step_ == 12 || end_date.month() == 1 {
+            let taxable_loss = f32::max(-self.taxable_growth_, 0.0);
+            self.loss_carryforward_ += taxable_loss * self.input_.portfolio.tax_loss_harvest_percent / 100.0;
+            self.taxable_growth_ = 0.0;
+        }
         self.simulation_results_.average_return = self.sum_of_returns_ / (self.simulation_results_.monthly_snapshot.len() as f32 + 1.0); 
 
         let monthly_balance = MonthlySnapshot {
             date: self.current_date_,
             balance: self.portfolio_.balance,
-            expenses: if self.current_date_ >= self.simulation_results_.retirement_date {self.expenses_} else {0.0}, 
-            income,
-            taxes,
+            expenses: education_expenses + one_time.expenses +
+                if self.current_date_ >= self.simulation_results_.retirement_date {spending} else {0.0},
+            income: income + wages + bonus.wages,
+            wages: wages + bonus.wages,
+            contributions: contributions + roth_contributions + bonus.contributions,
+            taxes: taxes + wage_taxes + state_wage_taxes + payroll_taxes + lump_sum_taxes + one_time_taxes +
+                bonus.federal_taxes + bonus.state_taxes + bonus.payroll_taxes,
+            payroll_taxes: payroll_taxes + bonus.payroll_taxes,
+            wage_taxes: wage_taxes + state_wage_taxes + bonus.federal_taxes + bonus.state_taxes,
+            taxable_income: total_taxable_income,
+            effective_tax_rate,
+            bracket_rate,
+            bracket_headroom,
+            taxable_wages: taxable_wages + bonus.wages - bonus.contributions,
+            social_security,
+            other_income: retirement_income - social_security * 0.85,
+            guaranteed_income: self.get_guaranteed_income(&self.current_date_),
+            fixed_income: self.get_fixed_income(&self.current_date_),
+            one_time_expenses: one_time.expenses,
+            bridge_healthcare,
+            aca_credit,
+            medicare_premiums,
+            roth_conversions: conversion_income,
+            interest,
+            dividends: qualified_dividends,
+            rebalance_gains,
+            taxable_withdrawals: taxable_withdrawals + taxes + one_time.taxable_withdrawals,
+            loss_deduction,
+            standard_deduction,
+            federal_taxes: federal_taxes * gross_up + wage_taxes + bonus.federal_taxes + nua_federal_taxes / months +
+                one_time.federal_taxes,
+            capital_gains_taxes: capital_gains_taxes * gross_up + sale_federal_taxes + nua_capital_gains_taxes / months,
+            state_taxes: state_taxes * gross_up + state_wage_taxes + bonus.state_taxes + sale_state_taxes + nua_state_taxes / months +
+                one_time.state_taxes,
+            irmaa: irmaa * gross_up,
             tax_rate,
+            withdrawals: withdrawals + roth_withdrawals + hsa_withdrawals + taxes + one_time.expenses + one_time_taxes,
+            net_deposits: self.portfolio_.net_flows / months,
             withdrawal_rate,
             annualized_return,
+            returns: annual_returns.map(|v| get_monthly_rate(v / 100.0) * 100.0),
+            credit_draws: (spending_draws + tax_draws) / months,
+            credit_available: self.credit_available_,
+            reverse_mortgage_balance: self.reverse_mortgage_balance_,
         };
 
+        if self.input_.simulation.check_invariants {
+            let violations = invariants::check_step(&self.portfolio_, start_balance, pre_growth_balance, &monthly_balance);
+            self.simulation_results_.violations.extend(violations);
+        }
         self.simulation_results_.monthly_snapshot.push(monthly_balance);
 
 
-        self.current_date_ = self.current_date_.checked_add_months(chrono::Months::new(1)).unwrap();
+        let is_depleted = self.portfolio_.balance == 0.0 && self.credit_available_ == 0.0;
+        if is_depleted {
+            // the step the money ran out in is cut by its discretionary
+            // expenses first
+            let (later, later_essential) = self.get_unfunded_expenses();
+            let essential = f32::max(unfunded - self.get_discretionary_expenses(&self.current_date_) * months, 0.0);
+            self.simulation_results_.unfunded_expenses = unfunded + later;
+            self.simulation_results_.unfunded_essential_expenses = essential + later_essential;
+        }
+
+        self.price_level_ /= get_inflation_discount(self.get_inflation(), self.months_per_step_);
+        self.current_date_ = self.current_date_.checked_add_months(chrono::Months::new(self.months_per_step_)).unwrap();
 
-        Ok(self.portfolio_.balance == 0.0)
+        Ok(is_depleted)
     }
 }        
     
+// the average monthly pay in the last year before retirement, less the
+// contributions and payroll taxes, for expenses given as a replacement ratio.
+// The pay doesn't depend on the expenses or returns, so any simulation of the
+// plan gives the same amount.
+pub fn get_final_year_pay(input: &Input) -> Result<f32, String> {
+    let results = run_simulation(input)?;
+    let working: Vec<&MonthlySnapshot> = results.monthly_snapshot.iter()
+        .take_while(|v| v.date < results.retirement_date)
+        .collect();
+    if working.is_empty() {
+        return Err("expenses.replacement_percent needs pay before retirement, but retirement has already started".to_string());
+    }
+
+    let last_year = &working[working.len().saturating_sub(results.snapshots_per_year())..];
+    let pay: f32 = last_year.iter().map(|v| v.wages - v.contributions - v.payroll_taxes).sum();
+    Ok(pay / last_year.len() as f32)
+}
+
 pub fn run_simulation(input: &Input) -> Result<SimulationResults, String> {
-    let mut simulation = Simulation::new(input);
+    run_simulation_with_returns(input, &input.portfolio.get_expected_returns(), input.portfolio.expected_inflation)
+}
+
+// the uniform simulation with other constant real returns of each asset
+// class and inflation, all annual percentages
+pub fn run_simulation_with_returns(input: &Input,
+                                   returns: &[f32; MAX_ASSET_CLASSES],
+                                   inflation: f32) -> Result<SimulationResults, String> {
+    run_simulation_with_context(&PlanContext::new(input), returns, inflation)
+}
+
+// the same, for a context that may differ from the plan's, such as one that
+// starts at another date
+pub fn run_simulation_with_context(context: &PlanContext,
+                                   returns: &[f32; MAX_ASSET_CLASSES],
+                                   inflation: f32) -> Result<SimulationResults, String> {
+    let mut simulation = Simulation::new(context);
+    simulation.set_inflation(inflation);
 
     loop {
-        let is_finished = simulation.run_simulation_one_month(
-            input.portfolio.us_equity_expected_returns,
-            input.portfolio.international_equity_expected_returns,
-            input.portfolio.bonds_expected_returns)?;
+        let is_finished = simulation.run_simulation_one_step(returns)?;
 
         if is_finished {
             break;
@@ -297,20 +2125,893 @@ pub fn run_simulation(input: &Input) -> Result<SimulationResults, String> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::fixture;
+    use crate::historical_scan::ReturnPath;
 
     #[test]
     fn test_social_security() {
-        let early = 1000.0;
         let full = 2000.0;
-        let delayed = 4000.0;
-
-        assert_eq!(get_social_security_monthly_income(60, early, full, delayed), 0.0);
-        assert_eq!(get_social_security_monthly_income(62, early, full, delayed), early);
-        assert_eq!(get_social_security_monthly_income(67, early, full, delayed), full);
-        assert_eq!(get_social_security_monthly_income(70, early, full, delayed), delayed);
-        assert_eq!(get_social_security_monthly_income(71, early, full, delayed), delayed);
-        assert_eq!(get_social_security_monthly_income(63, early, full, delayed), 1200.0);
-        assert_eq!(get_social_security_monthly_income(68, early, full, delayed), 2000.0 + 2000.0/3.0);
+        let close = |a: f32, b: f32| (a - b).abs() < 0.01;
+
+        assert_eq!(get_full_retirement_age_months(1937), 65 * 12);
+        assert_eq!(get_full_retirement_age_months(1940), 65 * 12 + 6);
+        assert_eq!(get_full_retirement_age_months(1950), 66 * 12);
+        assert_eq!(get_full_retirement_age_months(1957), 66 * 12 + 6);
+        assert_eq!(get_full_retirement_age_months(1960), 67 * 12);
+
+        // born 1960 or later: 30% less at 62, 24% more at 70
+        assert_eq!(get_social_security_monthly_income(60 * 12, 1965, full), 0.0);
+        assert!(close(get_social_security_monthly_income(62 * 12, 1965, full), 1400.0));
+        assert!(close(get_social_security_monthly_income(64 * 12, 1965, full), 1600.0));
+        assert_eq!(get_social_security_monthly_income(67 * 12, 1965, full), full);
+        assert!(close(get_social_security_monthly_income(68 * 12 + 6, 1965, full), 2240.0));
+        assert!(close(get_social_security_monthly_income(70 * 12, 1965, full), 2480.0));
+        assert!(close(get_social_security_monthly_income(71 * 12, 1965, full), 2480.0));
+
+        // born 1957, full retirement age 66 and 6 months: 27.5% less at 62
+        assert!(close(get_social_security_monthly_income(62 * 12, 1957, full), 1450.0));
+
+        // benefits claimed at 62 start in the first month the retiree is 62
+        // for the whole month
+        let date = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+        assert_eq!(get_social_security_start(&date(1965, 6, 15), 62 * 12), (date(2027, 7, 1), 62 * 12 + 1));
+        assert_eq!(get_social_security_start(&date(1965, 6, 2), 62 * 12), (date(2027, 6, 1), 62 * 12));
+        assert_eq!(get_social_security_start(&date(1965, 6, 15), 67 * 12), (date(2032, 6, 1), 67 * 12));
+
+        assert_eq!(get_spousal_monthly_income(61 * 12, 1965, 2000.0), 0.0);
+        assert!(close(get_spousal_monthly_income(62 * 12, 1965, 2000.0), 650.0));
+        assert!(close(get_spousal_monthly_income(64 * 12, 1965, 2000.0), 750.0));
+        assert_eq!(get_spousal_monthly_income(67 * 12, 1965, 2000.0), 1000.0);
+        assert_eq!(get_spousal_monthly_income(70 * 12, 1965, 2000.0), 1000.0);
+
+        // earnings test: $1 for $2 over the limit, $1 for $3 over the higher
+        // limit in the year of full retirement age
+        assert_eq!(get_earnings_test_withholding(20000.0, false), 0.0);
+        assert_eq!(get_earnings_test_withholding(EARNINGS_TEST_LIMIT + 10000.0, false), 5000.0);
+        assert_eq!(get_earnings_test_withholding(EARNINGS_TEST_LIMIT + 10000.0, true), 0.0);
+        assert_eq!(get_earnings_test_withholding(EARNINGS_TEST_LIMIT_FRA_YEAR + 9000.0, true), 3000.0);
+    }
+
+    #[test]
+    fn test_capital_gains_taxes() {
+        let levels = vec![
+            TaxLevel {income: 0.0, rate: 0.0},
+            TaxLevel {income: 100000.0, rate: 15.0},
+            TaxLevel {income: 500000.0, rate: 20.0},
+        ];
+
+        assert_eq!(get_capital_gains_taxes(50000.0, 40000.0, &levels), 0.0);
+        assert_eq!(get_capital_gains_taxes(80000.0, 40000.0, &levels), 3000.0);
+        assert_eq!(get_capital_gains_taxes(200000.0, 10000.0, &levels), 1500.0);
+        assert_eq!(get_capital_gains_taxes(490000.0, 20000.0, &levels), 3500.0);
+    }
+
+    #[test]
+    fn test_harvested_losses() {
+        // the losses offset the gains before any ordinary income
+        assert_eq!(apply_harvested_losses(10000.0, 4000.0, 5000.0, 1.0), (0.0, 250.0, 5750.0));
+        assert_eq!(apply_harvested_losses(3000.0, 4000.0, 5000.0, 1.0), (1000.0, 0.0, 0.0));
+
+        // at most $3,000 a year of ordinary income is offset, the rest is
+        // carried forward, and only as much as there is income
+        assert_eq!(apply_harvested_losses(10000.0, 0.0, 5000.0, 12.0), (0.0, 250.0, 7000.0));
+        assert_eq!(apply_harvested_losses(1200.0, 0.0, 5000.0, 12.0), (0.0, 100.0, 0.0));
+        assert_eq!(apply_harvested_losses(10000.0, 0.0, 100.0, 1.0), (0.0, 100.0, 9900.0));
+        assert_eq!(apply_harvested_losses(10000.0, 0.0, -50.0, 1.0), (0.0, 0.0, 10000.0));
+    }
+
+    #[test]
+    fn test_irmaa() {
+        let levels = vec![
+            IrmaaLevel {income: 200000.0, monthly_surcharge: 100.0},
+            IrmaaLevel {income: 300000.0, monthly_surcharge: 200.0},
+        ];
+
+        assert_eq!(get_irmaa(150000.0, &levels), 0.0);
+        assert_eq!(get_irmaa(200000.0, &levels), 0.0);
+        assert_eq!(get_irmaa(250000.0, &levels), 100.0);
+        assert_eq!(get_irmaa(400000.0, &levels), 200.0);
+    }
+
+    #[test]
+    fn test_premium_tax_credit() {
+        // a couple's poverty line is $21,150, and at 200% of it 6.6% of the
+        // income goes to the benchmark premium
+        assert!((get_premium_tax_credit(42300.0, 2, 20000.0) - (20000.0 - 42300.0 * 0.066)).abs() < 1.0);
+        assert!((get_premium_tax_credit(10000.0, 2, 20000.0) - (20000.0 - 10000.0 * 0.021)).abs() < 1.0);
+        assert_eq!(get_premium_tax_credit(85000.0, 2, 20000.0), 0.0);
+        assert_eq!(get_premium_tax_credit(42300.0, 2, 1000.0), 0.0);
+    }
+
+    #[test]
+    fn test_bridge_healthcare() {
+        let mut input = fixture::get_input();
+        input.retirees[1].retirement_age = 63;
+        let without = fixture::run_simulation(&input).unwrap();
+        input.expenses.bridge_healthcare = Some(crate::BridgeHealthcare {
+            monthly_premium: 1000.0,
+            monthly_out_of_pocket: 200.0,
+            inflation: input.portfolio.expected_inflation,
+            aca_subsidy: false,
+        });
+        let with = fixture::run_simulation(&input).unwrap();
+
+        // only the second retiree is covered, from the household's retirement
+        // until Medicare
+        let medicare_date = with.retirees[1].medicare_date;
+        for (a, b) in without.monthly_snapshot.iter().zip(with.monthly_snapshot.iter()) {
+            let is_bridged = b.date >= with.retirement_date && b.date < medicare_date;
+            assert!((b.bridge_healthcare - if is_bridged {1200.0} else {0.0}).abs() < 0.01);
+            assert!((b.expenses - a.expenses - b.bridge_healthcare).abs() < 0.01);
+        }
+        assert!(with.monthly_snapshot.iter().any(|v| v.bridge_healthcare > 0.0));
+
+        // costs growing faster than inflation are worth more in today's dollars
+        input.expenses.bridge_healthcare.as_mut().unwrap().inflation += 3.0;
+        let faster = fixture::run_simulation(&input).unwrap();
+        let last = with.monthly_snapshot.iter().rposition(|v| v.bridge_healthcare > 0.0).unwrap();
+        assert!(faster.monthly_snapshot[last].bridge_healthcare > 1200.0);
+    }
+
+    #[test]
+    fn test_medicare_premiums() {
+        let mut input = fixture::get_input();
+        let without = fixture::run_simulation(&input).unwrap();
+        input.expenses.medicare = Some(crate::Medicare {
+            part_b_monthly: 200.0,
+            part_d_monthly: 50.0,
+            medigap_monthly: 150.0,
+            inflation: input.portfolio.expected_inflation,
+        });
+        let with = fixture::run_simulation(&input).unwrap();
+
+        // each retiree on Medicare pays the premiums from the household's
+        // retirement until death
+        for (a, b) in without.monthly_snapshot.iter().zip(with.monthly_snapshot.iter()) {
+            let covered = with.retirees.iter()
+                .filter(|v| b.date >= with.retirement_date && b.date >= v.medicare_date && b.date < v.death_date)
+                .count();
+            assert!((b.medicare_premiums - 400.0 * covered as f32).abs() < 0.1);
+            assert!((b.expenses - a.expenses - b.medicare_premiums).abs() < 0.1);
+        }
+        assert!(with.monthly_snapshot.iter().any(|v| v.medicare_premiums > 0.0));
+
+        // premiums growing faster than inflation are worth more in today's
+        // dollars
+        input.expenses.medicare.as_mut().unwrap().inflation += 3.0;
+        let faster = fixture::run_simulation(&input).unwrap();
+        let last = with.monthly_snapshot.iter().rposition(|v| v.medicare_premiums > 0.0).unwrap();
+        assert!(faster.monthly_snapshot[last].medicare_premiums > with.monthly_snapshot[last].medicare_premiums);
+    }
+
+    // retirees who retired before the simulation starts don't work or
+    // contribute, and collect the benefits they've already claimed
+    #[test]
+    fn test_already_retired() {
+        let mut input = fixture::get_input();
+        for retiree in input.retirees.iter_mut() {
+            retiree.retirement_age = 50;
+            retiree.social_security_age = 62;
+            retiree.hsa_contribution_annual = 8300.0;
+        }
+        let results = fixture::run_simulation(&input).unwrap();
+        let first = &results.monthly_snapshot[0];
+        assert!(results.retirement_date < first.date);
+        assert_eq!(first.wages, 0.0);
+        assert_eq!(first.contributions, 0.0);
+        assert_eq!(first.payroll_taxes, 0.0);
+        assert!(first.social_security > 0.0);
+        assert!(first.expenses > 0.0);
+        assert!(first.withdrawals > 0.0);
+    }
+
+    #[test]
+    fn test_early_retirement() {
+        let input = fixture::get_input();
+        let context = fixture::get_context(&input);
+        let early = context.with_early_retirement(3, 2.0);
+        let planned = &context.retirees[1];
+        let retiree = &early.retirees[1];
+        assert_eq!(retiree.retirement_date, planned.retirement_date.checked_sub_months(chrono::Months::new(36)).unwrap());
+        assert!((retiree.social_security_income - planned.social_security_income * 0.94).abs() < 0.01);
+        // no one retires before today
+        assert_eq!(early.retirees[0].retirement_date, context.start_date);
+        assert_eq!(early.retirement_date, context.start_date);
+    }
+
+    #[test]
+    fn test_life_expectancies() {
+        let input = fixture::get_input();
+        let context = fixture::get_context(&input);
+        let longevity = context.with_life_expectancies(&[95, 85]);
+        assert_eq!(longevity.retirees[0].death_date, add_years(&context.retirees[0].death_date, 95 - input.retirees[0].life_expectency));
+        assert_eq!(longevity.retirees[1].death_date, add_years(&input.retirees[1].date_of_birth, 86));
+        assert_eq!(longevity.retirement_date, context.retirement_date);
+    }
+
+    #[test]
+    fn test_retirement_shift() {
+        let input = fixture::get_input();
+        let context = fixture::get_context(&input);
+        let planned = &context.retirees[1];
+        let later = context.with_retirement_shift(3);
+        assert_eq!(later.retirees[1].retirement_date, planned.retirement_date.checked_add_months(chrono::Months::new(3)).unwrap());
+        assert_eq!(later.retirees[1].social_security_income, planned.social_security_income);
+        let earlier = context.with_retirement_shift(-3);
+        assert_eq!(earlier.retirees[1].retirement_date, planned.retirement_date.checked_sub_months(chrono::Months::new(3)).unwrap());
+        // no one retires before today
+        assert_eq!(context.with_retirement_shift(-60).retirement_date, context.start_date);
+    }
+
+    #[test]
+    fn test_return_statistics() {
+        let input = fixture::get_input();
+        let mut results = fixture::run_simulation(&input).unwrap();
+        results.months_per_step = 12;
+        results.monthly_snapshot.truncate(3);
+        for (snapshot, annual_return) in results.monthly_snapshot.iter_mut().zip([10.0, -10.0, 5.0]) {
+            snapshot.annualized_return = annual_return;
+        }
+        let returns = results.get_return_statistics().unwrap();
+        let close = |a: f32, b: f32| (a - b).abs() < 0.01;
+
+        assert!(close(returns.geometric_mean, ((1.1f32 * 0.9 * 1.05).powf(1.0 / 3.0) - 1.0) * 100.0));
+        assert!(close(returns.volatility, 10.408));
+        assert_eq!(returns.best_year.0, 10.0);
+        assert_eq!(returns.worst_year.0, -10.0);
+        assert_eq!(returns.worst_year.1, results.monthly_snapshot[1].date.year());
+    }
+
+    #[test]
+    fn test_salary_growth() {
+        let mut input = fixture::get_input();
+        let retiree = &mut input.retirees[0];
+        retiree.salary_annual = 100000.0;
+        retiree.salary_growth_percent = 2.0;
+        retiree.raises = vec![crate::Raise {year: 2031, percent: 10.0}];
+        let start = NaiveDate::from_ymd_opt(2026, 3, 1).unwrap();
+        let close = |a: f32, b: f32| (a - b).abs() < 0.5;
+
+        assert!(close(retiree.get_salary(&start, &start), 100000.0));
+        assert!(close(retiree.get_salary(&start, &NaiveDate::from_ymd_opt(2027, 2, 1).unwrap()), 100000.0));
+        assert!(close(retiree.get_salary(&start, &NaiveDate::from_ymd_opt(2027, 3, 1).unwrap()), 102000.0));
+        assert!(close(retiree.get_salary(&start, &NaiveDate::from_ymd_opt(2031, 1, 1).unwrap()), 108243.2 * 1.1));
+    }
+
+    #[test]
+    fn test_compensation() {
+        let date = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+        // quarterly RSU vests for two years
+        let vests = crate::Compensation {
+            date: date(2026, 2, 15),
+            amount: 10000.0,
+            repeat_months: 3,
+            end_date: date(2028, 2, 14),
+            tax_rate: Some(22.0),
+            savings_percent: 100.0,
+        };
+
+        assert_eq!(vests.get_amount(&date(2026, 1, 1), &date(2027, 1, 1)), 40000.0);
+        assert_eq!(vests.get_amount(&date(2026, 2, 15), &date(2026, 3, 15)), 10000.0);
+        assert_eq!(vests.get_amount(&date(2026, 2, 16), &date(2026, 5, 15)), 0.0);
+        assert_eq!(vests.get_amount(&date(2027, 1, 1), &date(2030, 1, 1)), 40000.0);
+    }
+
+    #[test]
+    fn test_keeps_principal() {
+        let input = fixture::get_input();
+        let mut results = fixture::run_simulation(&input).unwrap();
+        let principal = results.retirement_balance.unwrap();
+        let retirement = results.monthly_snapshot.iter().position(|v| v.date >= results.retirement_date).unwrap();
+        assert_eq!(principal, results.monthly_snapshot[retirement - 1].balance);
+
+        results.monthly_snapshot.last_mut().unwrap().balance = principal;
+        assert!(results.keeps_principal());
+        results.monthly_snapshot.last_mut().unwrap().balance = principal - 1.0;
+        assert!(!results.keeps_principal());
+    }
+
+    #[test]
+    fn test_rental_properties() {
+        let mut input = fixture::get_input();
+        let without = fixture::run_simulation(&input).unwrap();
+        let start_date = fixture::get_start_date();
+        let sale_year = start_date.year() + 3;
+        let property = crate::RentalProperty {
+            name: "Duplex".to_string(),
+            value: 400000.0,
+            appreciation: 2.0,
+            monthly_rent: 1500.0,
+            sale_year: Some(sale_year),
+            basis: 300000.0,
+        };
+        let sale_date = NaiveDate::from_ymd_opt(sale_year, 1, 1).unwrap();
+        let price = property.get_value(&start_date, &sale_date);
+        assert!(price > 400000.0 * 1.02f32.powi(2));
+        assert!(property.is_held(&start_date));
+        assert!(!property.is_held(&sale_date));
+        input.rental_properties = vec![property];
+
+        // the rent is taxed like other income, and the gain on the sale
+        // is taxed, but most of the price is saved
+        let with = fixture::run_simulation(&input).unwrap();
+        let first = &with.monthly_snapshot[0];
+        assert!(first.other_income - without.monthly_snapshot[0].other_income >= 1500.0 - 0.01);
+        let sale = with.monthly_snapshot.iter().position(|v| add_months(&v.date, 1) > sale_date).unwrap();
+        let sale_taxes = with.monthly_snapshot[sale].capital_gains_taxes - without.monthly_snapshot[sale].capital_gains_taxes;
+        assert!(sale_taxes > 0.0 && sale_taxes < (price - 300000.0) * 0.2);
+        let difference = |i: usize| with.monthly_snapshot[i].balance - without.monthly_snapshot[i].balance;
+        assert!(difference(sale) - difference(sale - 1) > price * 0.9);
+    }
+
+    #[test]
+    fn test_employer_stock() {
+        let mut input = fixture::get_input();
+        input.retirees[1].employer_stock = Some(crate::EmployerStock {value: 100000.0, basis: 20000.0, nua: false});
+        let rollover = fixture::run_simulation(&input).unwrap();
+        input.retirees[1].employer_stock.as_mut().unwrap().nua = true;
+        let nua = fixture::run_simulation(&input).unwrap();
+
+        // the shares come out of the tax-deferred accounts when the retiree
+        // retires, with income tax on the basis and capital gains tax on the
+        // rest
+        let retirement_date = rollover.retirees[1].retirement_date;
+        let step = rollover.monthly_snapshot.iter().position(|v| add_months(&v.date, 1) > retirement_date).unwrap();
+        assert_eq!(nua.monthly_snapshot[step - 1].balance, rollover.monthly_snapshot[step - 1].balance);
+        let federal = nua.monthly_snapshot[step].federal_taxes - rollover.monthly_snapshot[step].federal_taxes;
+        let capital_gains = nua.monthly_snapshot[step].capital_gains_taxes - rollover.monthly_snapshot[step].capital_gains_taxes;
+        assert!(federal > 20000.0 * 0.1 && federal < 20000.0 * 0.4);
+        assert!(capital_gains > 0.0 && capital_gains <= 80000.0 * 0.2);
+        let taxes = nua.monthly_snapshot[step].balance - rollover.monthly_snapshot[step].balance;
+        assert!(taxes < 0.0 && taxes > -40000.0);
+    }
+
+    #[test]
+    fn test_home() {
+        let mut input = fixture::get_input();
+        let without = fixture::run_simulation(&input).unwrap();
+        let start_date = fixture::get_start_date();
+        let home = crate::Home {
+            value: 600000.0,
+            appreciation: 1.0,
+            property_tax_rate: 1.0,
+            insurance_annual: 2400.0,
+            // a year after the first retirement
+            downsize: Some(crate::Downsize {
+                age: 68,
+                new_value: 300000.0,
+                selling_costs_percent: 5.0,
+                monthly_expenses: None,
+            }),
+            reverse_mortgage: None,
+        };
+        assert_eq!(home.get_monthly_costs(600000.0), 700.0);
+        assert_eq!(home.get_monthly_costs(300000.0), 350.0);
+        assert_eq!(home.get_downsize_equity(&start_date, &start_date), 600000.0 * 0.95 - 300000.0);
+        input.home = Some(home);
+
+        // the home's costs are paid with the expenses, and after downsizing
+        // they're lower and the equity is saved
+        let context = fixture::get_context(&input);
+        let downsize_date = context.downsize_date.unwrap();
+        let equity = input.home.as_ref().unwrap().get_downsize_equity(&start_date, &downsize_date);
+        assert!(equity > 270000.0);
+        let with = fixture::run_simulation(&input).unwrap();
+        let expenses = |i: usize| with.monthly_snapshot[i].expenses - without.monthly_snapshot[i].expenses;
+        let retired = with.monthly_snapshot.iter().position(|v| v.date >= with.retirement_date).unwrap();
+        assert!(expenses(retired) > 700.0 && expenses(retired) < 750.0);
+        let step = with.monthly_snapshot.iter().position(|v| add_months(&v.date, 1) > downsize_date).unwrap();
+        assert!(expenses(step + 1) < 400.0);
+        let difference = |i: usize| with.monthly_snapshot[i].balance - without.monthly_snapshot[i].balance;
+        assert!(difference(step) - difference(step - 1) > equity * 0.99);
+    }
+
+    #[test]
+    fn test_fixed_income() {
+        let mut input = fixture::get_input();
+        input.retirees[0].pension_monthly_income = 5000.0;
+        input.retirees[0].pension_cola = crate::PensionCola::None;
+        let results = fixture::run_simulation(&input).unwrap();
+        let retired = results.monthly_snapshot.iter().position(|v| v.date >= results.retirement_date).unwrap();
+        let snapshot = &results.monthly_snapshot[retired];
+        assert!(snapshot.fixed_income > 0.0 && snapshot.fixed_income < snapshot.guaranteed_income);
+
+        // the share of the expenses shrinks with the pension's value
+        let shares = results.get_fixed_income_shares();
+        assert!(shares[0].1.unwrap() > 50.0);
+        assert!(shares.windows(2).all(|v| v[1].1.unwrap() <= v[0].1.unwrap()));
+        assert!(shares.last().unwrap().1.unwrap() < 50.0);
+
+        // a pension that keeps up with inflation isn't fixed income
+        input.retirees[0].pension_cola = crate::PensionCola::Cpi;
+        let results = fixture::run_simulation(&input).unwrap();
+        assert!(results.monthly_snapshot.iter().all(|v| v.fixed_income == 0.0));
+    }
+
+    #[test]
+    fn test_inflation_shock() {
+        let mut input = fixture::get_input();
+        input.retirees[0].pension_monthly_income = 5000.0;
+        input.retirees[0].pension_cola = crate::PensionCola::None;
+        let without = fixture::run_simulation(&input).unwrap();
+        input.simulation.inflation_shock = Some(crate::InflationShock {rate: 10.0, years: 40});
+        let with = fixture::run_simulation(&input).unwrap();
+
+        // a fixed pension loses value faster while the shock lasts, and
+        // the loss stays after it ends
+        let retired = with.monthly_snapshot.iter().position(|v| v.date >= with.retirement_date).unwrap();
+        assert!(with.monthly_snapshot[retired].fixed_income < without.monthly_snapshot[retired].fixed_income);
+        let last = with.monthly_snapshot.len() - 1;
+        assert!(with.monthly_snapshot[last].fixed_income < without.monthly_snapshot[last].fixed_income);
+        assert!(with.monthly_snapshot[0].expenses == without.monthly_snapshot[0].expenses);
+    }
+
+    #[test]
+    fn test_cash_allocation() {
+        let mut input = fixture::get_input();
+        let mut all_cash = crate::portfolio::Allocation {weights: [0.0; MAX_ASSET_CLASSES]};
+        all_cash.weights[crate::portfolio::CASH] = 100.0;
+        input.portfolio.pre_retirement_allocation = all_cash;
+        input.portfolio.post_retirement_allocation = all_cash;
+        let mut returns = [10.0; MAX_ASSET_CLASSES];
+        returns[crate::portfolio::CASH] = 0.0;
+        let results = fixture::run_simulation_with_returns(&input, &returns, 2.6).unwrap();
+        assert!(results.monthly_snapshot.iter()
+            .all(|v| v.returns[crate::portfolio::US_EQUITY] > 0.0 && v.returns[crate::portfolio::CASH] == 0.0));
+        assert!(results.monthly_snapshot.iter().all(|v| v.annualized_return == 0.0));
+
+        // only the cash return changes the results
+        returns[crate::portfolio::CASH] = 2.0;
+        let higher = fixture::run_simulation_with_returns(&input, &returns, 2.6).unwrap();
+        let ending = |results: &SimulationResults| results.monthly_snapshot.last().unwrap().balance;
+        assert!(ending(&higher) > ending(&results));
+    }
+
+    #[test]
+    fn test_asset_classes() {
+        let (portfolio, names) = fixture::parse_portfolio("
+    pre-retirement_allocation:
+        us_equities: 56.0
+        bonds: 20.0
+        international: 24.0
+    post-retirement_allocation:
+        us_equities: 32.0
+        reits: 10.0
+        bonds: 40.0
+        international: 18.0
+    asset_classes:
+        - name: reits
+          expected_returns: 4.0
+          historical: real_estate
+").unwrap();
+        assert_eq!(names, ["us_equity", "international_equity", "bonds", "cash", "reits"]);
+        assert_eq!(portfolio.num_asset_classes, 5);
+        assert_eq!(portfolio.post_retirement_allocation.weights[4], 10.0);
+        assert_eq!(portfolio.pre_retirement_allocation.weights[4], 0.0);
+        assert_eq!(portfolio.post_retirement_allocation.get_total(), 100.0);
+
+        // the historical years follow the mapped column
+        let path = ReturnPath {us_equity: 1.0, international_equity: 2.0, bonds: 3.0, cash: 4.0,
+                               corporate_bonds: 5.0, real_estate: 6.0, inflation: 2.0};
+        assert_eq!(path.get_returns(&portfolio)[..5], [1.0, 2.0, 3.0, 4.0, 6.0]);
+
+        // only the retirement allocation holds them, so their returns only
+        // change the results after retirement
+        let input = crate::Input {portfolio, ..fixture::get_input()};
+        let mut returns = input.portfolio.get_expected_returns();
+        let lower = fixture::run_simulation_with_returns(&input, &returns, 2.6).unwrap();
+        returns[4] += 5.0;
+        let higher = fixture::run_simulation_with_returns(&input, &returns, 2.6).unwrap();
+        let retired = lower.monthly_snapshot.iter().position(|v| v.date >= lower.retirement_date).unwrap();
+        assert_eq!(lower.monthly_snapshot[retired - 1].balance, higher.monthly_snapshot[retired - 1].balance);
+        assert!(higher.monthly_snapshot.last().unwrap().balance > lower.monthly_snapshot.last().unwrap().balance);
+    }
+
+    #[test]
+    fn test_inflation_linked() {
+        let allocations = "
+    pre-retirement_allocation:
+        us_equities: 56.0
+        bonds: 20.0
+        international: 24.0
+    post-retirement_allocation:
+        us_equities: 32.0
+        tips: 10.0
+        bonds: 40.0
+        international: 18.0
+    asset_classes:
+        - name: tips
+          inflation_linked: true
+          real_yield: 1.8";
+        let (mut portfolio, _) = fixture::parse_portfolio(allocations).unwrap();
+
+        // the real yield whatever the historical year's inflation
+        let mut path = ReturnPath {us_equity: 1.0, international_equity: 2.0, bonds: 3.0, cash: 4.0,
+                                   corporate_bonds: 5.0, real_estate: 6.0, inflation: 2.0};
+        assert_eq!(path.get_returns(&portfolio)[4], 1.8);
+        path.inflation = 12.0;
+        assert_eq!(path.get_returns(&portfolio)[4], 1.8);
+
+        // but more inflation is more taxable interest
+        portfolio.taxable_balance = portfolio.balance;
+        let (_, low) = portfolio.get_taxable_yields(true, 2.0);
+        let (_, high) = portfolio.get_taxable_yields(true, 12.0);
+        assert!((high - low - portfolio.balance * 0.1 * 0.1 / 12.0).abs() < 0.01);
+
+        // it has no other returns
+        assert!(fixture::parse_portfolio(&format!("{}\n          expected_returns: 2.0", allocations)).is_err());
+    }
+
+    #[test]
+    fn test_investment_fees() {
+        let fees = |advisor_fee_percent: f32| fixture::parse_portfolio(&format!(
+            "{}    bonds_expense_ratio: 0.5\n    advisor_fee_percent: {:.1}\n", fixture::ALLOCATIONS, advisor_fee_percent));
+        let (portfolio, _) = fees(1.0).unwrap();
+
+        // the fees come out of each year's return
+        let mut returns = [0.0; crate::portfolio::MAX_ASSET_CLASSES];
+        returns[crate::portfolio::BONDS] = 5.0;
+        let net = portfolio.get_net_returns(&returns);
+        assert!((net[crate::portfolio::BONDS] - (1.05 * 0.985 - 1.0) * 100.0).abs() < 1e-4);
+        assert!((net[crate::portfolio::US_EQUITY] - -1.0).abs() < 1e-4);
+
+        // and can't be negative
+        assert!(fees(-1.0).is_err());
+    }
+
+    #[test]
+    fn test_accounts() {
+        let accounts = "
+    accounts:
+        - name: 401k
+          type: tax_deferred
+          balance: 600000.0
+          allocation: {us_equities: 20.0, international: 0.0, bonds: 80.0}
+        - name: ira
+          type: ira
+          balance: 200000.0
+          basis: 50000.0
+          allocation: {us_equities: 20.0, international: 0.0, bonds: 80.0}
+        - name: brokerage
+          type: taxable
+          balance: 400000.0
+          allocation: {us_equities: 70.0, international: 30.0, bonds: 0.0}
+";
+        let (mut portfolio, _) = fixture::parse_portfolio_without_balances(accounts).unwrap();
+
+        // the accounts add up to the balances and the allocation
+        assert_eq!(portfolio.balance, 1200000.0);
+        assert_eq!(portfolio.taxable_balance, 400000.0);
+        assert_eq!((portfolio.ira_balance, portfolio.ira_basis), (200000.0, 50000.0));
+        assert!((portfolio.pre_retirement_allocation.weights[crate::portfolio::BONDS] - 160.0 / 3.0).abs() < 1e-3);
+        assert_eq!(portfolio.num_accounts, 3);
+
+        // the bonds held in the tax-deferred accounts pay no taxable interest
+        let (dividends, interest) = portfolio.get_taxable_yields(false, 2.0);
+        assert_eq!(interest, 0.0);
+        assert!((dividends - 400000.0 / 12.0 * (0.7 * 1.3 + 0.3 * 3.0) / 100.0).abs() < 0.01);
+
+        // each account grows with its own allocation, the brokerage account
+        // by all of the stock returns and the others by a fifth of them
+        let mut returns = [0.0; MAX_ASSET_CLASSES];
+        returns[crate::portfolio::US_EQUITY] = 10.0;
+        returns[crate::portfolio::INTERNATIONAL_EQUITY] = 10.0;
+        portfolio.grow(&returns, false, 12);
+        assert!((portfolio.taxable_balance - 440000.0).abs() < 1.0);
+        assert!((portfolio.ira_balance - 204000.0).abs() < 1.0);
+        assert!((portfolio.balance - 1256000.0).abs() < 1.0);
+        assert!((portfolio.accounts[0].balance - 612000.0).abs() < 1.0);
+
+        // a withdrawal from the taxable accounts only comes out of the
+        // brokerage account
+        portfolio.withdraw_taxable(40000.0);
+        portfolio.grow(&[0.0; MAX_ASSET_CLASSES], false, 1);
+        assert!((portfolio.accounts[2].balance - 400000.0).abs() < 1.0);
+        assert!((portfolio.accounts[0].balance - 612000.0).abs() < 1.0);
+
+        // the accounts replace the balance and both allocations
+        let balance = format!("    balance: 1200000.0{}", accounts);
+        assert!(fixture::parse_portfolio_without_balances(&balance).is_err());
+        assert!(fixture::parse_portfolio_without_balances(&format!("{}{}", accounts, fixture::ALLOCATIONS)).is_err());
+    }
+
+    #[test]
+    fn test_glide_path() {
+        let glide_path = "
+    glide_path:
+        - age: 60
+          us_equities: 30.0
+          bonds: 60.0
+          international: 10.0
+        - age: 70
+          us_equities: 50.0
+          bonds: 40.0
+          international: 10.0
+";
+        let (portfolio, _) = fixture::parse_portfolio(glide_path).unwrap();
+        assert_eq!(portfolio.num_glide_points, 2);
+
+        // a rising-equity bond tent, held before the first point and after
+        // the last
+        assert_eq!(portfolio.get_glide_allocation(55.0).weights[0], 30.0);
+        assert_eq!(portfolio.get_glide_allocation(65.0).weights[..3], [40.0, 10.0, 50.0]);
+        assert_eq!(portfolio.get_glide_allocation(80.0).weights[0], 50.0);
+
+        // the simulation follows the first retiree's age
+        let mut input = crate::Input {portfolio, ..fixture::get_input()};
+        crate::validate_input(&mut input).unwrap();
+        let context = fixture::get_context(&input);
+        let mut simulation = Simulation::new(&context);
+        simulation.run_simulation_one_step(&input.portfolio.get_expected_returns()).unwrap();
+        let age = simulation.portfolio_.age;
+        assert!(age > 60.0 && age < 70.0);
+        assert!((simulation.portfolio_.get_target_allocation(false).weights[0] - (30.0 + (age - 60.0) * 2.0)).abs() < 0.001);
+
+        // and replaces the allocations before and after retirement
+        let post_retirement = "
+    post-retirement_allocation:
+        us_equities: 100.0
+        bonds: 0.0
+        international: 0.0";
+        assert!(fixture::parse_portfolio(&format!("{}{}", post_retirement, glide_path)).is_err());
+    }
+
+    #[test]
+    fn test_floor_funded() {
+        let mut input = fixture::get_input();
+        input.expenses.monthly = 20000.0;
+        let results = fixture::run_simulation(&input).unwrap();
+        assert!(results.is_depleted());
+        assert_eq!(results.unfunded_essential_expenses, results.unfunded_expenses);
+        assert!(!results.is_floor_funded());
+
+        // the income after the money runs out pays for a small floor
+        input.expenses.essential_monthly = Some(1000.0);
+        let results = fixture::run_simulation(&input).unwrap();
+        assert!(results.is_depleted());
+        assert!(results.unfunded_expenses > 0.0);
+        assert!(results.is_floor_funded());
+    }
+
+    #[test]
+    fn test_cash_flow_rebalancing() {
+        let input = fixture::get_input();
+        let mut portfolio = input.portfolio;
+        portfolio.rebalancing = crate::Rebalancing::CashFlow;
+        portfolio.rebalance_band = 100.0;
+        portfolio.set_balance(100000.0);
+        let allocation = portfolio.pre_retirement_allocation;
+
+        // equities doubling leaves bonds underweight, and a deposit only
+        // buys bonds
+        let bonds_index = crate::portfolio::BONDS;
+        let mut equities_double = [0.0; MAX_ASSET_CLASSES];
+        equities_double[crate::portfolio::US_EQUITY] = 100.0;
+        equities_double[crate::portfolio::INTERNATIONAL_EQUITY] = 100.0;
+        portfolio.grow(&equities_double, false, 12);
+        let bonds = portfolio.holdings[bonds_index];
+        assert!((bonds - allocation.weights[bonds_index] * 1000.0).abs() < 1.0);
+        portfolio.deposit(10000.0);
+        portfolio.grow(&[0.0; MAX_ASSET_CLASSES], false, 1);
+        assert!((portfolio.holdings[bonds_index] - bonds - 10000.0).abs() < 1.0);
+        assert!(portfolio.get_current_allocation(false).weights[bonds_index] < allocation.weights[bonds_index]);
+
+        // outside a narrow band everything is rebalanced
+        portfolio.rebalance_band = 2.0;
+        portfolio.grow(&[0.0; MAX_ASSET_CLASSES], false, 1);
+        assert!((portfolio.get_current_allocation(false).weights[bonds_index] - allocation.weights[bonds_index]).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_rebalance_gains() {
+        let mut input = fixture::get_input();
+        let mut portfolio = input.portfolio;
+        portfolio.set_balance(100000.0);
+        portfolio.taxable_balance = portfolio.balance;
+
+        // equities doubling leaves $16,000 of them to sell at the next
+        // rebalance, half of it gains
+        let mut equities_double = [0.0; MAX_ASSET_CLASSES];
+        equities_double[crate::portfolio::US_EQUITY] = 100.0;
+        equities_double[crate::portfolio::INTERNATIONAL_EQUITY] = 100.0;
+        portfolio.grow(&equities_double, false, 12);
+        assert_eq!(portfolio.rebalance_gains, 0.0);
+        portfolio.grow(&[0.0; MAX_ASSET_CLASSES], false, 1);
+        assert!((portfolio.rebalance_gains - 8000.0).abs() < 1.0);
+
+        // the taxes on the gains lower the balance, unless nothing sold is a
+        // gain
+        input.portfolio.taxable_balance = input.portfolio.balance;
+        let taxed = fixture::run_simulation(&input).unwrap();
+        assert!(taxed.monthly_snapshot.iter().any(|v| v.rebalance_gains > 0.0));
+        input.portfolio.rebalance_gain_percent = 0.0;
+        let untaxed = fixture::run_simulation(&input).unwrap();
+        assert!(untaxed.monthly_snapshot.iter().all(|v| v.rebalance_gains == 0.0));
+        assert!(taxed.monthly_snapshot.last().unwrap().balance < untaxed.monthly_snapshot.last().unwrap().balance);
+    }
+
+    #[test]
+    fn test_cash_bucket() {
+        let input = fixture::get_input();
+        let mut portfolio = input.portfolio;
+        portfolio.cash_bucket_years = 2.0;
+        portfolio.set_balance(1000000.0);
+
+        // a good market fills the bucket with two years of withdrawals
+        portfolio.withdraw(4000.0);
+        portfolio.update_cash_bucket(4000.0, 4000.0);
+        assert_eq!(portfolio.cash_bucket, 96000.0);
+        assert!(!portfolio.is_down_market());
+
+        // the bucket earns the cash return through a crash, and then pays
+        // the withdrawals while the rest of the portfolio is down
+        let mut crash = [-30.0; MAX_ASSET_CLASSES];
+        crash[crate::portfolio::CASH] = 0.0;
+        portfolio.grow(&crash, true, 12);
+        assert_eq!(portfolio.cash_bucket, 96000.0);
+        assert!(portfolio.is_down_market());
+        let invested = portfolio.get_invested_balance();
+        portfolio.withdraw(4000.0);
+        portfolio.update_cash_bucket(4000.0, 4000.0);
+        assert_eq!(portfolio.cash_bucket, 92000.0);
+        assert!((portfolio.get_invested_balance() - invested).abs() < 0.1);
+
+        // a year of recovery refills it
+        portfolio.grow(&[50.0; MAX_ASSET_CLASSES], true, 12);
+        assert!(!portfolio.is_down_market());
+        portfolio.update_cash_bucket(0.0, 4000.0);
+        assert_eq!(portfolio.cash_bucket, 96000.0);
+    }
+
+    #[test]
+    fn test_nominal_returns() {
+        let input = fixture::get_input();
+        let real = fixture::run_simulation(&input).unwrap();
+
+        // the fixture's real returns compounded with 2.6% inflation
+        let yaml = yaml_rust::YamlLoader::load_from_str("
+portfolio:
+    balance: 1200000.0
+    taxable_percent: 0.0
+    hsa_balance: 0.0
+    pre-retirement_allocation:
+        us_equities: 56.0
+        bonds: 20.0
+        international: 24.0
+    post-retirement_allocation:
+        us_equities: 42.0
+        bonds: 40.0
+        international: 18.0
+    expected_returns: nominal
+    us_equity_expected_returns: 5.7806
+    us_equity_standard_deviation: 15.0
+    international_equity_expected_returns: 6.9092
+    international_equity_standard_deviation: 16.0
+    bonds_expected_returns: 5.2676
+    bonds_standard_deviation: 6.0
+    expected_inflation: 2.6
+    us_equity_dividend_yield: 1.3
+    international_equity_dividend_yield: 3.0
+    bonds_interest_yield: 4.0
+").unwrap();
+        let (portfolio, _) = crate::parse_portfolio(&yaml[0]).unwrap();
+        assert!(portfolio.nominal_returns);
+        assert!((portfolio.asset_classes[crate::portfolio::US_EQUITY].expected_returns - 3.1).abs() < 0.001);
+        assert!((portfolio.asset_classes[crate::portfolio::BONDS].expected_returns - 2.6).abs() < 0.001);
+
+        let nominal = fixture::run_simulation(&crate::Input {portfolio, ..input}).unwrap();
+        let ending = |results: &SimulationResults| results.monthly_snapshot.last().unwrap().balance;
+        assert!((ending(&nominal) - ending(&real)).abs() < ending(&real) * 0.001 + 1.0);
+    }
+
+    #[test]
+    fn test_reverse_mortgage() {
+        let mut input = fixture::get_input();
+        input.expenses.monthly = 15000.0;
+        input.home = Some(crate::Home {
+            value: 600000.0,
+            appreciation: 0.0,
+            property_tax_rate: 0.0,
+            insurance_annual: 0.0,
+            downsize: None,
+            reverse_mortgage: None,
+        });
+        let without = fixture::run_simulation(&input).unwrap();
+        assert!(without.is_depleted());
+
+        // the line of credit takes over where the portfolio ran out, and
+        // the loan grows with the unused credit
+        input.home.as_mut().unwrap().reverse_mortgage = Some(crate::ReverseMortgage {
+            age: 62,
+            credit_limit: 200000.0,
+            growth_rate: 2.0,
+        });
+        let with = fixture::run_simulation(&input).unwrap();
+        let first_draw = with.monthly_snapshot.iter().position(|v| v.credit_draws > 0.0).unwrap();
+        assert_eq!(first_draw, without.monthly_snapshot.len() - 1);
+        assert!(with.monthly_snapshot.len() > without.monthly_snapshot.len());
+        assert!(with.is_depleted());
+        let last = with.monthly_snapshot.last().unwrap();
+        let drawn: f32 = with.monthly_snapshot.iter().map(|v| v.credit_draws).sum();
+        assert!(drawn > 200000.0 && last.reverse_mortgage_balance > drawn);
+        assert!(with.get_depletion_age().unwrap() > without.get_depletion_age().unwrap());
+    }
+
+    #[test]
+    fn test_one_time_expenses() {
+        let mut input = fixture::get_input();
+        let without = fixture::run_simulation(&input).unwrap();
+        let start_date = fixture::get_start_date();
+        let date = add_months(&start_date, 60);
+        let car = crate::OneTimeExpense {
+            name: "Car".to_string(),
+            amount: 40000.0,
+            date,
+            inflation_adjusted: true,
+            every_years: Some(8),
+        };
+        let dates = car.get_dates(&start_date, &add_months(&date, 12 * 17));
+        assert_eq!(dates, vec![date, add_months(&date, 96), add_months(&date, 192)]);
+        let wedding = crate::OneTimeExpense {
+            inflation_adjusted: false,
+            every_years: None,
+            ..car.clone()
+        };
+        assert!((wedding.get_amount(3.0, &start_date, &add_months(&start_date, 120)) - 40000.0 / 1.03f32.powi(10)).abs() < 1.0);
+        input.one_time_expenses = vec![car];
+
+        // the car is withdrawn in its month with the taxes on it
+        let with = fixture::run_simulation(&input).unwrap();
+        let step = with.monthly_snapshot.iter().position(|v| add_months(&v.date, 1) > date).unwrap();
+        assert_eq!(with.monthly_snapshot[step].one_time_expenses, 40000.0);
+        assert_eq!(with.monthly_snapshot[step].expenses - without.monthly_snapshot[step].expenses, 40000.0);
+        assert!(with.monthly_snapshot[step].taxes > without.monthly_snapshot[step].taxes + 5000.0);
+        let difference = |i: usize| without.monthly_snapshot[i].balance - with.monthly_snapshot[i].balance;
+        assert_eq!(difference(step - 1), 0.0);
+        assert!(difference(step) > 45000.0);
+        assert_eq!(with.monthly_snapshot[step + 1].one_time_expenses, 0.0);
+    }
+
+    #[test]
+    fn test_windfalls() {
+        let mut input = fixture::get_input();
+        let without = fixture::run_simulation(&input).unwrap();
+        let start_date = fixture::get_start_date();
+        let windfall = |name: &str, probability| crate::Windfall {
+            name: name.to_string(),
+            amount: 100000.0,
+            date: add_months(&start_date, 2),
+            probability,
+        };
+        input.windfalls = vec![windfall("inheritance", 100.0), windfall("home sale", 50.0)];
+
+        // only the certain windfall is counted, unless a scenario says the
+        // other one happens
+        let context = fixture::get_context(&input);
+        assert_eq!(context.windfalls.len(), 1);
+        assert_eq!(context.with_windfalls(&[false, true]).windfalls[0].name, "home sale");
+
+        let with = fixture::run_simulation(&input).unwrap();
+        let difference = |i: usize| with.monthly_snapshot[i].balance - without.monthly_snapshot[i].balance;
+        assert_eq!(difference(1), 0.0);
+        assert!(difference(2) >= 100000.0);
+    }
+
+    #[test]
+    fn test_tax_bracket() {
+        // levels as parse_tax_levels leaves them
+        let levels = vec![
+            TaxLevel {income: 0.0, rate: 0.0},
+            TaxLevel {income: 49999.0, rate: 10.0},
+            TaxLevel {income: f32::MAX, rate: 20.0},
+        ];
+
+        assert_eq!(get_tax_bracket(1000.0, 24000.0, &levels), (0.0, 0.0));
+        assert_eq!(get_tax_bracket(4000.0, 24000.0, &levels), (10.0, 25999.0));
+        assert_eq!(get_tax_bracket(6500.0, 24000.0, &levels).0, 20.0);
+
+        assert!(get_brackets_filled(20000.0, 24000.0, &levels).is_empty());
+        assert_eq!(get_brackets_filled(84000.0, 24000.0, &levels), vec![(10.0, 49999.0), (20.0, 10001.0)]);
     }
 }
 
@@ -0,0 +1,135 @@
+/**************************************************************************
+* tax_report.rs
+*
+* Summary of the simulated tax return for a single year, to check the tax
+* calculations against a real return.
+**************************************************************************/
+
+use chrono::Datelike;
+use crate::{Input, signed_with_commas};
+use crate::simulate::{self, MonthlySnapshot, SimulationResults};
+
+// a year of the simulation, the amounts are annual
+#[derive(Debug, Default)]
+struct TaxYear {
+    months: u32,
+    taxable_wages: f32,
+    social_security: f32,
+    other_income: f32,
+    roth_conversions: f32,
+    interest: f32,
+    dividends: f32,
+    rebalance_gains: f32,
+    taxable_withdrawals: f32,
+    loss_deduction: f32,
+    standard_deduction: f32,
+    federal_taxes: f32,
+    capital_gains_taxes: f32,
+    state_taxes: f32,
+    irmaa: f32,
+    payroll_taxes: f32,
+    bracket_rate: f32,
+}
+
+impl TaxYear {
+    fn add(&mut self, snapshot: &MonthlySnapshot, months: u32) {
+        let m = months as f32;
+        self.months += months;
+        self.taxable_wages += snapshot.taxable_wages * m;
+        self.social_security += snapshot.social_security * m;
+        self.other_income += snapshot.other_income * m;
+        self.roth_conversions += snapshot.roth_conversions * m;
+        self.interest += snapshot.interest * m;
+        self.dividends += snapshot.dividends * m;
+        self.rebalance_gains += snapshot.rebalance_gains * m;
+        self.taxable_withdrawals += snapshot.taxable_withdrawals * m;
+        self.loss_deduction += snapshot.loss_deduction * m;
+        self.federal_taxes += snapshot.federal_taxes * m;
+        self.capital_gains_taxes += snapshot.capital_gains_taxes * m;
+        self.state_taxes += snapshot.state_taxes * m;
+        self.irmaa += snapshot.irmaa * m;
+        self.payroll_taxes += snapshot.payroll_taxes * m;
+        // the deduction and marginal rate at the end of the year
+        self.standard_deduction = snapshot.standard_deduction;
+        self.bracket_rate = snapshot.bracket_rate;
+    }
+
+    fn ordinary_income(&self) -> f32 {
+        self.taxable_wages + self.social_security * 0.85 + self.other_income + self.roth_conversions +
+            self.interest + self.taxable_withdrawals - self.loss_deduction
+    }
+}
+
+fn add_line(out: &mut String, label: &str, amount: f32) {
+    outln!(out, "  {:<40}{:>12}", label, signed_with_commas(amount));
+}
+
+pub fn print_tax_report(out: &mut String, input: &Input, results: &SimulationResults, year: i32) -> Result<(), String> {
+    let months_per_snapshot = 12 / results.snapshots_per_year() as u32;
+    let mut tax_year = TaxYear::default();
+    for snapshot in results.monthly_snapshot.iter().filter(|v| v.date.year() == year) {
+        tax_year.add(snapshot, months_per_snapshot);
+    }
+    if tax_year.months == 0 {
+        let first = results.monthly_snapshot.first().map_or(0, |v| v.date.year());
+        let last = results.monthly_snapshot.last().map_or(0, |v| v.date.year());
+        return Err(format!("{} isn't in the simulation, which runs from {} to {}", year, first, last));
+    }
+
+    outln!(out, "-= Tax report for {} =-", year);
+    outln!(out);
+    outln!(out, "Simulated with uniform returns, in today's dollars.");
+    if tax_year.months < 12 {
+        outln!(out, "The simulation only covers {} months of this year.", tax_year.months);
+    }
+
+    let t = &tax_year;
+    let ordinary_income = t.ordinary_income();
+    let taxable_ordinary_income = f32::max(ordinary_income - t.standard_deduction, 0.0);
+    outln!(out);
+    outln!(out, "Income:");
+    add_line(out, "Wages after pre-tax contributions", t.taxable_wages);
+    add_line(out, "Social security (85% taxable)", t.social_security * 0.85);
+    add_line(out, "Pensions and other income", t.other_income);
+    add_line(out, "Roth conversions (taxable part)", t.roth_conversions);
+    add_line(out, "Interest", t.interest);
+    add_line(out, "Withdrawals (taxable part)", t.taxable_withdrawals);
+    add_line(out, "Capital loss deduction", -t.loss_deduction);
+    add_line(out, "Ordinary income", ordinary_income);
+    add_line(out, "Standard deduction", -t.standard_deduction);
+    add_line(out, "Taxable ordinary income", taxable_ordinary_income);
+    add_line(out, "Qualified dividends", t.dividends);
+    add_line(out, "Capital gains from rebalancing", t.rebalance_gains);
+
+    // the brackets as the engine applies them
+    let (_, tax_levels) = input.tax_rates.for_year(year);
+    outln!(out);
+    outln!(out, "Brackets filled:");
+    for (rate, amount) in simulate::get_brackets_filled(ordinary_income, t.standard_deduction, tax_levels) {
+        add_line(out, &format!("{:.0}%", rate), amount);
+    }
+
+    outln!(out);
+    outln!(out, "Credits: none, credits aren't modelled");
+
+    let income_taxes = t.federal_taxes + t.capital_gains_taxes;
+    outln!(out);
+    outln!(out, "Taxes:");
+    add_line(out, "Federal income tax", t.federal_taxes);
+    add_line(out, "Capital gains tax", t.capital_gains_taxes);
+    add_line(out, "State income tax", t.state_taxes);
+    add_line(out, "Medicare surcharges (IRMAA)", t.irmaa);
+    add_line(out, "Payroll taxes", t.payroll_taxes);
+    add_line(out, "Total", income_taxes + t.state_taxes + t.irmaa + t.payroll_taxes);
+
+    let mut effective_rate = 0.0;
+    let capital_gains_income = t.dividends + t.rebalance_gains;
+    if ordinary_income + capital_gains_income > 0.0 {
+        effective_rate = income_taxes / (ordinary_income + capital_gains_income) * 100.0;
+    }
+    outln!(out);
+    outln!(out, "Marginal federal rate: {:.0}%", t.bracket_rate);
+    outln!(out, "Effective federal rate: {:.1}% (federal and capital gains taxes over ordinary income, dividends and gains)", effective_rate);
+
+    Ok(())
+}
@@ -0,0 +1,197 @@
+/**************************************************************************
+* tax_tables.rs
+*
+* Loads federal and state tax bracket tables for one or more years from a
+* CSV or JSON file, so the brackets can be kept current without changing
+* the configuration file.
+**************************************************************************/
+
+use yaml_rust::{Yaml, YamlLoader};
+use crate::{TaxLevel, build_tax_levels};
+
+// the standard deduction and brackets that take effect in a year
+#[derive(Debug, Clone)]
+pub struct TaxTable {
+    pub year: i32,
+    pub standard_deduction: f32,
+    pub tax_levels: Vec<TaxLevel>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TaxTables {
+    pub federal: Vec<TaxTable>,
+    pub state: Vec<TaxTable>,
+}
+
+// returns the table in effect for a year, the latest one that has started
+pub fn find_table(tables: &[TaxTable], year: i32) -> Option<&TaxTable> {
+    tables.iter().rev().find(|v| v.year <= year)
+}
+
+// a table as read from the file, before the levels are converted
+struct RawTable {
+    year: i32,
+    name: String,
+    standard_deduction: f32,
+    levels: Vec<TaxLevel>,
+}
+
+// JSON numbers without a decimal point are integers
+fn get_number(yaml: &Yaml, field_name: &str) -> Result<f64, String> {
+    match &yaml[field_name] {
+        Yaml::Integer(v) => Ok(*v as f64),
+        v => v.as_f64().ok_or(format!("Invalid value: {}", field_name)),
+    }
+}
+
+// CSV with a header line and one bracket per line:
+//
+//   year,table,standard_deduction,income,rate
+//   2026,federal,32200.0,24800.0,12.0
+//
+// Blank lines and lines starting with # are skipped.
+fn parse_csv(text: &str) -> Result<Vec<RawTable>, String> {
+    let mut tables: Vec<RawTable> = Vec::new();
+    let mut lines = text.lines().enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'));
+
+    let (_, header) = lines.next().ok_or("no header line")?;
+    let columns: Vec<&str> = header.split(',').map(|v| v.trim()).collect();
+    if columns != ["year", "table", "standard_deduction", "income", "rate"] {
+        return Err("header must be year,table,standard_deduction,income,rate".to_string());
+    }
+
+    for (line_number, line) in lines {
+        let values: Vec<&str> = line.split(',').map(|v| v.trim()).collect();
+        if values.len() != columns.len() {
+            return Err(format!("line {}: expected {} values", line_number, columns.len()));
+        }
+        let number = |i: usize| values[i].parse::<f32>()
+            .map_err(|_| format!("line {}: invalid {}", line_number, columns[i]));
+        let year = values[0].parse::<i32>()
+            .map_err(|_| format!("line {}: invalid year", line_number))?;
+        let standard_deduction = number(2)?;
+        let level = TaxLevel {income: number(3)?, rate: number(4)?};
+
+        match tables.iter_mut().find(|v| v.year == year && v.name == values[1]) {
+            Some(table) => {
+                if table.standard_deduction != standard_deduction {
+                    return Err(format!("line {}: standard_deduction differs from the rest of the table", line_number));
+                }
+                table.levels.push(level);
+            },
+            None => tables.push(RawTable {
+                year,
+                name: values[1].to_string(),
+                standard_deduction,
+                levels: vec![level],
+            }),
+        }
+    }
+
+    Ok(tables)
+}
+
+// JSON with a list of tables:
+//
+//   {"tables": [{"year": 2026, "table": "federal", "standard_deduction": 32200,
+//                "levels": [{"income": 24800, "rate": 12}]}]}
+fn parse_json(text: &str) -> Result<Vec<RawTable>, String> {
+    // JSON is a subset of YAML
+    let docs = YamlLoader::load_from_str(text).map_err(|e| e.to_string())?;
+    let doc = docs.first().ok_or("file is empty")?;
+    let vec = doc["tables"].as_vec().ok_or("no tables found")?;
+
+    let mut tables = Vec::new();
+    for element in vec {
+        let name = element["table"].as_str().ok_or("Invalid value: table")?;
+        let mut levels = Vec::new();
+        for level in element["levels"].as_vec().ok_or("Invalid value: levels")? {
+            levels.push(TaxLevel {
+                income: get_number(level, "income")? as f32,
+                rate: get_number(level, "rate")? as f32,
+            });
+        }
+        tables.push(RawTable {
+            year: get_number(element, "year")? as i32,
+            name: name.to_string(),
+            standard_deduction: get_number(element, "standard_deduction")? as f32,
+            levels,
+        });
+    }
+
+    Ok(tables)
+}
+
+// parses a tables file, JSON if the name ends in .json and CSV otherwise.
+// The levels have the same format as the levels in the configuration file.
+pub fn parse_tax_tables(fname: &str, text: &str) -> Result<TaxTables, String> {
+    let raw_tables = if fname.to_lowercase().ends_with(".json") {
+        parse_json(text)
+    }
+    else {
+        parse_csv(text)
+    }.map_err(|e| format!("Can't parse {}: {}", fname, e))?;
+
+    let mut tables = TaxTables::default();
+    for raw in raw_tables {
+        let list = match raw.name.as_str() {
+            "federal" => &mut tables.federal,
+            "state" => &mut tables.state,
+            _ => return Err(format!("{}: unknown table {}, must be federal or state", fname, raw.name)),
+        };
+        if list.iter().any(|v| v.year == raw.year) {
+            return Err(format!("{}: more than one {} table for {}", fname, raw.name, raw.year));
+        }
+        if raw.levels.is_empty() {
+            return Err(format!("{}: {} table for {} has no levels", fname, raw.name, raw.year));
+        }
+        list.push(TaxTable {
+            year: raw.year,
+            standard_deduction: raw.standard_deduction,
+            tax_levels: build_tax_levels(raw.levels),
+        });
+    }
+    tables.federal.sort_by_key(|v| v.year);
+    tables.state.sort_by_key(|v| v.year);
+
+    Ok(tables)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_csv_and_json_match() {
+        let csv = "\
+# brackets
+year,table,standard_deduction,income,rate
+2027,federal,31000.0,24000.0,12.0
+2027,federal,31000.0,98000.0,22.0
+2026,federal,30000.0,23850.0,12.0
+2026,state,0.0,0.0,4.4
+";
+        let json = r#"{"tables": [
+            {"year": 2027, "table": "federal", "standard_deduction": 31000,
+             "levels": [{"income": 24000, "rate": 12}, {"income": 98000.0, "rate": 22.0}]},
+            {"year": 2026, "table": "federal", "standard_deduction": 30000.0,
+             "levels": [{"income": 23850, "rate": 12}]},
+            {"year": 2026, "table": "state", "standard_deduction": 0,
+             "levels": [{"income": 0, "rate": 4.4}]}
+        ]}"#;
+
+        for tables in [parse_tax_tables("t.csv", csv).unwrap(), parse_tax_tables("t.json", json).unwrap()] {
+            assert_eq!(tables.federal.len(), 2);
+            assert_eq!(tables.state.len(), 1);
+            assert_eq!(find_table(&tables.federal, 2025).map(|v| v.year), None);
+            assert_eq!(find_table(&tables.federal, 2026).map(|v| v.standard_deduction), Some(30000.0));
+            assert_eq!(find_table(&tables.federal, 2030).map(|v| v.standard_deduction), Some(31000.0));
+            assert_eq!(find_table(&tables.federal, 2030).unwrap().tax_levels.len(), 3);
+            assert_eq!(find_table(&tables.state, 2030).unwrap().tax_levels[1].rate, 4.4);
+        }
+
+        assert!(parse_tax_tables("t.csv", "year,table,standard_deduction,income,rate\n2026,city,0,0,1\n").is_err());
+    }
+}
@@ -4,7 +4,7 @@
 * Utilities useful application-wide
 **************************************************************************/
 
-use chrono::{NaiveDate, Duration};
+use chrono::{Datelike, Months, NaiveDate};
 
 // given an annual interest rate, return the equivalent monthly rate. This
 // allows the inputs to be entered in the familiar annual yield, but the
@@ -17,16 +17,125 @@ pub fn get_monthly_rate(annual_rate: f32) -> f32 {
 
 pub fn get_age(date_of_birth: &NaiveDate, current_date: &NaiveDate) -> u32 {
     let years_diff = current_date.years_since(*date_of_birth);
-    match years_diff {
-        Some(v) => v,
-        None => 0,
+    years_diff.unwrap_or_default()
+}
+
+// number of whole months from start to end, 0 if end is before start
+pub fn months_between(start: &NaiveDate, end: &NaiveDate) -> u32 {
+    if end <= start {
+        return 0;
+    }
+    let mut months = (end.year() - start.year()) * 12 + end.month() as i32 - start.month() as i32;
+    if end.day() < start.day() {
+        months -= 1;
     }
+    months.max(0) as u32
 }
 
+// the date of a birthday, by calendar months so it doesn't drift with leap
+// years. A birthday on a day the month doesn't have, such as February 29th,
+// falls on the month's last day.
 pub fn add_years(date: &NaiveDate, years: u32) -> NaiveDate {
-    let days_to_add = years as i64 * 365;
-    match date.checked_add_signed(Duration::days(days_to_add)) {
+    add_months(date, years * 12)
+}
+
+pub fn add_months(date: &NaiveDate, months: u32) -> NaiveDate {
+    match date.checked_add_months(Months::new(months)) {
         Some(v) => v,
         None => *date,
     }
 }
+
+// age in whole months on a date
+pub fn get_age_months(date_of_birth: &NaiveDate, current_date: &NaiveDate) -> u32 {
+    months_between(date_of_birth, current_date)
+}
+
+// first day of the month in which someone reaches an age in months. By the
+// rules Social Security, Medicare and the IRS follow, a person reaches an age
+// the day before the birthday, so someone born on the 1st reaches it in the
+// month before.
+pub fn get_month_at_age(date_of_birth: &NaiveDate, age_months: u32) -> NaiveDate {
+    let date = add_months(date_of_birth, age_months).pred_opt().unwrap();
+    date.with_day(1).unwrap()
+}
+
+// returns how much a fixed nominal amount is worth in today's dollars after
+// the given number of months of inflation (annual rate as a percentage)
+pub fn get_inflation_discount(annual_inflation: f32, months: u32) -> f32 {
+    1.0 / (1.0 + get_monthly_rate(annual_inflation / 100.0)).powi(months as i32)
+}
+
+// returns the value at the given percentile (0-100) of a list of values,
+// interpolating between the closest two. Returns 0 for an empty list.
+pub fn get_percentile(values: &[f32], percentile: f32) -> f32 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    let position = (sorted.len() - 1) as f32 * percentile / 100.0;
+    let lower = position.floor() as usize;
+    let upper = position.ceil() as usize;
+    sorted[lower] + (sorted[upper] - sorted[lower]) * (position - lower as f32)
+}
+
+// a line of unicode blocks showing the shape of a list of values, scaled
+// from 0 to the largest of them. Each character is the average of an equal
+// part of the first `length` values; values past the end of the list count
+// as 0, and 0 is blank, so a list that stops early shows as a gap at the end.
+pub fn get_sparkline(values: &[f32], length: usize, width: usize) -> String {
+    const BLOCKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    let max = values.iter().fold(0.0, |a: f32, b| a.max(*b));
+    if length == 0 || max <= 0.0 {
+        return " ".repeat(width);
+    }
+
+    (0..width).map(|i| {
+        let start = i * length / width;
+        let end = usize::max((i + 1) * length / width, start + 1);
+        let sum: f32 = (start..end).filter_map(|j| values.get(j)).sum();
+        let average = sum / (end - start) as f32;
+        if average <= 0.0 {
+            ' '
+        }
+        else {
+            let level = (average / max * BLOCKS.len() as f32).ceil() as usize;
+            BLOCKS[level.clamp(1, BLOCKS.len()) - 1]
+        }
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentile() {
+        let values = [4.0, 1.0, 3.0, 2.0, 5.0];
+        assert_eq!(get_percentile(&values, 0.0), 1.0);
+        assert_eq!(get_percentile(&values, 50.0), 3.0);
+        assert_eq!(get_percentile(&values, 100.0), 5.0);
+        assert_eq!(get_percentile(&values, 62.5), 3.5);
+        assert_eq!(get_percentile(&[], 50.0), 0.0);
+    }
+
+    #[test]
+    fn test_sparkline() {
+        assert_eq!(get_sparkline(&[1.0, 2.0, 4.0, 8.0], 4, 4), "▁▂▄█");
+        assert_eq!(get_sparkline(&[8.0, 8.0, 4.0, 4.0], 4, 2), "█▄");
+        assert_eq!(get_sparkline(&[8.0, 4.0], 4, 4), "█▄  ");
+        assert_eq!(get_sparkline(&[], 4, 3), "   ");
+    }
+
+    #[test]
+    fn test_age_months() {
+        let date = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+        assert_eq!(add_years(&date(1960, 2, 29), 65), date(2025, 2, 28));
+        assert_eq!(get_age_months(&date(1965, 7, 15), &date(2025, 1, 14)), 59 * 12 + 5);
+        assert_eq!(get_age_months(&date(1965, 7, 15), &date(2025, 1, 15)), 59 * 12 + 6);
+        assert_eq!(get_month_at_age(&date(1960, 6, 15), 65 * 12), date(2025, 6, 1));
+        assert_eq!(get_month_at_age(&date(1960, 6, 1), 65 * 12), date(2025, 5, 1));
+    }
+}